@@ -1,12 +1,18 @@
+use anyhow::Context;
+use anyhow::Result;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde::Serialize;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::broadcast::error::RecvError;
 use tokio_util::sync::CancellationToken;
+use tracing::debug;
 use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use crate::web_client::report_lagged;
 use crate::web_client::WebClient;
 
 use super::web_client::sessions::acc_api;
@@ -23,6 +29,14 @@ mod tt_api {
     }
 
     #[derive(Debug, Serialize, Deserialize)]
+    pub struct BalanceResponse {
+        pub data: AccountData,
+        pub context: String,
+    }
+
+    // Defaulted as the balances endpoint leaves out fields the stream sends
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    #[serde(default)]
     pub struct AccountData {
         #[serde(rename = "account-number")]
         pub account_number: String,
@@ -131,17 +145,69 @@ mod tt_api {
     }
 }
 
+// Typed view over the balance fields strategies look at, the broker sends
+// every amount as a string and blanks out the ones that do not apply
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balance {
+    pub net_liquidating_value: Decimal,
+    pub derivative_buying_power: Decimal,
+    pub maintenance_requirement: Decimal,
+    pub cash_balance: Decimal,
+}
+
+fn parse_amount(name: &str, value: &str) -> Result<Decimal> {
+    if value.trim().is_empty() {
+        return Ok(Decimal::ZERO);
+    }
+    Decimal::from_str(value.trim()).with_context(|| format!("Invalid {}: {}", name, value))
+}
+
+impl TryFrom<&tt_api::AccountData> for Balance {
+    type Error = anyhow::Error;
+
+    fn try_from(data: &tt_api::AccountData) -> Result<Self> {
+        Ok(Self {
+            net_liquidating_value: parse_amount(
+                "net-liquidating-value",
+                &data.net_liquidating_value,
+            )?,
+            derivative_buying_power: parse_amount(
+                "derivative-buying-power",
+                &data.derivative_buying_power,
+            )?,
+            maintenance_requirement: parse_amount(
+                "maintenance-requirement",
+                &data.maintenance_requirement,
+            )?,
+            cash_balance: parse_amount("cash-balance", &data.cash_balance)?,
+        })
+    }
+}
+
+impl Balance {
+    pub async fn fetch(web_client: &WebClient) -> Result<Self> {
+        let response = web_client
+            .get::<tt_api::BalanceResponse>(&format!(
+                "accounts/{}/balances",
+                web_client.get_account()
+            ))
+            .await?;
+        Self::try_from(&response.data)
+    }
+}
+
 pub struct Account {}
 
 impl Account {
     pub fn new(web_client: Arc<WebClient>, cancel_token: CancellationToken) -> Self {
         let mut receiver = web_client.subscribe_acc_events();
+        let capacity = web_client.account_channel_capacity();
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     msg = receiver.recv() => {
                         match msg {
-                            Err(RecvError::Lagged(err)) => warn!("Publisher channel skipping a number of messages: {}", err),
+                            Err(RecvError::Lagged(dropped)) => report_lagged("account", dropped, capacity),
                             Err(RecvError::Closed) => {
                                 error!("Publisher channel closed");
                                 cancel_token.cancel();
@@ -166,8 +232,29 @@ impl Account {
                 return;
             }
             if let Ok(msg) = serde_json::from_str::<tt_api::AccountBalance>(&payload.data) {
-                info!("Last account balance message received, msg: {:?}", msg);
+                debug!("Last account balance message received, msg: {:?}", msg);
+                match Balance::try_from(&msg.data) {
+                    Ok(balance) => info!("Account balance: {:?}", balance),
+                    Err(err) => warn!("Failed to parse account balance, error: {}", err),
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(
+            parse_amount("cash-balance", "1234.56").unwrap(),
+            dec!(1234.56)
+        );
+        assert_eq!(parse_amount("cash-balance", "-10.0").unwrap(), dec!(-10));
+        assert_eq!(parse_amount("cash-balance", "").unwrap(), Decimal::ZERO);
+        assert!(parse_amount("cash-balance", "n/a").is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::db_client::DBClient;
+use crate::db_client::DbStoredCreds;
+use crate::settings::Settings;
+use crate::web_client::EndPoint;
+
+// Storage of the broker credentials, lets the auth flow run without postgres.
+// The futures are Send so a re-login can run from a spawned task
+pub trait AuthStore: Sync {
+    fn fetch(
+        &self,
+        username: &str,
+        endpoint: EndPoint,
+    ) -> impl Future<Output = Result<Vec<DbStoredCreds>>> + Send;
+    fn update(
+        &self,
+        session: &str,
+        remember: &str,
+        endpoint: EndPoint,
+    ) -> impl Future<Output = Result<()>> + Send;
+    fn insert(&self, creds: &DbStoredCreds) -> impl Future<Output = Result<()>> + Send;
+}
+
+// Credentials kept as a json array in a single file. Writes go to a sibling
+// file first and are renamed over it, a crash mid write leaves the old token
+#[derive(Debug)]
+pub struct FileAuthStore {
+    path: PathBuf,
+    writes: Mutex<()>,
+}
+
+impl FileAuthStore {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            writes: Mutex::new(()),
+        }
+    }
+
+    // A file not yet written holds no credentials
+    async fn read(&self) -> Result<Vec<DbStoredCreds>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read auth file: {}", self.path.display()))
+            }
+        };
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid auth file: {}", self.path.display()))
+    }
+
+    async fn write(&self, creds: &[DbStoredCreds]) -> Result<()> {
+        let staged = self.path.with_extension("tmp");
+        tokio::fs::write(&staged, serde_json::to_string_pretty(creds)?)
+            .await
+            .with_context(|| format!("Failed to write auth file: {}", staged.display()))?;
+        tokio::fs::rename(&staged, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace auth file: {}", self.path.display()))
+    }
+}
+
+impl AuthStore for FileAuthStore {
+    async fn fetch(&self, username: &str, endpoint: EndPoint) -> Result<Vec<DbStoredCreds>> {
+        Ok(self
+            .read()
+            .await?
+            .into_iter()
+            .filter(|creds| creds.username == username && creds.endpoint == endpoint)
+            .collect())
+    }
+
+    async fn update(&self, session: &str, remember: &str, endpoint: EndPoint) -> Result<()> {
+        let _write = self.writes.lock().await;
+        let mut stored = self.read().await?;
+        if !stored.iter().any(|creds| creds.endpoint == endpoint) {
+            bail!("No credentials stored for endpoint: {:?}", endpoint);
+        }
+        for creds in stored.iter_mut().filter(|creds| creds.endpoint == endpoint) {
+            creds.session = session.to_string();
+            creds.remember = remember.to_string();
+        }
+        debug!(
+            "Writing remember token {} to {}",
+            remember,
+            self.path.display()
+        );
+        self.write(&stored).await
+    }
+
+    async fn insert(&self, creds: &DbStoredCreds) -> Result<()> {
+        let _write = self.writes.lock().await;
+        let mut stored = self.read().await?;
+        stored.push(creds.clone());
+        self.write(&stored).await
+    }
+}
+
+// The store picked by the settings, postgres unless an auth file is given
+#[derive(Debug)]
+pub enum AuthBackend {
+    Postgres(DBClient),
+    File(FileAuthStore),
+}
+
+impl AuthBackend {
+    pub async fn new(settings: &Settings) -> Result<Self> {
+        match &settings.auth_file {
+            Some(path) => Ok(AuthBackend::File(FileAuthStore::new(path))),
+            None => Ok(AuthBackend::Postgres(DBClient::new(settings).await?)),
+        }
+    }
+
+    pub fn describe(&self, settings: &Settings) -> String {
+        match (self, &settings.database) {
+            (AuthBackend::File(store), _) => format!("file: {}", store.path.display()),
+            (AuthBackend::Postgres(_), Some(database)) => format!(
+                "postgres: {}:{}/{}",
+                database.host, database.port, database.name
+            ),
+            (AuthBackend::Postgres(_), None) => String::from("postgres"),
+        }
+    }
+
+    pub async fn close(&self) {
+        match self {
+            AuthBackend::Postgres(db) => db.close().await,
+            // Writes hold the lock until the rename, waiting on it is enough
+            AuthBackend::File(store) => drop(store.writes.lock().await),
+        }
+    }
+}
+
+impl AuthStore for AuthBackend {
+    async fn fetch(&self, username: &str, endpoint: EndPoint) -> Result<Vec<DbStoredCreds>> {
+        match self {
+            AuthBackend::Postgres(db) => db.fetch(username, endpoint).await,
+            AuthBackend::File(store) => store.fetch(username, endpoint).await,
+        }
+    }
+
+    async fn update(&self, session: &str, remember: &str, endpoint: EndPoint) -> Result<()> {
+        match self {
+            AuthBackend::Postgres(db) => db.update(session, remember, endpoint).await,
+            AuthBackend::File(store) => store.update(session, remember, endpoint).await,
+        }
+    }
+
+    async fn insert(&self, creds: &DbStoredCreds) -> Result<()> {
+        match self {
+            AuthBackend::Postgres(db) => db.insert(creds).await,
+            AuthBackend::File(store) => store.insert(creds).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_creds(endpoint: EndPoint) -> DbStoredCreds {
+        DbStoredCreds {
+            username: String::from("user"),
+            account: String::from("5WX01234"),
+            session: String::from("session"),
+            remember: String::from("remember"),
+            endpoint,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_store_keeps_token_across_restarts() {
+        let path = std::env::temp_dir().join(format!("auth-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let store = FileAuthStore::new(path);
+        assert!(store
+            .fetch("user", EndPoint::Sandbox)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(store
+            .update("new-session", "new-remember", EndPoint::Sandbox)
+            .await
+            .is_err());
+
+        store.insert(&make_creds(EndPoint::Sandbox)).await.unwrap();
+        store.insert(&make_creds(EndPoint::Live)).await.unwrap();
+        store
+            .update("new-session", "new-remember", EndPoint::Sandbox)
+            .await
+            .unwrap();
+        drop(store);
+
+        // A fresh store on the same file sees the rotated token
+        let store = FileAuthStore::new(path);
+        let sandbox = store.fetch("user", EndPoint::Sandbox).await.unwrap();
+        let live = store.fetch("user", EndPoint::Live).await.unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(sandbox.len(), 1);
+        assert_eq!(sandbox[0].remember, "new-remember");
+        assert_eq!(sandbox[0].session, "new-session");
+        assert_eq!(live, vec![make_creds(EndPoint::Live)]);
+    }
+}
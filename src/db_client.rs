@@ -1,12 +1,20 @@
 use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
 use sqlx::postgres::PgPoolOptions;
+use sqlx::FromRow;
 use sqlx::Pool;
 use sqlx::Postgres;
 use std::env;
+use tokio::sync::RwLock;
+use tracing::debug;
+use tracing::info;
 
 use super::settings::Settings;
+use crate::auth_store::AuthStore;
+use crate::web_client::EndPoint;
 
 #[derive(Debug)]
 pub struct SqlQueryBuilder;
@@ -71,16 +79,31 @@ impl SqlQueryBuilder {
     }
 }
 
+#[derive(FromRow, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct DbStoredCreds {
+    pub username: String,
+    pub account: String,
+    pub session: String,
+    pub remember: String,
+    #[sqlx(flatten)]
+    pub endpoint: EndPoint,
+}
+
 #[derive(Debug)]
 pub struct DBClient {
     pub pool: Pool<Postgres>,
+    // Held shared by every write so close can wait them out
+    writes: RwLock<()>,
 }
 
 impl DBClient {
     pub async fn new(settings: &Settings) -> Result<Self> {
-        let db_cfg = &settings.database;
-        let dbpass =
-            env::var("DB_PASSWORD").expect("Failed to read the 'dbpass' environment variable.");
+        let Some(db_cfg) = &settings.database else {
+            bail!("No database configured");
+        };
+        let Some(dbpass) = env::var("DB_PASSWORD").ok() else {
+            bail!("Failed to read the DB_PASSWORD environment variable");
+        };
         let database_url = format!(
             "postgresql://{}:{}@{}:{}/{}?sslmode=disable",
             db_cfg.user, dbpass, db_cfg.host, db_cfg.port, db_cfg.name
@@ -102,7 +125,84 @@ impl DBClient {
             }
         };
 
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            writes: RwLock::new(()),
+        })
+    }
+
+    // Waits on any write in progress, a refreshed remember token in
+    // particular, before the connections are shut
+    pub async fn close(&self) {
+        let _writes = self.writes.write().await;
+        self.pool.close().await;
+        info!("Database pool closed");
+    }
+}
+
+impl AuthStore for DBClient {
+    async fn fetch(&self, username: &str, endpoint: EndPoint) -> Result<Vec<DbStoredCreds>> {
+        let columns = vec!["username", "endpoint"];
+
+        let stmt = SqlQueryBuilder::prepare_fetch_statement("tasty_auth", &columns);
+        match sqlx::query_as::<_, DbStoredCreds>(&stmt)
+            .bind(username.to_string())
+            .bind::<i32>(endpoint.into())
+            .fetch_all(&self.pool)
+            .await
+        {
+            sqlx::Result::Ok(val) => Ok(val),
+            Err(err) => bail!(
+                "Failed to fetch transactions from db, err={}, closing app",
+                err
+            ),
+        }
+    }
+
+    async fn update(&self, session: &str, remember: &str, endpoint: EndPoint) -> Result<()> {
+        let _write = self.writes.read().await;
+        if self.pool.is_closed() {
+            bail!("Database pool closed, not writing remember token");
+        }
+        let stmt = SqlQueryBuilder::prepare_update_statement(
+            "tasty_auth",
+            &["session", "remember", "endpoint"],
+        );
+
+        debug!(
+            "Writing remember token {} to db for statement {}",
+            remember, stmt
+        );
+        match sqlx::query(&stmt)
+            .bind(session)
+            .bind(remember)
+            .bind::<i32>(endpoint.into())
+            .execute(&self.pool)
+            .await
+        {
+            sqlx::Result::Ok(_) => Ok(()),
+            Err(err) => bail!("Failed to publish to db, error={}", err),
+        }
+    }
+
+    async fn insert(&self, creds: &DbStoredCreds) -> Result<()> {
+        let _write = self.writes.read().await;
+        let stmt = SqlQueryBuilder::prepare_insert_statement(
+            "tasty_auth",
+            &["username", "account", "session", "remember", "endpoint"],
+        );
+        match sqlx::query(&stmt)
+            .bind(&creds.username)
+            .bind(&creds.account)
+            .bind(&creds.session)
+            .bind(&creds.remember)
+            .bind::<i32>(creds.endpoint.into())
+            .execute(&self.pool)
+            .await
+        {
+            sqlx::Result::Ok(_) => Ok(()),
+            Err(err) => bail!("Failed to insert credentials into db, error={}", err),
+        }
     }
 }
 
@@ -0,0 +1,267 @@
+use anyhow::bail;
+use anyhow::Result;
+use std::fmt;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::auth_store::AuthBackend;
+use crate::auth_store::AuthStore;
+use crate::settings::Config;
+use crate::settings::Settings;
+use crate::web_client::SessionHealth;
+use crate::web_client::WebClient;
+
+// In run order, each step needs the ones before it to have passed
+const STEPS: [&str; 9] = [
+    "config",
+    "credential store",
+    "stored credentials",
+    "TLS config",
+    "session token",
+    "api quote token",
+    "websockets",
+    "mktdata channel",
+    "account heartbeat",
+];
+
+const WAIT_TIMEOUT: Duration = Duration::from_secs(15);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    results: Vec<Result<String, String>>,
+}
+
+impl Report {
+    // Records the outcome of the next step, the value is handed back only
+    // when it passed so the run can stop at the first failure
+    fn record<T>(&mut self, result: Result<T>, detail: impl FnOnce(&T) -> String) -> Option<T> {
+        match result {
+            Ok(val) => {
+                self.results.push(Ok(detail(&val)));
+                Some(val)
+            }
+            Err(err) => {
+                self.results.push(Err(format!("{:#}", err)));
+                None
+            }
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.results.len() == STEPS.len() && self.results.iter().all(Result::is_ok)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, step) in STEPS.iter().enumerate() {
+            match self.results.get(index) {
+                Some(Ok(detail)) => writeln!(f, "PASS {}: {}", step, detail)?,
+                Some(Err(err)) => writeln!(f, "FAIL {}: {}", step, err)?,
+                None => writeln!(f, "SKIP {}", step)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Walks through everything startup does up to the point strategies would
+// start, no orders are sent and nothing is subscribed
+pub(crate) struct Doctor;
+
+impl Doctor {
+    pub async fn run(settings_path: &str) -> Report {
+        let mut report = Report::default();
+        let cancel_token = CancellationToken::new();
+        Self::check(&mut report, settings_path, &cancel_token).await;
+        cancel_token.cancel();
+        report
+    }
+
+    async fn check(
+        report: &mut Report,
+        settings_path: &str,
+        cancel_token: &CancellationToken,
+    ) -> Option<()> {
+        let settings = Config::read_config_file(settings_path).and_then(|settings| {
+            settings.validate()?;
+            Ok(settings)
+        });
+        let settings = report.record(settings, |settings| {
+            format!("{}, endpoint: {:?}", settings_path, settings.endpoint)
+        })?;
+
+        let db = report.record(AuthBackend::new(&settings).await, |db| {
+            db.describe(&settings)
+        })?;
+        let result = Self::check_session(report, &settings, &db, cancel_token).await;
+        db.close().await;
+        result
+    }
+
+    async fn check_session(
+        report: &mut Report,
+        settings: &Settings,
+        db: &AuthBackend,
+        cancel_token: &CancellationToken,
+    ) -> Option<()> {
+        let creds = db
+            .fetch(&settings.username, settings.endpoint)
+            .await
+            .and_then(|creds| {
+                WebClient::select_creds(creds, &settings.username, settings.endpoint)
+            });
+        let creds = report.record(creds, |creds| {
+            format!("user: {}, account: {}", creds.username, creds.account)
+        })?;
+
+        // Applied ahead of the login as startup does, a bad CA bundle would
+        // otherwise show up as a failed login
+        let web_client = async {
+            let mut web_client =
+                WebClient::new(&settings.api_base_url(), cancel_token.clone()).await?;
+            web_client.set_tls(&settings.tls)?;
+            Ok(web_client)
+        }
+        .await;
+        let mut web_client = report.record(web_client, |_| match &settings.tls.ca_bundle {
+            Some(path) => format!("CA bundle: {}", path),
+            None => String::from("system roots"),
+        })?;
+
+        web_client.set_request_timeout(Duration::from_secs(settings.request_timeout_secs));
+        let login = web_client.login(settings, db).await;
+        report.record(login, |_| settings.api_base_url())?;
+
+        let api_quote_token = report.record(web_client.api_quote_token().await, |_| {
+            String::from("issued")
+        })?;
+
+        let ws_url = settings.ws_url();
+        let streams = web_client
+            .open_streams(&ws_url, settings, &creds.account, api_quote_token)
+            .await;
+        report.record(streams, |_| ws_url.clone())?;
+
+        let channel = Self::wait_for(
+            &web_client,
+            cancel_token,
+            "mktdata CHANNEL_OPENED",
+            |health| health.name == "mktdata" && health.is_alive,
+        )
+        .await;
+        report.record(channel, |_| String::from("opened"))?;
+
+        let heartbeat = async {
+            Self::wait_for(&web_client, cancel_token, "account connect", |health| {
+                health.name == "account" && health.is_alive
+            })
+            .await?;
+            web_client.send_account_heartbeat().await?;
+            Self::wait_for(&web_client, cancel_token, "account heartbeat", |health| {
+                health.name == "account" && health.last_heartbeat.is_some()
+            })
+            .await
+        }
+        .await;
+        report.record(heartbeat, |_| String::from("received"))
+    }
+
+    // A session answering with an error status cancels the client, so that
+    // is reported straight away rather than waiting out the timeout
+    async fn wait_for(
+        web_client: &WebClient,
+        cancel_token: &CancellationToken,
+        what: &str,
+        is_ready: impl Fn(&SessionHealth) -> bool,
+    ) -> Result<()> {
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+        loop {
+            if web_client.session_health().await.iter().any(&is_ready) {
+                return Ok(());
+            }
+            if cancel_token.is_cancelled() {
+                bail!("{} rejected, see the log for the response", what);
+            }
+            if Instant::now() >= deadline {
+                bail!("No {} within {} seconds", what, WAIT_TIMEOUT.as_secs());
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_report_pinpoints_failed_step() {
+        let mut report = Report::default();
+        assert_eq!(
+            report.record(Ok(()), |_| String::from("settings.json")),
+            Some(())
+        );
+        assert_eq!(
+            report.record::<()>(Err(anyhow!("connection refused")), |_| String::new()),
+            None
+        );
+
+        assert!(!report.passed());
+        let lines = report.to_string();
+        let lines = lines.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), STEPS.len());
+        assert_eq!(lines[0], "PASS config: settings.json");
+        assert_eq!(lines[1], "FAIL credential store: connection refused");
+        assert!(lines[2..].iter().all(|line| line.starts_with("SKIP ")));
+    }
+
+    #[tokio::test]
+    async fn test_missing_config_fails_first_step() {
+        let report = Doctor::run("/nonexistent/settings.json").await;
+        assert!(!report.passed());
+        assert_eq!(report.results.len(), 1);
+        assert!(report.results[0].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bad_ca_bundle_fails_tls_step() {
+        use crate::db_client::DbStoredCreds;
+        use crate::web_client::EndPoint;
+
+        let dir = std::env::temp_dir();
+        let auth_path = dir.join(format!("doctor-auth-{}.json", std::process::id()));
+        let creds = [DbStoredCreds {
+            username: String::from("user"),
+            account: String::from("5WX01234"),
+            session: String::from("session"),
+            remember: String::from("remember"),
+            endpoint: EndPoint::Sandbox,
+        }];
+        std::fs::write(&auth_path, serde_json::to_string(&creds).unwrap()).unwrap();
+        let settings_path = dir.join(format!("doctor-settings-{}.json", std::process::id()));
+        let settings = serde_json::json!({
+            "username": "user",
+            "endpoint": "Sandbox",
+            "log_level": "info",
+            "auth_file": auth_path,
+            "tls": {"ca_bundle": dir.join("doctor-missing-bundle.pem")},
+        });
+        std::fs::write(&settings_path, settings.to_string()).unwrap();
+
+        let report = Doctor::run(settings_path.to_str().unwrap()).await;
+        std::fs::remove_file(&settings_path).unwrap();
+        std::fs::remove_file(&auth_path).unwrap();
+
+        assert!(!report.passed());
+        let lines = report.to_string();
+        let lines = lines.lines().collect::<Vec<&str>>();
+        assert!(lines[2].starts_with("PASS stored credentials"));
+        assert!(lines[3].starts_with("FAIL TLS config: Failed to read CA bundle"));
+        assert_eq!(lines[4], "SKIP session token");
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use chrono::Duration;
+use chrono::Utc;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+use crate::metrics;
+use crate::settings::HealthConfig;
+use crate::web_client::WebClient;
+
+#[derive(Clone)]
+struct HealthState {
+    web_client: Arc<WebClient>,
+    stale_after: Duration,
+}
+
+pub struct HealthServer;
+
+impl HealthServer {
+    pub async fn start(
+        config: &HealthConfig,
+        web_client: Arc<WebClient>,
+        cancel_token: CancellationToken,
+    ) -> Result<()> {
+        let state = HealthState {
+            web_client,
+            stale_after: Duration::seconds(config.stale_after_secs as i64),
+        };
+        let app = Router::new()
+            .route("/healthz", get(Self::healthz))
+            .route("/readyz", get(Self::readyz))
+            .route("/metrics", get(Self::metrics))
+            .with_state(state);
+
+        let listener = TcpListener::bind(("0.0.0.0", config.port)).await?;
+        info!("Health server listening on port: {}", config.port);
+        tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, app)
+                .with_graceful_shutdown(cancel_token.cancelled_owned())
+                .await
+            {
+                error!("Health server stopped, error: {}", err);
+            }
+        });
+        Ok(())
+    }
+
+    async fn healthz() -> StatusCode {
+        StatusCode::OK
+    }
+
+    async fn metrics(State(state): State<HealthState>) -> (StatusCode, String) {
+        match metrics::gather(&state.web_client).await {
+            Ok(val) => (StatusCode::OK, val),
+            Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        }
+    }
+
+    async fn readyz(State(state): State<HealthState>) -> StatusCode {
+        let now = Utc::now();
+        let mut ready = true;
+        for session in state.web_client.session_health().await {
+            if !session.is_alive || session.last_received + state.stale_after < now {
+                warn!(
+                    "Session: {} not ready, alive: {}, last received: {}",
+                    session.name, session.is_alive, session.last_received
+                );
+                ready = false;
+            }
+        }
+        if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use rust_decimal::Decimal;
+
+// Simple moving average of each full window, oldest first
+pub fn sma(values: &[Decimal], period: usize) -> Vec<Decimal> {
+    if period == 0 {
+        return Vec::new();
+    }
+    values
+        .windows(period)
+        .map(|window| window.iter().sum::<Decimal>() / Decimal::from(period))
+        .collect()
+}
+
+// Exponential moving average seeded with the simple average of the first
+// period values, one entry per value from there on
+pub fn ema(values: &[Decimal], period: usize) -> Vec<Decimal> {
+    let Some(seed) = sma(values, period).first().copied() else {
+        return Vec::new();
+    };
+    let alpha = Decimal::TWO / Decimal::from(period + 1);
+    let mut result = vec![seed];
+    values[period..].iter().fold(seed, |ema, value| {
+        let next = ema + alpha * (value - ema);
+        result.push(next);
+        next
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_sma() {
+        let values = [dec!(2), dec!(4), dec!(6), dec!(8), dec!(10)];
+        assert_eq!(sma(&values, 3), vec![dec!(4), dec!(6), dec!(8)]);
+        assert_eq!(sma(&values, 6), Vec::<Decimal>::new());
+        assert_eq!(sma(&values, 0), Vec::<Decimal>::new());
+    }
+
+    #[test]
+    fn test_ema() {
+        // seed of 4 then alpha 0.5 over 8 and 12
+        let values = [dec!(2), dec!(4), dec!(6), dec!(8), dec!(12)];
+        assert_eq!(ema(&values, 3), vec![dec!(4), dec!(6), dec!(9)]);
+        assert_eq!(ema(&values, 5), vec![dec!(6.4)]);
+        assert_eq!(ema(&values, 6), Vec::<Decimal>::new());
+    }
+}
@@ -1,6 +1,15 @@
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use clap::error::ErrorKind;
+use clap::CommandFactory;
 use clap::Parser;
+use clap::Subcommand;
+use rust_decimal::Decimal;
 use std::env;
+use std::io::BufRead;
+use std::io::Write;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
 use tokio_util::sync::CancellationToken;
 use tracing::error;
@@ -8,17 +17,31 @@ use tracing::info;
 use tracing::warn;
 
 mod account;
+mod auth_store;
 mod db_client;
+mod doctor;
+mod health;
+mod indicators;
+mod metrics;
 mod mktdata;
+mod notifier;
 mod orders;
 mod positions;
 mod settings;
+mod signals;
 mod strategies;
 mod tt_api;
+#[cfg(test)]
+mod utils;
 mod web_client;
 
-use db_client::DBClient;
+use auth_store::AuthBackend;
+use doctor::Doctor;
+use health::HealthServer;
 use settings::Config;
+use settings::Settings;
+use strategies::preview::Preview;
+use strategies::preview::PreviewStrategy;
 use strategies::Strategies;
 use web_client::EndPoint;
 use web_client::WebClient;
@@ -26,8 +49,65 @@ use web_client::WebClient;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Required by everything but print-config-template
     #[arg(short, long)]
-    settings: String,
+    settings: Option<String>,
+    /// Send orders to the exchange instead of the dry-run endpoint
+    #[arg(long, default_value_t = false)]
+    live: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the current positions and exit
+    Positions {
+        /// Only show positions on this underlying
+        #[arg(long)]
+        underlying: Option<String>,
+    },
+    /// Log in with a password and store the remember token in the database
+    Login {
+        /// Defaults to the username in the settings file
+        #[arg(long)]
+        username: Option<String>,
+        /// Defaults to the endpoint in the settings file
+        #[arg(long, value_enum)]
+        endpoint: Option<EndPoint>,
+        /// Required when the login holds more than one account
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// Check the config, database, credentials and both websockets, then exit
+    Doctor,
+    /// Print the fills since a date with their fees and exit
+    Transactions {
+        /// YYYY-MM-DD, fills from the start of this day in UTC
+        #[arg(long)]
+        since: NaiveDate,
+    },
+    /// Print the margin and fees of a described spread from a dry run and exit
+    Preview {
+        #[arg(long)]
+        underlying: String,
+        #[arg(long, value_enum)]
+        strategy: PreviewStrategy,
+        /// YYYY-MM-DD
+        #[arg(long)]
+        expiration: NaiveDate,
+        /// Comma separated, short then long for a vertical, low to high
+        /// otherwise
+        #[arg(long, value_delimiter = ',', required = true)]
+        strikes: Vec<Decimal>,
+        #[arg(long, default_value_t = 1)]
+        quantity: i32,
+        /// Net credit per spread
+        #[arg(long)]
+        price: Decimal,
+    },
+    /// Print a settings file with placeholder values to start from
+    PrintConfigTemplate,
 }
 
 fn start_logging() {
@@ -48,17 +128,40 @@ fn start_logging() {
     tracing::subscriber::set_global_default(subscriber).unwrap();
 }
 
-fn graceful_shutdown(is_graceful_shutdown: &mut bool, shutdown_signal: &CancellationToken) {
+const SHUTDOWN_CANCEL_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn graceful_shutdown(
+    is_graceful_shutdown: &mut bool,
+    shutdown_signal: &CancellationToken,
+    strategies: &Strategies,
+    cancel_orders: bool,
+) {
     *is_graceful_shutdown = true;
     info!("Graceful shutdown initiated");
+    if cancel_orders {
+        strategies.cancel_all_working(SHUTDOWN_CANCEL_TIMEOUT).await;
+    }
     shutdown_signal.cancel();
 }
 
-async fn startup_db() -> DBClient {
+// Taken from TASTY_PASSWORD when set so it can be scripted, otherwise read
+// from stdin. It is never echoed or logged
+fn read_password() -> std::io::Result<String> {
+    if let Ok(password) = env::var("TASTY_PASSWORD") {
+        return Ok(password);
+    }
+    eprint!("Password: ");
+    std::io::stderr().flush()?;
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn startup_auth_store() -> AuthBackend {
     let config =
         env::var("OPTIONS_CFG").expect("Failed to get the cfg file from the environment variable.");
     let settings = Config::read_config_file(&config).expect("Failed to parse config file");
-    match DBClient::new(&settings).await {
+    match AuthBackend::new(&settings).await {
         Err(val) => {
             info!("Settings file error: {val}");
             std::process::exit(1);
@@ -67,67 +170,230 @@ async fn startup_db() -> DBClient {
     }
 }
 
-const BASE_URL_UAT: &str = "api.cert.tastyworks.com";
-const BASE_URL_PROD: &str = "api.tastyworks.com";
-
-const WS_URL_UAT: &str = "streamer.cert.tastyworks.com";
-const WS_URL_PROD: &str = "streamer.tastyworks.com";
-
 #[tokio::main]
 async fn main() {
+    let cmdline_args = Args::parse();
+    // Printed ahead of the logging so the output can be redirected to a file
+    if let Some(Command::PrintConfigTemplate) = &cmdline_args.command {
+        match serde_json::to_string_pretty(&Settings::template()) {
+            Ok(template) => println!("{}", template),
+            Err(err) => {
+                eprintln!("Failed to write config template, error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let Some(settings_path) = cmdline_args.settings.as_deref() else {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  --settings <SETTINGS>",
+            )
+            .exit();
+    };
     start_logging();
     info!("___/********Options Trader********\\___");
-    let cmdline_args = Args::parse();
-    let settings = match Config::read_config_file(cmdline_args.settings.as_str()) {
+    if let Some(Command::Doctor) = &cmdline_args.command {
+        let report = Doctor::run(settings_path).await;
+        println!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
+    let mut settings = match Config::read_config_file(settings_path) {
         Err(val) => {
             info!("Settings file error: {val}");
             std::process::exit(1);
         }
         Ok(val) => val,
     };
-    let cancel_token = CancellationToken::new();
-    let (http_url, ws_url) = if settings.endpoint.eq(&EndPoint::Live) {
-        (BASE_URL_PROD, WS_URL_PROD)
+    if let Some(Command::Login {
+        endpoint: Some(endpoint),
+        ..
+    }) = &cmdline_args.command
+    {
+        settings.endpoint = *endpoint;
+    }
+    if let Err(err) = settings.validate() {
+        error!("Invalid settings: {}", err);
+        std::process::exit(1);
+    }
+    settings.live_trading = cmdline_args.live;
+    if settings.live_trading {
+        warn!("!!! LIVE TRADING ENABLED, orders will be sent to the exchange !!!");
     } else {
-        (BASE_URL_UAT, WS_URL_UAT)
-    };
-    let mut web_client = match WebClient::new(http_url, cancel_token.clone()).await {
+        info!("Dry run mode, orders will be sent to the dry-run endpoint");
+    }
+    let cancel_token = CancellationToken::new();
+    let http_url = settings.api_base_url();
+    let ws_url = settings.ws_url();
+    let mut web_client = match WebClient::new(&http_url, cancel_token.clone()).await {
         Ok(val) => val,
         Err(err) => {
             error!("{}", err);
             std::process::exit(1);
         }
     };
-    let db = startup_db().await;
+    let db = Arc::new(startup_auth_store().await);
+    web_client.set_request_timeout(Duration::from_secs(settings.request_timeout_secs));
+    if let Err(err) = web_client.set_tls(&settings.tls) {
+        error!("Failed to apply tls settings, error: {}, exiting app", err);
+        std::process::exit(1);
+    }
+    web_client.enable_reauth(&settings, Arc::clone(&db));
+    if let Some(Command::Login {
+        username, account, ..
+    }) = &cmdline_args.command
+    {
+        let username = username.as_deref().unwrap_or(&settings.username);
+        let password = match read_password() {
+            Ok(val) if !val.is_empty() => val,
+            Ok(_) => {
+                error!("No password given, exiting app");
+                std::process::exit(1);
+            }
+            Err(err) => {
+                error!("Failed to read password, error: {}", err);
+                std::process::exit(1);
+            }
+        };
+        match web_client
+            .seed_credentials(
+                username,
+                settings.endpoint,
+                password,
+                account.as_deref(),
+                db.as_ref(),
+            )
+            .await
+        {
+            Ok(creds) => info!(
+                "Credentials stored for user: {} account: {}",
+                creds.username, creds.account
+            ),
+            Err(err) => {
+                error!("Failed to store credentials, error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        db.close().await;
+        std::process::exit(0);
+    }
+    if let Some(Command::Positions { underlying }) = &cmdline_args.command {
+        if let Err(err) = web_client.login(&settings, db.as_ref()).await {
+            error!("Failed to authenticate, error: {}, exiting app", err);
+            std::process::exit(1);
+        }
+        if let Err(err) =
+            Strategies::print_positions(&web_client, &settings, underlying.as_deref()).await
+        {
+            error!("Failed to fetch positions, error: {}", err);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if let Some(Command::Transactions { since }) = &cmdline_args.command {
+        if let Err(err) = web_client.login(&settings, db.as_ref()).await {
+            error!("Failed to authenticate, error: {}, exiting app", err);
+            std::process::exit(1);
+        }
+        let since = since.and_time(NaiveTime::MIN).and_utc();
+        let transactions = match web_client
+            .get_transactions(web_client.get_account(), since)
+            .await
+        {
+            Ok(val) => val,
+            Err(err) => {
+                error!("Failed to fetch transactions, error: {}", err);
+                std::process::exit(1);
+            }
+        };
+        let fills = transactions
+            .iter()
+            .filter(|transaction| transaction.is_fill())
+            .collect::<Vec<_>>();
+        fills.iter().for_each(|fill| println!("{}", fill));
+        println!(
+            "Fills: {}, fees: {}, net: {}",
+            fills.len(),
+            fills.iter().map(|fill| fill.fees()).sum::<Decimal>(),
+            fills.iter().map(|fill| fill.net_value()).sum::<Decimal>()
+        );
+        std::process::exit(0);
+    }
+    if let Some(Command::Preview {
+        underlying,
+        strategy,
+        expiration,
+        strikes,
+        quantity,
+        price,
+    }) = &cmdline_args.command
+    {
+        if let Err(err) = web_client.login(&settings, db.as_ref()).await {
+            error!("Failed to authenticate, error: {}, exiting app", err);
+            std::process::exit(1);
+        }
+        let preview = Preview {
+            underlying: underlying.clone(),
+            strategy: *strategy,
+            expiration: *expiration,
+            strikes: strikes.clone(),
+            quantity: *quantity,
+            price: *price,
+        };
+        match preview.run(&web_client, &settings).await {
+            Ok(result) => println!("{}", result),
+            Err(err) => {
+                error!("Failed to preview order, error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
     let mut is_graceful_shutdown = false;
     let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
-    if let Err(err) = web_client.startup(ws_url, settings, &db).await {
+    if let Err(err) = web_client.startup(&ws_url, &settings, db.as_ref()).await {
         error!("Failed to startup web_client, error: {}, exiting app", err);
         std::process::exit(1);
     }
-    let _strategies = match Strategies::new(Arc::new(web_client), cancel_token.clone()).await {
-        Err(err) => {
-            error!("Failed to startup strategies, error: {}, exiting app", err);
+    let web_client = Arc::new(web_client);
+    if let Some(health) = &settings.health {
+        if let Err(err) =
+            HealthServer::start(health, Arc::clone(&web_client), cancel_token.clone()).await
+        {
+            error!(
+                "Failed to startup health server, error: {}, exiting app",
+                err
+            );
             std::process::exit(1);
         }
-        Ok(val) => val,
-    };
+    }
+    let strategies =
+        match Strategies::new(Arc::clone(&web_client), &settings, cancel_token.clone()).await {
+            Err(err) => {
+                error!("Failed to startup strategies, error: {}, exiting app", err);
+                std::process::exit(1);
+            }
+            Ok(val) => val,
+        };
     loop {
         tokio::select! {
             _ = cancel_token.cancelled() => {
                 if is_graceful_shutdown {
+                    web_client.store_unstored_token(db.as_ref(), settings.endpoint).await;
+                    db.close().await;
                     std::process::exit(0);
                 }
                 else {
-                    warn!("exiting early");
+                    warn!("exiting early, database pool was not cleanly closed");
                     std::process::exit(1)
                 }
             }
             _ = sigterm.recv() => {
-                graceful_shutdown(&mut is_graceful_shutdown, &cancel_token);
+                graceful_shutdown(&mut is_graceful_shutdown, &cancel_token, &strategies, settings.cancel_orders_on_shutdown).await;
             }
             _ = signal::ctrl_c() => {
-                graceful_shutdown(&mut is_graceful_shutdown, &cancel_token);
+                graceful_shutdown(&mut is_graceful_shutdown, &cancel_token, &strategies, settings.cancel_orders_on_shutdown).await;
             }
         }
     }
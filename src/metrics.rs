@@ -0,0 +1,104 @@
+use anyhow::Result;
+use chrono::Utc;
+use prometheus::register_gauge_vec;
+use prometheus::register_int_counter;
+use prometheus::register_int_counter_vec;
+use prometheus::register_int_gauge;
+use prometheus::Encoder;
+use prometheus::GaugeVec;
+use prometheus::IntCounter;
+use prometheus::IntCounterVec;
+use prometheus::IntGauge;
+use prometheus::TextEncoder;
+use std::sync::LazyLock;
+
+use crate::web_client::WebClient;
+
+pub static MKTDATA_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mktdata_events_total",
+        "Market data events received by event type",
+        &["event_type"]
+    )
+    .unwrap()
+});
+
+pub static TRACKED_SNAPSHOTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "mktdata_tracked_snapshots",
+        "Number of symbols with a market data snapshot"
+    )
+    .unwrap()
+});
+
+pub static STALE_SYMBOL_INTERVALS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "mktdata_stale_intervals_total",
+        "Staleness intervals passed without market data for a symbol"
+    )
+    .unwrap()
+});
+
+pub static MKTDATA_SEQUENCE_GAPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mktdata_sequence_gaps_total",
+        "Quotes arriving with a skipped or out of order sequence by kind",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub static MKTDATA_RESUBSCRIBES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "mktdata_resubscribes_total",
+        "Symbols resubscribed after going stale"
+    )
+    .unwrap()
+});
+
+pub static CHANNEL_DROPPED_MESSAGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "channel_dropped_messages_total",
+        "Messages a lagging receiver lost from a websocket channel by channel",
+        &["channel"]
+    )
+    .unwrap()
+});
+
+pub static ORDERS_IN_FLIGHT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("orders_in_flight", "Number of orders placed and not closed").unwrap()
+});
+
+pub static ORDERS_PLACED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("orders_placed_total", "Orders sent to the broker").unwrap()
+});
+
+pub static WEBSOCKET_RECONNECTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "websocket_reconnects_total",
+        "Websocket reconnections by session",
+        &["session"]
+    )
+    .unwrap()
+});
+
+pub static HEARTBEAT_AGE: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "websocket_last_received_age_seconds",
+        "Seconds since the last message was received by session",
+        &["session"]
+    )
+    .unwrap()
+});
+
+pub async fn gather(web_client: &WebClient) -> Result<String> {
+    let now = Utc::now();
+    for session in web_client.session_health().await {
+        let age = (now - session.last_received).num_milliseconds() as f64 / 1000.;
+        HEARTBEAT_AGE.with_label_values(&[session.name]).set(age);
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
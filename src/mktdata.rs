@@ -1,4 +1,5 @@
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
 use core::fmt;
@@ -8,24 +9,53 @@ use percent_encoding::CONTROLS;
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
-use tokio::time::sleep;
+use tokio::time::interval;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use crate::metrics::MKTDATA_EVENTS;
+use crate::metrics::MKTDATA_RESUBSCRIBES;
+use crate::metrics::MKTDATA_SEQUENCE_GAPS;
+use crate::metrics::STALE_SYMBOL_INTERVALS;
+use crate::metrics::TRACKED_SNAPSHOTS;
 use crate::positions::OptionType;
+use crate::settings::StalenessConfig;
+use crate::settings::SymbolConfig;
 use crate::tt_api::mktdata::*;
+use crate::tt_api::option_chain::Expiration;
+use crate::tt_api::StreamApiError;
+use crate::tt_api::StreamErrorKind;
+use crate::web_client::report_lagged;
+use crate::web_client::sessions::md_api;
 
 use super::web_client::WebClient;
 
-const UTF8_ECODING: &AsciiSet = &CONTROLS.add(b' ').add(b'/');
+// How long a new subscription may go without data before it is reported
+const FIRST_TICK_GRACE: Duration = Duration::from_secs(30);
+
+// Instruments rarely change intraday, so a lookup is reused for this long
+const INSTRUMENT_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, PartialEq)]
+enum SequenceCheck {
+    InOrder,
+    Gap(u64),
+    OutOfOrder,
+}
+
+pub(crate) const UTF8_ECODING: &AsciiSet = &CONTROLS.add(b' ').add(b'/');
 
 pub(crate) trait FeedEventExt {
     type Event;
@@ -48,50 +78,179 @@ impl FeedEventExt for Greeks {
     }
 }
 
+impl FeedEventExt for Candle {
+    type Event = Candle;
+
+    fn extract_event(snapshot: &Snapshot) -> Option<Self::Event> {
+        snapshot.candle.clone()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct Snapshot {
     pub symbol: String,
     pub underlying: String,
     pub streamer_symbol: String,
+    pub subscribed_at: Instant,
+    pub first_tick_at: Option<Instant>,
     pub last_update: Instant,
+    pub stale_intervals: u32,
+    // Cleared whenever the feed restarts so a reset is not taken as a gap
+    pub last_sequence: Option<u64>,
     pub strike_price: Option<Decimal>,
+    // Futures quotes arrive unscaled, multiplying by this gives real prices
+    pub display_factor: Decimal,
     pub quote: Option<Quote>,
     pub greeks: Option<Greeks>,
+    pub candle: Option<Candle>,
+    pub candles: VecDeque<Candle>,
+}
+
+impl Snapshot {
+    pub fn midprice(&self) -> Option<Decimal> {
+        self.quote
+            .as_ref()
+            .and_then(|quote| quote.midprice())
+            .map(|midprice| (midprice * self.display_factor).normalize())
+    }
+
+    // Bid and ask in real prices, None unless both sides are quoted
+    pub fn touch(&self) -> Option<(Decimal, Decimal)> {
+        let quote = self.quote.as_ref()?;
+        let (bid, ask) = (quote.bid_price.abs(), quote.ask_price.abs());
+        if bid.is_zero() || ask.is_zero() {
+            return None;
+        }
+        Some((
+            (bid * self.display_factor).normalize(),
+            (ask * self.display_factor).normalize(),
+        ))
+    }
+
+    // Keeps a bounded history of candles in time order, evicting the oldest.
+    // dxlink resends the forming candle as it changes and may replay history
+    // newest first, so a candle replaces the one with its time and an older
+    // one is slotted in behind
+    fn push_candle(&mut self, candle: Candle, capacity: usize) {
+        if capacity == 0 {
+            self.candle = Some(candle);
+            return;
+        }
+        match self
+            .candles
+            .iter()
+            .rposition(|held| held.time <= candle.time)
+        {
+            Some(at) if self.candles[at].time == candle.time => self.candles[at] = candle,
+            Some(at) => self.candles.insert(at + 1, candle),
+            None => self.candles.push_front(candle),
+        }
+        while self.candles.len() > capacity {
+            self.candles.pop_front();
+        }
+        self.candle = self.candles.back().cloned();
+    }
+
+    // Up to the last n candles, oldest first
+    pub fn recent_candles(&self, n: usize) -> Vec<Candle> {
+        self.candles
+            .iter()
+            .skip(self.candles.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+}
+
+// What the instruments endpoint says about a symbol, the display factor is
+// one for anything but futures
+#[derive(Clone, Debug)]
+struct Instrument {
+    streamer_symbol: String,
+    display_factor: Decimal,
+}
+
+// Streamer symbol and display factor as the instruments endpoint gives them
+type InstrumentFields = (Option<String>, Decimal);
+
+// Instrument lookups by symbol, so resubscribing does not hit the
+// instruments endpoint again until the entry is older than the ttl
+#[derive(Clone)]
+struct InstrumentCache {
+    ttl: Duration,
+    instruments: Arc<Mutex<HashMap<String, (Instant, Instrument)>>>,
+}
+
+impl InstrumentCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            instruments: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn get(&self, symbol: &str) -> Option<Instrument> {
+        self.instruments
+            .lock()
+            .await
+            .get(symbol)
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < self.ttl)
+            .map(|(_, instrument)| instrument.clone())
+    }
+
+    async fn insert(&self, symbol: &str, instrument: Instrument) {
+        self.instruments
+            .lock()
+            .await
+            .insert(symbol.to_string(), (Instant::now(), instrument));
+    }
 }
 
 pub(crate) struct MktData {
     web_client: Arc<WebClient>,
     events: Arc<Mutex<Vec<Snapshot>>>,
+    instruments: InstrumentCache,
+    symbols: SymbolConfig,
+    // Symbols watched only as part of a chain band, by underlying, released
+    // once the band moves off them
+    chain_symbols: HashMap<String, HashSet<String>>,
 }
 
 impl MktData {
-    pub fn new(client: Arc<WebClient>, cancel_token: CancellationToken) -> Self {
+    pub fn new(
+        client: Arc<WebClient>,
+        candle_history: usize,
+        data_format: DataFormat,
+        staleness: StalenessConfig,
+        symbols: SymbolConfig,
+        cancel_token: CancellationToken,
+    ) -> Self {
         let mut receiver = client.subscribe_md_events();
+        let capacity = client.md_channel_capacity();
         let events = Arc::new(Mutex::new(Vec::new()));
         let event_writer = Arc::clone(&events);
+        let watchdog_client = Arc::clone(&client);
+        // An interval rather than a sleep so a busy feed can't starve the watchdog
+        let mut watchdog = interval(Duration::from_secs(1));
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     msg = receiver.recv() => {
                         match msg {
-                            Err(RecvError::Lagged(err)) => warn!("Publisher channel skipping a number of messages: {}", err),
+                            Err(RecvError::Lagged(dropped)) => report_lagged("md", dropped, capacity),
                             Err(RecvError::Closed) => {
                                 error!("Publisher channel closed");
                                 cancel_token.cancel();
                             }
                             std::result::Result::Ok(val) => {
-                                Self::handle_msg(&event_writer, val).await
+                                Self::handle_msg(&event_writer, val, candle_history, data_format).await
                             }
                         }
                     }
-                    _ = sleep(Duration::from_secs(1)) => {
-                        event_writer.lock().await.iter_mut().for_each(|snapshot| {
-                            let instant = &mut snapshot.last_update;
-                            if Instant::now().duration_since(*instant).gt(&Duration::from_secs(30)) {
-                                warn!("Not received any mktdata for symbol: {} for 30 seconds", snapshot.streamer_symbol);
-                                instant.clone_from(&Instant::now());
-                            }
-                        })
+                    _ = watchdog.tick() => {
+                        let stale = Self::check_staleness(&mut event_writer.lock().await, &staleness, Instant::now());
+                        for (streamer_symbol, event_type) in stale {
+                            Self::resubscribe(&watchdog_client, &streamer_symbol, event_type).await;
+                        }
                     }
                     _ = cancel_token.cancelled() => {
                         break
@@ -103,20 +262,131 @@ impl MktData {
         Self {
             web_client: client,
             events,
+            instruments: InstrumentCache::new(INSTRUMENT_CACHE_TTL),
+            symbols,
+            chain_symbols: HashMap::new(),
         }
     }
 
-    async fn handle_msg(events: &Arc<Mutex<Vec<Snapshot>>>, msg: String) {
+    // COMPACT data alternates each event type with a flat array of values,
+    // mapped back onto field names in the order asked for in FEED_SETUP. An
+    // event type nothing asked for is skipped without losing the rest
+    fn decode_compact(msg: &str) -> Result<FeedDataMessage> {
+        let compact = serde_json::from_str::<CompactFeedDataMessage>(msg)?;
+        let mut data = Vec::new();
+        for pair in compact.data.chunks(2) {
+            let [event_type, values] = pair else {
+                bail!("Compact feed data has an event type without values");
+            };
+            let fields: &[&str] = match event_type.as_str() {
+                Some("Quote") => &md_api::QUOTE_FIELDS,
+                Some("Greeks") => &md_api::GREEKS_FIELDS,
+                Some("Candle") => &md_api::CANDLE_FIELDS,
+                _ => {
+                    warn!(
+                        "Skipping unexpected event type: {} in compact feed data",
+                        event_type
+                    );
+                    continue;
+                }
+            };
+            let Some(values) = values.as_array() else {
+                bail!(
+                    "Compact feed data values for {} are not an array",
+                    event_type
+                );
+            };
+            if values.len() % fields.len() != 0 {
+                bail!(
+                    "Compact feed data for {} has {} values, expected a multiple of {}",
+                    event_type,
+                    values.len(),
+                    fields.len()
+                );
+            }
+            for event in values.chunks(fields.len()) {
+                let event = fields
+                    .iter()
+                    .map(|field| field.to_string())
+                    .zip(event.iter().cloned())
+                    .collect::<serde_json::Map<String, serde_json::Value>>();
+                data.push(serde_json::from_value::<FeedEvent>(
+                    serde_json::Value::Object(event),
+                )?);
+            }
+        }
+        Ok(FeedDataMessage {
+            message: compact.message,
+            data,
+        })
+    }
+
+    async fn handle_msg(
+        events: &Arc<Mutex<Vec<Snapshot>>>,
+        msg: String,
+        candle_history: usize,
+        data_format: DataFormat,
+    ) {
+        fn get_event_type(data: &FeedEvent) -> &str {
+            match data {
+                FeedEvent::Quote(_) => "Quote",
+                FeedEvent::Greeks(_) => "Greeks",
+                FeedEvent::Candle(_) => "Candle",
+            }
+        }
+
         fn get_symbol(data: &FeedEvent) -> &str {
             match data {
-                FeedEvent::QuoteEvent(event) => event.event_symbol.as_ref(),
-                FeedEvent::GreeksEvent(event) => event.event_symbol.as_ref(),
+                FeedEvent::Quote(event) => event.event_symbol.as_ref(),
+                FeedEvent::Greeks(event) => event.event_symbol.as_ref(),
+                FeedEvent::Candle(event) => event.event_symbol.as_ref(),
+            }
+        }
+
+        let msg_type = serde_json::from_str::<md_api::Header>(&msg)
+            .map(|header| header.msg_type)
+            .unwrap_or_default();
+        // A reopened channel means a new connection, sequences start over
+        if msg_type == "CHANNEL_OPENED" {
+            events
+                .lock()
+                .await
+                .iter_mut()
+                .for_each(|snapshot| snapshot.last_sequence = None);
+            return;
+        }
+
+        // The session has unsubscribed a bad symbol, its snapshot goes too so
+        // nothing reads the last price it had
+        if msg_type == "ERROR" {
+            let error = serde_json::from_str::<StreamApiError>(&msg).unwrap_or_default();
+            let mut writer = events.lock().await;
+            let symbols = writer
+                .iter()
+                .map(|snapshot| snapshot.streamer_symbol.clone())
+                .collect::<Vec<_>>();
+            if let StreamErrorKind::BadSymbol(symbol) = error.kind(&symbols) {
+                warn!("Evicting snapshot of bad symbol: {}", symbol);
+                writer.retain(|snapshot| snapshot.streamer_symbol != symbol);
+                TRACKED_SNAPSHOTS.set(writer.len() as i64);
             }
+            return;
         }
 
-        match serde_json::from_str::<FeedDataMessage>(&msg) {
-            serde_json::Result::Ok(mut msg) => {
+        let decoded = match data_format {
+            DataFormat::Full => {
+                serde_json::from_str::<FeedDataMessage>(&msg).map_err(|err| anyhow!(err))
+            }
+            DataFormat::Compact => Self::decode_compact(&msg),
+        };
+        match decoded {
+            std::result::Result::Ok(mut msg) => {
                 debug!("Last mktdata message received, msg: {:?}", msg);
+                msg.data.iter().for_each(|event| {
+                    MKTDATA_EVENTS
+                        .with_label_values(&[get_event_type(event)])
+                        .inc()
+                });
 
                 let mut writer = events.lock().await;
                 writer.iter_mut().for_each(|snapshot| {
@@ -126,19 +396,24 @@ impl MktData {
                             return;
                         }
                         match &event {
-                            FeedEvent::QuoteEvent(event) => {
+                            FeedEvent::Quote(event) => {
+                                Self::track_sequence(snapshot, event.sequence as u64);
                                 snapshot.quote = Some(event.clone());
                             }
-                            FeedEvent::GreeksEvent(event) => {
+                            FeedEvent::Greeks(event) => {
                                 snapshot.greeks = Some(event.clone());
                             }
-                            _ => (),
+                            FeedEvent::Candle(event) => {
+                                snapshot.push_candle(event.clone(), candle_history);
+                            }
                         }
                         snapshot.last_update = Instant::now();
+                        snapshot.first_tick_at.get_or_insert(snapshot.last_update);
+                        snapshot.stale_intervals = 0;
                     })
                 });
             }
-            serde_json::Result::Err(err) => {
+            Err(err) => {
                 warn!(
                     "No Last mktdata message received: {:?}, error: {:?}",
                     msg, err
@@ -148,6 +423,126 @@ impl MktData {
         debug!("Writer updated {}", events.lock().await.len());
     }
 
+    fn check_sequence(last: Option<u64>, sequence: u64) -> SequenceCheck {
+        match last {
+            Some(last) if sequence <= last => SequenceCheck::OutOfOrder,
+            Some(last) if sequence > last + 1 => SequenceCheck::Gap(sequence - last - 1),
+            _ => SequenceCheck::InOrder,
+        }
+    }
+
+    // Events the feed leaves unnumbered carry a zero sequence and are not
+    // tracked, an out of order event keeps the highest sequence seen
+    fn track_sequence(snapshot: &mut Snapshot, sequence: u64) {
+        if sequence == 0 {
+            return;
+        }
+        match Self::check_sequence(snapshot.last_sequence, sequence) {
+            SequenceCheck::InOrder => (),
+            SequenceCheck::Gap(missed) => {
+                MKTDATA_SEQUENCE_GAPS.with_label_values(&["gap"]).inc();
+                warn!(
+                    "Sequence gap for symbol: {}, missed {} events before sequence: {}",
+                    snapshot.streamer_symbol, missed, sequence
+                );
+            }
+            SequenceCheck::OutOfOrder => {
+                MKTDATA_SEQUENCE_GAPS
+                    .with_label_values(&["out_of_order"])
+                    .inc();
+                warn!(
+                    "Out of order sequence for symbol: {}, got: {} after: {:?}",
+                    snapshot.streamer_symbol, sequence, snapshot.last_sequence
+                );
+                return;
+            }
+        }
+        snapshot.last_sequence = Some(sequence);
+    }
+
+    // Counts whole threshold intervals without data per symbol and returns
+    // the symbols due a resubscribe along with the events they carry
+    fn check_staleness(
+        snapshots: &mut [Snapshot],
+        config: &StalenessConfig,
+        now: Instant,
+    ) -> Vec<(String, &'static [&'static str])> {
+        let threshold = config.threshold_secs.max(1);
+        let mut stale = Vec::new();
+        for snapshot in snapshots.iter_mut() {
+            let intervals = (now.duration_since(snapshot.last_update).as_secs() / threshold) as u32;
+            if intervals <= snapshot.stale_intervals {
+                continue;
+            }
+            snapshot.stale_intervals = intervals;
+            STALE_SYMBOL_INTERVALS.inc();
+            warn!(
+                "Not received any mktdata for symbol: {} for {} seconds",
+                snapshot.streamer_symbol,
+                intervals as u64 * threshold
+            );
+            if config.resubscribe_after > 0 && intervals.is_multiple_of(config.resubscribe_after) {
+                let event_type: &'static [&'static str] =
+                    match (snapshot.streamer_symbol.contains("{="), &snapshot.greeks) {
+                        (true, _) => &["Candle"],
+                        (false, Some(_)) => &["Quote", "Greeks"],
+                        (false, None) => &["Quote"],
+                    };
+                snapshot.last_sequence = None;
+                stale.push((snapshot.streamer_symbol.clone(), event_type));
+            }
+        }
+        stale
+    }
+
+    async fn resubscribe(web_client: &WebClient, streamer_symbol: &str, event_type: &[&str]) {
+        info!("Resubscribing to stale symbol: {}", streamer_symbol);
+        MKTDATA_RESUBSCRIBES.inc();
+        if let Err(err) = web_client
+            .unsubscribe_from_symbol(streamer_symbol, event_type)
+            .await
+        {
+            error!(
+                "Failed to unsubscribe from stale symbol: {}, error: {}",
+                streamer_symbol, err
+            );
+        }
+        if let Err(err) = web_client
+            .subscribe_to_symbol(streamer_symbol, event_type)
+            .await
+        {
+            error!(
+                "Failed to resubscribe to stale symbol: {}, error: {}",
+                streamer_symbol, err
+            );
+        }
+    }
+
+    // Symbols subscribed for longer than the grace period that have never
+    // ticked, usually a streamer symbol the feed does not know
+    pub async fn subscription_report(&self) -> Vec<Snapshot> {
+        Self::never_ticked(&self.events.lock().await, FIRST_TICK_GRACE, Instant::now())
+    }
+
+    fn never_ticked(snapshots: &[Snapshot], grace: Duration, now: Instant) -> Vec<Snapshot> {
+        snapshots
+            .iter()
+            .filter(|snapshot| {
+                snapshot.first_tick_at.is_none()
+                    && now.duration_since(snapshot.subscribed_at) >= grace
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub async fn is_stale(&self, symbol: &str) -> bool {
+        self.events
+            .lock()
+            .await
+            .iter()
+            .any(|snapshot| snapshot.symbol.eq(symbol) && snapshot.stale_intervals > 0)
+    }
+
     pub async fn subscribe_to_feed(
         &mut self,
         symbol: &str,
@@ -156,26 +551,217 @@ impl MktData {
         instrument_type: OptionType,
         strike_price: Option<Decimal>,
     ) -> anyhow::Result<()> {
-        let streamer_symbol = self.get_streamer_symbol(symbol, instrument_type).await?;
+        if self.is_subscribed(symbol).await {
+            debug!(
+                "Already subscribed to mktdata events for symbol: {}",
+                symbol
+            );
+            // Held for its own sake now, a chain band moving off it keeps it
+            self.chain_symbols
+                .values_mut()
+                .for_each(|chain| _ = chain.remove(symbol));
+            return Ok(());
+        }
+
+        let instrument = self.get_instrument(symbol, instrument_type).await?;
         info!(
             "Subscribing to mktdata events for symbol: {}",
-            streamer_symbol
+            instrument.streamer_symbol
         );
 
         self.web_client
-            .subscribe_to_symbol(&streamer_symbol, event_type)
+            .subscribe_to_symbol(&instrument.streamer_symbol, event_type)
             .await?;
         Self::stash_subscription(
             &mut self.events,
             symbol,
             underlying,
-            &streamer_symbol,
+            &instrument.streamer_symbol,
             strike_price,
+            instrument.display_factor,
+        )
+        .await;
+        Ok(())
+    }
+
+    // Candles of a symbol aggregated over period such as 5m, dxlink names
+    // them by the streamer symbol with the period appended. Returns the
+    // symbol the candle snapshot is kept under
+    pub async fn subscribe_to_candles(
+        &mut self,
+        symbol: &str,
+        underlying: &str,
+        instrument_type: OptionType,
+        period: &str,
+    ) -> anyhow::Result<String> {
+        let candle_symbol = Self::candle_symbol(symbol, period);
+        if self.is_subscribed(&candle_symbol).await {
+            return Ok(candle_symbol);
+        }
+
+        let instrument = self.get_instrument(symbol, instrument_type).await?;
+        let streamer_symbol = Self::candle_symbol(&instrument.streamer_symbol, period);
+        info!("Subscribing to candles for symbol: {}", streamer_symbol);
+        self.web_client
+            .subscribe_to_symbol(&streamer_symbol, &["Candle"])
+            .await?;
+        Self::stash_subscription(
+            &mut self.events,
+            &candle_symbol,
+            underlying,
+            &streamer_symbol,
+            None,
+            instrument.display_factor,
         )
         .await;
+        Ok(candle_symbol)
+    }
+
+    fn candle_symbol(symbol: &str, period: &str) -> String {
+        format!("{}{{={}}}", symbol, period)
+    }
+
+    // Watches calls and puts for a band of strikes around the underlying's
+    // current price, the underlying has to be subscribed already. The chain
+    // is fetched by the caller so no request is made under the lock, and
+    // symbols the band has moved off since the last call are released
+    pub async fn subscribe_to_chain(
+        &mut self,
+        underlying: &str,
+        expiration: &Expiration,
+        strikes_around: u32,
+        max_symbols: usize,
+    ) -> anyhow::Result<()> {
+        let Some(spot) = self
+            .get_snapshot_by_symbol::<Quote>(underlying)
+            .await
+            .and_then(|snapshot| snapshot.midprice())
+        else {
+            bail!("No quote for underlying: {}", underlying);
+        };
+        let band = Self::chain_band(expiration, spot, strikes_around, max_symbols);
+        if band.len() == max_symbols {
+            warn!(
+                "Capping chain subscription for: {} at {} symbols",
+                underlying, max_symbols
+            );
+        }
+
+        let held = self.chain_symbols.remove(underlying).unwrap_or_default();
+        for symbol in held
+            .iter()
+            .filter(|symbol| !band.iter().any(|(in_band, _, _)| in_band == symbol))
+        {
+            if let Err(err) = self.unsubscribe(symbol).await {
+                warn!("Failed to release chain symbol: {}, error: {}", symbol, err);
+            }
+        }
+        let mut legs = Vec::new();
+        for (symbol, streamer_symbol, strike_price) in &band {
+            if !self.is_subscribed(symbol).await {
+                legs.push((*symbol, *streamer_symbol, *strike_price));
+            }
+        }
+        // Band symbols already held for a position are not the chain's to release
+        let chain = band
+            .iter()
+            .map(|(symbol, _, _)| *symbol)
+            .filter(|symbol| held.contains(*symbol) || legs.iter().any(|leg| leg.0 == *symbol))
+            .cloned()
+            .collect();
+        self.chain_symbols.insert(underlying.to_string(), chain);
+        if legs.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Subscribing to {} chain symbols for: {} expiring: {}",
+            legs.len(),
+            underlying,
+            expiration.expiration_date
+        );
+        let streamer_symbols = legs
+            .iter()
+            .map(|(_, streamer_symbol, _)| streamer_symbol.to_string())
+            .collect::<Vec<_>>();
+        self.web_client
+            .subscribe_to_symbols(&streamer_symbols, &["Quote", "Greeks"])
+            .await?;
+        for (symbol, streamer_symbol, strike_price) in legs {
+            Self::stash_subscription(
+                &mut self.events,
+                symbol,
+                underlying,
+                streamer_symbol,
+                Some(strike_price),
+                Decimal::ONE,
+            )
+            .await;
+        }
         Ok(())
     }
 
+    // Call and put symbols with their streamer symbols and strike for the
+    // band around spot, capped at max_symbols
+    fn chain_band(
+        expiration: &Expiration,
+        spot: Decimal,
+        strikes_around: u32,
+        max_symbols: usize,
+    ) -> Vec<(&String, &String, Decimal)> {
+        expiration
+            .strikes_around(spot, strikes_around as usize)
+            .into_iter()
+            .flat_map(|strike| {
+                [
+                    (
+                        &strike.call,
+                        &strike.call_streamer_symbol,
+                        strike.strike_price,
+                    ),
+                    (
+                        &strike.put,
+                        &strike.put_streamer_symbol,
+                        strike.strike_price,
+                    ),
+                ]
+            })
+            .take(max_symbols)
+            .collect()
+    }
+
+    // Releases the feed slot and forgets the snapshot for a symbol no longer held
+    pub async fn unsubscribe(&mut self, symbol: &str) -> anyhow::Result<()> {
+        let Some(snapshot) = Self::drop_snapshot(&self.events, symbol).await else {
+            return Ok(());
+        };
+        info!(
+            "Unsubscribing from mktdata events for symbol: {}",
+            snapshot.streamer_symbol
+        );
+        self.web_client
+            .unsubscribe_from_symbol(&snapshot.streamer_symbol, &["Quote", "Greeks"])
+            .await
+    }
+
+    async fn drop_snapshot(events: &Mutex<Vec<Snapshot>>, symbol: &str) -> Option<Snapshot> {
+        let mut writer = events.lock().await;
+        let index = writer
+            .iter()
+            .position(|snapshot| snapshot.symbol.eq(symbol))?;
+        let snapshot = writer.remove(index);
+        TRACKED_SNAPSHOTS.set(writer.len() as i64);
+        Some(snapshot)
+    }
+
+    async fn is_subscribed(&self, symbol: &str) -> bool {
+        self.events
+            .lock()
+            .await
+            .iter()
+            .any(|snapshot| snapshot.symbol.eq(symbol))
+    }
+
     pub async fn get_snapshot_by_symbol<'a, T>(&self, symbol: &str) -> Option<Snapshot>
     where
         T: FeedEventExt + 'a,
@@ -224,63 +810,227 @@ impl MktData {
         events
     }
 
-    async fn get_streamer_symbol(
+    async fn get_instrument(
         &self,
         symbol: &str,
         instrument_type: OptionType,
-    ) -> Result<String> {
-        let symbol = utf8_percent_encode(symbol, UTF8_ECODING).to_string();
+    ) -> Result<Instrument> {
+        if let Some(instrument) = self.instruments.get(symbol).await {
+            return Ok(instrument);
+        }
+        // Indices are not equities, their streamer symbol is known already
+        let index = match instrument_type {
+            OptionType::Equity => self.symbols.index_streamer_symbol(symbol),
+            _ => None,
+        };
+        if let Some(streamer_symbol) = index {
+            return Ok(Instrument {
+                streamer_symbol,
+                display_factor: Decimal::ONE,
+            });
+        }
+        let instrument = self.fetch_instrument(symbol, instrument_type).await?;
+        self.instruments.insert(symbol, instrument.clone()).await;
+        Ok(instrument)
+    }
 
-        async fn streamer_symbol<Response>(web_client: &WebClient, endpoint: &str) -> Response
+    async fn fetch_instrument(
+        &self,
+        symbol: &str,
+        instrument_type: OptionType,
+    ) -> Result<Instrument> {
+        let symbol = match instrument_type {
+            OptionType::Equity | OptionType::Future => self.symbols.normalize(symbol),
+            _ => symbol.to_string(),
+        };
+        let symbol = utf8_percent_encode(&symbol, UTF8_ECODING).to_string();
+
+        async fn streamer_symbol<Response>(
+            web_client: &WebClient,
+            endpoint: &str,
+        ) -> Result<Response>
         where
             Response: for<'a> Deserialize<'a> + Serialize + fmt::Debug,
         {
-            match web_client.get::<Response>(endpoint).await {
-                anyhow::Result::Ok(response) => response,
-                Err(e) => panic!("Error getting streamer symbol: {:?}", e),
-            }
+            web_client
+                .get::<Response>(endpoint)
+                .await
+                .map_err(|err| anyhow!("Error getting streamer symbol: {}", err))
         }
 
-        let streamer_symbol = match instrument_type {
+        let (streamer_symbol, display_factor) = match instrument_type {
             OptionType::Equity => {
-                streamer_symbol::<Response<Equity>>(
+                let equity = streamer_symbol::<Response<Equity>>(
                     &self.web_client,
                     &format!("instruments/equities/{}", symbol),
                 )
-                .await
-                .data
-                .streamer_symbol
+                .await?
+                .data;
+                (equity.streamer_symbol, Decimal::ONE)
             }
             OptionType::Future => {
-                streamer_symbol::<Response<Future>>(
+                let future = streamer_symbol::<Response<Future>>(
                     &self.web_client,
                     &format!("instruments/futures/{}", symbol),
                 )
-                .await
-                .data
-                .streamer_symbol
+                .await?
+                .data;
+                (
+                    future.streamer_symbol,
+                    Self::parse_display_factor(future.display_factor.as_deref()),
+                )
             }
-            OptionType::EquityOption => {
+            OptionType::EquityOption => Self::equity_option_fields(
                 streamer_symbol::<Response<EquityOption>>(
                     &self.web_client,
                     &format!("instruments/equity-options/{}", symbol),
                 )
-                .await
-                .data
-                .streamer_symbol
-            }
-            OptionType::FutureOption => {
+                .await?
+                .data,
+            ),
+            OptionType::FutureOption => Self::future_option_fields(
                 streamer_symbol::<Response<FutureOption>>(
                     &self.web_client,
                     &format!("instruments/future-options/{}", symbol),
                 )
-                .await
-                .data
-                .streamer_symbol
+                .await?
+                .data,
+            ),
+        };
+
+        match streamer_symbol {
+            Some(streamer_symbol) => Ok(Instrument {
+                streamer_symbol,
+                display_factor,
+            }),
+            None => Err(anyhow!("Error getting streamer symbol: {}", symbol)),
+        }
+    }
+
+    fn equity_option_fields(option: EquityOption) -> InstrumentFields {
+        (option.streamer_symbol, Decimal::ONE)
+    }
+
+    fn future_option_fields(option: FutureOption) -> InstrumentFields {
+        let display_factor = option.display_factor.as_deref().or(option
+            .future_option_product
+            .as_ref()
+            .and_then(|product| product.display_factor.as_deref()));
+        (
+            option.streamer_symbol,
+            Self::parse_display_factor(display_factor),
+        )
+    }
+
+    // Looks up every option symbol not already cached in one request and
+    // caches what comes back, so subscribing a condor's legs costs one call
+    // rather than four. Symbols the broker does not know are left out of
+    // the returned symbol to streamer symbol map
+    pub async fn resolve_streamer_symbols(
+        &self,
+        symbols: &[&str],
+        instrument_type: OptionType,
+    ) -> Result<HashMap<String, String>> {
+        let mut resolved = HashMap::new();
+        let mut missing = Vec::new();
+        for symbol in symbols {
+            match self.instruments.get(symbol).await {
+                Some(instrument) => {
+                    resolved.insert(symbol.to_string(), instrument.streamer_symbol);
+                }
+                None => missing.push(("symbol[]", *symbol)),
             }
+        }
+        if missing.is_empty() {
+            return Ok(resolved);
+        }
+
+        let fields = match instrument_type {
+            OptionType::EquityOption => self
+                .web_client
+                .get_with_query::<Response<Instruments<EquityOption>>>(
+                    "instruments/equity-options",
+                    &missing,
+                )
+                .await?
+                .data
+                .items
+                .into_iter()
+                .map(|option| (option.symbol.clone(), Self::equity_option_fields(option)))
+                .collect::<Vec<_>>(),
+            OptionType::FutureOption => self
+                .web_client
+                .get_with_query::<Response<Instruments<FutureOption>>>(
+                    "instruments/future-options",
+                    &missing,
+                )
+                .await?
+                .data
+                .items
+                .into_iter()
+                .map(|option| {
+                    (
+                        Some(option.symbol.clone()),
+                        Self::future_option_fields(option),
+                    )
+                })
+                .collect(),
+            _ => bail!(
+                "Batched lookups are for options only, not: {:?}",
+                instrument_type
+            ),
         };
+        for (symbol, (streamer_symbol, display_factor)) in fields {
+            let (Some(symbol), Some(streamer_symbol)) = (symbol, streamer_symbol) else {
+                continue;
+            };
+            if !missing.iter().any(|(_, missing)| *missing == symbol) {
+                continue;
+            }
+            self.instruments
+                .insert(
+                    &symbol,
+                    Instrument {
+                        streamer_symbol: streamer_symbol.clone(),
+                        display_factor,
+                    },
+                )
+                .await;
+            resolved.insert(symbol, streamer_symbol);
+        }
+        Ok(resolved)
+    }
+
+    fn parse_decimal(value: Option<&str>) -> Option<Decimal> {
+        value.and_then(|value| Decimal::from_str(value).ok())
+    }
 
-        streamer_symbol.ok_or(anyhow!("Error getting streamer symbol: {}", symbol))
+    fn parse_display_factor(display_factor: Option<&str>) -> Decimal {
+        Self::parse_decimal(display_factor)
+            .filter(|factor| !factor.is_zero())
+            .unwrap_or(Decimal::ONE)
+    }
+
+    // Lets tests price orders without a feed behind the snapshots
+    #[cfg(test)]
+    pub(crate) async fn push_test_quote(&self, symbol: &str, underlying: &str, quote: Quote) {
+        let mut events = Arc::clone(&self.events);
+        Self::stash_subscription(
+            &mut events,
+            symbol,
+            underlying,
+            &quote.event_symbol,
+            None,
+            Decimal::ONE,
+        )
+        .await;
+        let mut snapshots = events.lock().await;
+        if let Some(snapshot) = snapshots
+            .iter_mut()
+            .find(|snapshot| snapshot.symbol == symbol)
+        {
+            snapshot.quote = Some(quote);
+        }
     }
 
     async fn stash_subscription(
@@ -289,16 +1039,582 @@ impl MktData {
         underlying: &str,
         streamer_symbol: &str,
         strike_price: Option<Decimal>,
+        display_factor: Decimal,
     ) {
+        let mut writer = events.lock().await;
+        if let Some(snapshot) = writer
+            .iter_mut()
+            .find(|snapshot| snapshot.symbol.eq(symbol))
+        {
+            snapshot.underlying = underlying.to_string();
+            if snapshot.streamer_symbol != streamer_symbol {
+                snapshot.subscribed_at = Instant::now();
+                snapshot.first_tick_at = None;
+            }
+            snapshot.streamer_symbol = streamer_symbol.to_string();
+            snapshot.strike_price = strike_price;
+            snapshot.display_factor = display_factor;
+            return;
+        }
+
         let snapshot = Snapshot {
             symbol: symbol.to_string(),
             underlying: underlying.to_string(),
             streamer_symbol: streamer_symbol.to_string(),
             strike_price,
+            display_factor,
+            subscribed_at: Instant::now(),
+            first_tick_at: None,
+            last_update: Instant::now(),
+            stale_intervals: 0,
+            last_sequence: None,
+            quote: None,
+            greeks: None,
+            candle: None,
+            candles: VecDeque::new(),
+        };
+        writer.push(snapshot);
+        TRACKED_SNAPSHOTS.set(writer.len() as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::make_mktdata;
+    use crate::utils::fixtures::make_quote;
+    use crate::utils::fixtures::make_web_client;
+    use crate::utils::fixtures::start_stub_server;
+    use crate::utils::fixtures::UNREACHABLE_URL;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn test_stash_subscription_is_idempotent() {
+        let mut events = Arc::new(Mutex::new(Vec::new()));
+        for _ in 0..2 {
+            MktData::stash_subscription(
+                &mut events,
+                "SPY   240315C00425000",
+                "SPY",
+                ".SPY240315C425",
+                Some(Decimal::from(425)),
+                Decimal::ONE,
+            )
+            .await;
+        }
+        assert_eq!(events.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_drop_snapshot_removes_symbol() {
+        let mut events = Arc::new(Mutex::new(Vec::new()));
+        for (symbol, streamer_symbol) in [
+            ("SPY   240315C00425000", ".SPY240315C425"),
+            ("SPY   240315C00430000", ".SPY240315C430"),
+        ] {
+            MktData::stash_subscription(
+                &mut events,
+                symbol,
+                "SPY",
+                streamer_symbol,
+                None,
+                Decimal::ONE,
+            )
+            .await;
+        }
+
+        let dropped = MktData::drop_snapshot(&events, "SPY   240315C00425000").await;
+        assert_eq!(dropped.unwrap().streamer_symbol, ".SPY240315C425");
+        let remaining = events.lock().await;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].symbol, "SPY   240315C00430000");
+        drop(remaining);
+        assert!(MktData::drop_snapshot(&events, "SPY   240315C00425000")
+            .await
+            .is_none());
+    }
+
+    #[test]
+    fn test_candle_history_is_bounded() {
+        let make_candle = |time: u32, close: f64| -> Candle {
+            serde_json::from_value(serde_json::json!({
+                "eventSymbol": "SPX{=5m}", "time": time,
+                "open": close, "high": close, "low": close, "close": close,
+            }))
+            .unwrap()
+        };
+        let mut snapshot = Snapshot {
+            symbol: String::from("SPX"),
+            underlying: String::from("SPX"),
+            streamer_symbol: String::from("SPX"),
+            subscribed_at: Instant::now(),
+            first_tick_at: None,
             last_update: Instant::now(),
+            stale_intervals: 0,
+            last_sequence: None,
+            strike_price: None,
+            display_factor: Decimal::ONE,
             quote: None,
             greeks: None,
+            candle: None,
+            candles: VecDeque::new(),
         };
-        events.lock().await.push(snapshot);
+        // History replayed newest first keeps the newest, then the forming
+        // candle is updated in place
+        (1..=5)
+            .rev()
+            .for_each(|time| snapshot.push_candle(make_candle(time, time as f64), 3));
+        snapshot.push_candle(make_candle(5, 5.5), 3);
+
+        let closes = |candles: Vec<Candle>| -> Vec<f64> {
+            candles.iter().map(|candle| candle.close).collect()
+        };
+        assert_eq!(closes(snapshot.recent_candles(10)), vec![3.0, 4.0, 5.5]);
+        assert_eq!(snapshot.candle.as_ref().unwrap().close, 5.5);
+
+        // A new candle evicts the oldest
+        snapshot.push_candle(make_candle(6, 6.0), 3);
+        assert_eq!(closes(snapshot.recent_candles(2)), vec![5.5, 6.0]);
+        assert_eq!(closes(snapshot.recent_candles(10)), vec![4.0, 5.5, 6.0]);
+    }
+
+    #[tokio::test]
+    async fn test_futures_midprice_uses_display_factor() {
+        let future: Future = serde_json::from_value(serde_json::json!({
+            "symbol": "/ESM4",
+            "streamer-symbol": "/ESM24:XCME",
+            "display-factor": "0.01",
+        }))
+        .unwrap();
+        let display_factor = MktData::parse_display_factor(future.display_factor.as_deref());
+        assert_eq!(display_factor, dec!(0.01));
+        assert_eq!(MktData::parse_display_factor(Some("0")), Decimal::ONE);
+        assert_eq!(MktData::parse_display_factor(None), Decimal::ONE);
+
+        let mut events = Arc::new(Mutex::new(Vec::new()));
+        MktData::stash_subscription(
+            &mut events,
+            "/ESM4",
+            "/ES",
+            "/ESM24:XCME",
+            None,
+            display_factor,
+        )
+        .await;
+        let mut snapshots = events.lock().await;
+        snapshots[0].quote = Some(make_quote("/ESM24:XCME", dec!(512525), dec!(512575)));
+        assert_eq!(snapshots[0].midprice(), Some(dec!(5125.50)));
+    }
+
+    #[test]
+    fn test_decode_compact_quote() {
+        let full: Quote = serde_json::from_value(serde_json::json!({
+            "eventSymbol": "SPY", "eventTime": 0, "sequence": 0, "timeNanoPart": 0,
+            "bidTime": 1, "bidExchangeCode": "Q", "bidPrice": 421.5, "bidSize": 10,
+            "askTime": 2, "askExchangeCode": "Q", "askPrice": 421.55, "askSize": 12,
+        }))
+        .unwrap();
+        let msg = r#"{"type":"FEED_DATA","channel":1,"data":["Quote",[
+            "Quote","SPY",0,0,0,1,"Q",421.5,10,2,"Q",421.55,12,
+            "Quote","QQQ",0,0,0,1,"Q",360.1,5,2,"Q",360.2,7]]}"#;
+
+        let decoded = MktData::decode_compact(msg).unwrap();
+
+        assert_eq!(decoded.data.len(), 2);
+        let FeedEvent::Quote(quote) = &decoded.data[0] else {
+            panic!("Expected a quote, got: {:?}", decoded.data[0]);
+        };
+        assert_eq!(
+            serde_json::to_value(quote).unwrap(),
+            serde_json::to_value(&full).unwrap()
+        );
+        let FeedEvent::Quote(quote) = &decoded.data[1] else {
+            panic!("Expected a quote, got: {:?}", decoded.data[1]);
+        };
+        assert_eq!(quote.event_symbol, "QQQ");
+        assert_eq!(quote.ask_price, dec!(360.2));
+    }
+
+    #[test]
+    fn test_decode_compact_candles_and_unknown_types() {
+        let msg = r#"{"type":"FEED_DATA","channel":1,"data":[
+            "Trade",["Trade","SPY",421.5,100],
+            "Candle",["Candle","SPY{=5m}",1710500000000,420.0,422.0,419.5,421.5,1200],
+            "Quote",["Quote","SPY",0,0,0,1,"Q",421.5,10,2,"Q",421.55,12]]}"#;
+
+        let decoded = MktData::decode_compact(msg).unwrap();
+
+        assert_eq!(decoded.data.len(), 2);
+        let FeedEvent::Candle(candle) = &decoded.data[0] else {
+            panic!("Expected a candle, got: {:?}", decoded.data[0]);
+        };
+        assert_eq!(candle.event_symbol, "SPY{=5m}");
+        assert_eq!(candle.close, 421.5);
+        assert!(matches!(decoded.data[1], FeedEvent::Quote(_)));
+    }
+
+    #[test]
+    fn test_chain_band_capped() {
+        let strikes = [5080, 5090, 5100, 5110, 5120]
+            .iter()
+            .map(|strike| {
+                serde_json::json!({
+                    "strike-price": strike.to_string(),
+                    "call": format!("SPXW  240315C0{}000", strike),
+                    "call-streamer-symbol": format!(".SPXW240315C{}", strike),
+                    "put": format!("SPXW  240315P0{}000", strike),
+                    "put-streamer-symbol": format!(".SPXW240315P{}", strike),
+                })
+            })
+            .collect::<Vec<_>>();
+        let expiration: Expiration = serde_json::from_value(serde_json::json!({
+            "expiration-date": "2024-03-15",
+            "days-to-expiration": 0,
+            "strikes": strikes,
+        }))
+        .unwrap();
+
+        let band = MktData::chain_band(&expiration, dec!(5099), 1, 100);
+        let symbols = band
+            .iter()
+            .map(|(symbol, _, _)| symbol.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            symbols,
+            vec![
+                "SPXW  240315C05090000",
+                "SPXW  240315P05090000",
+                "SPXW  240315C05100000",
+                "SPXW  240315P05100000",
+                "SPXW  240315C05110000",
+                "SPXW  240315P05110000",
+            ]
+        );
+        assert_eq!(band[2].1, ".SPXW240315C5100");
+        assert_eq!(MktData::chain_band(&expiration, dec!(5099), 1, 4).len(), 4);
+    }
+
+    #[test]
+    fn test_decode_compact_rejects_partial_event() {
+        let msg = r#"{"type":"FEED_DATA","channel":1,"data":["Greeks",["Greeks","SPY",1.0]]}"#;
+        assert!(MktData::decode_compact(msg).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscription_report_lists_symbols_that_never_ticked() {
+        let mut events = Arc::new(Mutex::new(Vec::new()));
+        for (symbol, streamer_symbol) in [
+            ("SPY   240315C00425000", ".SPY240315C425"),
+            ("SPY   240315C00430000", ".SPY240315C430X"),
+        ] {
+            MktData::stash_subscription(
+                &mut events,
+                symbol,
+                "SPY",
+                streamer_symbol,
+                None,
+                Decimal::ONE,
+            )
+            .await;
+        }
+        let msg = r#"{"type":"FEED_DATA","channel":1,"data":[{"eventType":"Quote",
+            "eventSymbol":".SPY240315C425","eventTime":0,"sequence":0,"timeNanoPart":0,
+            "bidTime":0,"bidExchangeCode":"","bidPrice":1.1,"bidSize":1,
+            "askTime":0,"askExchangeCode":"","askPrice":1.2,"askSize":1}]}"#;
+        MktData::handle_msg(&events, msg.to_string(), 0, DataFormat::Full).await;
+
+        let snapshots = events.lock().await;
+        assert!(snapshots[0].first_tick_at.is_some());
+        let grace = Duration::from_secs(30);
+        let subscribed_at = snapshots[1].subscribed_at;
+        assert!(MktData::never_ticked(&snapshots, grace, subscribed_at).is_empty());
+        let silent = MktData::never_ticked(&snapshots, grace, subscribed_at + grace);
+        assert_eq!(silent.len(), 1);
+        assert_eq!(silent[0].streamer_symbol, ".SPY240315C430X");
+    }
+
+    #[tokio::test]
+    async fn test_candle_snapshot_by_symbol() {
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mut mktdata = make_mktdata(web_client, &cancel_token);
+        MktData::stash_subscription(
+            &mut mktdata.events,
+            "SPX{=5m}",
+            "SPX",
+            "SPX{=5m}",
+            None,
+            Decimal::ONE,
+        )
+        .await;
+        let msg = r#"{"type":"FEED_DATA","channel":1,"data":[{"eventType":"Candle",
+            "eventSymbol":"SPX{=5m}","time":1710513000000,"open":5120.5,"high":5131.25,
+            "low":5118.0,"close":5129.75,"volume":0}]}"#;
+        MktData::handle_msg(&mktdata.events, msg.to_string(), 0, DataFormat::Full).await;
+
+        let snapshot = mktdata
+            .get_snapshot_by_symbol::<Candle>("SPX{=5m}")
+            .await
+            .unwrap();
+        let candle = Candle::extract_event(&snapshot).unwrap();
+        assert_eq!(candle.close, 5129.75);
+        assert_eq!(candle.high, 5131.25);
+        assert!(Quote::extract_event(&snapshot).is_none());
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_sequence_gaps_counted_and_reset_on_reconnect() {
+        assert_eq!(MktData::check_sequence(None, 7), SequenceCheck::InOrder);
+        assert_eq!(MktData::check_sequence(Some(7), 8), SequenceCheck::InOrder);
+        assert_eq!(MktData::check_sequence(Some(7), 10), SequenceCheck::Gap(2));
+        assert_eq!(
+            MktData::check_sequence(Some(7), 7),
+            SequenceCheck::OutOfOrder
+        );
+
+        let mut events = Arc::new(Mutex::new(Vec::new()));
+        MktData::stash_subscription(&mut events, "SPX", "SPX", "SPX", None, Decimal::ONE).await;
+        let quote = |sequence: u64| {
+            format!(
+                r#"{{"type":"FEED_DATA","channel":1,"data":[{{"eventType":"Quote",
+                "eventSymbol":"SPX","eventTime":0,"sequence":{},"timeNanoPart":0,
+                "bidTime":0,"bidExchangeCode":"","bidPrice":1.1,"bidSize":1,
+                "askTime":0,"askExchangeCode":"","askPrice":1.2,"askSize":1}}]}}"#,
+                sequence
+            )
+        };
+        let count = |kind| MKTDATA_SEQUENCE_GAPS.with_label_values(&[kind]).get();
+        let (gaps, out_of_order) = (count("gap"), count("out_of_order"));
+
+        for sequence in [1, 2, 5, 4] {
+            MktData::handle_msg(&events, quote(sequence), 0, DataFormat::Full).await;
+        }
+        assert_eq!(events.lock().await[0].last_sequence, Some(5));
+        assert_eq!(count("gap") - gaps, 1);
+        assert_eq!(count("out_of_order") - out_of_order, 1);
+
+        let reopened = r#"{"type":"CHANNEL_OPENED","channel":1,"service":"FEED","parameters":{}}"#;
+        MktData::handle_msg(&events, reopened.to_string(), 0, DataFormat::Full).await;
+        MktData::handle_msg(&events, quote(1), 0, DataFormat::Full).await;
+        assert_eq!(events.lock().await[0].last_sequence, Some(1));
+        assert_eq!(count("out_of_order") - out_of_order, 1);
+    }
+
+    #[tokio::test]
+    async fn test_bad_symbol_snapshot_evicted() {
+        let mut events = Arc::new(Mutex::new(Vec::new()));
+        MktData::stash_subscription(&mut events, "SPX", "SPX", "SPX", None, Decimal::ONE).await;
+        MktData::stash_subscription(
+            &mut events,
+            "SPXW  240315C05100000",
+            "SPX",
+            ".SPXW240315C5100",
+            None,
+            Decimal::ONE,
+        )
+        .await;
+
+        let error = r#"{"type":"ERROR","channel":0,"error":"INVALID_MESSAGE","message":"Unknown symbol .SPXW240315C5100"}"#;
+        MktData::handle_msg(&events, error.to_string(), 0, DataFormat::Full).await;
+        let symbols = events
+            .lock()
+            .await
+            .iter()
+            .map(|snapshot| snapshot.streamer_symbol.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(symbols, vec!["SPX"]);
+    }
+
+    #[tokio::test]
+    async fn test_stale_symbol_is_resubscribed_every_k_intervals() {
+        let mut events = Arc::new(Mutex::new(Vec::new()));
+        MktData::stash_subscription(&mut events, "SPX", "SPX", "SPX", None, Decimal::ONE).await;
+        MktData::stash_subscription(
+            &mut events,
+            "SPX{=5m}",
+            "SPX",
+            "SPX{=5m}",
+            None,
+            Decimal::ONE,
+        )
+        .await;
+        let config = StalenessConfig {
+            threshold_secs: 30,
+            resubscribe_after: 2,
+        };
+        let start = events.lock().await[0].last_update;
+        let mut snapshots = events.lock().await;
+        snapshots[1].last_update = start;
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(MktData::check_staleness(&mut snapshots, &config, at(29)).is_empty());
+        assert!(MktData::check_staleness(&mut snapshots, &config, at(30)).is_empty());
+        assert_eq!(snapshots[0].stale_intervals, 1);
+        let stale = MktData::check_staleness(&mut snapshots, &config, at(60));
+        assert_eq!(
+            stale,
+            vec![
+                (String::from("SPX"), &["Quote"][..]),
+                (String::from("SPX{=5m}"), &["Candle"][..]),
+            ]
+        );
+        // Only fires once per interval, not on every watchdog tick
+        assert!(MktData::check_staleness(&mut snapshots, &config, at(61)).is_empty());
+        assert_eq!(
+            MktData::check_staleness(&mut snapshots, &config, at(120)).len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_instrument_lookups_are_cached_until_ttl() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&lookups);
+        let router = axum::Router::new().fallback(move || {
+            let counter = Arc::clone(&counter);
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                axum::Json(serde_json::json!({
+                    "data": {
+                        "instrument-type": "Equity Option",
+                        "root-symbol": "SPY",
+                        "underlying-symbol": "SPY",
+                        "streamer-symbol": ".SPY240315C425",
+                        "shares-per-contract": 100,
+                    },
+                    "context": "/instruments/equity-options/SPY",
+                }))
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mut mktdata = make_mktdata(web_client, &cancel_token);
+        let symbol = "SPY   240315C00425000";
+        for _ in 0..2 {
+            let instrument = mktdata
+                .get_instrument(symbol, OptionType::EquityOption)
+                .await
+                .unwrap();
+            assert_eq!(instrument.streamer_symbol, ".SPY240315C425");
+            assert_eq!(instrument.display_factor, Decimal::ONE);
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        mktdata.instruments.ttl = Duration::ZERO;
+        mktdata
+            .get_instrument(symbol, OptionType::EquityOption)
+            .await
+            .unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        // Indices resolve to their prefixed streamer symbol without a lookup
+        let index = mktdata
+            .get_instrument("spxw", OptionType::Equity)
+            .await
+            .unwrap();
+        cancel_token.cancel();
+        assert_eq!(index.streamer_symbol, "$SPX");
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_instrument_lookup_is_an_error() {
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mktdata = make_mktdata(web_client, &cancel_token);
+        let result = mktdata
+            .get_instrument("SPY   240315C00425000", OptionType::EquityOption)
+            .await;
+        cancel_token.cancel();
+        assert!(result.is_err());
+        assert!(mktdata
+            .instruments
+            .get("SPY   240315C00425000")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batched_instrument_lookup() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&lookups);
+        let router = axum::Router::new().route(
+            "/instruments/equity-options",
+            axum::routing::get(move |query: axum::extract::RawQuery| {
+                let counter = Arc::clone(&counter);
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let requested = url::form_urlencoded::parse(query.0.unwrap().as_bytes())
+                        .filter(|(key, _)| key == "symbol[]")
+                        .map(|(_, symbol)| symbol.into_owned())
+                        .collect::<Vec<_>>();
+                    // The broker only lists the symbols it knows
+                    let items = requested
+                        .iter()
+                        .filter(|symbol| symbol.starts_with("SPY"))
+                        .map(|symbol| {
+                            serde_json::json!({
+                                "instrument-type": "Equity Option",
+                                "root-symbol": "SPY",
+                                "underlying-symbol": "SPY",
+                                "symbol": symbol,
+                                "streamer-symbol": format!(".SPY240315{}", &symbol[12..13]),
+                                "shares-per-contract": 100,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    axum::Json(serde_json::json!({
+                        "data": {"items": items},
+                        "context": "/instruments/equity-options",
+                    }))
+                }
+            }),
+        );
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mktdata = make_mktdata(web_client, &cancel_token);
+        let symbols = [
+            "SPY   240315C00425000",
+            "SPY   240315P00415000",
+            "XYZ   240315P00010000",
+        ];
+        let resolved = mktdata
+            .resolve_streamer_symbols(&symbols, OptionType::EquityOption)
+            .await
+            .unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(resolved["SPY   240315C00425000"], ".SPY240315C");
+        assert_eq!(resolved["SPY   240315P00415000"], ".SPY240315P");
+
+        // Resolved legs are cached, only the unknown one is asked for again
+        let instrument = mktdata
+            .get_instrument("SPY   240315P00415000", OptionType::EquityOption)
+            .await
+            .unwrap();
+        assert_eq!(instrument.streamer_symbol, ".SPY240315P");
+        let resolved = mktdata
+            .resolve_streamer_symbols(&symbols[..2], OptionType::EquityOption)
+            .await
+            .unwrap();
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        mktdata
+            .resolve_streamer_symbols(&symbols, OptionType::EquityOption)
+            .await
+            .unwrap();
+        cancel_token.cancel();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
     }
 }
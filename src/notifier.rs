@@ -0,0 +1,193 @@
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use tracing::warn;
+use url::Url;
+
+use crate::settings::NotificationConfig;
+use crate::strategies::ExitReason;
+use crate::web_client::http_client::HttpClient;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum OrderEvent {
+    Placed {
+        underlying: String,
+        order_id: i32,
+        price: Decimal,
+    },
+    Filled {
+        underlying: String,
+        order_id: i32,
+    },
+    Rejected {
+        underlying: String,
+        reason: String,
+    },
+    LiquidationTriggered {
+        underlying: String,
+        reason: ExitReason,
+    },
+}
+
+impl fmt::Display for OrderEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderEvent::Placed {
+                underlying,
+                order_id,
+                price,
+            } => write!(
+                f,
+                "Order {} placed for {} at {}",
+                order_id, underlying, price
+            ),
+            OrderEvent::Filled {
+                underlying,
+                order_id,
+            } => write!(f, "Order {} filled for {}", order_id, underlying),
+            OrderEvent::Rejected { underlying, reason } => {
+                write!(f, "Order rejected for {}: {}", underlying, reason)
+            }
+            OrderEvent::LiquidationTriggered { underlying, reason } => write!(
+                f,
+                "Liquidating {} position, exit reason: {}",
+                underlying, reason
+            ),
+        }
+    }
+}
+
+pub(crate) trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, event: &'a OrderEvent) -> BoxFuture<'a, Result<()>>;
+}
+
+// Slack incoming webhooks read the text field, other receivers get the same
+// message
+#[derive(Debug, Serialize)]
+struct WebhookMessage {
+    text: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct WebhookNotifier {
+    http_client: HttpClient,
+    endpoint: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> Result<Self> {
+        let url = Url::parse(url)?;
+        let mut endpoint = url.path().trim_start_matches('/').to_string();
+        if let Some(query) = url.query() {
+            endpoint = format!("{}?{}", endpoint, query);
+        }
+        Ok(Self {
+            http_client: HttpClient::new(&url.origin().ascii_serialization()),
+            endpoint,
+        })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify<'a>(&'a self, event: &'a OrderEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let message = WebhookMessage {
+                text: event.to_string(),
+            };
+            self.http_client
+                .post_no_content(&self.endpoint, message, None)
+                .await
+        })
+    }
+}
+
+// Best effort, every event is sent from its own task so a slow or failing
+// receiver never holds up trading
+#[derive(Clone, Default)]
+pub(crate) struct Notifications {
+    notifier: Option<Arc<dyn Notifier>>,
+}
+
+impl Notifications {
+    pub fn new(config: Option<&NotificationConfig>) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        Ok(Self::with_notifier(Arc::new(WebhookNotifier::new(
+            &config.webhook_url,
+        )?)))
+    }
+
+    pub fn with_notifier(notifier: Arc<dyn Notifier>) -> Self {
+        Self {
+            notifier: Some(notifier),
+        }
+    }
+
+    pub fn send(&self, event: OrderEvent) {
+        let Some(notifier) = self.notifier.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            if let Err(err) = notifier.notify(&event).await {
+                warn!("Failed to send notification: {}, error: {}", event, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::start_stub_server;
+    use tokio::sync::mpsc;
+
+    async fn start_webhook(sender: mpsc::Sender<serde_json::Value>) -> String {
+        let router = axum::Router::new().route(
+            "/services/T000/B000/XXXX",
+            axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+                let sender = sender.clone();
+                async move {
+                    sender.send(body).await.unwrap();
+                    "ok"
+                }
+            }),
+        );
+        format!(
+            "{}/services/T000/B000/XXXX",
+            start_stub_server(router).await
+        )
+    }
+
+    #[tokio::test]
+    async fn test_webhook_receives_liquidation() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let url = start_webhook(sender).await;
+        let notifications =
+            Notifications::with_notifier(Arc::new(WebhookNotifier::new(&url).unwrap()));
+
+        notifications.send(OrderEvent::LiquidationTriggered {
+            underlying: String::from("SPX"),
+            reason: ExitReason::StrikeCrossed,
+        });
+
+        let body = receiver.recv().await.unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"text": "Liquidating SPX position, exit reason: StrikeCrossed"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_failure_is_reported() {
+        let notifier = WebhookNotifier::new("http://127.0.0.1:1/services/T000").unwrap();
+        let event = OrderEvent::Filled {
+            underlying: String::from("SPX"),
+            order_id: 1,
+        };
+        assert!(notifier.notify(&event).await.is_err());
+    }
+}
@@ -1,28 +1,64 @@
-use anyhow::Ok;
+use anyhow::bail;
 use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
+use rust_decimal::RoundingStrategy;
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+use tokio::sync::OwnedMutexGuard;
 use tokio::sync::RwLock;
+use tokio::time::interval;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 use tracing::error;
+use tracing::field;
 use tracing::info;
+use tracing::info_span;
 use tracing::warn;
+use tracing::Instrument;
+use tracing::Span;
 
+use crate::metrics::ORDERS_IN_FLIGHT;
+use crate::metrics::ORDERS_PLACED;
 use crate::mktdata::MktData;
-use crate::mktdata::Snapshot;
+use crate::notifier::Notifications;
+use crate::notifier::OrderEvent;
 use crate::positions::Direction;
 use crate::positions::OptionType;
 use crate::positions::PriceEffect;
 use crate::positions::StrategyType;
+use crate::settings::ChaseConfig;
+use crate::settings::OrderConfig;
+use crate::settings::PricePolicy;
+use crate::settings::Settings;
+use crate::strategies::error::StrategyError;
+use crate::strategies::ExitReason;
 use crate::strategies::StrategyMeta;
 use crate::tt_api::mktdata::Quote;
+use crate::tt_api::mktdata::TickSizes;
 use crate::tt_api::orders::*;
+use crate::web_client::report_lagged;
 use crate::web_client::WebClient;
 
-use super::web_client::sessions::acc_api;
+// Used when the instrument gives no tick size for the price
+const DEFAULT_TICK: Decimal = dec!(0.01);
+// Finished orders are kept around this long for anything reading fills
+const FINISHED_ORDER_RETENTION: TimeDelta = TimeDelta::hours(1);
+// Stamped on every order placed so a restart only picks up its own
+const ORDER_SOURCE: &str = "options-trader";
+// Tick schedules change rarely, one is fetched again once this old
+const TICK_SIZE_TTL: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Debug)]
 enum OrderType {
@@ -44,31 +80,357 @@ impl fmt::Display for OrderType {
     }
 }
 
+// Whether an order opens a position or closes one, picks the leg actions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrderIntent {
+    Open,
+    Close,
+}
+
+impl OrderIntent {
+    fn action(self, direction: Direction) -> String {
+        match (self, direction) {
+            (OrderIntent::Open, Direction::Long) => String::from("Buy to Open"),
+            (OrderIntent::Open, Direction::Short) => String::from("Sell to Open"),
+            (OrderIntent::Close, Direction::Long) => String::from("Sell to Close"),
+            (OrderIntent::Close, Direction::Short) => String::from("Buy to Close"),
+        }
+    }
+}
+
+// How much of a position a liquidation closes, fraction is of the position's
+// contracts and contracts caps it further when set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CloseSize {
+    pub fraction: Decimal,
+    pub contracts: Option<i32>,
+}
+
+impl Default for CloseSize {
+    fn default() -> Self {
+        CloseSize {
+            fraction: Decimal::ONE,
+            contracts: None,
+        }
+    }
+}
+
+impl CloseSize {
+    // Whole contracts to close out of a position of lots, never more than lots
+    fn contracts_of(&self, lots: i32) -> Result<i32> {
+        let by_fraction = (Decimal::from(lots) * self.fraction)
+            .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+            .to_i32()
+            .unwrap_or_default();
+        let close = self
+            .contracts
+            .map_or(by_fraction, |contracts| contracts.min(by_fraction))
+            .min(lots);
+        if close <= 0 {
+            bail!(
+                "Closing {:?} of a {} lot rounds to zero contracts",
+                self,
+                lots
+            );
+        }
+        Ok(close)
+    }
+
+    // Scales every leg by the share of the position being closed
+    fn scale(&self, order: &mut Order) -> Result<()> {
+        let lots = order
+            .legs
+            .iter()
+            .map(|leg| leg.quantity)
+            .min()
+            .unwrap_or_default();
+        let close = self.contracts_of(lots)?;
+        for leg in order.legs.iter_mut() {
+            leg.quantity = leg.quantity * close / lots;
+        }
+        Ok(())
+    }
+}
+
+// Pending until the broker reports the order live at the exchange, only
+// Pending and Working orders are in flight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OrderState {
+    Pending,
+    Working,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+impl OrderState {
+    // None for a status that says nothing new about the order
+    fn from_status(status: &str) -> Option<OrderState> {
+        match status {
+            "Received" | "Routed" | "In Flight" | "Contingent" => Some(OrderState::Pending),
+            "Live" | "Cancel Requested" | "Replace Requested" => Some(OrderState::Working),
+            "Filled" => Some(OrderState::Filled),
+            "Cancelled" | "Expired" | "Removed" | "Partially Removed" => {
+                Some(OrderState::Cancelled)
+            }
+            "Rejected" => Some(OrderState::Rejected),
+            _ => None,
+        }
+    }
+
+    fn is_in_flight(self) -> bool {
+        matches!(self, OrderState::Pending | OrderState::Working)
+    }
+}
+
+#[derive(Debug)]
+struct TrackedOrder {
+    // Carries the broker id once placed, a replace swaps in the new one
+    order: Order,
+    state: OrderState,
+    submitted_at: DateTime<Utc>,
+    strategy_type: StrategyType,
+    underlying: String,
+    initial_price: Decimal,
+    // Offset from the midprice given up so a liquidation fills, the chaser
+    // keeps it when the order is moved
+    slippage: Decimal,
+    remaining_quantity: i32,
+    exit_reason: Option<ExitReason>,
+    is_chasing: bool,
+    // False for orders found working at startup, shutdown leaves those alone
+    placed_here: bool,
+}
+
+impl TrackedOrder {
+    fn is_in_flight(&self) -> bool {
+        self.state.is_in_flight()
+    }
+
+    fn is_closing(&self) -> bool {
+        self.order
+            .legs
+            .iter()
+            .all(|leg| leg.action.ends_with("to Close"))
+    }
+}
+
+// One option tick size, applies to prices below the threshold or to every
+// price when there is none
+#[derive(Debug, Clone, PartialEq)]
+struct OptionTick {
+    threshold: Option<Decimal>,
+    value: Decimal,
+}
+
+impl OptionTick {
+    fn from_tick_sizes(tick_sizes: &[TickSizes]) -> Vec<OptionTick> {
+        fn parse(value: &Option<String>) -> Option<Decimal> {
+            value
+                .as_ref()
+                .and_then(|value| Decimal::from_str(value).ok())
+        }
+
+        tick_sizes
+            .iter()
+            .filter_map(|tick| {
+                Some(OptionTick {
+                    threshold: parse(&tick.threshold),
+                    value: parse(&tick.value)?,
+                })
+            })
+            .collect()
+    }
+}
+
+// Option tick sizes per underlying, fetched from the instruments endpoint the
+// first time an order on that underlying is priced and again once older
+// than the ttl
+#[derive(Clone)]
+struct TickSizeCache {
+    ttl: Duration,
+    ticks: Arc<Mutex<HashMap<String, FetchedTicks>>>,
+}
+
+// A tick schedule and when it was fetched
+type FetchedTicks = (Instant, Vec<OptionTick>);
+
+impl Default for TickSizeCache {
+    fn default() -> Self {
+        Self {
+            ttl: TICK_SIZE_TTL,
+            ticks: Arc::default(),
+        }
+    }
+}
+
+impl TickSizeCache {
+    async fn get(
+        &self,
+        web_client: &WebClient,
+        underlying: &str,
+        order: &Order,
+    ) -> Vec<OptionTick> {
+        let cached = self.ticks.lock().await.get(underlying).cloned();
+        if let Some((fetched_at, ticks)) = &cached {
+            if fetched_at.elapsed() < self.ttl {
+                return ticks.clone();
+            }
+        }
+        let is_future = order
+            .legs
+            .first()
+            .is_some_and(|leg| leg.instrument_type.starts_with("Future"));
+        let ticks = match is_future {
+            true => web_client.get_future(underlying).await.map(|future| {
+                future
+                    .option_tick_sizes
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|tick| tick.value.as_ref())
+                    .filter_map(|value| Decimal::from_str(value).ok())
+                    .map(|value| OptionTick {
+                        threshold: None,
+                        value,
+                    })
+                    .collect()
+            }),
+            false => web_client
+                .get_equity(underlying)
+                .await
+                .map(|equity| OptionTick::from_tick_sizes(&equity.option_tick_sizes)),
+        };
+        match ticks {
+            Ok(ticks) => {
+                self.ticks
+                    .lock()
+                    .await
+                    .insert(underlying.to_string(), (Instant::now(), ticks.clone()));
+                ticks
+            }
+            // Not cached so the next order retries the lookup, an expired
+            // schedule is still better than the default tick meanwhile
+            Err(err) => {
+                warn!(
+                    "Failed to fetch tick sizes for symbol: {}, error: {}",
+                    underlying, err
+                );
+                cached.map(|(_, ticks)| ticks).unwrap_or_default()
+            }
+        }
+    }
+
+    async fn round(
+        &self,
+        web_client: &WebClient,
+        underlying: &str,
+        order: &Order,
+        price: Decimal,
+        marketable: bool,
+    ) -> Decimal {
+        let tick = self.tick(web_client, underlying, order, price).await;
+        Orders::round_to_tick(price, tick, Orders::price_effect(order), marketable)
+    }
+
+    async fn tick(
+        &self,
+        web_client: &WebClient,
+        underlying: &str,
+        order: &Order,
+        price: Decimal,
+    ) -> Decimal {
+        Orders::tick_for_price(&self.get(web_client, underlying, order).await, price)
+    }
+}
+
+// One order operation per underlying at a time, held from the in flight
+// check until the order is tracked so a second trigger sees the first
+#[derive(Clone, Default)]
+struct UnderlyingGuards {
+    guards: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+}
+
+impl UnderlyingGuards {
+    async fn lock(&self, underlying: &str) -> OwnedMutexGuard<()> {
+        let guard = Arc::clone(
+            self.guards
+                .lock()
+                .await
+                .entry(underlying.to_string())
+                .or_default(),
+        );
+        guard.lock_owned().await
+    }
+
+    // Waits out every placement in progress, each holds only its own
+    // underlying so taking them in turn cannot deadlock
+    async fn lock_all(&self) -> Vec<OwnedMutexGuard<()>> {
+        let guards: Vec<Arc<Mutex<()>>> = self.guards.lock().await.values().cloned().collect();
+        let mut held = Vec::with_capacity(guards.len());
+        for guard in guards {
+            held.push(guard.lock_owned().await);
+        }
+        held
+    }
+}
+
 pub struct Orders {
     web_client: Arc<WebClient>,
     mkt_data: Arc<RwLock<MktData>>,
-    orders: Vec<Order>,
+    orders: Arc<Mutex<Vec<TrackedOrder>>>,
+    tick_sizes: TickSizeCache,
+    guards: UnderlyingGuards,
+    order_config: OrderConfig,
+    min_quote_size: f64,
+    stale_quote_after: Duration,
+    live_trading: bool,
+    notifications: Notifications,
+    // Positions already announced as liquidating, keyed by their legs, so a
+    // retried or re-placed exit does not notify again
+    liquidations_notified: Mutex<HashSet<String>>,
 }
 
 impl Orders {
-    pub fn new(
+    pub async fn new(
         web_client: Arc<WebClient>,
         mkt_data: Arc<RwLock<MktData>>,
+        settings: &Settings,
+        notifications: Notifications,
         cancel_token: CancellationToken,
     ) -> Self {
+        let live_trading = settings.live_trading;
+        let orders = Arc::new(Mutex::new(Self::get_working_orders(&web_client).await));
+        let tick_sizes = TickSizeCache::default();
+        Self::chase_monitor(
+            Arc::clone(&orders),
+            Arc::clone(&mkt_data),
+            Arc::clone(&web_client),
+            tick_sizes.clone(),
+            settings.chase.clone(),
+            live_trading,
+            cancel_token.clone(),
+        );
         let mut receiver = web_client.subscribe_acc_events();
+        let capacity = web_client.account_channel_capacity();
+        let order_writer = Arc::clone(&orders);
+        let fill_client = Arc::clone(&web_client);
+        let fill_mkt_data = Arc::clone(&mkt_data);
+        let fill_tick_sizes = tick_sizes.clone();
+        let fill_notifications = notifications.clone();
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     msg = receiver.recv() => {
                         match msg {
-                            Err(RecvError::Lagged(err)) => warn!("Publisher channel skipping a number of messages: {}", err),
+                            Err(RecvError::Lagged(dropped)) => report_lagged("account", dropped, capacity),
                             Err(RecvError::Closed) => {
                                 error!("Publisher channel closed");
                                 cancel_token.cancel();
                             }
-                            std::result::Result::Ok(val) => {
-                                Self::handle_msg(val, &cancel_token);
+                            Ok(val) => {
+                                if let Some(id) = Self::handle_msg(val, &order_writer, &fill_notifications).await {
+                                    Self::rework_residual(id, &order_writer, &fill_mkt_data, &fill_client, &fill_tick_sizes, live_trading).await;
+                                }
                             }
                         }
                     }
@@ -81,34 +443,390 @@ impl Orders {
         Self {
             web_client,
             mkt_data,
-            orders: Vec::new(),
+            orders,
+            tick_sizes,
+            guards: UnderlyingGuards::default(),
+            order_config: settings.order.clone(),
+            min_quote_size: settings.min_quote_size,
+            stale_quote_after: Duration::from_secs(settings.staleness.threshold_secs),
+            live_trading,
+            notifications,
+            liquidations_notified: Mutex::default(),
+        }
+    }
+
+    // Seeds the in flight orders with those left working by a previous run,
+    // orders entered by hand are never tracked
+    async fn get_working_orders(web_client: &WebClient) -> Vec<TrackedOrder> {
+        let orders = match web_client.get_live_orders().await {
+            Ok(val) => val
+                .iter()
+                .filter(|order| !order.is_terminal())
+                .filter(|order| {
+                    let ours = order.source.as_deref() == Some(ORDER_SOURCE);
+                    if !ours {
+                        debug!("Ignoring working order {} not placed by us", order.id);
+                    }
+                    ours
+                })
+                .map(|order| {
+                    info!("Found working order: {} status: {}", order.id, order.status);
+                    let underlying = order.underlying_symbol.clone();
+                    let remaining_quantity = order.remaining_quantity().unwrap_or(order.size);
+                    let state =
+                        OrderState::from_status(&order.status).unwrap_or(OrderState::Working);
+                    let order = Order::from(order);
+                    TrackedOrder {
+                        state,
+                        // The broker does not say when, the time it was found will do
+                        submitted_at: Utc::now(),
+                        initial_price: order.price,
+                        slippage: Decimal::ZERO,
+                        remaining_quantity,
+                        exit_reason: None,
+                        order,
+                        strategy_type: StrategyType::Other,
+                        underlying,
+                        is_chasing: false,
+                        placed_here: false,
+                    }
+                })
+                .collect(),
+            Err(err) => {
+                error!("Failed to fetch live orders from broker, error: {}", err);
+                Vec::new()
+            }
+        };
+        Self::set_in_flight_metric(&orders);
+        orders
+    }
+
+    fn set_in_flight_metric(orders: &[TrackedOrder]) {
+        ORDERS_IN_FLIGHT.set(
+            orders
+                .iter()
+                .filter(|tracked| tracked.is_in_flight())
+                .count() as i64,
+        );
+    }
+
+    fn chase_monitor(
+        orders: Arc<Mutex<Vec<TrackedOrder>>>,
+        mkt_data: Arc<RwLock<MktData>>,
+        web_client: Arc<WebClient>,
+        tick_sizes: TickSizeCache,
+        chase: ChaseConfig,
+        live_trading: bool,
+        cancel_token: CancellationToken,
+    ) {
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(chase.interval_secs));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        Self::chase_working_orders(&orders, &mkt_data, &web_client, &tick_sizes, &chase, live_trading).await;
+                    }
+                    _ = cancel_token.cancelled() => {
+                        break
+                    }
+                }
+            }
+        });
+    }
+
+    // Walks resting orders toward the current midprice, bounded by the max
+    // adjustment from the price the order was first placed at. Each step is
+    // taken from a snapshot so the lock is not held while the broker replaces
+    async fn chase_working_orders(
+        orders: &Mutex<Vec<TrackedOrder>>,
+        mkt_data: &Arc<RwLock<MktData>>,
+        web_client: &Arc<WebClient>,
+        tick_sizes: &TickSizeCache,
+        chase: &ChaseConfig,
+        live_trading: bool,
+    ) {
+        let chasing = orders
+            .lock()
+            .await
+            .iter()
+            .filter(|working| working.is_chasing && working.is_in_flight())
+            .filter_map(|working| {
+                Some((
+                    working.order.id?,
+                    working.order.clone(),
+                    working.strategy_type,
+                    working.underlying.clone(),
+                    working.initial_price,
+                    working.slippage,
+                    working.exit_reason.is_some(),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        for (id, mut order, strategy_type, underlying, initial_price, slippage, is_exit) in chasing
+        {
+            let target =
+                match Self::get_midprice(strategy_type, &underlying, mkt_data, &order).await {
+                    Ok(val) if !val.is_zero() => val.abs() + slippage,
+                    _ => continue,
+                };
+
+            let Some(price) = Self::next_chase_price(order.price, initial_price, target, chase)
+            else {
+                // Only a market that has run past the cap ends the chase, one
+                // that comes back inside it is followed again
+                if (target - initial_price).abs() >= chase.max_adjustment
+                    && (order.price - initial_price).abs() >= chase.max_adjustment
+                {
+                    warn!(
+                        "Order: {} for symbol: {} reached max adjustment, no longer chasing",
+                        id, underlying
+                    );
+                    if let Some(working) = orders
+                        .lock()
+                        .await
+                        .iter_mut()
+                        .find(|working| working.order.id == Some(id))
+                    {
+                        working.is_chasing = false;
+                    }
+                }
+                continue;
+            };
+            let price = tick_sizes
+                .round(web_client, &underlying, &order, price, is_exit)
+                .await;
+            if price == order.price {
+                continue;
+            }
+            info!(
+                "Chasing order: {} for symbol: {} from: {} to: {}",
+                id, underlying, order.price, price
+            );
+            order.price = price;
+            match Self::replace_order(
+                web_client.get_account(),
+                id,
+                &order,
+                live_trading,
+                web_client,
+            )
+            .await
+            {
+                Ok(val) => {
+                    // The order may have been filled or cancelled while the
+                    // replace was out, that update is kept
+                    let mut orders = orders.lock().await;
+                    if let Some(working) = orders
+                        .iter_mut()
+                        .find(|working| working.order.id == Some(id) && working.is_in_flight())
+                    {
+                        working.order.id = Some(val.id);
+                        working.order.price = order.price;
+                        working.state = OrderState::Pending;
+                    }
+                }
+                Err(err) => error!("Failed to replace order: {}, error: {}", id, err),
+            }
+        }
+    }
+
+    fn next_chase_price(
+        price: Decimal,
+        initial_price: Decimal,
+        midprice: Decimal,
+        chase: &ChaseConfig,
+    ) -> Option<Decimal> {
+        if (midprice - price).abs() < chase.tick_threshold {
+            return None;
+        }
+        let next_price = midprice.clamp(
+            initial_price - chase.max_adjustment,
+            initial_price + chase.max_adjustment,
+        );
+        if next_price == price {
+            None
+        } else {
+            Some(next_price)
+        }
+    }
+
+    // Holds the underlying as an order placement would
+    #[cfg(test)]
+    pub(crate) async fn lock_underlying(&self, underlying: &str) -> OwnedMutexGuard<()> {
+        self.guards.lock(underlying).await
+    }
+
+    // No broker or feed tasks behind it, for tests outside this module
+    #[cfg(test)]
+    pub(crate) fn new_for_test(web_client: Arc<WebClient>, mkt_data: Arc<RwLock<MktData>>) -> Self {
+        Self {
+            web_client,
+            mkt_data,
+            orders: Arc::new(Mutex::new(Vec::new())),
+            tick_sizes: TickSizeCache::default(),
+            guards: UnderlyingGuards::default(),
+            order_config: OrderConfig::default(),
+            min_quote_size: 1.0,
+            stale_quote_after: Duration::from_secs(30),
+            live_trading: false,
+            notifications: Notifications::default(),
+            liquidations_notified: Default::default(),
+        }
+    }
+
+    pub async fn has_order_in_flight(&self, underlying: &str) -> bool {
+        self.orders
+            .lock()
+            .await
+            .iter()
+            .any(|working| working.is_in_flight() && working.underlying == underlying)
+    }
+
+    pub async fn open_position<Meta>(
+        &self,
+        meta_data: &Meta,
+        price_effect: PriceEffect,
+    ) -> Result<(), StrategyError>
+    where
+        Meta: StrategyMeta + ?Sized,
+    {
+        let _guard = self.guards.lock(meta_data.get_underlying()).await;
+        if self.has_order_in_flight(meta_data.get_underlying()).await {
+            debug!("Order {} already in flight", meta_data.get_underlying());
+            return Ok(());
         }
+
+        let order = Self::build_order_from_meta(
+            meta_data,
+            price_effect,
+            OrderIntent::Open,
+            &self.order_config,
+        )?;
+        info!(
+            underlying = meta_data.get_underlying(),
+            "Calling open position for {}",
+            meta_data.get_underlying()
+        );
+        self.submit_order(meta_data, order, None)
+            .instrument(Self::order_span(meta_data))
+            .await
+    }
+
+    // Runs a described position through the dry-run endpoint at the given
+    // price, it is never sent to the exchange whatever the command line says
+    pub async fn preview_position<Meta>(
+        web_client: &WebClient,
+        meta_data: &Meta,
+        price_effect: PriceEffect,
+        price: Decimal,
+        order_config: &OrderConfig,
+    ) -> Result<DryRunResult>
+    where
+        Meta: StrategyMeta + ?Sized,
+    {
+        let mut order =
+            Self::build_order_from_meta(meta_data, price_effect, OrderIntent::Open, order_config)?;
+        order.price = price;
+        let response = web_client
+            .post::<Order, DryRunResponse>(
+                &Self::order_endpoint(
+                    format!("accounts/{}/orders", web_client.get_account()),
+                    false,
+                ),
+                order,
+            )
+            .await?;
+        Ok(response.data)
     }
 
     pub async fn liquidate_position<Meta>(
-        &mut self,
+        &self,
         meta_data: &Meta,
         price_effect: PriceEffect,
-    ) -> Result<()>
+        reason: ExitReason,
+        size: CloseSize,
+    ) -> Result<(), StrategyError>
     where
-        Meta: StrategyMeta,
+        Meta: StrategyMeta + ?Sized,
     {
+        let _guard = self.guards.lock(meta_data.get_underlying()).await;
         // check to see if order in flight
-        if self.orders.iter().any(|order| {
-            order.legs.iter().any(|leg| {
-                meta_data
-                    .get_symbols()
-                    .iter()
-                    .any(|symbol| *symbol == leg.symbol)
-            })
+        if self.orders.lock().await.iter().any(|working| {
+            working.is_in_flight()
+                && working.order.legs.iter().any(|leg| {
+                    meta_data
+                        .get_symbols()
+                        .iter()
+                        .any(|symbol| *symbol == leg.symbol)
+                })
         }) {
             debug!("Order {} already in flight", meta_data.get_underlying());
             return Ok(());
         }
 
-        let mut order = Self::build_order_from_meta(meta_data, price_effect)?;
+        let mut order = Self::build_order_from_meta(
+            meta_data,
+            price_effect,
+            OrderIntent::Close,
+            &self.order_config,
+        )?;
+        size.scale(&mut order)?;
+
+        if !Self::is_liquid(&self.mkt_data, &order, self.min_quote_size).await {
+            warn!(
+                "Quote size below minimum for symbol: {}, not liquidating",
+                meta_data.get_underlying()
+            );
+            return Ok(());
+        }
+
+        info!(
+            underlying = meta_data.get_underlying(),
+            strategy_type = ?meta_data.get_position().strategy_type,
+            %reason,
+            "Calling liquidate position for {}, reason: {}",
+            meta_data.get_underlying(),
+            reason
+        );
+        self.submit_order(meta_data, order, Some(reason))
+            .instrument(Self::order_span(meta_data))
+            .await
+    }
+
+    // Carries the fields a log aggregator filters on through everything
+    // logged while the order is priced and placed, the id once it is known
+    fn order_span<Meta>(meta_data: &Meta) -> Span
+    where
+        Meta: StrategyMeta + ?Sized,
+    {
+        info_span!(
+            "order",
+            underlying = meta_data.get_underlying(),
+            strategy_type = ?meta_data.get_position().strategy_type,
+            order_id = field::Empty,
+        )
+    }
 
-        // if not in flight find the midprice of strategy
+    // Prices the order at the strategy midprice and tracks it once placed
+    async fn submit_order<Meta>(
+        &self,
+        meta_data: &Meta,
+        mut order: Order,
+        exit_reason: Option<ExitReason>,
+    ) -> Result<(), StrategyError>
+    where
+        Meta: StrategyMeta + ?Sized,
+    {
+        // A stale book should not keep a position from being closed, the
+        // last quote still prices it
+        match Self::check_quotes_fresh(&self.mkt_data, &order, self.stale_quote_after).await {
+            Err(StrategyError::StaleQuote) if exit_reason.is_some() => warn!(
+                "Quotes stale for symbol: {}, liquidating on the last quote",
+                meta_data.get_underlying()
+            ),
+            result => result?,
+        }
         let midprice = Self::get_midprice(
             meta_data.get_position().strategy_type,
             meta_data.get_underlying(),
@@ -123,36 +841,188 @@ impl Orders {
         );
 
         if midprice.eq(&Decimal::ZERO) {
-            warn!("Failed to calculate midprice");
-            return Ok(());
+            return Err(StrategyError::NoMarketData);
         }
+        let is_credit_close = Self::settle_close_effect(&mut order, midprice);
+        let midprice = midprice.abs();
 
-        info!(
-            "Calling liquidate position for {}",
-            meta_data.get_underlying()
-        );
-        // then build the order
+        let slippage = match exit_reason {
+            Some(_) => {
+                let tick = self
+                    .tick_sizes
+                    .tick(
+                        &self.web_client,
+                        meta_data.get_underlying(),
+                        &order,
+                        midprice,
+                    )
+                    .await;
+                Self::slippage(
+                    midprice,
+                    tick,
+                    Self::price_effect(&order),
+                    self.order_config.slippage_ticks,
+                )
+            }
+            None => Decimal::ZERO,
+        };
+        let touch = match self.order_config.price_policy {
+            PricePolicy::Mid => None,
+            policy => Self::touch_prices(
+                meta_data.get_position().strategy_type,
+                &self.mkt_data,
+                &order,
+            )
+            .await
+            .map(|(bid, ask)| match is_credit_close {
+                true => (-ask, -bid),
+                false => (bid, ask),
+            })
+            .and_then(|touch| Self::price_at_touch(policy, Self::price_effect(&order), touch)),
+        };
+        if let Some(touch) = touch {
+            info!(
+                "For symbol: {}, {:?} policy prices at: {} rather than mid: {}",
+                meta_data.get_underlying(),
+                self.order_config.price_policy,
+                touch,
+                midprice
+            );
+        }
+        let midprice = self
+            .tick_sizes
+            .round(
+                &self.web_client,
+                meta_data.get_underlying(),
+                &order,
+                touch.unwrap_or(midprice + slippage),
+                exit_reason.is_some(),
+            )
+            .await;
         order.price = midprice;
-        if let Err(err) =
-            Self::place_order(self.web_client.get_account(), &order, &self.web_client).await
+        let mut orders = self.orders.lock().await;
+        match Self::place_order(
+            self.web_client.get_account(),
+            &order,
+            self.live_trading,
+            &self.web_client,
+        )
+        .await
         {
-            error!("Failed to place order, error: {}", err);
-            return Err(err);
+            Ok(val) => {
+                Span::current().record("order_id", val.id);
+                info!(order_id = val.id, "Order placed at: {}", midprice);
+                if let Some(reason) = exit_reason {
+                    self.notify_liquidation(meta_data, reason).await;
+                }
+                self.notifications.send(OrderEvent::Placed {
+                    underlying: meta_data.get_underlying().to_string(),
+                    order_id: val.id,
+                    price: midprice,
+                });
+                order.id = Some(val.id)
+            }
+            Err(err) => {
+                self.notifications.send(OrderEvent::Rejected {
+                    underlying: meta_data.get_underlying().to_string(),
+                    reason: err.to_string(),
+                });
+                return Err(StrategyError::from_broker(err));
+            }
         }
-        self.orders.push(order);
+        let now = Utc::now();
+        orders.retain(|tracked| {
+            tracked.is_in_flight() || now - tracked.submitted_at < FINISHED_ORDER_RETENTION
+        });
+        orders.push(TrackedOrder {
+            remaining_quantity: order.quantity(),
+            order,
+            state: OrderState::Pending,
+            submitted_at: now,
+            strategy_type: meta_data.get_position().strategy_type,
+            underlying: meta_data.get_underlying().to_string(),
+            initial_price: midprice,
+            slippage,
+            exit_reason,
+            // Only liquidations are walked toward the midprice
+            is_chasing: exit_reason.is_some(),
+            placed_here: true,
+        });
+        Self::set_in_flight_metric(&orders);
         Ok(())
     }
 
-    fn build_order_from_meta<Meta>(meta_data: &Meta, price_effect: PriceEffect) -> Result<Order>
+    // Sent once the exit order is placed, not on every attempt to price it
+    async fn notify_liquidation<Meta>(&self, meta_data: &Meta, reason: ExitReason)
     where
-        Meta: StrategyMeta,
+        Meta: StrategyMeta + ?Sized,
     {
-        fn get_action(direction: Direction) -> String {
-            match direction {
-                Direction::Long => String::from("Sell to Close"),
-                Direction::Short => String::from("Buy to Close"),
+        let position = meta_data.get_symbols().join(",");
+        if self.liquidations_notified.lock().await.insert(position) {
+            self.notifications.send(OrderEvent::LiquidationTriggered {
+                underlying: meta_data.get_underlying().to_string(),
+                reason,
+            });
+        }
+    }
+
+    // Only orders this process placed are cancelled, and none in dry run as
+    // nothing reached the broker
+    pub async fn cancel_all_working(&self) {
+        if !self.live_trading {
+            return;
+        }
+        let _guards = self.guards.lock_all().await;
+        let ids: Vec<i32> = self
+            .orders
+            .lock()
+            .await
+            .iter()
+            .filter(|working| working.is_in_flight() && working.placed_here)
+            .filter_map(|working| working.order.id)
+            .collect();
+        let mut cancelled = Vec::new();
+        for id in ids {
+            info!("Cancelling working order: {}", id);
+            match Self::cancel_order(self.web_client.get_account(), id, &self.web_client).await {
+                Ok(_) => cancelled.push(id),
+                Err(err) => error!("Failed to cancel order: {}, error: {}", id, err),
             }
         }
+        let mut orders = self.orders.lock().await;
+        orders
+            .iter_mut()
+            .filter(|working| working.order.id.is_some_and(|id| cancelled.contains(&id)))
+            .for_each(|working| working.state = OrderState::Cancelled);
+        Self::set_in_flight_metric(&orders);
+    }
+
+    fn build_order_from_meta<Meta>(
+        meta_data: &Meta,
+        price_effect: PriceEffect,
+        intent: OrderIntent,
+        order_config: &OrderConfig,
+    ) -> Result<Order>
+    where
+        Meta: StrategyMeta + ?Sized,
+    {
+        if !order_config
+            .time_in_force
+            .allows_gtc_date(order_config.gtc_date.as_deref())
+        {
+            bail!(
+                "Gtc date: {:?} is only valid on GTD orders, time in force: {}",
+                order_config.gtc_date,
+                order_config.time_in_force
+            );
+        }
+
+        // Closing orders start out paid for until priced, opening orders
+        // take the requested effect
+        let price_effect = match intent {
+            OrderIntent::Open => price_effect,
+            OrderIntent::Close => PriceEffect::Debit,
+        };
 
         fn get_symbol(symbol: &str, instrument_type: OptionType) -> String {
             match instrument_type {
@@ -162,9 +1032,11 @@ impl Orders {
         }
 
         let order = Order {
-            time_in_force: String::from("DAY"),
+            time_in_force: order_config.time_in_force.to_string(),
+            gtc_date: order_config.gtc_date.clone(),
             order_type: OrderType::Limit.to_string(),
-            price_effect: PriceEffect::Debit.to_string(),
+            price_effect: price_effect.to_string(),
+            source: Some(ORDER_SOURCE.to_string()),
             legs: meta_data
                 .get_position()
                 .legs
@@ -173,7 +1045,7 @@ impl Orders {
                     instrument_type: leg.option_type.to_string(),
                     symbol: get_symbol(&leg.symbol, leg.option_type),
                     quantity: leg.quantity,
-                    action: get_action(leg.direction),
+                    action: intent.action(leg.direction),
                 })
                 .collect(),
             ..Default::default()
@@ -182,120 +1054,1787 @@ impl Orders {
         Ok(order)
     }
 
-    async fn get_midprice(
-        strategy_type: StrategyType,
-        symbol: &str,
+    async fn check_quotes_fresh(
         mktdata: &Arc<RwLock<MktData>>,
         order: &Order,
-    ) -> Result<Decimal> {
-        fn get_mid_price(event: Option<Snapshot>) -> Decimal {
-            if let Some(quote) = &event.as_ref().unwrap().quote {
-                return quote.midprice();
+        stale_after: Duration,
+    ) -> Result<(), StrategyError> {
+        let reader = mktdata.read().await;
+        for leg in &order.legs {
+            match reader.get_snapshot_by_symbol::<Quote>(&leg.symbol).await {
+                None => return Err(StrategyError::NoMarketData),
+                Some(snapshot) if snapshot.last_update.elapsed() > stale_after => {
+                    return Err(StrategyError::StaleQuote)
+                }
+                Some(_) => (),
             }
-            Decimal::default()
         }
+        Ok(())
+    }
 
+    async fn is_liquid(mktdata: &Arc<RwLock<MktData>>, order: &Order, min_size: f64) -> bool {
         let reader = mktdata.read().await;
-        let calculated_midprice = match strategy_type {
-            StrategyType::CreditSpread | StrategyType::CalendarSpread => {
-                let sell_mid = get_mid_price(
-                    reader
-                        .get_snapshot_by_symbol::<Quote>(&order.legs[0].symbol)
-                        .await,
-                );
-                let buy_mid = get_mid_price(
-                    reader
-                        .get_snapshot_by_symbol::<Quote>(&order.legs[1].symbol)
-                        .await,
-                );
-                let mid = sell_mid - buy_mid;
-                info!(
-                    "New calc symbol:{} mid: {} sell mid: {} buy mid: {}",
-                    symbol, mid, sell_mid, buy_mid
-                );
-                mid
+        for leg in &order.legs {
+            let quote = reader
+                .get_snapshot_by_symbol::<Quote>(&leg.symbol)
+                .await
+                .and_then(|snapshot| snapshot.quote);
+            let buying = leg.action.starts_with("Buy");
+            match quote {
+                Some(quote) if quote.is_liquid(buying, min_size) => (),
+                _ => return false,
             }
-            StrategyType::IronCondor => {
-                let call_sell_mid = get_mid_price(
-                    reader
-                        .get_snapshot_by_symbol::<Quote>(&order.legs[1].symbol)
-                        .await,
-                );
-                let call_buy_mid = get_mid_price(
-                    reader
-                        .get_snapshot_by_symbol::<Quote>(&order.legs[0].symbol)
-                        .await,
-                );
-                let put_sell_mid = get_mid_price(
-                    reader
-                        .get_snapshot_by_symbol::<Quote>(&order.legs[2].symbol)
-                        .await,
-                );
-                let put_buy_mid = get_mid_price(
-                    reader
-                        .get_snapshot_by_symbol::<Quote>(&order.legs[3].symbol)
-                        .await,
-                );
-                let mid = (call_sell_mid - call_buy_mid) + (put_sell_mid - put_buy_mid);
-                info!(
-                    "New calc mid: {} call spread: {} put spread: {}",
-                    mid,
-                    call_sell_mid - call_buy_mid,
-                    put_sell_mid - put_buy_mid
-                );
-                mid
+        }
+        true
+    }
+
+    async fn get_midprice(
+        strategy_type: StrategyType,
+        symbol: &str,
+        mktdata: &Arc<RwLock<MktData>>,
+        order: &Order,
+    ) -> Result<Decimal, StrategyError> {
+        // A leg whose subscription failed has no quote, pricing it at zero
+        // would skew the net mid so the order is not priced at all
+        let reader = mktdata.read().await;
+        let mut leg_mids = Vec::with_capacity(order.legs.len());
+        for leg in &order.legs {
+            match reader
+                .get_snapshot_by_symbol::<Quote>(&leg.symbol)
+                .await
+                .and_then(|snapshot| snapshot.midprice())
+            {
+                Some(mid) => leg_mids.push(mid),
+                None => return Err(StrategyError::NoMarketData),
             }
-            _ => Decimal::default(),
-        };
+        }
+
+        let calculated_midprice = Self::leg_signs(strategy_type, order)?
+            .into_iter()
+            .zip(&leg_mids)
+            .map(|(sign, leg_mid)| sign * leg_mid)
+            .sum::<Decimal>();
+        info!(
+            "New calc symbol:{} mid: {} leg mids: {:?}",
+            symbol, calculated_midprice, leg_mids
+        );
         debug!(
-            "For leg symbol: {}, calculated midprice: {}",
-            order.legs[0].symbol, calculated_midprice,
+            "For symbol: {}, calculated midprice: {}",
+            symbol, calculated_midprice,
         );
 
         Ok(calculated_midprice)
     }
 
-    async fn place_order(
-        account_number: &str,
+    // How each leg counts toward the net price, the first leg of a calendar
+    // is the short one. For every other spread the short legs are the ones
+    // bought back or sold to open, which keeps the net independent of leg
+    // order. Empty for strategies that are not priced
+    fn leg_signs(
+        strategy_type: StrategyType,
         order: &Order,
-        web_client: &Arc<WebClient>,
-    ) -> Result<OrderData> {
+    ) -> Result<Vec<Decimal>, StrategyError> {
+        match strategy_type {
+            StrategyType::CalendarSpread => match order.legs.len() {
+                2 => Ok(vec![Decimal::ONE, Decimal::NEGATIVE_ONE]),
+                _ => Err(StrategyError::NoMarketData),
+            },
+            StrategyType::CreditSpread if order.legs.len() != 2 => Err(StrategyError::NoMarketData),
+            StrategyType::CreditSpread
+            | StrategyType::IronCondor
+            | StrategyType::IronButterfly
+            | StrategyType::Diagonal
+            | StrategyType::Strangle
+            | StrategyType::Straddle => Ok(order
+                .legs
+                .iter()
+                .map(|leg| match leg.action.as_str() {
+                    "Buy to Close" | "Sell to Open" => Decimal::ONE,
+                    _ => Decimal::NEGATIVE_ONE,
+                })
+                .collect()),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    // Net bid and ask of the order from the touch of every leg, a leg that
+    // counts negatively gives its opposite side. None when a leg has no two
+    // sided quote
+    async fn touch_prices(
+        strategy_type: StrategyType,
+        mktdata: &Arc<RwLock<MktData>>,
+        order: &Order,
+    ) -> Option<(Decimal, Decimal)> {
+        let signs = Self::leg_signs(strategy_type, order).ok()?;
+        if signs.is_empty() {
+            return None;
+        }
+        let reader = mktdata.read().await;
+        let (mut bid, mut ask) = (Decimal::ZERO, Decimal::ZERO);
+        for (leg, sign) in order.legs.iter().zip(signs) {
+            let (leg_bid, leg_ask) = reader
+                .get_snapshot_by_symbol::<Quote>(&leg.symbol)
+                .await?
+                .touch()?;
+            match sign.is_sign_positive() {
+                true => {
+                    bid += leg_bid;
+                    ask += leg_ask;
+                }
+                false => {
+                    bid -= leg_ask;
+                    ask -= leg_bid;
+                }
+            }
+        }
+        Some((bid, ask))
+    }
+
+    // Paying less on a debit and taking more on a credit is passive, the far
+    // side of the book is aggressive
+    fn price_at_touch(
+        policy: PricePolicy,
+        price_effect: PriceEffect,
+        (bid, ask): (Decimal, Decimal),
+    ) -> Option<Decimal> {
+        match (policy, price_effect) {
+            (PricePolicy::Mid, _) => None,
+            (PricePolicy::Aggressive, PriceEffect::Debit)
+            | (PricePolicy::Passive, PriceEffect::Credit) => Some(ask),
+            (PricePolicy::Aggressive, PriceEffect::Credit)
+            | (PricePolicy::Passive, PriceEffect::Debit) => Some(bid),
+        }
+    }
+
+    fn tick_for_price(ticks: &[OptionTick], price: Decimal) -> Decimal {
+        ticks
+            .iter()
+            .find(|tick| {
+                tick.threshold
+                    .is_none_or(|threshold| price.abs() < threshold)
+            })
+            .map(|tick| tick.value)
+            .unwrap_or(DEFAULT_TICK)
+    }
+
+    // The net counts the short legs up, so a close with a negative net takes
+    // in more than it pays and is a credit. True when the close was flipped
+    fn settle_close_effect(order: &mut Order, net: Decimal) -> bool {
+        let is_close = order
+            .legs
+            .iter()
+            .all(|leg| leg.action.ends_with("to Close"));
+        let price_effect = match net.is_sign_negative() {
+            true => PriceEffect::Credit,
+            false => PriceEffect::Debit,
+        };
+        if is_close {
+            order.price_effect = price_effect.to_string();
+        }
+        is_close && price_effect == PriceEffect::Credit
+    }
+
+    fn price_effect(order: &Order) -> PriceEffect {
+        match order.price_effect.as_str() {
+            "Credit" => PriceEffect::Credit,
+            _ => PriceEffect::Debit,
+        }
+    }
+
+    // Liquidations pay up on a debit and take less on a credit to fill
+    // sooner, a credit is never taken below a single tick
+    fn slippage(
+        midprice: Decimal,
+        tick: Decimal,
+        price_effect: PriceEffect,
+        slippage_ticks: u32,
+    ) -> Decimal {
+        let slippage = tick * Decimal::from(slippage_ticks);
+        match price_effect {
+            PriceEffect::Debit => slippage,
+            PriceEffect::Credit => -slippage.min((midprice - tick).max(Decimal::ZERO)),
+        }
+    }
+
+    // Snaps to the tick in the trader's favour, debits are rounded down so
+    // less is paid and credits up so more is taken in. A liquidation rounds
+    // toward the far side instead so none of its slippage is given back
+    fn round_to_tick(
+        price: Decimal,
+        tick: Decimal,
+        price_effect: PriceEffect,
+        marketable: bool,
+    ) -> Decimal {
+        if tick <= Decimal::ZERO {
+            return price;
+        }
+        let ticks = price / tick;
+        let ticks = match (price_effect, marketable) {
+            (PriceEffect::Debit, false) | (PriceEffect::Credit, true) => ticks.floor(),
+            (PriceEffect::Debit, true) | (PriceEffect::Credit, false) => ticks.ceil(),
+        };
+        (ticks * tick).normalize()
+    }
+
+    // Orders only reach the exchange when live trading was asked for on the
+    // command line, everything else goes to the dry-run endpoint
+    fn order_endpoint(path: String, live_trading: bool) -> String {
+        match live_trading {
+            true => path,
+            false => format!("{}/dry-run", path),
+        }
+    }
+
+    async fn place_order(
+        account_number: &str,
+        order: &Order,
+        live_trading: bool,
+        web_client: &Arc<WebClient>,
+    ) -> Result<OrderData> {
         info!("Placing order: {:?}", order);
-        web_client
-            .post::<Order, OrderData>(
-                &format!("accounts/{}/orders/dry-run", account_number),
+        ORDERS_PLACED.inc();
+        let response = web_client
+            .post::<Order, OrderResponse>(
+                &Self::order_endpoint(format!("accounts/{}/orders", account_number), live_trading),
                 order.clone(),
             )
-            .await
+            .await?;
+        Ok(response.data.order)
+    }
+
+    async fn cancel_order(
+        account_number: &str,
+        order_id: i32,
+        web_client: &Arc<WebClient>,
+    ) -> Result<OrderData> {
+        let response = web_client
+            .delete::<OrderResponse>(&format!("accounts/{}/orders/{}", account_number, order_id))
+            .await?;
+        Ok(response.data.order)
     }
 
     async fn replace_order(
         account_number: &str,
-        order: Order,
+        order_id: i32,
+        order: &Order,
+        live_trading: bool,
         web_client: &Arc<WebClient>,
     ) -> Result<OrderData> {
-        // web_client
-        //     .put::<Order, OrderData>(
-        //         &format!(
-        //             "accounts/{}/orders/{}/dry-run",
-        //             account_number,
-        //             order.preflight_id.clone().unwrap()
-        //         ),
-        //         order,
-        //     )
-        //     .await
-        Ok(OrderData::default())
+        let mut order = order.clone();
+        order.id = None;
+        let response = web_client
+            .put::<Order, OrderResponse>(
+                &Self::order_endpoint(
+                    format!("accounts/{}/orders/{}", account_number, order_id),
+                    live_trading,
+                ),
+                order,
+            )
+            .await?;
+        Ok(response.data.order)
+    }
+
+    fn parse_order_update(msg: &str) -> Option<OrderData> {
+        let update = serde_json::from_str::<OrderUpdate>(msg).ok()?;
+        if update.msg_type.ne("Order") {
+            return None;
+        }
+        Some(update.data)
     }
 
-    fn handle_msg(msg: String, _cancel_token: &CancellationToken) {
-        if let serde_json::Result::Ok(payload) = serde_json::from_str::<acc_api::Payload>(&msg) {
-            if payload.msg_type.ne("Order") {
-                return;
+    // Terminal or fully filled orders leave flight so the position can be
+    // liquidated again, a partially filled liquidation is cut down to the
+    // residual and its id returned so it can be re-worked
+    async fn handle_msg(
+        msg: String,
+        orders: &Mutex<Vec<TrackedOrder>>,
+        notifications: &Notifications,
+    ) -> Option<i32> {
+        let update = Self::parse_order_update(&msg)?;
+        info!(
+            order_id = update.id,
+            underlying = %update.underlying_symbol,
+            "Order: {} for symbol: {} status: {}",
+            update.id,
+            update.underlying_symbol,
+            update.status
+        );
+        match update.status.as_str() {
+            "Filled" => {
+                for leg in &update.legs {
+                    info!(
+                        order_id = update.id,
+                        underlying = %update.underlying_symbol,
+                        streamer_symbol = %leg.symbol,
+                        "Order: {} leg: {} {} filled at: {:?}",
+                        update.id,
+                        leg.action,
+                        leg.symbol,
+                        leg.average_fill_price()
+                    );
+                }
+                notifications.send(OrderEvent::Filled {
+                    underlying: update.underlying_symbol.clone(),
+                    order_id: update.id,
+                })
             }
-            info!("msg received: {}", msg);
-            if let serde_json::Result::Ok(msg) = serde_json::from_str::<acc_api::Payload>(&msg) {
-                info!("Last mktdata message received, msg: {:?}", msg);
+            "Rejected" => notifications.send(OrderEvent::Rejected {
+                underlying: update.underlying_symbol.clone(),
+                reason: format!("order {} rejected by the exchange", update.id),
+            }),
+            _ => (),
+        }
+        let remaining = update.remaining_quantity();
+        let state = match remaining {
+            Some(0) => Some(OrderState::Filled),
+            _ => OrderState::from_status(&update.status),
+        };
+        let mut orders = orders.lock().await;
+        let working = orders
+            .iter_mut()
+            .find(|working| working.order.id == Some(update.id))?;
+        if let Some(state) = state {
+            working.state = state;
+        }
+        if !working.is_in_flight() {
+            if let Some(reason) = working.exit_reason {
+                info!(
+                    order_id = update.id,
+                    underlying = %update.underlying_symbol,
+                    strategy_type = ?working.strategy_type,
+                    %reason,
+                    "Liquidation order: {} for symbol: {} {}, exit reason: {}",
+                    update.id, update.underlying_symbol, update.status, reason
+                );
             }
+            Self::set_in_flight_metric(&orders);
+            return None;
+        }
+        let remaining = remaining?;
+        if remaining >= working.remaining_quantity || !working.is_closing() {
+            return None;
         }
+        info!(
+            "Order: {} for symbol: {} partially filled, remaining: {}",
+            update.id, working.underlying, remaining
+        );
+        for leg in working.order.legs.iter_mut() {
+            if let Some(filled) = update
+                .legs
+                .iter()
+                .find(|filled| filled.symbol == leg.symbol)
+            {
+                leg.quantity = filled.remaining_quantity;
+            }
+        }
+        working.remaining_quantity = remaining;
+        Some(update.id)
+    }
+
+    // Replaces the residual of a partial fill at a refreshed midprice, the
+    // last price is kept when no fresh midprice is available. The lock is
+    // only held to snapshot the order and to record the replacement so fills
+    // and stop checks are not blocked behind the broker
+    async fn rework_residual(
+        id: i32,
+        orders: &Mutex<Vec<TrackedOrder>>,
+        mkt_data: &Arc<RwLock<MktData>>,
+        web_client: &Arc<WebClient>,
+        tick_sizes: &TickSizeCache,
+        live_trading: bool,
+    ) {
+        let Some((mut order, strategy_type, underlying, slippage, is_exit)) = orders
+            .lock()
+            .await
+            .iter()
+            .find(|working| working.order.id == Some(id))
+            .map(|working| {
+                (
+                    working.order.clone(),
+                    working.strategy_type,
+                    working.underlying.clone(),
+                    working.slippage,
+                    working.exit_reason.is_some(),
+                )
+            })
+        else {
+            return;
+        };
+        match Self::get_midprice(strategy_type, &underlying, mkt_data, &order).await {
+            Ok(val) if !val.is_zero() => {
+                order.price = tick_sizes
+                    .round(
+                        web_client,
+                        &underlying,
+                        &order,
+                        val.abs() + slippage,
+                        is_exit,
+                    )
+                    .await
+            }
+            _ => warn!(
+                "No midprice for symbol: {}, re-working residual at: {}",
+                underlying, order.price
+            ),
+        }
+        match Self::replace_order(
+            web_client.get_account(),
+            id,
+            &order,
+            live_trading,
+            web_client,
+        )
+        .await
+        {
+            Ok(val) => {
+                info!(
+                    "Replaced order: {} with: {} for residual quantity: {} at: {}",
+                    id,
+                    val.id,
+                    order.quantity(),
+                    order.price
+                );
+                // The order may have been filled or cancelled while the
+                // replace was out, that update is kept
+                let mut orders = orders.lock().await;
+                if let Some(working) = orders
+                    .iter_mut()
+                    .find(|working| working.order.id == Some(id) && working.is_in_flight())
+                {
+                    order.id = Some(val.id);
+                    working.order = order;
+                    working.state = OrderState::Pending;
+                }
+            }
+            Err(err) => error!("Failed to replace residual order: {}, error: {}", id, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::Notifier;
+    use crate::positions::OptionLeg;
+    use crate::positions::OptionSide;
+    use crate::positions::Position;
+    use crate::utils::fixtures::make_mktdata;
+    use crate::utils::fixtures::make_web_client;
+    use crate::utils::fixtures::push_quotes;
+    use crate::utils::fixtures::start_stub_server;
+    use crate::utils::fixtures::UNREACHABLE_URL;
+    use chrono::NaiveDate;
+    use futures_util::future::BoxFuture;
+    use rust_decimal_macros::dec;
+
+    struct TestSpread {
+        position: Position,
+    }
+
+    impl StrategyMeta for TestSpread {
+        fn get_underlying(&self) -> &str {
+            "SPX"
+        }
+
+        fn get_symbols(&self) -> Vec<&str> {
+            self.position
+                .legs
+                .iter()
+                .map(|leg| leg.symbol.as_str())
+                .collect()
+        }
+
+        fn get_instrument_type(&self) -> OptionType {
+            OptionType::EquityOption
+        }
+
+        fn get_position(&self) -> &Position {
+            &self.position
+        }
+
+        fn get_entry_price(&self) -> Option<Decimal> {
+            None
+        }
+    }
+
+    fn make_spread() -> TestSpread {
+        let make_leg = |symbol: &str, direction: Direction, strike_price: Decimal| OptionLeg {
+            symbol: symbol.to_string(),
+            underlying: "SPX".to_string(),
+            expiration_date: NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+            direction,
+            side: OptionSide::Put,
+            strike_price,
+            quantity: 1,
+            multiplier: dec!(100),
+            average_open_price: None,
+            mark_price: None,
+            option_type: OptionType::EquityOption,
+        };
+        TestSpread {
+            position: Position {
+                legs: vec![
+                    make_leg("SPXW  240315P05100000", Direction::Short, dec!(5100)),
+                    make_leg("SPXW  240315P05090000", Direction::Long, dec!(5090)),
+                ],
+                strategy_type: StrategyType::CreditSpread,
+            },
+        }
+    }
+
+    #[test]
+    fn test_close_size_scales_legs() {
+        let build = || {
+            let mut spread = make_spread();
+            for leg in spread.position.legs.iter_mut() {
+                leg.quantity = 3;
+            }
+            Orders::build_order_from_meta(
+                &spread,
+                PriceEffect::Debit,
+                OrderIntent::Close,
+                &OrderConfig::default(),
+            )
+            .unwrap()
+        };
+
+        let half = CloseSize {
+            fraction: dec!(0.5),
+            contracts: None,
+        };
+        let mut order = build();
+        half.scale(&mut order).unwrap();
+        assert!(order.legs.iter().all(|leg| leg.quantity == 2));
+
+        let mut order = build();
+        CloseSize {
+            contracts: Some(5),
+            ..Default::default()
+        }
+        .scale(&mut order)
+        .unwrap();
+        assert!(order.legs.iter().all(|leg| leg.quantity == 3));
+
+        let tenth = CloseSize {
+            fraction: dec!(0.1),
+            contracts: None,
+        };
+        let err = tenth.scale(&mut build()).unwrap_err();
+        assert!(err.to_string().contains("rounds to zero contracts"));
+    }
+
+    #[test]
+    fn test_build_opening_order() {
+        let order = Orders::build_order_from_meta(
+            &make_spread(),
+            PriceEffect::Credit,
+            OrderIntent::Open,
+            &OrderConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(order.time_in_force, "Day");
+        assert_eq!(order.price_effect, "Credit");
+        assert_eq!(order.legs[0].action, "Sell to Open");
+        assert_eq!(order.legs[1].action, "Buy to Open");
+    }
+
+    #[test]
+    fn test_build_closing_order() {
+        let order = Orders::build_order_from_meta(
+            &make_spread(),
+            PriceEffect::Credit,
+            OrderIntent::Close,
+            &OrderConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(order.price_effect, "Debit");
+        assert_eq!(order.legs[0].action, "Buy to Close");
+        assert_eq!(order.legs[1].action, "Sell to Close");
+        assert_eq!(order.source.as_deref(), Some(ORDER_SOURCE));
+    }
+
+    #[test]
+    fn test_order_intent_actions() {
+        assert_eq!(OrderIntent::Open.action(Direction::Long), "Buy to Open");
+        assert_eq!(OrderIntent::Open.action(Direction::Short), "Sell to Open");
+        assert_eq!(OrderIntent::Close.action(Direction::Long), "Sell to Close");
+        assert_eq!(OrderIntent::Close.action(Direction::Short), "Buy to Close");
+    }
+
+    #[test]
+    fn test_build_order_time_in_force() {
+        let gtc = OrderConfig {
+            time_in_force: TimeInForce::Gtc,
+            gtc_date: None,
+            ..Default::default()
+        };
+        let order = Orders::build_order_from_meta(
+            &make_spread(),
+            PriceEffect::Credit,
+            OrderIntent::Open,
+            &gtc,
+        )
+        .unwrap();
+        assert_eq!(order.time_in_force, "GTC");
+        assert_eq!(order.gtc_date, None);
+
+        let gtc_with_date = OrderConfig {
+            gtc_date: Some(String::from("2024-03-15")),
+            ..gtc
+        };
+        assert!(Orders::build_order_from_meta(
+            &make_spread(),
+            PriceEffect::Credit,
+            OrderIntent::Open,
+            &gtc_with_date
+        )
+        .is_err());
+    }
+
+    fn make_working_order(id: i32) -> TrackedOrder {
+        TrackedOrder {
+            order: Order {
+                id: Some(id),
+                ..Default::default()
+            },
+            state: OrderState::Pending,
+            submitted_at: Utc::now(),
+            strategy_type: StrategyType::CreditSpread,
+            underlying: "SPX".to_string(),
+            initial_price: dec!(1.00),
+            slippage: Decimal::ZERO,
+            remaining_quantity: 1,
+            exit_reason: Some(ExitReason::StrikeCrossed),
+            is_chasing: true,
+            placed_here: true,
+        }
+    }
+
+    // Dry run defaults, tests override the fields they exercise
+    fn make_orders(web_client: Arc<WebClient>, mkt_data: MktData) -> Orders {
+        Orders::new_for_test(web_client, Arc::new(RwLock::new(mkt_data)))
+    }
+
+    #[tokio::test]
+    async fn test_midprice_needs_every_leg_quoted() {
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mkt_data = make_mktdata(web_client, &cancel_token);
+        push_quotes(
+            &mkt_data,
+            "SPX",
+            &[("SPXW  240315P05100000", dec!(4.00), dec!(4.20))],
+        )
+        .await;
+        let mkt_data = Arc::new(RwLock::new(mkt_data));
+        let order = Orders::build_order_from_meta(
+            &make_spread(),
+            PriceEffect::Debit,
+            OrderIntent::Close,
+            &OrderConfig::default(),
+        )
+        .unwrap();
+
+        // The long leg was never subscribed
+        let midprice =
+            Orders::get_midprice(StrategyType::CreditSpread, "SPX", &mkt_data, &order).await;
+        assert!(matches!(midprice, Err(StrategyError::NoMarketData)));
+
+        let mut short_only = order.clone();
+        short_only.legs.truncate(1);
+        let midprice =
+            Orders::get_midprice(StrategyType::CreditSpread, "SPX", &mkt_data, &short_only).await;
+        assert!(matches!(midprice, Err(StrategyError::NoMarketData)));
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_diagonal_and_strangle_closes_priced() {
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mkt_data = make_mktdata(web_client, &cancel_token);
+        push_quotes(
+            &mkt_data,
+            "SPY",
+            &[
+                ("SPY   240315C00430000", dec!(2.00), dec!(2.20)),
+                ("SPY   240419C00425000", dec!(5.00), dec!(5.20)),
+                ("SPY   240315P00410000", dec!(0.80), dec!(1.00)),
+            ],
+        )
+        .await;
+        let mkt_data = Arc::new(RwLock::new(mkt_data));
+        let make_order = |legs: &[(&str, &str)]| Order {
+            legs: legs
+                .iter()
+                .map(|(symbol, action)| Leg {
+                    instrument_type: OptionType::EquityOption.to_string(),
+                    symbol: symbol.to_string(),
+                    quantity: 1,
+                    action: action.to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        // Selling the dearer long leg makes the diagonal close a credit
+        let diagonal = make_order(&[
+            ("SPY   240419C00425000", "Sell to Close"),
+            ("SPY   240315C00430000", "Buy to Close"),
+        ]);
+        let midprice =
+            Orders::get_midprice(StrategyType::Diagonal, "SPY", &mkt_data, &diagonal).await;
+        assert_eq!(midprice.unwrap(), dec!(-3.00));
+
+        let strangle = make_order(&[
+            ("SPY   240315C00430000", "Buy to Close"),
+            ("SPY   240315P00410000", "Buy to Close"),
+        ]);
+        let midprice =
+            Orders::get_midprice(StrategyType::Strangle, "SPY", &mkt_data, &strangle).await;
+        assert_eq!(midprice.unwrap(), dec!(3.00));
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_price_policy_on_known_book() {
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mkt_data = make_mktdata(web_client, &cancel_token);
+        push_quotes(
+            &mkt_data,
+            "SPX",
+            &[
+                ("SPXW  240315P05100000", dec!(4.00), dec!(4.20)),
+                ("SPXW  240315P05090000", dec!(2.00), dec!(2.20)),
+            ],
+        )
+        .await;
+        let mkt_data = Arc::new(RwLock::new(mkt_data));
+        let build = |price_effect, intent| {
+            Orders::build_order_from_meta(
+                &make_spread(),
+                price_effect,
+                intent,
+                &OrderConfig::default(),
+            )
+            .unwrap()
+        };
+        let close = build(PriceEffect::Debit, OrderIntent::Close);
+        let open = build(PriceEffect::Credit, OrderIntent::Open);
+
+        let touch = Orders::touch_prices(StrategyType::CreditSpread, &mkt_data, &close)
+            .await
+            .unwrap();
+        assert_eq!(touch, (dec!(1.80), dec!(2.20)));
+        let midprice = Orders::get_midprice(StrategyType::CreditSpread, "SPX", &mkt_data, &close)
+            .await
+            .unwrap();
+        assert_eq!(midprice, dec!(2.00));
+
+        // Signs follow the leg actions, so the long leg first nets the same
+        let mut reversed = close.clone();
+        reversed.legs.reverse();
+        let midprice =
+            Orders::get_midprice(StrategyType::CreditSpread, "SPX", &mkt_data, &reversed)
+                .await
+                .unwrap();
+        assert_eq!(midprice, dec!(2.00));
+
+        let price = |policy, order: &Order| {
+            Orders::price_at_touch(policy, Orders::price_effect(order), touch)
+        };
+        assert_eq!(price(PricePolicy::Mid, &close), None);
+        assert_eq!(price(PricePolicy::Aggressive, &close), Some(dec!(2.20)));
+        assert_eq!(price(PricePolicy::Passive, &close), Some(dec!(1.80)));
+        assert_eq!(price(PricePolicy::Aggressive, &open), Some(dec!(1.80)));
+        assert_eq!(price(PricePolicy::Passive, &open), Some(dec!(2.20)));
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_liquidations_place_one_order() {
+        use axum::http::Method;
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        let placed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&placed);
+        // Slow to answer so the second trigger arrives while the first is
+        // still being placed
+        let router = axum::Router::new().fallback(move |method: Method| {
+            let counter = Arc::clone(&counter);
+            async move {
+                if method != Method::POST {
+                    return Err(axum::http::StatusCode::NOT_FOUND);
+                }
+                let id = counter.fetch_add(1, Ordering::SeqCst) as i32 + 1;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(axum::Json(serde_json::json!({
+                    "data": {"order": {"id": id, "status": "Received"}},
+                    "context": "/accounts/5WX01234/orders/dry-run",
+                })))
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mkt_data = make_mktdata(Arc::clone(&web_client), &cancel_token);
+        push_quotes(
+            &mkt_data,
+            "SPX",
+            &[
+                ("SPXW  240315P05100000", dec!(4.00), dec!(4.20)),
+                ("SPXW  240315P05090000", dec!(2.00), dec!(2.20)),
+            ],
+        )
+        .await;
+        // Shared as the stop check and SPX monitor share it, each on its own task
+        let orders = Arc::new(make_orders(web_client, mkt_data));
+
+        let liquidate = || {
+            let orders = Arc::clone(&orders);
+            tokio::spawn(async move {
+                orders
+                    .liquidate_position(
+                        &make_spread(),
+                        PriceEffect::Debit,
+                        ExitReason::StrikeCrossed,
+                        CloseSize::default(),
+                    )
+                    .await
+            })
+        };
+        let (first, second) = tokio::join!(liquidate(), liquidate());
+        first.unwrap().unwrap();
+        second.unwrap().unwrap();
+        cancel_token.cancel();
+
+        assert_eq!(placed.load(Ordering::SeqCst), 1);
+        let tracked = orders.orders.lock().await;
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].order.id, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_stale_quotes_do_not_block_liquidation() {
+        let router = axum::Router::new().fallback(|| async {
+            axum::Json(serde_json::json!({
+                "data": {"order": {"id": 1, "status": "Received"}},
+                "context": "/accounts/5WX01234/orders/dry-run",
+            }))
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mkt_data = make_mktdata(Arc::clone(&web_client), &cancel_token);
+        push_quotes(
+            &mkt_data,
+            "SPX",
+            &[
+                ("SPXW  240315P05100000", dec!(4.00), dec!(4.20)),
+                ("SPXW  240315P05090000", dec!(2.00), dec!(2.20)),
+            ],
+        )
+        .await;
+        // Every quote is older than a zero threshold
+        let orders = Orders {
+            stale_quote_after: Duration::ZERO,
+            ..make_orders(web_client, mkt_data)
+        };
+        let order = Orders::build_order_from_meta(
+            &make_spread(),
+            PriceEffect::Debit,
+            OrderIntent::Close,
+            &OrderConfig::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            Orders::check_quotes_fresh(&orders.mkt_data, &order, Duration::ZERO).await,
+            Err(StrategyError::StaleQuote)
+        ));
+        assert!(
+            Orders::check_quotes_fresh(&orders.mkt_data, &order, Duration::from_secs(30))
+                .await
+                .is_ok()
+        );
+
+        orders
+            .liquidate_position(
+                &make_spread(),
+                PriceEffect::Debit,
+                ExitReason::StrikeCrossed,
+                CloseSize::default(),
+            )
+            .await
+            .unwrap();
+        cancel_token.cancel();
+        assert_eq!(orders.orders.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_long_spread_closes_for_a_credit() {
+        let router = axum::Router::new().fallback(|| async {
+            axum::Json(serde_json::json!({
+                "data": {"order": {"id": 1, "status": "Received"}},
+                "context": "/accounts/5WX01234/orders/dry-run",
+            }))
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mkt_data = make_mktdata(Arc::clone(&web_client), &cancel_token);
+        push_quotes(
+            &mkt_data,
+            "SPX",
+            &[
+                ("SPXW  240315P05100000", dec!(4.00), dec!(4.20)),
+                ("SPXW  240315P05090000", dec!(2.00), dec!(2.20)),
+            ],
+        )
+        .await;
+        let orders = Orders {
+            order_config: OrderConfig {
+                slippage_ticks: 2,
+                ..Default::default()
+            },
+            ..make_orders(web_client, mkt_data)
+        };
+        // Long the dearer put, selling it back takes in more than the short
+        // put costs to buy back
+        let mut spread = make_spread();
+        spread.position.legs[0].direction = Direction::Long;
+        spread.position.legs[1].direction = Direction::Short;
+
+        orders
+            .liquidate_position(
+                &spread,
+                PriceEffect::Debit,
+                ExitReason::StrikeCrossed,
+                CloseSize::default(),
+            )
+            .await
+            .unwrap();
+        cancel_token.cancel();
+
+        let tracked = orders.orders.lock().await;
+        assert_eq!(tracked[0].order.price_effect, "Credit");
+        // Two ticks below the 2.00 mid so it fills sooner
+        assert_eq!(tracked[0].order.price, dec!(1.98));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_only_orders_placed_here() {
+        use axum::http::Method;
+        use axum::http::Uri;
+
+        let deleted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&deleted);
+        let router = axum::Router::new().fallback(move |method: Method, uri: Uri| {
+            let seen = Arc::clone(&seen);
+            async move {
+                if method != Method::DELETE {
+                    return Err(axum::http::StatusCode::NOT_FOUND);
+                }
+                let id: i32 = uri.path().rsplit('/').next().unwrap().parse().unwrap();
+                seen.lock().unwrap().push(id);
+                Ok(axum::Json(serde_json::json!({
+                    "data": {"order": {"id": id, "status": "Cancelled"}},
+                    "context": uri.path(),
+                })))
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mkt_data = make_mktdata(Arc::clone(&web_client), &cancel_token);
+        let seeded = TrackedOrder {
+            placed_here: false,
+            ..make_working_order(2)
+        };
+        let mut orders = Orders {
+            orders: Arc::new(Mutex::new(vec![make_working_order(1), seeded])),
+            ..make_orders(web_client, mkt_data)
+        };
+
+        orders.cancel_all_working().await;
+        assert!(deleted.lock().unwrap().is_empty());
+
+        orders.live_trading = true;
+        orders.cancel_all_working().await;
+        cancel_token.cancel();
+        assert_eq!(*deleted.lock().unwrap(), vec![1]);
+        let tracked = orders.orders.lock().await;
+        assert_eq!(tracked[0].state, OrderState::Cancelled);
+        assert_eq!(tracked[1].state, OrderState::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_on_placement_in_progress() {
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mkt_data = make_mktdata(Arc::clone(&web_client), &cancel_token);
+        let orders = Arc::new(Orders {
+            live_trading: true,
+            ..make_orders(web_client, mkt_data)
+        });
+
+        let guard = orders.lock_underlying("SPX").await;
+        let cancel = tokio::spawn({
+            let orders = Arc::clone(&orders);
+            async move { orders.cancel_all_working().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!cancel.is_finished());
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), cancel)
+            .await
+            .unwrap()
+            .unwrap();
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_startup_seeds_only_our_orders() {
+        let working = |id: i32, source: Option<&str>| {
+            serde_json::json!({
+                "id": id, "status": "Live", "underlying-symbol": "SPX", "source": source,
+                "legs": [{
+                    "instrument-type": "Equity Option", "symbol": "SPXW  240315P05100000",
+                    "quantity": 1, "remaining-quantity": 1, "action": "Buy to Close",
+                }],
+            })
+        };
+        let items = vec![
+            working(1, Some(ORDER_SOURCE)),
+            working(2, Some("WBT")),
+            working(3, None),
+        ];
+        let router = axum::Router::new().fallback(move || {
+            let items = items.clone();
+            async move {
+                axum::Json(serde_json::json!({
+                    "data": {"items": items},
+                    "context": "/accounts/5WX01234/orders/live",
+                }))
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = WebClient::new(&url, cancel_token.clone()).await.unwrap();
+        let seeded = Orders::get_working_orders(&web_client).await;
+        cancel_token.cancel();
+
+        assert_eq!(seeded.len(), 1);
+        assert_eq!(seeded[0].order.id, Some(1));
+        assert!(!seeded[0].placed_here);
+    }
+
+    fn make_order_msg(id: i32, status: &str) -> String {
+        format!(
+            r#"{{"type":"Order","data":{{"id":{},"status":"{}","underlying-symbol":"SPX"}},"timestamp":1}}"#,
+            id, status
+        )
+    }
+
+    #[test]
+    fn test_parse_order_update_ignores_other_payloads() {
+        let msg = r#"{"type":"AccountBalance","data":{"id":1},"timestamp":1}"#;
+        assert!(Orders::parse_order_update(msg).is_none());
+        let update = Orders::parse_order_update(&make_order_msg(7, "Received")).unwrap();
+        assert_eq!(update.id, 7);
+        assert_eq!(update.status, "Received");
+    }
+
+    #[tokio::test]
+    async fn test_order_filled_payload() {
+        let fill = |fill_id: &str, quantity: &str, fill_price: &str| {
+            serde_json::json!({
+                "ext-group-fill-id": "0",
+                "ext-exec-id": format!("{}-exec", fill_id),
+                "fill-id": fill_id,
+                "quantity": quantity,
+                "fill-price": fill_price,
+                "filled-at": "2024-03-15T14:32:05.123+00:00",
+                "destination-venue": "CBOE_EXCHANGE",
+            })
+        };
+        let msg = serde_json::json!({
+            "type": "Order",
+            "data": {
+                "id": 1,
+                "account-number": "5WX01234",
+                "time-in-force": "Day",
+                "order-type": "Limit",
+                "size": 2,
+                "underlying-symbol": "SPX",
+                "underlying-instrument-type": "Index",
+                "price": "1.95",
+                "price-effect": "Debit",
+                "status": "Filled",
+                "cancellable": false,
+                "editable": false,
+                "edited": false,
+                "legs": [
+                    {
+                        "instrument-type": "Equity Option",
+                        "symbol": "SPXW  240315P05100000",
+                        "quantity": 2,
+                        "remaining-quantity": 0,
+                        "action": "Buy to Close",
+                        "fills": [fill("f-1", "1", "4.1"), fill("f-2", "1", "4.2")],
+                    },
+                    {
+                        "instrument-type": "Equity Option",
+                        "symbol": "SPXW  240315P05090000",
+                        "quantity": 2,
+                        "remaining-quantity": 0,
+                        "action": "Sell to Close",
+                        "fills": [fill("f-3", "2", "2.2")],
+                    },
+                ],
+            },
+            "timestamp": 1_710_513_125,
+        })
+        .to_string();
+
+        let update = Orders::parse_order_update(&msg).unwrap();
+        assert_eq!(update.status, "Filled");
+        assert_eq!(update.legs[0].fills.len(), 2);
+        assert_eq!(update.legs[0].fills[1].fill_id, "f-2");
+        assert_eq!(update.legs[0].average_fill_price(), Some(dec!(4.15)));
+        assert_eq!(update.legs[1].average_fill_price(), Some(dec!(2.2)));
+        assert_eq!(update.remaining_quantity(), Some(0));
+
+        let orders = Mutex::new(vec![make_working_order(1)]);
+        Orders::handle_msg(msg, &orders, &Notifications::default()).await;
+        assert_eq!(states(&orders).await, vec![OrderState::Filled]);
+    }
+
+    // Shares what the formatter writes so the test can read it back
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fill_logs_carry_order_fields() {
+        let capture = LogCapture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .compact()
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let msg = serde_json::json!({
+            "type": "Order",
+            "data": {
+                "id": 1,
+                "status": "Filled",
+                "underlying-symbol": "SPX",
+                "legs": [{
+                    "instrument-type": "Equity Option",
+                    "symbol": "SPXW  240315P05100000",
+                    "quantity": 1,
+                    "remaining-quantity": 0,
+                    "action": "Buy to Close",
+                    "fills": [],
+                }],
+            },
+            "timestamp": 1,
+        })
+        .to_string();
+        let orders = Mutex::new(vec![make_working_order(1)]);
+        Orders::handle_msg(msg, &orders, &Notifications::default()).await;
+
+        let logs = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let fill = logs
+            .lines()
+            .find(|line| line.contains("filled at"))
+            .unwrap();
+        assert!(fill.contains("order_id=1"));
+        assert!(fill.contains("underlying=SPX"));
+        assert!(fill.contains("streamer_symbol=SPXW  240315P05100000"));
+    }
+
+    async fn states(orders: &Mutex<Vec<TrackedOrder>>) -> Vec<OrderState> {
+        orders
+            .lock()
+            .await
+            .iter()
+            .map(|tracked| tracked.state)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_order_state_transitions() {
+        let orders = Mutex::new(vec![
+            make_working_order(1),
+            make_working_order(2),
+            make_working_order(3),
+        ]);
+        let notifications = Notifications::default();
+        let update =
+            |id, status| Orders::handle_msg(make_order_msg(id, status), &orders, &notifications);
+
+        update(1, "Received").await;
+        update(2, "Live").await;
+        update(3, "Routed").await;
+        assert_eq!(
+            states(&orders).await,
+            vec![
+                OrderState::Pending,
+                OrderState::Working,
+                OrderState::Pending
+            ]
+        );
+
+        // Statuses that say nothing new leave the state alone
+        update(2, "Unknown").await;
+        update(1, "Filled").await;
+        update(2, "Cancelled").await;
+        update(3, "Rejected").await;
+        assert_eq!(
+            states(&orders).await,
+            vec![
+                OrderState::Filled,
+                OrderState::Cancelled,
+                OrderState::Rejected
+            ]
+        );
+        assert!(orders
+            .lock()
+            .await
+            .iter()
+            .all(|tracked| !tracked.is_in_flight()));
+    }
+
+    struct RecordingNotifier {
+        sender: tokio::sync::mpsc::UnboundedSender<OrderEvent>,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn notify<'a>(&'a self, event: &'a OrderEvent) -> BoxFuture<'a, Result<()>> {
+            self.sender.send(event.clone()).unwrap();
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fill_and_rejection_notified() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let notifications = Notifications::with_notifier(Arc::new(RecordingNotifier { sender }));
+        let orders = Mutex::new(vec![make_working_order(1), make_working_order(2)]);
+
+        Orders::handle_msg(make_order_msg(1, "Routed"), &orders, &notifications).await;
+        Orders::handle_msg(make_order_msg(1, "Filled"), &orders, &notifications).await;
+        Orders::handle_msg(make_order_msg(2, "Rejected"), &orders, &notifications).await;
+
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            OrderEvent::Filled {
+                underlying: String::from("SPX"),
+                order_id: 1
+            }
+        );
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            OrderEvent::Rejected {
+                underlying: String::from("SPX"),
+                reason: String::from("order 2 rejected by the exchange")
+            }
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_liquidation_notified_once_after_placement() {
+        use std::sync::atomic::AtomicI32;
+        use std::sync::atomic::Ordering;
+
+        let next_id = Arc::new(AtomicI32::new(1));
+        let router = axum::Router::new().fallback(move || {
+            let id = next_id.fetch_add(1, Ordering::SeqCst);
+            async move {
+                axum::Json(serde_json::json!({
+                    "data": {"order": {"id": id, "status": "Received"}},
+                    "context": "/accounts/5WX01234/orders/dry-run",
+                }))
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mkt_data = make_mktdata(Arc::clone(&web_client), &cancel_token);
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let orders = Orders {
+            notifications: Notifications::with_notifier(Arc::new(RecordingNotifier { sender })),
+            ..make_orders(web_client, mkt_data)
+        };
+        let spread = make_spread();
+        let liquidate = || {
+            orders.liquidate_position(
+                &spread,
+                PriceEffect::Debit,
+                ExitReason::StrikeCrossed,
+                CloseSize::default(),
+            )
+        };
+
+        // Nothing to price the exit with, nothing is placed or announced
+        liquidate().await.unwrap();
+        assert!(orders.orders.lock().await.is_empty());
+        push_quotes(
+            &*orders.mkt_data.read().await,
+            "SPX",
+            &[
+                ("SPXW  240315P05100000", dec!(4.00), dec!(4.20)),
+                ("SPXW  240315P05090000", dec!(2.00), dec!(2.20)),
+            ],
+        )
+        .await;
+        liquidate().await.unwrap();
+        orders.orders.lock().await[0].state = OrderState::Cancelled;
+        liquidate().await.unwrap();
+        cancel_token.cancel();
+
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            events.push(receiver.recv().await.unwrap());
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(receiver.try_recv().is_err());
+        let triggered = events
+            .iter()
+            .filter(|event| matches!(event, OrderEvent::LiquidationTriggered { .. }))
+            .count();
+        let placed = events
+            .iter()
+            .filter(|event| matches!(event, OrderEvent::Placed { .. }))
+            .count();
+        assert_eq!((triggered, placed), (1, 2));
+    }
+
+    fn make_fill_msg(id: i32, remaining: [i32; 2]) -> String {
+        serde_json::json!({
+            "type": "Order",
+            "data": {
+                "id": id,
+                "status": "Live",
+                "underlying-symbol": "SPX",
+                "legs": [
+                    {
+                        "instrument-type": "Equity Option",
+                        "symbol": "SPXW  240315P05100000",
+                        "quantity": 3,
+                        "remaining-quantity": remaining[0],
+                        "action": "Buy to Close",
+                        "fills": [],
+                    },
+                    {
+                        "instrument-type": "Equity Option",
+                        "symbol": "SPXW  240315P05090000",
+                        "quantity": 3,
+                        "remaining-quantity": remaining[1],
+                        "action": "Sell to Close",
+                        "fills": [],
+                    },
+                ],
+            },
+            "timestamp": 1,
+        })
+        .to_string()
+    }
+
+    fn make_liquidation(id: i32) -> TrackedOrder {
+        let mut order = Orders::build_order_from_meta(
+            &make_spread(),
+            PriceEffect::Credit,
+            OrderIntent::Close,
+            &OrderConfig::default(),
+        )
+        .unwrap();
+        order.id = Some(id);
+        order.legs.iter_mut().for_each(|leg| leg.quantity = 3);
+        TrackedOrder {
+            remaining_quantity: order.quantity(),
+            order,
+            ..make_working_order(id)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partial_fill_cuts_order_to_residual() {
+        let orders = Mutex::new(vec![make_liquidation(1)]);
+        assert_eq!(
+            Orders::handle_msg(make_fill_msg(1, [3, 3]), &orders, &Notifications::default()).await,
+            None
+        );
+
+        assert_eq!(
+            Orders::handle_msg(make_fill_msg(1, [1, 1]), &orders, &Notifications::default()).await,
+            Some(1)
+        );
+        {
+            let orders = orders.lock().await;
+            assert_eq!(orders[0].remaining_quantity, 1);
+            assert!(orders[0].order.legs.iter().all(|leg| leg.quantity == 1));
+        }
+
+        // The same residual reported again is not re-worked twice
+        assert_eq!(
+            Orders::handle_msg(make_fill_msg(1, [1, 1]), &orders, &Notifications::default()).await,
+            None
+        );
+
+        // Nothing left to fill is treated as filled even before the status moves
+        assert_eq!(
+            Orders::handle_msg(make_fill_msg(1, [0, 0]), &orders, &Notifications::default()).await,
+            None
+        );
+        assert_eq!(states(&orders).await, vec![OrderState::Filled]);
+    }
+
+    #[tokio::test]
+    async fn test_partial_fill_on_opening_order_left_alone() {
+        let mut working = make_liquidation(1);
+        working
+            .order
+            .legs
+            .iter_mut()
+            .for_each(|leg| leg.action = leg.action.replace("Close", "Open"));
+        let orders = Mutex::new(vec![working]);
+        assert_eq!(
+            Orders::handle_msg(make_fill_msg(1, [1, 1]), &orders, &Notifications::default()).await,
+            None
+        );
+        assert_eq!(orders.lock().await[0].remaining_quantity, 3);
+    }
+
+    #[tokio::test]
+    async fn test_residual_replace_does_not_hold_orders_lock() {
+        let received = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Notify::new());
+        let router = axum::Router::new().fallback({
+            let (received, release) = (Arc::clone(&received), Arc::clone(&release));
+            move || {
+                let (received, release) = (Arc::clone(&received), Arc::clone(&release));
+                async move {
+                    received.notify_one();
+                    release.notified().await;
+                    axum::Json(serde_json::json!({
+                        "data": {"order": {"id": 2, "status": "Received", "underlying-symbol": "SPX"}},
+                        "context": "/accounts/5WX01234/orders/1",
+                    }))
+                }
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mkt_data = Arc::new(RwLock::new(make_mktdata(
+            Arc::clone(&web_client),
+            &cancel_token,
+        )));
+        let orders = Arc::new(Mutex::new(vec![make_liquidation(1)]));
+        let rework = tokio::spawn({
+            let orders = Arc::clone(&orders);
+            async move {
+                Orders::rework_residual(
+                    1,
+                    &orders,
+                    &mkt_data,
+                    &web_client,
+                    &TickSizeCache::default(),
+                    false,
+                )
+                .await
+            }
+        });
+
+        // The replace is still out, the lock is free meanwhile
+        received.notified().await;
+        drop(
+            tokio::time::timeout(Duration::from_secs(1), orders.lock())
+                .await
+                .unwrap(),
+        );
+        release.notify_one();
+        rework.await.unwrap();
+        cancel_token.cancel();
+
+        let orders = orders.lock().await;
+        assert_eq!(orders[0].order.id, Some(2));
+        assert_eq!(orders[0].state, OrderState::Pending);
+    }
+
+    fn make_chase_config() -> ChaseConfig {
+        ChaseConfig {
+            interval_secs: 5,
+            tick_threshold: dec!(0.05),
+            max_adjustment: dec!(0.20),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chase_steps_to_live_midprice_without_holding_lock() {
+        let received = Arc::new(tokio::sync::Notify::new());
+        let release = Arc::new(tokio::sync::Notify::new());
+        let router = axum::Router::new().fallback({
+            let (received, release) = (Arc::clone(&received), Arc::clone(&release));
+            move || {
+                let (received, release) = (Arc::clone(&received), Arc::clone(&release));
+                async move {
+                    received.notify_one();
+                    release.notified().await;
+                    axum::Json(serde_json::json!({
+                        "data": {"order": {"id": 2, "status": "Received", "underlying-symbol": "SPX"}},
+                        "context": "/accounts/5WX01234/orders/1",
+                    }))
+                }
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(&url, &cancel_token).await;
+        let mkt_data = make_mktdata(Arc::clone(&web_client), &cancel_token);
+        push_quotes(
+            &mkt_data,
+            "SPX",
+            &[
+                ("SPXW  240315P05100000", dec!(4.00), dec!(4.20)),
+                ("SPXW  240315P05090000", dec!(3.00), dec!(3.00)),
+            ],
+        )
+        .await;
+        let mkt_data = Arc::new(RwLock::new(mkt_data));
+        let tick_sizes = TickSizeCache::default();
+        tick_sizes.ticks.lock().await.insert(
+            "SPX".to_string(),
+            (
+                Instant::now(),
+                vec![OptionTick {
+                    threshold: None,
+                    value: dec!(0.05),
+                }],
+            ),
+        );
+        let mut working = make_liquidation(1);
+        working.order.price = dec!(1.00);
+        let orders = Arc::new(Mutex::new(vec![working]));
+        let chase = tokio::spawn({
+            let orders = Arc::clone(&orders);
+            async move {
+                Orders::chase_working_orders(
+                    &orders,
+                    &mkt_data,
+                    &web_client,
+                    &tick_sizes,
+                    &make_chase_config(),
+                    false,
+                )
+                .await
+            }
+        });
+
+        // The replace is still out, the lock is free meanwhile
+        received.notified().await;
+        drop(
+            tokio::time::timeout(Duration::from_secs(1), orders.lock())
+                .await
+                .unwrap(),
+        );
+        release.notify_one();
+        chase.await.unwrap();
+        cancel_token.cancel();
+
+        // Moved to the net mid of 4.10 less 3.00
+        let orders = orders.lock().await;
+        assert_eq!(orders[0].order.id, Some(2));
+        assert_eq!(orders[0].order.price, dec!(1.10));
+        assert!(orders[0].is_chasing);
+    }
+
+    #[test]
+    fn test_chase_ignores_moves_inside_threshold() {
+        let chase = make_chase_config();
+        assert_eq!(
+            Orders::next_chase_price(dec!(1.00), dec!(1.00), dec!(1.03), &chase),
+            None
+        );
+    }
+
+    #[test]
+    fn test_chase_follows_midprice() {
+        let chase = make_chase_config();
+        assert_eq!(
+            Orders::next_chase_price(dec!(1.00), dec!(1.00), dec!(1.10), &chase),
+            Some(dec!(1.10))
+        );
+    }
+
+    #[test]
+    fn test_chase_bounded_by_max_adjustment() {
+        let chase = make_chase_config();
+        assert_eq!(
+            Orders::next_chase_price(dec!(1.10), dec!(1.00), dec!(1.50), &chase),
+            Some(dec!(1.20))
+        );
+        assert_eq!(
+            Orders::next_chase_price(dec!(1.20), dec!(1.00), dec!(1.50), &chase),
+            None
+        );
+    }
+
+    fn make_spx_ticks() -> Vec<OptionTick> {
+        let equity: crate::tt_api::mktdata::Equity = serde_json::from_value(serde_json::json!({
+            "symbol": "SPX",
+            "option-tick-sizes": [
+                {"value": "0.05", "threshold": "3.0"},
+                {"value": "0.1"},
+            ],
+        }))
+        .unwrap();
+        OptionTick::from_tick_sizes(&equity.option_tick_sizes)
+    }
+
+    #[tokio::test]
+    async fn test_tick_sizes_refetched_once_expired() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::atomic::Ordering;
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&lookups);
+        let router = axum::Router::new().fallback(move || {
+            let tick = match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => "0.05",
+                _ => "0.1",
+            };
+            async move {
+                axum::Json(serde_json::json!({
+                    "data": {"symbol": "SPX", "option-tick-sizes": [{"value": tick}]},
+                    "context": "/instruments/equities/SPX",
+                }))
+            }
+        });
+        let url = start_stub_server(router).await;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = WebClient::new(&url, cancel_token.clone()).await.unwrap();
+        let order = Order::default();
+        let mut tick_sizes = TickSizeCache::default();
+        for _ in 0..2 {
+            let tick = tick_sizes.tick(&web_client, "SPX", &order, dec!(1)).await;
+            assert_eq!(tick, dec!(0.05));
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        tick_sizes.ttl = Duration::ZERO;
+        let tick = tick_sizes.tick(&web_client, "SPX", &order, dec!(1)).await;
+        assert_eq!(tick, dec!(0.1));
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        // A failed refresh keeps the expired schedule
+        let unreachable = WebClient::new(UNREACHABLE_URL, cancel_token.clone())
+            .await
+            .unwrap();
+        let tick = tick_sizes.tick(&unreachable, "SPX", &order, dec!(1)).await;
+        assert_eq!(tick, dec!(0.1));
+        cancel_token.cancel();
+    }
+
+    #[test]
+    fn test_tick_for_price_uses_threshold() {
+        let ticks = make_spx_ticks();
+        assert_eq!(Orders::tick_for_price(&ticks, dec!(1.23)), dec!(0.05));
+        assert_eq!(Orders::tick_for_price(&ticks, dec!(3.00)), dec!(0.1));
+        assert_eq!(Orders::tick_for_price(&ticks, dec!(12.34)), dec!(0.1));
+        assert_eq!(Orders::tick_for_price(&[], dec!(1.234)), DEFAULT_TICK);
+    }
+
+    #[test]
+    fn test_spx_price_rounded_in_traders_favour() {
+        let tick = Orders::tick_for_price(&make_spx_ticks(), dec!(1.23));
+        assert_eq!(
+            Orders::round_to_tick(dec!(1.23), tick, PriceEffect::Debit, false),
+            dec!(1.2)
+        );
+        assert_eq!(
+            Orders::round_to_tick(dec!(1.23), tick, PriceEffect::Credit, false),
+            dec!(1.25)
+        );
+        // Prices already on a tick are left alone
+        assert_eq!(
+            Orders::round_to_tick(dec!(1.25), tick, PriceEffect::Debit, false),
+            dec!(1.25)
+        );
+        assert_eq!(
+            Orders::round_to_tick(dec!(1.2375), tick, PriceEffect::Credit, false),
+            dec!(1.25)
+        );
+        // Liquidations round toward the far side of the book
+        assert_eq!(
+            Orders::round_to_tick(dec!(1.23), tick, PriceEffect::Debit, true),
+            dec!(1.25)
+        );
+        assert_eq!(
+            Orders::round_to_tick(dec!(1.23), tick, PriceEffect::Credit, true),
+            dec!(1.2)
+        );
+    }
+
+    #[test]
+    fn test_liquidation_slippage_direction() {
+        let tick = dec!(0.05);
+        // Closing a short spread is a debit, more is paid
+        assert_eq!(
+            Orders::slippage(dec!(1.20), tick, PriceEffect::Debit, 2),
+            dec!(0.10)
+        );
+        // Closing a long spread is a credit, less is taken
+        assert_eq!(
+            Orders::slippage(dec!(1.20), tick, PriceEffect::Credit, 2),
+            dec!(-0.10)
+        );
+        assert_eq!(
+            Orders::slippage(dec!(0.10), tick, PriceEffect::Credit, 3),
+            dec!(-0.05)
+        );
+        assert_eq!(
+            Orders::slippage(dec!(1.20), tick, PriceEffect::Debit, 0),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_orders_default_to_dry_run() {
+        let path = String::from("accounts/5WT0001/orders");
+        assert_eq!(
+            Orders::order_endpoint(path.clone(), false),
+            "accounts/5WT0001/orders/dry-run"
+        );
+        assert_eq!(
+            Orders::order_endpoint(path, true),
+            "accounts/5WT0001/orders"
+        );
     }
 }
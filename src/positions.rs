@@ -3,18 +3,27 @@ use anyhow::bail;
 use anyhow::Result;
 use chrono::NaiveDate;
 use rust_decimal::Decimal;
+use std::collections::HashMap;
 use std::fmt;
 use std::str::FromStr;
 
+use crate::mktdata::MktData;
+use crate::tt_api::mktdata::FutureOption;
+use crate::tt_api::mktdata::Greeks;
+use crate::tt_api::mktdata::Quote;
 use crate::tt_api::positions::*;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StrategyType {
     Call,
     Put,
     CreditSpread,
     IronCondor,
+    IronButterfly,
     CalendarSpread,
+    Diagonal,
+    Strangle,
+    Straddle,
     Other,
 }
 
@@ -112,12 +121,48 @@ impl OptionType {
     }
 }
 
-fn parse_future_option(
-    symbol: &str,
-    underlying: &str,
-    direction: &str,
-    quantity: i32,
-) -> Result<OptionLeg> {
+const EQUITY_OPTION_MULTIPLIER: i32 = 100;
+
+// Index options that settle in cash at expiry rather than deliver shares
+const CASH_SETTLED_UNDERLYINGS: [&str; 9] = [
+    "SPX", "XSP", "NDX", "NDXP", "RUT", "VIX", "DJX", "OEX", "XEO",
+];
+
+fn future_option_multiplier(leg: &Leg, instrument: Option<&FutureOption>) -> Result<Decimal> {
+    if let Some(multiplier) = leg.multiplier {
+        return Ok(Decimal::from(multiplier));
+    }
+
+    let derived = instrument.and_then(|instrument| {
+        instrument
+            .notional_value?
+            .checked_div(instrument.strike_factor?)
+    });
+    match derived {
+        Some(multiplier) => Ok(multiplier),
+        None => bail!(
+            "Failed to determine multiplier for future option symbol: {}",
+            leg.symbol
+        ),
+    }
+}
+
+// The strike in a future option symbol is quoted in the product's own units, the
+// strike factor converts it into the price units of the underlying future. The
+// display factor only applies to quoted prices so takes no part here
+fn future_option_strike(raw_strike: &str, instrument: Option<&FutureOption>) -> Result<Decimal> {
+    let raw_strike = match Decimal::from_str(raw_strike) {
+        Ok(val) => val,
+        Err(err) => bail!("Failed to parse strike: {}, error: {}", raw_strike, err),
+    };
+    let strike_factor = instrument
+        .and_then(|instrument| instrument.strike_factor)
+        .unwrap_or(Decimal::ONE);
+    Ok((raw_strike * strike_factor).normalize())
+}
+
+fn parse_future_option(leg: &Leg, instrument: Option<&FutureOption>) -> Result<OptionLeg> {
+    let symbol = leg.symbol.as_str();
     if symbol.len() < 20 || !symbol.starts_with("./") {
         bail!(
             "Invalid format whilst parsing future option symbol: {} len: {}",
@@ -141,27 +186,25 @@ fn parse_future_option(
     };
     let option_type = OptionSide::parse(parts[2].chars().nth(6).unwrap());
 
-    //TODO fix the strike price
-    let strike_price = Decimal::from_str(&parts[2][7..])?;
+    let strike_price = future_option_strike(&parts[2][7..], instrument)?;
 
     Ok(OptionLeg {
         symbol: symbol.to_string(),
-        underlying: underlying.to_string(),
+        underlying: leg.underlying_symbol.clone().unwrap_or_default(),
         expiration_date,
-        direction: Direction::parse(direction),
+        direction: Direction::parse(leg.quantity_direction.as_deref().unwrap_or_default()),
         side: option_type,
         strike_price,
-        quantity,
+        quantity: leg.quantity,
+        multiplier: future_option_multiplier(leg, instrument)?,
+        average_open_price: leg.average_open_price,
+        mark_price: leg.mark_price,
         option_type: OptionType::FutureOption,
     })
 }
 
-fn parse_equity_option(
-    symbol: &str,
-    underlying: &str,
-    direction: &str,
-    quantity: i32,
-) -> Result<OptionLeg> {
+fn parse_equity_option(leg: &Leg) -> Result<OptionLeg> {
+    let symbol = leg.symbol.as_str();
     if symbol.len() != 21 {
         bail!(
             "Invalid format whilst parsing equity option symbol: {}, len: {}",
@@ -176,17 +219,23 @@ fn parse_equity_option(
     };
     let option_type = OptionSide::parse(symbol.chars().nth(12).unwrap());
 
-    let strike_price = Decimal::from_str(symbol[13..].trim_start_matches('0'))?;
-    let strike_price = strike_price / Decimal::new(1000, 0);
+    // The strike is a fixed width field of eight digits in thousandths of a dollar
+    let strike_price = match symbol[13..].parse::<i64>() {
+        Ok(val) => Decimal::new(val, 3),
+        Err(err) => bail!("Failed to parse strike: {}, error: {}", &symbol[13..], err),
+    };
 
     Ok(OptionLeg {
         symbol: symbol.to_string(),
-        underlying: underlying.to_string(),
+        underlying: leg.underlying_symbol.clone().unwrap_or_default(),
         expiration_date,
-        direction: Direction::parse(direction),
+        direction: Direction::parse(leg.quantity_direction.as_deref().unwrap_or_default()),
         side: option_type,
         strike_price,
-        quantity,
+        quantity: leg.quantity,
+        multiplier: Decimal::from(leg.multiplier.unwrap_or(EQUITY_OPTION_MULTIPLIER)),
+        average_open_price: leg.average_open_price,
+        mark_price: leg.mark_price,
         option_type: OptionType::EquityOption,
     })
 }
@@ -200,9 +249,30 @@ pub(crate) struct OptionLeg {
     pub side: OptionSide,
     pub strike_price: Decimal,
     pub quantity: i32,
+    pub multiplier: Decimal,
+    pub average_open_price: Option<Decimal>,
+    // The broker's mark as of the last positions pull, what its UI values
+    // the leg at
+    pub mark_price: Option<Decimal>,
     pub option_type: OptionType,
 }
 
+impl OptionLeg {
+    // Profit or loss of the leg if it were closed at the given mark
+    pub fn unrealized_pnl(&self, mark: Decimal) -> Option<Decimal> {
+        let sign = match self.direction {
+            Direction::Long => Decimal::ONE,
+            Direction::Short => Decimal::NEGATIVE_ONE,
+        };
+        let entry_price = self.average_open_price?;
+        Some((mark - entry_price) * Decimal::from(self.quantity) * self.multiplier * sign)
+    }
+
+    pub fn is_cash_settled(&self) -> bool {
+        CASH_SETTLED_UNDERLYINGS.contains(&self.underlying.as_str())
+    }
+}
+
 impl fmt::Display for OptionLeg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let fmt = format!(
@@ -218,6 +288,29 @@ pub struct Position {
     pub strategy_type: StrategyType,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum PositionChange {
+    Added(String),
+    Removed(String),
+    Reduced { key: String, from: i32, to: i32 },
+    Increased { key: String, from: i32, to: i32 },
+}
+
+impl fmt::Display for PositionChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PositionChange::Added(key) => write!(f, "added {}", key),
+            PositionChange::Removed(key) => write!(f, "removed {}", key),
+            PositionChange::Reduced { key, from, to } => {
+                write!(f, "reduced {} from {} to {}", key, from, to)
+            }
+            PositionChange::Increased { key, from, to } => {
+                write!(f, "increased {} from {} to {}", key, from, to)
+            }
+        }
+    }
+}
+
 impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let leg_strings: Vec<String> = self.legs.iter().map(|leg| format!("{}", leg)).collect();
@@ -227,75 +320,893 @@ impl fmt::Display for Position {
 }
 
 impl Position {
-    pub fn new(legs: Vec<Leg>) -> Position {
-        let mut symbols = Self::parse_complex_symbols(&legs);
-        let strategy_type = Self::determine_strategy(&symbols, &legs);
+    // Identifies a position across refreshes by underlying, expiry and
+    // strikes, so a change in quantity is not mistaken for a new position
+    pub fn signature(&self) -> String {
+        let mut strikes: Vec<String> = self
+            .legs
+            .iter()
+            .map(|leg| {
+                format!(
+                    "{} {}{}",
+                    leg.expiration_date,
+                    leg.strike_price.normalize(),
+                    leg.side
+                )
+            })
+            .collect();
+        strikes.sort();
+        let underlying = self
+            .legs
+            .first()
+            .map(|leg| leg.underlying.as_str())
+            .unwrap_or_default();
+        format!("{} [{}]", underlying, strikes.join(", "))
+    }
+
+    pub fn find_leg(&self, side: OptionSide, direction: Direction) -> Option<&OptionLeg> {
+        self.legs
+            .iter()
+            .find(|leg| leg.side == side && leg.direction == direction)
+    }
+
+    // Whole units of the strategy held, a 2 lot vertical is 2 rather than
+    // the 4 contracts across its legs
+    pub fn quantity(&self) -> i32 {
+        self.legs
+            .iter()
+            .map(|leg| leg.quantity.abs())
+            .min()
+            .unwrap_or_default()
+    }
+
+    pub fn reconcile(previous: &[&Position], current: &[&Position]) -> Vec<PositionChange> {
+        let previous: HashMap<String, i32> = previous
+            .iter()
+            .map(|position| (position.signature(), position.quantity()))
+            .collect();
+        let current: HashMap<String, i32> = current
+            .iter()
+            .map(|position| (position.signature(), position.quantity()))
+            .collect();
+
+        let mut changes: Vec<PositionChange> = current
+            .iter()
+            .filter_map(|(key, &to)| match previous.get(key) {
+                None => Some(PositionChange::Added(key.clone())),
+                Some(&from) if to < from => Some(PositionChange::Reduced {
+                    key: key.clone(),
+                    from,
+                    to,
+                }),
+                Some(&from) if to > from => Some(PositionChange::Increased {
+                    key: key.clone(),
+                    from,
+                    to,
+                }),
+                Some(_) => None,
+            })
+            .collect();
+        changes.extend(
+            previous
+                .keys()
+                .filter(|key| !current.contains_key(*key))
+                .map(|key| PositionChange::Removed(key.clone())),
+        );
+        changes.sort_by_key(|change| change.to_string());
+        changes
+    }
+
+    // A leg that fails to parse rejects the whole position, managing what is
+    // left of it would misjudge its risk
+    pub fn new(legs: Vec<Leg>, instruments: &HashMap<String, FutureOption>) -> Result<Position> {
+        let mut symbols = Self::parse_complex_symbols(&legs, instruments)?;
+        let strategy_type = classify(&symbols);
+        // Calls ahead of puts, each by strike descending
         symbols.sort_by(|a, b| {
-            b.strike_price
-                .partial_cmp(&a.strike_price)
-                .unwrap_or(std::cmp::Ordering::Equal)
+            (a.side == OptionSide::Put)
+                .cmp(&(b.side == OptionSide::Put))
+                .then_with(|| {
+                    b.strike_price
+                        .partial_cmp(&a.strike_price)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
         });
-        Self {
+        Ok(Self {
             legs: symbols,
             strategy_type,
-        }
+        })
     }
 
-    fn parse_complex_symbols(legs: &[Leg]) -> Vec<OptionLeg> {
-        fn unsupported_option_type(_: &str, _: &str, _: &str, _: i32) -> Result<OptionLeg> {
-            Err(anyhow!("Unsupported option type"))
+    // The sold and bought legs of a two leg spread, picked by direction so
+    // neither the sort above nor a strike tie decides which is which
+    pub fn short_leg(&self) -> Option<&OptionLeg> {
+        self.spread_leg(Direction::Short)
+    }
+
+    pub fn long_leg(&self) -> Option<&OptionLeg> {
+        self.spread_leg(Direction::Long)
+    }
+
+    fn spread_leg(&self, direction: Direction) -> Option<&OptionLeg> {
+        match self.legs.as_slice() {
+            [first, second] if first.direction != second.direction => [first, second]
+                .into_iter()
+                .find(|leg| leg.direction == direction),
+            _ => None,
         }
+    }
 
-        let symbols: Vec<OptionLeg> = legs
-            .iter()
-            .filter_map(|leg| {
-                let parser = match OptionType::get_symbol_type(
-                    leg.instrument_type.as_ref().unwrap().as_str(),
-                ) {
-                    OptionType::EquityOption => parse_equity_option,
-                    OptionType::FutureOption => parse_future_option,
-                    _ => unsupported_option_type,
-                };
-                parser(
-                    &leg.symbol,
-                    leg.underlying_symbol.as_ref().unwrap().as_str(),
-                    leg.quantity_direction.as_ref().unwrap(),
-                    leg.quantity,
-                )
-                .ok()
+    // Net price the position was opened at, positive for a credit and
+    // negative for a debit
+    pub fn entry_price(&self) -> Option<Decimal> {
+        self.legs.iter().try_fold(Decimal::ZERO, |acc, leg| {
+            let price = leg.average_open_price?;
+            Some(match leg.direction {
+                Direction::Short => acc + price,
+                Direction::Long => acc - price,
             })
-            .collect();
+        })
+    }
+
+    // Defined risk positions hold the widest wing less the credit taken in,
+    // naked shorts are approximated at a fifth of the strike notional
+    pub fn estimated_margin(&self) -> Decimal {
+        let Some(first) = self.legs.first() else {
+            return Decimal::ZERO;
+        };
+        let units = Decimal::from(
+            self.legs
+                .iter()
+                .map(|leg| leg.quantity.abs())
+                .min()
+                .unwrap_or_default(),
+        );
+        let credit = self.entry_price().unwrap_or_default();
+        let per_unit = match self.strategy_type {
+            _ if credit.is_sign_negative() => -credit,
+            StrategyType::CreditSpread | StrategyType::IronCondor | StrategyType::IronButterfly => {
+                let width = |side: OptionSide| {
+                    let strikes = self
+                        .legs
+                        .iter()
+                        .filter(|leg| leg.side == side)
+                        .map(|leg| leg.strike_price);
+                    strikes.clone().max().unwrap_or_default() - strikes.min().unwrap_or_default()
+                };
+                width(OptionSide::Call).max(width(OptionSide::Put)) - credit
+            }
+            StrategyType::Call | StrategyType::Put if first.direction == Direction::Short => {
+                first.strike_price * Decimal::new(2, 1) - credit
+            }
+            _ => credit,
+        };
+        per_unit.max(Decimal::ZERO) * first.multiplier * units
+    }
 
-        symbols
+    // Net price to close the position at current mids, on the same sign
+    // convention as the entry price
+    pub async fn close_price(&self, mktdata: &MktData) -> Option<Decimal> {
+        let mut price = Decimal::ZERO;
+        for leg in &self.legs {
+            let snapshot = mktdata.get_snapshot_by_symbol::<Quote>(&leg.symbol).await?;
+            let mark = snapshot.midprice()?;
+            match leg.direction {
+                Direction::Short => price += mark,
+                Direction::Long => price -= mark,
+            }
+        }
+        Some(price)
     }
 
-    fn determine_strategy(symbols: &[OptionLeg], legs: &[Leg]) -> StrategyType {
-        match legs.len() {
-            1 => Self::single_leg_strategies(symbols),
-            2 => Self::double_leg_strategies(symbols),
-            4 => StrategyType::IronCondor,
-            _ => StrategyType::Other,
+    // Valued at the broker's mark so the figure agrees with its UI, the feed
+    // midprice stands in for a leg the broker sent no mark for
+    pub async fn unrealized_pnl(&self, mktdata: &MktData) -> Option<Decimal> {
+        let mut pnl = Decimal::ZERO;
+        for leg in &self.legs {
+            let mark = match leg.mark_price {
+                Some(mark) => mark,
+                None => mktdata
+                    .get_snapshot_by_symbol::<Quote>(&leg.symbol)
+                    .await?
+                    .midprice()?,
+            };
+            pnl += leg.unrealized_pnl(mark)?;
         }
+        Some(pnl)
     }
 
-    fn single_leg_strategies(symbols: &[OptionLeg]) -> StrategyType {
-        match symbols[0].side {
+    // Net theta across the legs, positive while time decay is working for
+    // the position
+    pub async fn net_theta(&self, mktdata: &MktData) -> Option<f64> {
+        let mut theta = 0.;
+        for leg in &self.legs {
+            let snapshot = mktdata
+                .get_snapshot_by_symbol::<Greeks>(&leg.symbol)
+                .await?;
+            let sign = match leg.direction {
+                Direction::Long => 1.,
+                Direction::Short => -1.,
+            };
+            theta += snapshot.greeks?.theta * f64::from(leg.quantity) * sign;
+        }
+        Some(theta)
+    }
+
+    fn parse_complex_symbols(
+        legs: &[Leg],
+        instruments: &HashMap<String, FutureOption>,
+    ) -> Result<Vec<OptionLeg>> {
+        legs.iter()
+            .map(|leg| {
+                match OptionType::get_symbol_type(leg.instrument_type.as_ref().unwrap().as_str()) {
+                    OptionType::EquityOption => parse_equity_option(leg),
+                    OptionType::FutureOption => {
+                        parse_future_option(leg, instruments.get(&leg.symbol))
+                    }
+                    _ => Err(anyhow!("Unsupported option type for leg: {}", leg.symbol)),
+                }
+            })
+            .collect()
+    }
+}
+
+// Names the shape of a position from its legs alone. Legs of unequal size
+// are ratio spreads and anything unrecognised is Other
+pub(crate) fn classify(legs: &[OptionLeg]) -> StrategyType {
+    if legs
+        .iter()
+        .any(|leg| leg.quantity.abs() != legs[0].quantity.abs())
+    {
+        return StrategyType::Other;
+    }
+    match legs {
+        [leg] => match leg.side {
             OptionSide::Call => StrategyType::Call,
             OptionSide::Put => StrategyType::Put,
+        },
+        [first, second] => classify_pair(first, second),
+        [_, _, _, _] => classify_four(legs),
+        _ => StrategyType::Other,
+    }
+}
+
+fn classify_pair(first: &OptionLeg, second: &OptionLeg) -> StrategyType {
+    let same_expiry = first.expiration_date == second.expiration_date;
+    let same_strike = first.strike_price == second.strike_price;
+    let same_direction = first.direction == second.direction;
+    match (first.side == second.side, same_direction, same_expiry) {
+        // One bought against one sold on the same side
+        (true, false, true) if !same_strike => StrategyType::CreditSpread,
+        (true, false, false) if same_strike => StrategyType::CalendarSpread,
+        (true, false, false) => StrategyType::Diagonal,
+        // A call and a put both bought or both sold
+        (false, true, true) if same_strike => StrategyType::Straddle,
+        (false, true, true) => StrategyType::Strangle,
+        _ => StrategyType::Other,
+    }
+}
+
+// A call spread and a put spread on one expiry, a butterfly sells both at the
+// same body strike
+fn classify_four(legs: &[OptionLeg]) -> StrategyType {
+    let find = |side: OptionSide, direction: Direction| {
+        let mut matching = legs
+            .iter()
+            .filter(|leg| leg.side == side && leg.direction == direction);
+        match (matching.next(), matching.next()) {
+            (Some(leg), None) => Some(leg),
+            _ => None,
+        }
+    };
+    let (Some(short_call), Some(long_call), Some(short_put), Some(long_put)) = (
+        find(OptionSide::Call, Direction::Short),
+        find(OptionSide::Call, Direction::Long),
+        find(OptionSide::Put, Direction::Short),
+        find(OptionSide::Put, Direction::Long),
+    ) else {
+        return StrategyType::Other;
+    };
+    if legs
+        .iter()
+        .any(|leg| leg.expiration_date != legs[0].expiration_date)
+    {
+        return StrategyType::Other;
+    }
+    // Both wings sit outside the short strikes
+    if long_call.strike_price <= short_call.strike_price
+        || long_put.strike_price >= short_put.strike_price
+        || short_put.strike_price > short_call.strike_price
+    {
+        return StrategyType::Other;
+    }
+    match short_call.strike_price == short_put.strike_price {
+        true => StrategyType::IronButterfly,
+        false => StrategyType::IronCondor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::make_mktdata;
+    use crate::utils::fixtures::make_web_client;
+    use crate::utils::fixtures::push_quotes;
+    use crate::utils::fixtures::UNREACHABLE_URL;
+
+    fn make_leg(symbol: &str, instrument_type: &str, multiplier: Option<i32>) -> Leg {
+        serde_json::from_value(serde_json::json!({
+            "symbol": symbol,
+            "instrument-type": instrument_type,
+            "underlying-symbol": "SPY",
+            "quantity": 1,
+            "quantity-direction": "Short",
+            "multiplier": multiplier,
+            "is-frozen": false,
+            "is-suppressed": false,
+        }))
+        .unwrap()
+    }
+
+    fn make_priced_leg(symbol: &str, direction: &str, average_open_price: &str) -> Leg {
+        serde_json::from_value(serde_json::json!({
+            "symbol": symbol,
+            "instrument-type": "Equity Option",
+            "underlying-symbol": "SPY",
+            "quantity": 1,
+            "quantity-direction": direction,
+            "average-open-price": average_open_price,
+            "is-frozen": false,
+            "is-suppressed": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_entry_price_nets_leg_directions() {
+        let legs = vec![
+            make_priced_leg("SPY   240315P00420000", "Short", "2.50"),
+            make_priced_leg("SPY   240315P00415000", "Long", "1.25"),
+        ];
+        let position = Position::new(legs, &HashMap::new()).unwrap();
+        assert_eq!(position.entry_price(), Some(Decimal::new(125, 2)));
+    }
+
+    fn make_spread(short_strike: &str, long_strike: &str, quantity: i32) -> Position {
+        let legs = vec![
+            make_priced_leg(
+                &format!("SPY   240315P00{}000", short_strike),
+                "Short",
+                "2.50",
+            ),
+            make_priced_leg(
+                &format!("SPY   240315P00{}000", long_strike),
+                "Long",
+                "1.25",
+            ),
+        ];
+        let mut position = Position::new(legs, &HashMap::new()).unwrap();
+        position
+            .legs
+            .iter_mut()
+            .for_each(|leg| leg.quantity = quantity);
+        position
+    }
+
+    #[tokio::test]
+    async fn test_unrealized_pnl_prefers_broker_mark() {
+        let cancel_token = tokio_util::sync::CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mktdata = make_mktdata(web_client, &cancel_token);
+        push_quotes(
+            &mktdata,
+            "SPY",
+            &[
+                (
+                    "SPY   240315P00420000",
+                    Decimal::new(190, 2),
+                    Decimal::new(210, 2),
+                ),
+                (
+                    "SPY   240315P00415000",
+                    Decimal::new(90, 2),
+                    Decimal::new(110, 2),
+                ),
+            ],
+        )
+        .await;
+
+        // Mids of 2.00 and 1.00 against entries of 2.50 and 1.25
+        let mut position = make_spread("420", "415", 1);
+        assert_eq!(
+            position.unrealized_pnl(&mktdata).await,
+            Some(Decimal::new(25, 0))
+        );
+
+        // Marks of 1.80 and 0.95 replace the mids, the long leg falls back
+        // to its mid once the broker sends no mark for it
+        position.legs[0].mark_price = Some(Decimal::new(180, 2));
+        position.legs[1].mark_price = Some(Decimal::new(95, 2));
+        assert_eq!(
+            position.unrealized_pnl(&mktdata).await,
+            Some(Decimal::new(40, 0))
+        );
+        position.legs[1].mark_price = None;
+        assert_eq!(
+            position.unrealized_pnl(&mktdata).await,
+            Some(Decimal::new(45, 0))
+        );
+    }
+
+    #[test]
+    fn test_estimated_margin_for_spx_put_spread() {
+        let legs = vec![
+            make_priced_leg("SPXW  240315P05100000", "Short", "2.50"),
+            make_priced_leg("SPXW  240315P05095000", "Long", "1.25"),
+        ];
+        let position = Position::new(legs, &HashMap::new()).unwrap();
+        // 5 wide less 1.25 credit on a 100 multiplier
+        assert_eq!(position.estimated_margin(), Decimal::from(375));
+
+        let mut position = position;
+        position.legs.iter_mut().for_each(|leg| leg.quantity = 2);
+        assert_eq!(position.estimated_margin(), Decimal::from(750));
+    }
+
+    #[test]
+    fn test_reconcile_reports_changes() {
+        let kept = make_spread("420", "415", 2);
+        let closed = make_spread("410", "405", 1);
+        let opened = make_spread("400", "395", 1);
+        let reduced = make_spread("420", "415", 1);
+
+        let changes = Position::reconcile(&[&kept, &closed], &[&reduced, &opened]);
+
+        assert_eq!(
+            changes,
+            vec![
+                PositionChange::Added(opened.signature()),
+                PositionChange::Reduced {
+                    key: kept.signature(),
+                    from: 2,
+                    to: 1
+                },
+                PositionChange::Removed(closed.signature()),
+            ]
+        );
+        assert!(Position::reconcile(&[&kept], &[&kept]).is_empty());
+
+        // The same strikes in another expiry are another position
+        let mut rolled = make_spread("420", "415", 2);
+        rolled
+            .legs
+            .iter_mut()
+            .for_each(|leg| leg.expiration_date = NaiveDate::from_ymd_opt(2024, 3, 22).unwrap());
+        assert_ne!(rolled.signature(), kept.signature());
+        assert_eq!(
+            Position::reconcile(&[&kept], &[&rolled]),
+            vec![
+                PositionChange::Added(rolled.signature()),
+                PositionChange::Removed(kept.signature()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_iron_condor_legs_in_any_order() {
+        let legs = vec![
+            make_priced_leg("SPY   240315P00415000", "Short", "1.10"),
+            make_priced_leg("SPY   240315C00430000", "Long", "0.50"),
+            make_priced_leg("SPY   240315P00410000", "Long", "0.60"),
+            make_priced_leg("SPY   240315C00425000", "Short", "1.00"),
+        ];
+        let position = Position::new(legs, &HashMap::new()).unwrap();
+        assert!(matches!(position.strategy_type, StrategyType::IronCondor));
+
+        let strike = |side, direction| {
+            position
+                .find_leg(side, direction)
+                .map(|leg| leg.strike_price)
+                .unwrap()
+        };
+        assert_eq!(
+            strike(OptionSide::Call, Direction::Long),
+            Decimal::from(430)
+        );
+        assert_eq!(
+            strike(OptionSide::Call, Direction::Short),
+            Decimal::from(425)
+        );
+        assert_eq!(
+            strike(OptionSide::Put, Direction::Short),
+            Decimal::from(415)
+        );
+        assert_eq!(strike(OptionSide::Put, Direction::Long), Decimal::from(410));
+
+        let sides: Vec<OptionSide> = position.legs.iter().map(|leg| leg.side).collect();
+        assert_eq!(
+            sides,
+            vec![
+                OptionSide::Call,
+                OptionSide::Call,
+                OptionSide::Put,
+                OptionSide::Put
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spread_legs_by_direction() {
+        let sorted = make_spread("420", "415", 1);
+        let mut reversed = make_spread("420", "415", 1);
+        reversed.legs.reverse();
+        for position in [&sorted, &reversed] {
+            assert_eq!(
+                position.short_leg().unwrap().symbol,
+                "SPY   240315P00420000"
+            );
+            assert_eq!(position.long_leg().unwrap().symbol, "SPY   240315P00415000");
         }
+
+        // A call spread sorts its long leg first and is still told apart
+        let legs = vec![
+            make_priced_leg("SPY   240315C00425000", "Short", "2.00"),
+            make_priced_leg("SPY   240315C00430000", "Long", "1.00"),
+        ];
+        let position = Position::new(legs, &HashMap::new()).unwrap();
+        assert_eq!(position.legs[0].direction, Direction::Long);
+        assert_eq!(
+            position.short_leg().unwrap().strike_price,
+            Decimal::from(425)
+        );
+        assert_eq!(
+            position.long_leg().unwrap().strike_price,
+            Decimal::from(430)
+        );
+
+        // Two legs on the same side of the book are not a spread
+        let legs = vec![
+            make_priced_leg("SPY   240315P00420000", "Short", "2.50"),
+            make_priced_leg("SPY   240315P00415000", "Short", "1.25"),
+        ];
+        assert!(Position::new(legs, &HashMap::new())
+            .unwrap()
+            .short_leg()
+            .is_none());
     }
 
-    fn double_leg_strategies(symbols: &[OptionLeg]) -> StrategyType {
-        let leg1 = &symbols[0];
-        let leg2 = &symbols[1];
+    #[test]
+    fn test_iron_butterfly_classification() {
+        let legs = vec![
+            make_priced_leg("SPY   240315C00430000", "Long", "0.50"),
+            make_priced_leg("SPY   240315C00420000", "Short", "3.00"),
+            make_priced_leg("SPY   240315P00420000", "Short", "3.10"),
+            make_priced_leg("SPY   240315P00410000", "Long", "0.60"),
+        ];
+        let position = Position::new(legs, &HashMap::new()).unwrap();
+        assert!(matches!(
+            position.strategy_type,
+            StrategyType::IronButterfly
+        ));
+
+        let legs = vec![
+            make_priced_leg("SPY   240315C00430000", "Long", "0.50"),
+            make_priced_leg("SPY   240315C00425000", "Short", "1.00"),
+            make_priced_leg("SPY   240315P00415000", "Short", "1.10"),
+            make_priced_leg("SPY   240315P00410000", "Long", "0.60"),
+        ];
+        let position = Position::new(legs, &HashMap::new()).unwrap();
+        assert!(matches!(position.strategy_type, StrategyType::IronCondor));
+    }
 
-        if leg1.expiration_date == leg2.expiration_date {
-            return StrategyType::CreditSpread;
+    fn option_leg(
+        side: OptionSide,
+        direction: Direction,
+        strike: i64,
+        expiry: u32,
+        quantity: i32,
+    ) -> OptionLeg {
+        OptionLeg {
+            symbol: format!("SPY {} {:?} {} {:?}", expiry, side, strike, direction),
+            underlying: String::from("SPY"),
+            expiration_date: NaiveDate::from_ymd_opt(2024, 3, expiry).unwrap(),
+            direction,
+            side,
+            strike_price: Decimal::from(strike),
+            quantity,
+            multiplier: Decimal::from(100),
+            average_open_price: None,
+            mark_price: None,
+            option_type: OptionType::EquityOption,
         }
+    }
 
-        if leg1.strike_price == leg2.strike_price {
-            return StrategyType::CalendarSpread;
+    #[test]
+    fn test_classify_single_legs() {
+        use Direction::*;
+        use OptionSide::*;
+        for direction in [Long, Short] {
+            assert_eq!(
+                classify(&[option_leg(Call, direction, 420, 15, 1)]),
+                StrategyType::Call
+            );
+            assert_eq!(
+                classify(&[option_leg(Put, direction, 420, 15, 1)]),
+                StrategyType::Put
+            );
         }
-        StrategyType::Other
+        assert_eq!(classify(&[]), StrategyType::Other);
+    }
+
+    #[test]
+    fn test_classify_two_leg_strategies() {
+        use Direction::*;
+        use OptionSide::*;
+        let cases = [
+            (
+                "put credit spread",
+                [(Put, Short, 420, 15), (Put, Long, 415, 15)],
+                StrategyType::CreditSpread,
+            ),
+            (
+                "call credit spread",
+                [(Call, Short, 430, 15), (Call, Long, 435, 15)],
+                StrategyType::CreditSpread,
+            ),
+            (
+                "call debit spread",
+                [(Call, Long, 430, 15), (Call, Short, 435, 15)],
+                StrategyType::CreditSpread,
+            ),
+            (
+                "put calendar",
+                [(Put, Short, 420, 15), (Put, Long, 420, 22)],
+                StrategyType::CalendarSpread,
+            ),
+            (
+                "call calendar",
+                [(Call, Long, 430, 22), (Call, Short, 430, 15)],
+                StrategyType::CalendarSpread,
+            ),
+            (
+                "put diagonal",
+                [(Put, Short, 420, 15), (Put, Long, 415, 22)],
+                StrategyType::Diagonal,
+            ),
+            (
+                "call diagonal",
+                [(Call, Long, 425, 22), (Call, Short, 430, 15)],
+                StrategyType::Diagonal,
+            ),
+            (
+                "short strangle",
+                [(Call, Short, 430, 15), (Put, Short, 410, 15)],
+                StrategyType::Strangle,
+            ),
+            (
+                "long strangle",
+                [(Put, Long, 410, 15), (Call, Long, 430, 15)],
+                StrategyType::Strangle,
+            ),
+            (
+                "short straddle",
+                [(Call, Short, 420, 15), (Put, Short, 420, 15)],
+                StrategyType::Straddle,
+            ),
+            (
+                "long straddle",
+                [(Put, Long, 420, 15), (Call, Long, 420, 15)],
+                StrategyType::Straddle,
+            ),
+            (
+                "risk reversal",
+                [(Call, Long, 430, 15), (Put, Short, 410, 15)],
+                StrategyType::Other,
+            ),
+            (
+                "two short puts",
+                [(Put, Short, 420, 15), (Put, Short, 415, 15)],
+                StrategyType::Other,
+            ),
+            (
+                "call against put across expiries",
+                [(Call, Short, 430, 15), (Put, Short, 410, 22)],
+                StrategyType::Other,
+            ),
+        ];
+        for (name, legs, expected) in cases {
+            let legs = legs.map(|(side, direction, strike, expiry)| {
+                option_leg(side, direction, strike, expiry, 1)
+            });
+            assert_eq!(classify(&legs), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_classify_four_leg_strategies() {
+        use Direction::*;
+        use OptionSide::*;
+        let cases = [
+            (
+                "iron condor",
+                [
+                    (Put, Short, 415, 15),
+                    (Call, Long, 430, 15),
+                    (Put, Long, 410, 15),
+                    (Call, Short, 425, 15),
+                ],
+                StrategyType::IronCondor,
+            ),
+            (
+                "iron butterfly",
+                [
+                    (Call, Long, 430, 15),
+                    (Call, Short, 420, 15),
+                    (Put, Short, 420, 15),
+                    (Put, Long, 410, 15),
+                ],
+                StrategyType::IronButterfly,
+            ),
+            (
+                "wings inside the shorts",
+                [
+                    (Call, Long, 420, 15),
+                    (Call, Short, 425, 15),
+                    (Put, Short, 415, 15),
+                    (Put, Long, 418, 15),
+                ],
+                StrategyType::Other,
+            ),
+            (
+                "condor across expiries",
+                [
+                    (Call, Long, 430, 22),
+                    (Call, Short, 425, 22),
+                    (Put, Short, 415, 15),
+                    (Put, Long, 410, 15),
+                ],
+                StrategyType::Other,
+            ),
+            (
+                "four calls",
+                [
+                    (Call, Long, 410, 15),
+                    (Call, Short, 415, 15),
+                    (Call, Short, 425, 15),
+                    (Call, Long, 430, 15),
+                ],
+                StrategyType::Other,
+            ),
+        ];
+        for (name, legs, expected) in cases {
+            let legs = legs.map(|(side, direction, strike, expiry)| {
+                option_leg(side, direction, strike, expiry, 1)
+            });
+            assert_eq!(classify(&legs), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_classify_ratio_spreads_as_other() {
+        use Direction::*;
+        use OptionSide::*;
+        let ratio = [
+            option_leg(Put, Long, 420, 15, 1),
+            option_leg(Put, Short, 410, 15, 2),
+        ];
+        assert_eq!(classify(&ratio), StrategyType::Other);
+
+        let sized = [
+            option_leg(Put, Short, 420, 15, 3),
+            option_leg(Put, Long, 410, 15, 3),
+        ];
+        assert_eq!(classify(&sized), StrategyType::CreditSpread);
+
+        // A regular butterfly holds its body as one leg of twice the size
+        let butterfly = [
+            option_leg(Call, Long, 410, 15, 1),
+            option_leg(Call, Short, 420, 15, 2),
+            option_leg(Call, Long, 430, 15, 1),
+        ];
+        assert_eq!(classify(&butterfly), StrategyType::Other);
+    }
+
+    #[test]
+    fn test_entry_price_requires_every_leg() {
+        let legs = vec![
+            make_priced_leg("SPY   240315P00420000", "Short", "2.50"),
+            make_leg("SPY   240315P00415000", "Equity Option", None),
+        ];
+        let position = Position::new(legs, &HashMap::new()).unwrap();
+        assert_eq!(position.entry_price(), None);
+    }
+
+    #[test]
+    fn test_unparsed_leg_rejects_position() {
+        let legs = vec![
+            make_priced_leg("SPY   240315P00420000", "Short", "2.50"),
+            make_leg("SPY   240315P004150", "Equity Option", None),
+        ];
+        let err = Position::new(legs, &HashMap::new()).err().unwrap();
+        assert!(err.to_string().starts_with(
+            "Invalid format whilst parsing equity option symbol: SPY   240315P004150"
+        ));
+    }
+
+    #[test]
+    fn test_equity_option_defaults_to_100_multiplier() {
+        let leg = make_leg("SPY   240315C00425000", "Equity Option", None);
+        let option_leg = parse_equity_option(&leg).unwrap();
+        assert_eq!(option_leg.multiplier, Decimal::from(100));
+    }
+
+    #[test]
+    fn test_equity_option_fractional_strike() {
+        let leg = make_leg("SPY   240315P00000500", "Equity Option", None);
+        let option_leg = parse_equity_option(&leg).unwrap();
+        assert_eq!(option_leg.strike_price, Decimal::new(500, 3));
+        assert_eq!(option_leg.side, OptionSide::Put);
+    }
+
+    #[test]
+    fn test_equity_option_whole_strikes() {
+        let leg = make_leg("SPY   240315C00425000", "Equity Option", None);
+        let option_leg = parse_equity_option(&leg).unwrap();
+        assert_eq!(option_leg.strike_price, Decimal::from(425));
+
+        let leg = make_leg("SPX   240315C04250000", "Equity Option", None);
+        let option_leg = parse_equity_option(&leg).unwrap();
+        assert_eq!(option_leg.strike_price, Decimal::from(4250));
+    }
+
+    fn make_future_option(symbol: &str, strike_factor: &str) -> FutureOption {
+        serde_json::from_value(serde_json::json!({
+            "symbol": symbol,
+            "root-symbol": "/ES",
+            "underlying-symbol": "/ESZ4",
+            "notional-value": "50.0",
+            "strike-factor": strike_factor,
+            "display-factor": "0.01",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_future_option_strike() {
+        let symbol = "./ESZ4 EW4X4 241122P5800";
+        let leg = make_leg(symbol, "Future Option", Some(50));
+        let instrument = make_future_option(symbol, "1.0");
+        let option_leg = parse_future_option(&leg, Some(&instrument)).unwrap();
+        assert_eq!(option_leg.strike_price, Decimal::from(5800));
+        assert_eq!(option_leg.side, OptionSide::Put);
+        assert_eq!(
+            option_leg.expiration_date,
+            NaiveDate::from_ymd_opt(2024, 11, 22).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_future_option_strike_scaled_by_strike_factor() {
+        let symbol = "./6EZ4 EUUX4 241108C10950";
+        let leg = make_leg(symbol, "Future Option", Some(125000));
+        let instrument = make_future_option(symbol, "0.0001");
+        let option_leg = parse_future_option(&leg, Some(&instrument)).unwrap();
+        assert_eq!(option_leg.strike_price, Decimal::new(1095, 3));
+    }
+
+    #[test]
+    fn test_future_option_multiplier_from_leg() {
+        let leg = make_leg("./ESZ4 EW4X4 241122P5800", "Future Option", Some(50));
+        let option_leg = parse_future_option(&leg, None).unwrap();
+        assert_eq!(option_leg.multiplier, Decimal::from(50));
+    }
+
+    #[test]
+    fn test_future_option_multiplier_from_instrument() {
+        let leg = make_leg("./ESZ4 EW4X4 241122P5800", "Future Option", None);
+        let instrument = make_future_option("./ESZ4 EW4X4 241122P5800", "1.0");
+        let option_leg = parse_future_option(&leg, Some(&instrument)).unwrap();
+        assert_eq!(option_leg.multiplier, Decimal::from(50));
+        assert!(parse_future_option(&leg, None).is_err());
     }
 }
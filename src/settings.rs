@@ -1,19 +1,396 @@
+use chrono::NaiveTime;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
 use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 
+use crate::positions::StrategyType;
+use crate::tt_api::mktdata::DataFormat;
+use crate::tt_api::mktdata::FeedContract;
+use crate::tt_api::orders::TimeInForce;
 use crate::web_client::EndPoint;
 use anyhow::Result;
+use url::Host;
+use url::Url;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Settings {
+    #[serde(default)]
     pub username: String,
     pub endpoint: EndPoint,
     pub log_level: String,
-    pub database: DatabaseConfig,
+    // Postgres holds the credentials unless auth_file is set, then the
+    // database section can be left out
+    #[serde(default)]
+    pub database: Option<DatabaseConfig>,
+    #[serde(default)]
+    pub auth_file: Option<String>,
+    pub api_base_url: Option<String>,
+    pub ws_url: Option<String>,
+    pub capture_path: Option<String>,
+    pub health: Option<HealthConfig>,
+    #[serde(default = "default_cancel_orders_on_shutdown")]
+    pub cancel_orders_on_shutdown: bool,
+    #[serde(default)]
+    pub chase: ChaseConfig,
+    #[serde(default)]
+    pub order: OrderConfig,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub staleness: StalenessConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub channels: ChannelConfig,
+    #[serde(default)]
+    pub symbols: SymbolConfig,
+    #[serde(default)]
+    pub strategies: StrategiesConfig,
+    #[serde(default)]
+    pub min_quote_size: f64,
+    // Candles kept per symbol for the indicators, oldest evicted first
+    #[serde(default = "default_candle_history")]
+    pub candle_history: usize,
+    // FEED_DATA layout asked of dxlink, COMPACT cuts the bandwidth
+    #[serde(default)]
+    pub data_format: DataFormat,
+    // dxlink feed contract for the channel, AUTO unless latency matters more
+    #[serde(default)]
+    pub feed_contract: FeedContract,
+    // dxlink protocol version sent in SETUP, the server's is logged beside it
+    #[serde(default = "default_dxlink_version")]
+    pub dxlink_version: String,
+    #[serde(default = "default_position_refresh_secs")]
+    pub position_refresh_secs: u64,
+    #[serde(default = "default_stop_check_secs")]
+    pub stop_check_secs: u64,
+    // Broker api calls give up after this long so a hung request cannot
+    // stall the strategy loop
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    pub spx: Option<SpxConfig>,
+    pub flatten: Option<FlattenConfig>,
+    pub loss_multiple: Option<Decimal>,
+    // Only these underlyings are managed when set, ignored ones never are
+    pub monitored_underlyings: Option<Vec<String>>,
+    #[serde(default)]
+    pub ignored_underlyings: Vec<String>,
+    pub notifications: Option<NotificationConfig>,
+    // Only ever set from the --live command line flag
+    #[serde(skip)]
+    pub live_trading: bool,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_cancel_orders_on_shutdown() -> bool {
+    true
+}
+
+fn default_dxlink_version() -> String {
+    String::from("0.1")
+}
+
+fn default_candle_history() -> usize {
+    100
+}
+
+fn default_position_refresh_secs() -> u64 {
+    30
+}
+
+fn default_request_timeout_secs() -> u64 {
+    10
+}
+
+fn default_stop_check_secs() -> u64 {
+    5
+}
+
+const BASE_URL_UAT: &str = "https://api.cert.tastyworks.com";
+const BASE_URL_PROD: &str = "https://api.tastyworks.com";
+
+const WS_URL_UAT: &str = "wss://streamer.cert.tastyworks.com";
+const WS_URL_PROD: &str = "wss://streamer.tastyworks.com";
+
+impl Settings {
+    // Every section filled in with placeholders to edit, the optional ones
+    // left as null show where they go
+    pub fn template() -> Self {
+        Self {
+            username: String::from("tastytrade-username"),
+            endpoint: EndPoint::Sandbox,
+            log_level: String::from("info"),
+            database: Some(DatabaseConfig {
+                name: String::from("options_trader"),
+                port: 5432,
+                host: String::from("localhost"),
+                user: String::from("postgres"),
+            }),
+            auth_file: None,
+            api_base_url: None,
+            ws_url: None,
+            capture_path: None,
+            health: Some(HealthConfig {
+                port: 8080,
+                stale_after_secs: default_stale_after_secs(),
+            }),
+            cancel_orders_on_shutdown: default_cancel_orders_on_shutdown(),
+            chase: ChaseConfig::default(),
+            order: OrderConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            staleness: StalenessConfig::default(),
+            tls: TlsConfig::default(),
+            channels: ChannelConfig::default(),
+            symbols: SymbolConfig::default(),
+            strategies: StrategiesConfig::default(),
+            min_quote_size: 1.0,
+            candle_history: default_candle_history(),
+            data_format: DataFormat::default(),
+            feed_contract: FeedContract::default(),
+            dxlink_version: default_dxlink_version(),
+            position_refresh_secs: default_position_refresh_secs(),
+            stop_check_secs: default_stop_check_secs(),
+            request_timeout_secs: default_request_timeout_secs(),
+            spx: Some(SpxConfig {
+                otm_points: dec!(20),
+                wing_width: dec!(5),
+                entry_check_secs: default_entry_check_secs(),
+                ema_period: default_ema_period(),
+                history_len: default_history_len(),
+                candle_period: default_candle_period(),
+                strikes_around: None,
+                max_chain_subscriptions: default_max_chain_subscriptions(),
+                entry_times: vec![String::from("10:00"), String::from("14:30")],
+                entry_window_mins: default_entry_window_mins(),
+                spx_contracts: default_spx_contracts(),
+                max_concurrent_spx_positions: default_max_concurrent_spx_positions(),
+            }),
+            flatten: Some(FlattenConfig {
+                before: String::from("15:45"),
+                cash_settled: false,
+            }),
+            loss_multiple: Some(dec!(2)),
+            monitored_underlyings: None,
+            ignored_underlyings: Vec::new(),
+            notifications: None,
+            live_trading: false,
+        }
+    }
+
+    // Explicit urls in the config win over the defaults for the endpoint
+    pub fn api_base_url(&self) -> String {
+        match (&self.api_base_url, self.endpoint) {
+            (Some(url), _) => url.clone(),
+            (None, EndPoint::Live) => BASE_URL_PROD.to_string(),
+            (None, EndPoint::Sandbox) => BASE_URL_UAT.to_string(),
+        }
+    }
+
+    pub fn ws_url(&self) -> String {
+        match (&self.ws_url, self.endpoint) {
+            (Some(url), _) => url.clone(),
+            (None, EndPoint::Live) => WS_URL_PROD.to_string(),
+            (None, EndPoint::Sandbox) => WS_URL_UAT.to_string(),
+        }
+    }
+
+    pub fn underlying_filter(&self) -> UnderlyingFilter {
+        let normalize = |symbols: &[String]| {
+            symbols
+                .iter()
+                .map(|symbol| self.symbols.normalize(symbol))
+                .collect::<Vec<_>>()
+        };
+        UnderlyingFilter {
+            monitored: self.monitored_underlyings.as_deref().map(normalize),
+            ignored: normalize(&self.ignored_underlyings),
+        }
+    }
+
+    // Collects every problem so a bad config can be fixed in one pass
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if self.username.trim().is_empty() {
+            problems.push(String::from("username must not be empty"));
+        }
+        match (&self.database, &self.auth_file) {
+            (Some(database), None) => {
+                if database.port == 0 {
+                    problems.push(String::from("database port must not be zero"));
+                }
+                if Host::parse(&database.host).is_err() {
+                    problems.push(format!(
+                        "database host: {:?} is not a valid host name or address",
+                        database.host
+                    ));
+                }
+            }
+            (None, None) => problems.push(String::from(
+                "database must be set unless auth_file is given",
+            )),
+            (_, Some(path)) => {
+                if path.trim().is_empty() {
+                    problems.push(String::from("auth_file must not be empty"));
+                }
+            }
+        }
+        if self.request_timeout_secs == 0 {
+            problems.push(String::from("request_timeout_secs must be positive"));
+        }
+        // A zero period would panic the timers driving the strategies
+        for (name, secs) in [
+            ("position_refresh_secs", self.position_refresh_secs),
+            ("stop_check_secs", self.stop_check_secs),
+            ("chase interval_secs", self.chase.interval_secs),
+        ] {
+            if secs == 0 {
+                problems.push(format!("{} must be positive", name));
+            }
+        }
+        if !self
+            .order
+            .time_in_force
+            .allows_gtc_date(self.order.gtc_date.as_deref())
+        {
+            problems.push(format!(
+                "order gtc_date: {:?} must be set only for GTD orders, time in force is {}",
+                self.order.gtc_date, self.order.time_in_force
+            ));
+        }
+        if let Some(spx) = &self.spx {
+            if let Err(err) = spx.entry_times() {
+                problems.push(format!("spx {}", err));
+            }
+            if spx.entry_window_mins <= 0 {
+                problems.push(String::from("spx entry_window_mins must be positive"));
+            }
+            if spx.entry_check_secs == 0 {
+                problems.push(String::from("spx entry_check_secs must be positive"));
+            }
+            if spx.spx_contracts == 0 {
+                problems.push(String::from("spx spx_contracts must be positive"));
+            }
+            if spx.max_concurrent_spx_positions == 0 {
+                problems.push(String::from(
+                    "spx max_concurrent_spx_positions must be positive",
+                ));
+            }
+            if spx.history_len.max(spx.ema_period) > self.candle_history {
+                problems.push(format!(
+                    "spx history_len and ema_period must fit in candle_history: {}",
+                    self.candle_history
+                ));
+            }
+        }
+        if let Some(flatten) = &self.flatten {
+            if let Err(err) = flatten.before() {
+                problems.push(format!("flatten {}", err));
+            }
+        }
+        if self.dxlink_version.trim().is_empty() {
+            problems.push(String::from("dxlink_version must not be empty"));
+        }
+        for (name, capacity) in [
+            ("md", self.channels.md_channel_capacity),
+            ("account", self.channels.account_channel_capacity),
+            ("ws", self.channels.ws_channel_capacity),
+        ] {
+            if capacity == 0 {
+                problems.push(format!(
+                    "channels {}_channel_capacity must be positive",
+                    name
+                ));
+            }
+        }
+        for (name, grace) in [
+            ("account", self.reconnect.account_heartbeat_grace),
+            ("mktdata", self.reconnect.mktdata_heartbeat_grace),
+        ] {
+            if grace <= 1.0 {
+                problems.push(format!(
+                    "reconnect {}_heartbeat_grace: {} must be greater than one",
+                    name, grace
+                ));
+            }
+        }
+        for (name, url, prod, uat) in [
+            (
+                "api_base_url",
+                &self.api_base_url,
+                BASE_URL_PROD,
+                BASE_URL_UAT,
+            ),
+            ("ws_url", &self.ws_url, WS_URL_PROD, WS_URL_UAT),
+        ] {
+            let Some(url) = url else {
+                continue;
+            };
+            let Ok(parsed) = Url::parse(url) else {
+                problems.push(format!("{}: {} is not a valid url", name, url));
+                continue;
+            };
+            let host = parsed.host_str().unwrap_or_default();
+            let conflicting = match self.endpoint {
+                EndPoint::Live => uat,
+                EndPoint::Sandbox => prod,
+            };
+            if Url::parse(conflicting).is_ok_and(|other| other.host_str() == Some(host)) {
+                problems.push(format!(
+                    "{}: {} does not match the {:?} endpoint",
+                    name, url, self.endpoint
+                ));
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError { problems }),
+        }
+    }
+}
+
+// Decides which underlyings the app manages, positions in anything else are
+// left for the trader to handle
+#[derive(Debug, Clone, Default)]
+pub struct UnderlyingFilter {
+    pub monitored: Option<Vec<String>>,
+    pub ignored: Vec<String>,
+}
+
+impl UnderlyingFilter {
+    pub fn is_monitored(&self, underlying: &str) -> bool {
+        let listed = |symbols: &[String]| {
+            symbols
+                .iter()
+                .any(|symbol| symbol.eq_ignore_ascii_case(underlying))
+        };
+        if listed(&self.ignored) {
+            return false;
+        }
+        match &self.monitored {
+            Some(monitored) => listed(monitored),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.problems.join(", "))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DatabaseConfig {
     pub name: String,
     pub port: u16,
@@ -21,6 +398,338 @@ pub struct DatabaseConfig {
     pub user: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HealthConfig {
+    pub port: u16,
+    #[serde(default = "default_stale_after_secs")]
+    pub stale_after_secs: u64,
+}
+
+fn default_stale_after_secs() -> u64 {
+    60
+}
+
+// Order events are posted here, a Slack incoming webhook url works as is
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NotificationConfig {
+    pub webhook_url: String,
+}
+
+// Opens 0DTE SPX credit spreads up to max_concurrent_spx_positions, on the
+// side given by the last candle close against the ema of the closes
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SpxConfig {
+    pub otm_points: Decimal,
+    pub wing_width: Decimal,
+    #[serde(default = "default_entry_check_secs")]
+    pub entry_check_secs: u64,
+    #[serde(default = "default_ema_period")]
+    pub ema_period: usize,
+    // Candles the ema reads, of candle_period each such as 5m
+    #[serde(default = "default_history_len")]
+    pub history_len: usize,
+    #[serde(default = "default_candle_period")]
+    pub candle_period: String,
+    // Watch this many strikes either side of spot before picking the legs,
+    // at most max_chain_subscriptions calls and puts to stay inside the
+    // dxlink subscription limits
+    pub strikes_around: Option<u32>,
+    #[serde(default = "default_max_chain_subscriptions")]
+    pub max_chain_subscriptions: usize,
+    // HH:MM in America/New_York, entries are tried on every check when empty
+    #[serde(default)]
+    pub entry_times: Vec<String>,
+    // How long after an entry time the entry is still tried
+    #[serde(default = "default_entry_window_mins")]
+    pub entry_window_mins: i64,
+    // Contracts per entry, fewer are sent when buying power runs short
+    #[serde(default = "default_spx_contracts")]
+    pub spx_contracts: u32,
+    #[serde(default = "default_max_concurrent_spx_positions")]
+    pub max_concurrent_spx_positions: usize,
+}
+
+impl SpxConfig {
+    pub fn entry_times(&self) -> Result<Vec<NaiveTime>> {
+        self.entry_times
+            .iter()
+            .map(|time| {
+                NaiveTime::parse_from_str(time, "%H:%M").map_err(|err| {
+                    anyhow::anyhow!("entry time: {:?} is not HH:MM, error: {}", time, err)
+                })
+            })
+            .collect()
+    }
+}
+
+// Positions with a leg expiring today are closed from the before HH:MM New
+// York time on. Index options settle in cash and are left to expire unless
+// cash_settled is set
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FlattenConfig {
+    pub before: String,
+    #[serde(default)]
+    pub cash_settled: bool,
+}
+
+impl FlattenConfig {
+    pub fn before(&self) -> Result<NaiveTime> {
+        NaiveTime::parse_from_str(&self.before, "%H:%M").map_err(|err| {
+            anyhow::anyhow!("before: {:?} is not HH:MM, error: {}", self.before, err)
+        })
+    }
+}
+
+fn default_entry_check_secs() -> u64 {
+    60
+}
+
+fn default_ema_period() -> usize {
+    20
+}
+
+fn default_history_len() -> usize {
+    100
+}
+
+fn default_candle_period() -> String {
+    String::from("5m")
+}
+
+fn default_max_chain_subscriptions() -> usize {
+    100
+}
+
+fn default_entry_window_mins() -> i64 {
+    15
+}
+
+fn default_spx_contracts() -> u32 {
+    1
+}
+
+fn default_max_concurrent_spx_positions() -> usize {
+    1
+}
+
+// Controls how resting liquidation orders are walked toward the midprice
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ChaseConfig {
+    pub interval_secs: u64,
+    pub tick_threshold: Decimal,
+    pub max_adjustment: Decimal,
+}
+
+impl Default for ChaseConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 5,
+            tick_threshold: dec!(0.05),
+            max_adjustment: dec!(0.50),
+        }
+    }
+}
+
+// Time in force given to every order placed, gtc_date is the YYYY-MM-DD
+// expiry of GTD orders. Liquidations are priced slippage_ticks past the
+// midprice unless price_policy prices orders at a touch instead
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OrderConfig {
+    pub time_in_force: TimeInForce,
+    pub gtc_date: Option<String>,
+    pub slippage_ticks: u32,
+    pub price_policy: PricePolicy,
+}
+
+// Where an order is first priced in the book of its legs. Aggressive takes
+// the far touch to fill at once, passive joins the near touch
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PricePolicy {
+    #[default]
+    Mid,
+    Aggressive,
+    Passive,
+}
+
+// More than max_attempts redials inside window_secs stops the app rather
+// than hammering the broker during an outage. A session that hears nothing
+// for its heartbeat interval times the grace is redialled
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    pub max_attempts: usize,
+    pub window_secs: u64,
+    pub account_heartbeat_grace: f64,
+    pub mktdata_heartbeat_grace: f64,
+}
+
+impl ReconnectConfig {
+    pub fn heartbeat_grace(&self, session: &str) -> f64 {
+        match session {
+            "account" => self.account_heartbeat_grace,
+            _ => self.mktdata_heartbeat_grace,
+        }
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            window_secs: 300,
+            account_heartbeat_grace: 2.0,
+            mktdata_heartbeat_grace: 1.2,
+        }
+    }
+}
+
+// TLS for the websockets and the REST client, behind an inspecting proxy
+// ca_bundle names a PEM file of the proxy's certificates. Both trust it in
+// place of the system roots. min_version applies to the websockets only,
+// the REST client keeps curl's own floor
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub min_version: TlsVersion,
+    pub ca_bundle: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+}
+
+// A symbol is stale once threshold_secs pass without data, every
+// resubscribe_after stale intervals in a row it is resubscribed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StalenessConfig {
+    pub threshold_secs: u64,
+    pub resubscribe_after: u32,
+}
+
+impl Default for StalenessConfig {
+    fn default() -> Self {
+        Self {
+            threshold_secs: 30,
+            resubscribe_after: 3,
+        }
+    }
+}
+
+// Messages buffered between the websockets and the app. A receiver that falls
+// further behind than the md or account capacity loses the oldest messages,
+// a bigger buffer rides out bursts such as a large chain at the cost of memory
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ChannelConfig {
+    pub md_channel_capacity: usize,
+    pub account_channel_capacity: usize,
+    pub ws_channel_capacity: usize,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            md_channel_capacity: 100,
+            account_channel_capacity: 50,
+            ws_channel_capacity: 100,
+        }
+    }
+}
+
+// Maps the underlyings a trader writes to the form the broker uses. Aliases
+// fold option roots such as SPXW onto their index, and indices are streamed
+// under index_prefix and the root rather than looked up as equities
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SymbolConfig {
+    pub aliases: BTreeMap<String, String>,
+    pub indices: Vec<String>,
+    pub index_prefix: String,
+}
+
+impl Default for SymbolConfig {
+    fn default() -> Self {
+        Self {
+            aliases: [("SPXW", "SPX"), ("NDXP", "NDX"), ("RUTW", "RUT")]
+                .into_iter()
+                .map(|(alias, root)| (alias.to_string(), root.to_string()))
+                .collect(),
+            indices: ["SPX", "XSP", "NDX", "RUT", "VIX", "DJX"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            index_prefix: String::from("$"),
+        }
+    }
+}
+
+impl SymbolConfig {
+    // Upper cased with any index decoration dropped, $SPX.X and spxw both
+    // come out as SPX. Futures keep their leading slash
+    pub fn normalize(&self, symbol: &str) -> String {
+        let symbol = symbol.trim().to_uppercase();
+        let root = symbol.strip_prefix(&self.index_prefix).unwrap_or(&symbol);
+        let root = root.strip_suffix(".X").unwrap_or(root);
+        self.aliases
+            .get(root)
+            .cloned()
+            .unwrap_or_else(|| root.to_string())
+    }
+
+    // Only known ahead for indices, everything else comes from instruments
+    pub fn index_streamer_symbol(&self, symbol: &str) -> Option<String> {
+        let root = self.normalize(symbol);
+        self.indices
+            .contains(&root)
+            .then(|| format!("{}{}", self.index_prefix, root))
+    }
+}
+
+// Which strategy types the app may liquidate on its own, a disabled type is
+// still monitored and its exit signals logged. Everything is on by default
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct StrategiesConfig {
+    pub credit_spread: bool,
+    pub iron_condor: bool,
+    pub iron_butterfly: bool,
+    pub calendar: bool,
+    pub diagonal: bool,
+}
+
+impl Default for StrategiesConfig {
+    fn default() -> Self {
+        Self {
+            credit_spread: true,
+            iron_condor: true,
+            iron_butterfly: true,
+            calendar: true,
+            diagonal: true,
+        }
+    }
+}
+
+impl StrategiesConfig {
+    pub fn is_enabled(&self, strategy_type: StrategyType) -> bool {
+        match strategy_type {
+            StrategyType::CreditSpread => self.credit_spread,
+            StrategyType::IronCondor => self.iron_condor,
+            StrategyType::IronButterfly => self.iron_butterfly,
+            StrategyType::CalendarSpread => self.calendar,
+            StrategyType::Diagonal => self.diagonal,
+            // Never tracked, so there is nothing to liquidate
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Config {}
 
@@ -33,3 +742,274 @@ impl Config {
         Ok(settings)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_settings(overrides: serde_json::Value) -> Settings {
+        let mut config = serde_json::json!({
+            "username": "user",
+            "endpoint": "Sandbox",
+            "log_level": "info",
+            "database": {"name": "db", "port": 5432, "host": "localhost", "user": "user"},
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(overrides.as_object().unwrap().clone());
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn test_template_round_trips() {
+        let path = std::env::temp_dir().join(format!("settings-{}.json", std::process::id()));
+        let template = serde_json::to_string_pretty(&Settings::template()).unwrap();
+        std::fs::write(&path, &template).unwrap();
+
+        let settings = Config::read_config_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(settings.validate().is_ok());
+        assert_eq!(serde_json::to_string_pretty(&settings).unwrap(), template);
+    }
+
+    #[test]
+    fn test_urls_default_to_endpoint() {
+        let settings = make_settings(serde_json::json!({}));
+        assert_eq!(settings.api_base_url(), BASE_URL_UAT);
+        assert_eq!(settings.ws_url(), WS_URL_UAT);
+
+        let settings = make_settings(serde_json::json!({"endpoint": "Live"}));
+        assert_eq!(settings.api_base_url(), BASE_URL_PROD);
+        assert_eq!(settings.ws_url(), WS_URL_PROD);
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        assert!(make_settings(serde_json::json!({})).validate().is_ok());
+
+        // A missing username is reported by validation rather than serde
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "endpoint": "Live",
+            "log_level": "info",
+            "api_base_url": BASE_URL_UAT,
+            "database": {"name": "db", "port": 0, "host": "bad host", "user": "user"},
+        }))
+        .unwrap();
+        let err = settings.validate().unwrap_err();
+        assert_eq!(err.problems.len(), 4);
+        assert_eq!(err.problems[0], "username must not be empty");
+        assert!(err.problems[3].starts_with("api_base_url"));
+    }
+
+    #[test]
+    fn test_auth_file_replaces_database() {
+        let settings = make_settings(serde_json::json!({"database": null}));
+        assert_eq!(
+            settings.validate().unwrap_err().problems,
+            vec!["database must be set unless auth_file is given"]
+        );
+
+        let settings = make_settings(serde_json::json!({
+            "database": null,
+            "auth_file": "/var/lib/options-trader/auth.json",
+        }));
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_gtc_date_matches_time_in_force() {
+        let settings = make_settings(serde_json::json!({}));
+        assert_eq!(settings.order.time_in_force, TimeInForce::Day);
+
+        let settings = make_settings(serde_json::json!({
+            "order": {"time_in_force": "GTC"},
+        }));
+        assert!(settings.validate().is_ok());
+
+        let settings = make_settings(serde_json::json!({
+            "order": {"time_in_force": "GTD"},
+        }));
+        assert!(settings.validate().unwrap_err().problems[0].starts_with("order gtc_date"));
+
+        let settings = make_settings(serde_json::json!({
+            "order": {"time_in_force": "GTD", "gtc_date": "2024-03-15"},
+        }));
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_entry_times() {
+        let spx = serde_json::json!({"otm_points": 20, "wing_width": 5, "entry_times": ["10:00", "14:30"]});
+        let settings = make_settings(serde_json::json!({ "spx": spx }));
+        assert!(settings.validate().is_ok());
+        assert_eq!(
+            settings.spx.unwrap().entry_times().unwrap(),
+            vec![
+                NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(14, 30, 0).unwrap()
+            ]
+        );
+
+        let spx = serde_json::json!({"otm_points": 20, "wing_width": 5, "entry_times": ["25:00"]});
+        let err = make_settings(serde_json::json!({ "spx": spx }))
+            .validate()
+            .unwrap_err();
+        assert_eq!(err.problems.len(), 1);
+        assert!(err.problems[0].starts_with("spx entry time: \"25:00\""));
+
+        let spx = serde_json::json!({"otm_points": 20, "wing_width": 5, "spx_contracts": 0});
+        let err = make_settings(serde_json::json!({ "spx": spx }))
+            .validate()
+            .unwrap_err();
+        assert_eq!(err.problems, vec!["spx spx_contracts must be positive"]);
+    }
+
+    #[test]
+    fn test_validate_intervals_positive() {
+        let settings = make_settings(serde_json::json!({
+            "position_refresh_secs": 0,
+            "stop_check_secs": 0,
+            "chase": {"interval_secs": 0},
+            "spx": {"otm_points": 20, "wing_width": 5, "entry_check_secs": 0},
+        }));
+        assert_eq!(
+            settings.validate().unwrap_err().problems,
+            vec![
+                "position_refresh_secs must be positive",
+                "stop_check_secs must be positive",
+                "chase interval_secs must be positive",
+                "spx entry_check_secs must be positive",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tls_min_version_at_least_1_2() {
+        let tls = |version: &str| {
+            serde_json::from_value::<TlsConfig>(serde_json::json!({"min_version": version}))
+        };
+        assert_eq!(tls("1.2").unwrap().min_version, TlsVersion::Tls12);
+        assert!(tls("1.0").is_err());
+        assert!(tls("1.1").is_err());
+    }
+
+    #[test]
+    fn test_validate_ema_fits_candle_history() {
+        let settings = make_settings(serde_json::json!({
+            "candle_history": 50,
+            "spx": {"otm_points": 20, "wing_width": 5, "history_len": 60},
+        }));
+        assert_eq!(
+            settings.validate().unwrap_err().problems,
+            vec!["spx history_len and ema_period must fit in candle_history: 50"]
+        );
+    }
+
+    #[test]
+    fn test_validate_heartbeat_grace() {
+        let settings = make_settings(serde_json::json!({
+            "reconnect": {"account_heartbeat_grace": 3.0},
+        }));
+        assert!(settings.validate().is_ok());
+        assert_eq!(settings.reconnect.heartbeat_grace("account"), 3.0);
+        assert_eq!(settings.reconnect.heartbeat_grace("mktdata"), 1.2);
+
+        let settings = make_settings(serde_json::json!({
+            "reconnect": {"mktdata_heartbeat_grace": 1.0},
+        }));
+        let err = settings.validate().unwrap_err();
+        assert_eq!(
+            err.problems,
+            vec!["reconnect mktdata_heartbeat_grace: 1 must be greater than one"]
+        );
+    }
+
+    #[test]
+    fn test_underlying_filter() {
+        let filter = make_settings(serde_json::json!({})).underlying_filter();
+        assert!(filter.is_monitored("AAPL"));
+
+        let filter = make_settings(serde_json::json!({
+            "monitored_underlyings": ["SPX", "/ES"],
+            "ignored_underlyings": ["/ES"],
+        }))
+        .underlying_filter();
+        assert!(filter.is_monitored("SPX"));
+        assert!(filter.is_monitored("spx"));
+        assert!(!filter.is_monitored("/ES"));
+        assert!(!filter.is_monitored("AAPL"));
+
+        let filter = make_settings(serde_json::json!({
+            "ignored_underlyings": ["AAPL"],
+        }))
+        .underlying_filter();
+        assert!(!filter.is_monitored("AAPL"));
+        assert!(filter.is_monitored("SPX"));
+
+        let filter = make_settings(serde_json::json!({
+            "monitored_underlyings": ["spxw", "$NDX.X"],
+        }))
+        .underlying_filter();
+        assert!(filter.is_monitored("SPX"));
+        assert!(filter.is_monitored("NDX"));
+        assert!(!filter.is_monitored("SPXW"));
+    }
+
+    #[test]
+    fn test_strategies_enabled_by_type() {
+        let settings = make_settings(serde_json::json!({}));
+        assert!(settings.strategies.is_enabled(StrategyType::IronCondor));
+        assert!(!settings.strategies.is_enabled(StrategyType::Other));
+
+        let settings = make_settings(serde_json::json!({
+            "strategies": {"iron_condor": false},
+        }));
+        assert!(!settings.strategies.is_enabled(StrategyType::IronCondor));
+        assert!(settings.strategies.is_enabled(StrategyType::CreditSpread));
+        assert!(settings.strategies.is_enabled(StrategyType::Diagonal));
+    }
+
+    #[test]
+    fn test_symbol_normalization() {
+        let symbols = SymbolConfig::default();
+        for symbol in ["SPX", "spx", "SPXW", " $SPX ", "$SPX.X"] {
+            assert_eq!(symbols.normalize(symbol), "SPX");
+            assert_eq!(
+                symbols.index_streamer_symbol(symbol).as_deref(),
+                Some("$SPX")
+            );
+        }
+        assert_eq!(symbols.normalize("/es"), "/ES");
+        assert_eq!(symbols.index_streamer_symbol("/ES"), None);
+        assert_eq!(symbols.index_streamer_symbol("SPY"), None);
+
+        let settings = make_settings(serde_json::json!({
+            "symbols": {"index_prefix": "", "aliases": {"MYIDX": "SPX"}},
+        }));
+        assert_eq!(settings.symbols.normalize("myidx"), "SPX");
+        assert_eq!(
+            settings.symbols.index_streamer_symbol("SPX").as_deref(),
+            Some("SPX")
+        );
+    }
+
+    #[test]
+    fn test_validate_allows_local_overrides() {
+        let settings = make_settings(serde_json::json!({
+            "endpoint": "Live",
+            "api_base_url": "http://localhost:8080",
+        }));
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn test_urls_overridden_by_config() {
+        let settings = make_settings(serde_json::json!({
+            "api_base_url": "http://localhost:8080",
+            "ws_url": "ws://localhost:8081",
+        }));
+        assert_eq!(settings.api_base_url(), "http://localhost:8080");
+        assert_eq!(settings.ws_url(), "ws://localhost:8081");
+    }
+}
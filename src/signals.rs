@@ -0,0 +1,50 @@
+use rust_decimal::Decimal;
+
+use crate::indicators::ema;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarketDirection {
+    Bullish,
+    Bearish,
+    Neutral,
+}
+
+// Price above its short ema reads as bullish, below as bearish
+pub fn market_direction(prices: &[Decimal], period: usize) -> MarketDirection {
+    let (Some(ema), Some(last)) = (ema(prices, period).last().copied(), prices.last()) else {
+        return MarketDirection::Neutral;
+    };
+    match last.cmp(&ema) {
+        std::cmp::Ordering::Greater => MarketDirection::Bullish,
+        std::cmp::Ordering::Less => MarketDirection::Bearish,
+        std::cmp::Ordering::Equal => MarketDirection::Neutral,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_ema_known_values() {
+        let prices = [dec!(1), dec!(2), dec!(3), dec!(4), dec!(5)];
+        // seed of 2 then alpha 0.5 over 4 and 5
+        assert_eq!(ema(&prices, 3).last(), Some(&dec!(4)));
+        assert_eq!(ema(&prices, 6).last(), None);
+    }
+
+    #[test]
+    fn test_market_direction() {
+        let rising = [dec!(5000), dec!(5001), dec!(5002), dec!(5010)];
+        assert_eq!(market_direction(&rising, 3), MarketDirection::Bullish);
+
+        let falling = [dec!(5010), dec!(5002), dec!(5001), dec!(4990)];
+        assert_eq!(market_direction(&falling, 3), MarketDirection::Bearish);
+
+        let flat = [dec!(5000), dec!(5000), dec!(5000), dec!(5000)];
+        assert_eq!(market_direction(&flat, 3), MarketDirection::Neutral);
+
+        assert_eq!(market_direction(&rising[..2], 3), MarketDirection::Neutral);
+    }
+}
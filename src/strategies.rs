@@ -1,62 +1,407 @@
 use anyhow::bail;
 use anyhow::Result;
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::Utc;
+use percent_encoding::utf8_percent_encode;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 use std::iter::Iterator;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
-use tokio::time::sleep;
+use tokio::time::interval;
 use tokio_util::sync::CancellationToken;
+use tracing::debug;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 
 // use crate::mktdata::tt_api::CandleData;
 use super::account::Account;
+use super::account::Balance;
 use super::mktdata::MktData;
+use super::orders::CloseSize;
 use super::orders::Orders;
 use super::positions::Position;
 use super::web_client::WebClient;
 use crate::mktdata::Snapshot;
+use crate::mktdata::UTF8_ECODING;
+use crate::notifier::Notifications;
 use crate::positions::Direction;
 use crate::positions::OptionLeg;
 use crate::positions::OptionSide;
 use crate::positions::OptionType;
 use crate::positions::PriceEffect;
 use crate::positions::StrategyType;
+use crate::settings::Settings;
+use crate::settings::SpxConfig;
+use crate::settings::StrategiesConfig;
+use crate::settings::UnderlyingFilter;
+use crate::signals::market_direction;
+use crate::signals::MarketDirection;
+use crate::tt_api::mktdata::Candle;
+use crate::tt_api::mktdata::FutureOption;
+use crate::tt_api::mktdata::Greeks;
 use crate::tt_api::mktdata::Quote;
+use crate::tt_api::mktdata::Response;
+use crate::tt_api::option_chain::Strike;
 use crate::tt_api::positions::AccountPositions;
 use crate::tt_api::positions::Leg;
+use error::StrategyError;
+use flatten::FlattenRule;
+use schedule::EntrySchedule;
+
+pub(crate) mod error;
+mod flatten;
+pub(crate) mod preview;
+mod schedule;
+
+const SPX: &str = "SPX";
+
+// Why a position was liquidated, carried through to the order so every
+// exit can be traced back to the rule that fired
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ExitReason {
+    StrikeCrossed,
+    MaxLoss,
+    ThetaFlip,
+    Expiring,
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ExitReason::StrikeCrossed => String::from("StrikeCrossed"),
+            ExitReason::MaxLoss => String::from("MaxLoss"),
+            ExitReason::ThetaFlip => String::from("ThetaFlip"),
+            ExitReason::Expiring => String::from("Expiring"),
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+// One symbol to stream for the tracked strategies
+#[derive(Debug, Clone, PartialEq)]
+struct FeedSubscription {
+    symbol: String,
+    underlying: String,
+    event_types: &'static [&'static str],
+    option_type: OptionType,
+    strike_price: Option<Decimal>,
+}
+
+// Greeks summed over the legs of every tracked strategy, each leg signed by
+// direction and scaled by quantity. Partial when a leg had no greeks yet
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct PortfolioGreeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub theta: f64,
+    pub vega: f64,
+    pub legs: usize,
+    pub missing: usize,
+}
+
+impl PortfolioGreeks {
+    fn add(&mut self, leg: &OptionLeg, greeks: Option<&Greeks>) {
+        let Some(greeks) = greeks else {
+            self.missing += 1;
+            return;
+        };
+        let weight = f64::from(leg.quantity)
+            * match leg.direction {
+                Direction::Long => 1.,
+                Direction::Short => -1.,
+            };
+        self.delta += greeks.delta * weight;
+        self.gamma += greeks.gamma * weight;
+        self.theta += greeks.theta * weight;
+        self.vega += greeks.vega * weight;
+        self.legs += 1;
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.missing > 0
+    }
+}
+
+impl fmt::Display for PortfolioGreeks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "delta: {:.2}, gamma: {:.4}, theta: {:.2}, vega: {:.2}, legs: {}",
+            self.delta, self.gamma, self.theta, self.vega, self.legs
+        )?;
+        if self.is_partial() {
+            write!(f, ", partial, {} legs without greeks", self.missing)?;
+        }
+        Ok(())
+    }
+}
 
 struct SpxSpread {
-    web_client: Arc<RwLock<WebClient>>,
+    web_client: Arc<WebClient>,
+    mktdata: Arc<RwLock<MktData>>,
+    orders: Arc<Orders>,
+    config: SpxConfig,
+    schedule: EntrySchedule,
 }
 
 impl SpxSpread {
-    fn new(web_client: Arc<RwLock<WebClient>>) -> Self {
-        Self::market_monitor();
-        Self { web_client }
+    fn new(
+        web_client: Arc<WebClient>,
+        mktdata: Arc<RwLock<MktData>>,
+        orders: Arc<Orders>,
+        config: SpxConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            web_client,
+            mktdata,
+            orders,
+            schedule: EntrySchedule::new(&config)?,
+            config,
+        })
     }
 
-    fn market_monitor() {
+    fn market_monitor(mut self, cancel_token: CancellationToken) {
         tokio::spawn(async move {
-            tokio::select! {
-                _ = sleep(Duration::from_secs(5)) => {
-                    // if orders.has_symbol() {
-                    //     return
-                    // }
-
-                    // let diretion = get_market_conditions();
-                    // if diretion.is_none() {
-                    //     return
-                    // }
-                    // let _ = orders.enter_position();
+            let mut entry_check = interval(Duration::from_secs(self.config.entry_check_secs));
+            loop {
+                tokio::select! {
+                    _ = entry_check.tick() => {
+                        if let Err(err) = self.enter_position().await {
+                            error!("Failed to enter SPX position, error: {}", err);
+                        }
+                    }
+                    _ = cancel_token.cancelled() => {
+                        break
+                    }
                 }
             }
         });
     }
+
+    async fn enter_position(&mut self) -> Result<()> {
+        let Some(spot) = self.get_spot().await? else {
+            warn!("No SPX quote available, not entering");
+            return Ok(());
+        };
+        // Read on every check so the candle history is in by the entry time
+        let closes = self.get_closes().await?;
+
+        let now = Utc::now();
+        if !self.schedule.is_due(now) {
+            return Ok(());
+        }
+
+        if self.orders.has_order_in_flight(SPX).await {
+            debug!("SPX order already in flight");
+            return Ok(());
+        }
+        let held = self.open_positions().await?;
+        if held >= self.config.max_concurrent_spx_positions {
+            debug!("SPX positions held: {}, not entering", held);
+            return Ok(());
+        }
+
+        // Sell puts below the market when bullish and calls above it when bearish
+        let (side, offset) = match market_direction(&closes, self.config.ema_period) {
+            MarketDirection::Bullish => (OptionSide::Put, -Decimal::ONE),
+            MarketDirection::Bearish => (OptionSide::Call, Decimal::ONE),
+            MarketDirection::Neutral => {
+                debug!("SPX direction neutral, not entering");
+                return Ok(());
+            }
+        };
+
+        let chain = self.web_client.get_option_chain(SPX).await?;
+        let Some(expiration) = chain.expiration_by_dte(0) else {
+            bail!("No SPX expiration found in option chain");
+        };
+        if let Some(strikes_around) = self.config.strikes_around {
+            self.mktdata
+                .write()
+                .await
+                .subscribe_to_chain(
+                    SPX,
+                    expiration,
+                    strikes_around,
+                    self.config.max_chain_subscriptions,
+                )
+                .await?;
+        }
+        let short_price = spot + offset * self.config.otm_points;
+        let (Some(short_strike), Some(long_strike)) = (
+            expiration.strike_nearest_price(short_price),
+            expiration.strike_nearest_price(short_price + offset * self.config.wing_width),
+        ) else {
+            bail!(
+                "No SPX strikes found for expiration: {}",
+                expiration.expiration_date
+            );
+        };
+        if long_strike.strike_price == short_strike.strike_price {
+            bail!(
+                "SPX wing strike: {} same as short strike",
+                long_strike.strike_price
+            );
+        }
+
+        let expiration_date = NaiveDate::parse_from_str(&expiration.expiration_date, "%Y-%m-%d")?;
+        let make_leg = |strike: &Strike, direction: Direction| OptionLeg {
+            symbol: match side {
+                OptionSide::Call => strike.call.clone(),
+                OptionSide::Put => strike.put.clone(),
+            },
+            underlying: SPX.to_string(),
+            expiration_date,
+            direction,
+            side,
+            strike_price: strike.strike_price,
+            quantity: 1,
+            multiplier: Decimal::from(100),
+            average_open_price: None,
+            mark_price: None,
+            option_type: OptionType::EquityOption,
+        };
+        let mut position = Position {
+            legs: vec![
+                make_leg(short_strike, Direction::Short),
+                make_leg(long_strike, Direction::Long),
+            ],
+            strategy_type: StrategyType::CreditSpread,
+        };
+        let buying_power = Balance::fetch(&self.web_client)
+            .await?
+            .derivative_buying_power;
+        let contracts = Self::affordable_contracts(
+            self.config.spx_contracts,
+            position.estimated_margin(),
+            buying_power,
+        );
+        if contracts == 0 {
+            warn!(
+                "Buying power: {} short of a single SPX spread, not entering",
+                buying_power
+            );
+            return Ok(());
+        }
+        if contracts < self.config.spx_contracts {
+            warn!(
+                "Buying power: {} covers {} of {} SPX contracts",
+                buying_power, contracts, self.config.spx_contracts
+            );
+        }
+        position
+            .legs
+            .iter_mut()
+            .for_each(|leg| leg.quantity = contracts as i32);
+        let spread = CreditSpread::new(position, None);
+        info!(
+            "Entering {}, estimated margin: {}",
+            spread,
+            spread.get_position().estimated_margin()
+        );
+
+        for leg in &spread.get_position().legs {
+            self.mktdata
+                .write()
+                .await
+                .subscribe_to_feed(
+                    &leg.symbol,
+                    SPX,
+                    &["Quote"],
+                    leg.option_type,
+                    Some(leg.strike_price),
+                )
+                .await?;
+        }
+        match self
+            .orders
+            .open_position(&spread, PriceEffect::Credit)
+            .await
+        {
+            Err(err) if err.is_retryable() => {
+                debug!("Deferring SPX entry, error: {}", err);
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+            Ok(()) => {
+                self.schedule.mark_entered(now);
+                Ok(())
+            }
+        }
+    }
+
+    async fn get_spot(&self) -> Result<Option<Decimal>> {
+        let mut mktdata = self.mktdata.write().await;
+        mktdata
+            .subscribe_to_feed(SPX, SPX, &["Quote"], OptionType::Equity, None)
+            .await?;
+        Ok(mktdata
+            .get_snapshot_by_symbol::<Quote>(SPX)
+            .await
+            .and_then(|snapshot| snapshot.midprice()))
+    }
+
+    // Closes of the SPX candles the ema reads, oldest first
+    async fn get_closes(&self) -> Result<Vec<Decimal>> {
+        let mut mktdata = self.mktdata.write().await;
+        let symbol = mktdata
+            .subscribe_to_candles(SPX, SPX, OptionType::Equity, &self.config.candle_period)
+            .await?;
+        let Some(snapshot) = mktdata.get_snapshot_by_symbol::<Candle>(&symbol).await else {
+            return Ok(Vec::new());
+        };
+        Ok(snapshot
+            .recent_candles(self.config.history_len.max(self.config.ema_period))
+            .iter()
+            .filter_map(|candle| Decimal::from_f64(candle.close))
+            .map(|close| (close * snapshot.display_factor).normalize())
+            .collect())
+    }
+
+    // The configured contracts capped by how many spreads the buying power
+    // covers at the estimated margin of a single one
+    fn affordable_contracts(
+        contracts: u32,
+        margin_per_contract: Decimal,
+        buying_power: Decimal,
+    ) -> u32 {
+        if margin_per_contract <= Decimal::ZERO {
+            return contracts;
+        }
+        let affordable = (buying_power.max(Decimal::ZERO) / margin_per_contract)
+            .floor()
+            .to_u32()
+            .unwrap_or(u32::MAX);
+        contracts.min(affordable)
+    }
+
+    // Each spread holds one short SPX leg, so those are counted as positions
+    async fn open_positions(&self) -> Result<usize> {
+        let positions = self
+            .web_client
+            .get_with_query::<AccountPositions>(
+                &format!("accounts/{}/positions", self.web_client.get_account()),
+                &[("underlying-symbol", SPX)],
+            )
+            .await?;
+        Ok(positions
+            .data
+            .legs
+            .iter()
+            .filter(|leg| {
+                leg.underlying_symbol.as_deref() == Some(SPX)
+                    && leg.quantity_direction.as_deref() == Some("Short")
+            })
+            .count())
+    }
 }
 
 pub(crate) trait StrategyMeta: Sync + Send {
@@ -64,61 +409,84 @@ pub(crate) trait StrategyMeta: Sync + Send {
     fn get_symbols(&self) -> Vec<&str>;
     fn get_instrument_type(&self) -> OptionType;
     fn get_position(&self) -> &Position;
+    fn get_entry_price(&self) -> Option<Decimal>;
 }
 
 struct CreditSpread {
     position: Position,
+    loss_multiple: Option<Decimal>,
 }
 
 impl CreditSpread {
-    fn new(position: Position) -> Self {
-        Self { position }
+    fn new(position: Position, loss_multiple: Option<Decimal>) -> Self {
+        Self {
+            position,
+            loss_multiple,
+        }
     }
 
-    async fn should_exit(&self, mktdata: &MktData) -> bool {
-        fn get_option_type(position: &Position) -> OptionSide {
-            position.legs[0].side
+    // Skipped when the credit taken in is not known
+    fn exceeds_max_loss(
+        entry_credit: Option<Decimal>,
+        close_price: Decimal,
+        loss_multiple: Decimal,
+    ) -> bool {
+        match entry_credit {
+            Some(credit) if credit > Decimal::ZERO => close_price > credit * loss_multiple,
+            _ => false,
         }
+    }
 
-        fn get_strike_price(position: &Position) -> Decimal {
-            match get_option_type(position) {
-                OptionSide::Call => position.legs[1].strike_price,
-                OptionSide::Put => position.legs[0].strike_price,
-            }
+    async fn should_exit(&self, mktdata: &MktData) -> Option<ExitReason> {
+        fn get_midprice(snapshot: &Snapshot) -> Option<Decimal> {
+            snapshot.midprice()
         }
 
-        fn get_midprice(snapshot: &Snapshot) -> Decimal {
-            if let Some(quote) = &snapshot.quote {
-                return quote.midprice();
+        if let Some(pnl) = self.position.unrealized_pnl(mktdata).await {
+            info!(
+                "Unrealized pnl for position: {} is {}, entry price: {:?}",
+                self.get_underlying(),
+                pnl,
+                self.get_entry_price()
+            );
+        }
+
+        if let Some(loss_multiple) = self.loss_multiple {
+            if let Some(close_price) = self.position.close_price(mktdata).await {
+                if Self::exceeds_max_loss(self.get_entry_price(), close_price, loss_multiple) {
+                    info!(
+                        "Should exit position: {} cost to close: {} exceeds max loss",
+                        self.get_underlying(),
+                        close_price
+                    );
+                    return Some(ExitReason::MaxLoss);
+                }
             }
-            dec!(0)
         }
 
         let mkt_event = mktdata
             .get_snapshot_by_symbol::<Quote>(self.get_underlying())
             .await;
 
-        if let Some(snapshot) = mkt_event {
-            let mid_price = get_midprice(&snapshot);
-            if mid_price == dec!(0) {
-                return false;
-            }
-            let strike_price = get_strike_price(&self.position);
-            let result = match get_option_type(&self.position) {
-                OptionSide::Call => strike_price < mid_price,
-                OptionSide::Put => strike_price > mid_price,
-            };
-
-            info!(
-                "Should exit position: {} mid price: {} has crossed strike price: {}",
-                self.get_underlying(),
-                mid_price,
-                strike_price
-            );
-            result
-        } else {
-            false
+        let mid_price = get_midprice(&mkt_event?)?;
+        // The short strike is the one the spread is lost past
+        let short_leg = self.position.short_leg()?;
+        let strike_price = short_leg.strike_price;
+        let crossed = match short_leg.side {
+            OptionSide::Call => strike_price < mid_price,
+            OptionSide::Put => strike_price > mid_price,
+        };
+        if !crossed {
+            return None;
         }
+
+        info!(
+            "Should exit position: {} mid price: {} has crossed strike price: {}",
+            self.get_underlying(),
+            mid_price,
+            strike_price
+        );
+        Some(ExitReason::StrikeCrossed)
     }
 
     fn print(&self) {
@@ -130,8 +498,13 @@ impl fmt::Display for CreditSpread {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "CreditSpread {}: [{}\n]",
-            &self.position.legs.first().unwrap().symbol,
+            "CreditSpread {} / {}: [{}\n]",
+            self.position
+                .short_leg()
+                .map_or("-", |leg| leg.symbol.as_str()),
+            self.position
+                .long_leg()
+                .map_or("-", |leg| leg.symbol.as_str()),
             &self.position
         )
     }
@@ -157,6 +530,10 @@ impl StrategyMeta for CreditSpread {
     fn get_position(&self) -> &Position {
         &self.position
     }
+
+    fn get_entry_price(&self) -> Option<Decimal> {
+        self.position.entry_price()
+    }
 }
 
 struct CalendarSpread {
@@ -168,16 +545,16 @@ impl CalendarSpread {
         Self { position }
     }
 
-    async fn should_exit(&self, mktdata: &MktData) -> bool {
+    async fn should_exit(&self, mktdata: &MktData) -> Option<ExitReason> {
         let total_theta = 0.;
         for complex_symbol in &self.position.legs {
             // if let Some(event) = mktdata.get_snapshot_events(complex_symbol.symbol()).await {
-            //     // if let FeedEvent::GreeksEvent(greek) = event {
+            //     // if let FeedEvent::Greeks(greek) = event {
             //     //     total_theta += greek.theta;
             //     // }
             // }
         }
-        total_theta < 0.
+        (total_theta < 0.).then_some(ExitReason::ThetaFlip)
     }
 
     fn print(&self) {
@@ -215,6 +592,103 @@ impl StrategyMeta for CalendarSpread {
     fn get_position(&self) -> &Position {
         &self.position
     }
+
+    fn get_entry_price(&self) -> Option<Decimal> {
+        self.position.entry_price()
+    }
+}
+
+// Short the near expiry and long a further one at a different strike, so it
+// earns theta like a calendar but is tested by the short strike like a
+// vertical
+struct DiagonalSpread {
+    position: Position,
+}
+
+impl DiagonalSpread {
+    fn new(position: Position) -> Self {
+        Self { position }
+    }
+
+    async fn should_exit(&self, mktdata: &MktData) -> Option<ExitReason> {
+        let short_leg = self
+            .position
+            .legs
+            .iter()
+            .find(|leg| leg.direction == Direction::Short)?;
+
+        if let Some(mid_price) = mktdata
+            .get_snapshot_by_symbol::<Quote>(self.get_underlying())
+            .await
+            .and_then(|snapshot| snapshot.midprice())
+        {
+            let crossed = match short_leg.side {
+                OptionSide::Call => short_leg.strike_price < mid_price,
+                OptionSide::Put => short_leg.strike_price > mid_price,
+            };
+            if crossed {
+                info!(
+                    "Should exit position: {} mid price: {} has crossed strike price: {}",
+                    self.get_underlying(),
+                    mid_price,
+                    short_leg.strike_price
+                );
+                return Some(ExitReason::StrikeCrossed);
+            }
+        }
+
+        let theta = self.position.net_theta(mktdata).await?;
+        if theta >= 0. {
+            return None;
+        }
+        info!(
+            "Should exit position: {} net theta: {} has turned negative",
+            self.get_underlying(),
+            theta
+        );
+        Some(ExitReason::ThetaFlip)
+    }
+
+    fn print(&self) {
+        info!("{}", &self);
+    }
+}
+
+impl fmt::Display for DiagonalSpread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DiagonalSpread {}: [{}\n]",
+            &self.position.legs.first().unwrap().symbol,
+            &self.position
+        )
+    }
+}
+
+impl StrategyMeta for DiagonalSpread {
+    fn get_underlying(&self) -> &str {
+        &self.position.legs.first().unwrap().underlying
+    }
+
+    fn get_symbols(&self) -> Vec<&str> {
+        self.position
+            .legs
+            .iter()
+            .map(|leg| leg.symbol.as_str())
+            .collect()
+    }
+
+    fn get_instrument_type(&self) -> OptionType {
+        self.position.legs.first().unwrap().option_type
+    }
+
+    fn get_position(&self) -> &Position {
+        &self.position
+    }
+
+    fn get_entry_price(&self) -> Option<Decimal> {
+        self.position.entry_price()
+    }
 }
 
 struct IronCondor {
@@ -227,26 +701,27 @@ impl IronCondor {
     }
 
     //Matches the near leg strike price against underlying mid price
-    async fn should_exit(&self, mktdata: &MktData) -> bool {
-        fn get_strike_prices(position: &Position) -> (Decimal, Decimal) {
-            (position.legs[1].strike_price, position.legs[2].strike_price)
+    async fn should_exit(&self, mktdata: &MktData) -> Option<ExitReason> {
+        fn get_strike_prices(position: &Position) -> Option<(Decimal, Decimal)> {
+            Some((
+                position
+                    .find_leg(OptionSide::Call, Direction::Short)?
+                    .strike_price,
+                position
+                    .find_leg(OptionSide::Put, Direction::Short)?
+                    .strike_price,
+            ))
         }
 
         let mkt_event = mktdata
             .get_snapshot_by_symbol::<Quote>(self.get_underlying())
             .await;
 
-        if let Some(snapshot) = mkt_event {
-            let mid_price = snapshot.quote.unwrap().midprice();
-            if mid_price == Decimal::default() {
-                return false;
-            }
-            let (call_strike_price, put_strike_price) = get_strike_prices(&self.position);
+        let mid_price = mkt_event?.midprice()?;
+        let (call_strike_price, put_strike_price) = get_strike_prices(&self.position)?;
 
-            call_strike_price < mid_price || put_strike_price > mid_price
-        } else {
-            false
-        }
+        (call_strike_price < mid_price || put_strike_price > mid_price)
+            .then_some(ExitReason::StrikeCrossed)
     }
 
     fn print(&self) {
@@ -285,48 +760,208 @@ impl StrategyMeta for IronCondor {
     fn get_position(&self) -> &Position {
         &self.position
     }
+
+    fn get_entry_price(&self) -> Option<Decimal> {
+        self.position.entry_price()
+    }
+}
+
+struct IronButterfly {
+    position: Position,
+}
+
+impl IronButterfly {
+    fn new(position: Position) -> Self {
+        Self { position }
+    }
+
+    //Exits once the underlying moves past the body strike by more than the
+    //credit taken in
+    async fn should_exit(&self, mktdata: &MktData) -> Option<ExitReason> {
+        let credit = self.get_entry_price()?;
+        let body_strike_price = self
+            .position
+            .find_leg(OptionSide::Call, Direction::Short)?
+            .strike_price;
+
+        let mid_price = mktdata
+            .get_snapshot_by_symbol::<Quote>(self.get_underlying())
+            .await?
+            .midprice()?;
+
+        (body_strike_price + credit < mid_price || body_strike_price - credit > mid_price)
+            .then_some(ExitReason::StrikeCrossed)
+    }
+
+    fn print(&self) {
+        info!("{}", &self);
+    }
+}
+
+impl fmt::Display for IronButterfly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "IronButterfly {}: [{}\n]",
+            &self.position.legs.first().unwrap().underlying,
+            &self.position
+        )
+    }
+}
+
+impl StrategyMeta for IronButterfly {
+    fn get_underlying(&self) -> &str {
+        &self.position.legs.first().unwrap().underlying
+    }
+
+    fn get_symbols(&self) -> Vec<&str> {
+        self.position
+            .legs
+            .iter()
+            .map(|leg| leg.symbol.as_str())
+            .collect()
+    }
+
+    fn get_instrument_type(&self) -> OptionType {
+        self.position.legs.first().unwrap().option_type
+    }
+
+    fn get_position(&self) -> &Position {
+        &self.position
+    }
+
+    fn get_entry_price(&self) -> Option<Decimal> {
+        self.position.entry_price()
+    }
 }
 
 enum Strategy {
     Calendar(CalendarSpread),
     Credit(CreditSpread),
+    Diagonal(DiagonalSpread),
     Condor(IronCondor),
+    Butterfly(IronButterfly),
     NotTracked,
 }
 
-pub(crate) struct Strategies {}
+impl Strategy {
+    fn get_position(&self) -> Option<&Position> {
+        match self {
+            Strategy::Calendar(strat) => Some(strat.get_position()),
+            Strategy::Credit(strat) => Some(strat.get_position()),
+            Strategy::Diagonal(strat) => Some(strat.get_position()),
+            Strategy::Condor(strat) => Some(strat.get_position()),
+            Strategy::Butterfly(strat) => Some(strat.get_position()),
+            Strategy::NotTracked => None,
+        }
+    }
+
+    fn meta(&self) -> Option<&dyn StrategyMeta> {
+        match self {
+            Strategy::Calendar(strat) => Some(strat),
+            Strategy::Credit(strat) => Some(strat),
+            Strategy::Diagonal(strat) => Some(strat),
+            Strategy::Condor(strat) => Some(strat),
+            Strategy::Butterfly(strat) => Some(strat),
+            Strategy::NotTracked => None,
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::Calendar(strat) => write!(f, "{}", strat),
+            Strategy::Credit(strat) => write!(f, "{}", strat),
+            Strategy::Diagonal(strat) => write!(f, "{}", strat),
+            Strategy::Condor(strat) => write!(f, "{}", strat),
+            Strategy::Butterfly(strat) => write!(f, "{}", strat),
+            Strategy::NotTracked => write!(f, "NotTracked"),
+        }
+    }
+}
+
+pub(crate) struct Strategies {
+    orders: Arc<Orders>,
+}
 
 impl Strategies {
-    pub async fn new(web_client: Arc<WebClient>, cancel_token: CancellationToken) -> Result<Self> {
+    pub async fn new(
+        web_client: Arc<WebClient>,
+        settings: &Settings,
+        cancel_token: CancellationToken,
+    ) -> Result<Self> {
         let _account = Account::new(Arc::clone(&web_client), cancel_token.clone());
         let mktdata = Arc::new(RwLock::new(MktData::new(
             Arc::clone(&web_client),
+            settings.candle_history,
+            settings.data_format,
+            settings.staleness.clone(),
+            settings.symbols.clone(),
             cancel_token.clone(),
         )));
-        let mut orders = Orders::new(
-            Arc::clone(&web_client),
-            Arc::clone(&mktdata),
-            cancel_token.clone(),
+        let notifications = Notifications::new(settings.notifications.as_ref())?;
+        let orders = Arc::new(
+            Orders::new(
+                Arc::clone(&web_client),
+                Arc::clone(&mktdata),
+                settings,
+                notifications,
+                cancel_token.clone(),
+            )
+            .await,
         );
-        let mut strategies = match Self::get_strategies(&web_client).await {
-            Ok(val) => val,
-            Err(err) => bail!(
-                "Failed to pull strategies on initialisation, error: {}",
-                err
-            ),
-        };
+        let order_writer = Arc::clone(&orders);
+        if let Some(config) = &settings.spx {
+            SpxSpread::new(
+                Arc::clone(&web_client),
+                Arc::clone(&mktdata),
+                Arc::clone(&orders),
+                config.clone(),
+            )?
+            .market_monitor(cancel_token.clone());
+        }
+        let loss_multiple = settings.loss_multiple;
+        let filter = settings.underlying_filter();
+        let flatten = settings
+            .flatten
+            .as_ref()
+            .map(FlattenRule::new)
+            .transpose()?;
+        let enabled = settings.strategies.clone();
+        let mut strategies =
+            match Self::get_strategies(&web_client, loss_multiple, &filter, None).await {
+                Ok(val) => val,
+                Err(err) => bail!(
+                    "Failed to pull strategies on initialisation, error: {}",
+                    err
+                ),
+            };
         Self::subscribe_to_updates(&strategies, &mktdata, &cancel_token).await;
 
+        let mut position_refresh = interval(Duration::from_secs(settings.position_refresh_secs));
+        let mut stop_check = interval(Duration::from_secs(settings.stop_check_secs));
+        // Positions were just pulled, skip the immediate first tick
+        position_refresh.tick().await;
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     _ = cancel_token.cancelled() => {
                         break
                     }
-                    _ = sleep(Duration::from_secs(30)) => {
-                        strategies = match Self::get_strategies(&web_client).await {
+                    _ = position_refresh.tick() => {
+                        strategies = match Self::get_strategies(&web_client, loss_multiple, &filter, None).await {
                             Ok(val) => {
+                                Self::reconcile(&strategies, &val, &mktdata).await;
                                 Self::subscribe_to_updates(&val, &mktdata, &cancel_token).await;
+                                let greeks = Self::portfolio_greeks(&val, &*mktdata.read().await).await;
+                                info!("Portfolio greeks, {}", greeks);
+                                for snapshot in mktdata.read().await.subscription_report().await {
+                                    warn!(
+                                        "Never received mktdata for symbol: {}, streamer symbol: {}, subscribed {:?} ago",
+                                        snapshot.symbol, snapshot.streamer_symbol, snapshot.subscribed_at.elapsed()
+                                    );
+                                }
                                 val
                             }
                             Err(err) => {
@@ -336,21 +971,54 @@ impl Strategies {
                             }
                         }
                     }
-                    _ = sleep(Duration::from_secs(5)) => {
-                        let read_guard = mktdata.read().await;
-                        for strategy in &strategies {
-                            if let Err(err) = Self::check_stops(strategy, &read_guard, &mut orders).await {
-                                error!("Issue checking stops, error: {}", err);
-                            }
-                        }
-                    }
-                    _ = cancel_token.cancelled() => {
-                        break
+                    _ = stop_check.tick() => {
+                        Self::run_stop_check(&strategies, &mktdata, &order_writer, flatten.as_ref(), &enabled, Utc::now()).await;
                     }
                 }
             }
         });
-        Ok(Self {})
+        Ok(Self { orders })
+    }
+
+    // Waits on any order placement in progress before cancelling, so nothing
+    // placed by the app is left working in the market
+    pub async fn cancel_all_working(&self, timeout: Duration) {
+        let cancel = async { self.orders.cancel_all_working().await };
+        if tokio::time::timeout(timeout, cancel).await.is_err() {
+            error!("Timed out cancelling working orders on shutdown");
+        }
+    }
+
+    async fn reconcile(
+        previous: &[Strategy],
+        current: &[Strategy],
+        mktdata: &Arc<RwLock<MktData>>,
+    ) {
+        let previous: Vec<&Position> = previous.iter().filter_map(Strategy::get_position).collect();
+        let current: Vec<&Position> = current.iter().filter_map(Strategy::get_position).collect();
+        for change in Position::reconcile(&previous, &current) {
+            info!("Position {}", change);
+        }
+
+        // Legs no longer held free up their feed slots
+        let held: HashSet<&str> = current
+            .iter()
+            .flat_map(|position| position.legs.iter().map(|leg| leg.symbol.as_str()))
+            .collect();
+        let closed: HashSet<&str> = previous
+            .iter()
+            .flat_map(|position| position.legs.iter().map(|leg| leg.symbol.as_str()))
+            .filter(|symbol| !held.contains(symbol))
+            .collect();
+        let mut writer = mktdata.write().await;
+        for symbol in closed {
+            if let Err(err) = writer.unsubscribe(symbol).await {
+                error!(
+                    "Failed to unsubscribe from symbol: {} feed, error: {}",
+                    symbol, err
+                );
+            }
+        }
     }
 
     async fn subscribe_to_updates(
@@ -358,132 +1026,254 @@ impl Strategies {
         mktdata: &Arc<RwLock<MktData>>,
         _cancel_token: &CancellationToken,
     ) {
-        fn get_underlying_instrument_type(instrument_type: OptionType) -> OptionType {
-            match instrument_type {
-                OptionType::EquityOption => OptionType::Equity,
-                OptionType::FutureOption => OptionType::Future,
-                _ => panic!("Unsupported Type"),
+        let subscriptions = Self::feed_subscriptions(strategies);
+        // One instruments request per option type covers every leg, the
+        // subscriptions below then find their streamer symbols cached
+        for option_type in [OptionType::EquityOption, OptionType::FutureOption] {
+            let symbols = subscriptions
+                .iter()
+                .filter(|subscription| subscription.option_type == option_type)
+                .map(|subscription| subscription.symbol.as_str())
+                .collect::<Vec<_>>();
+            if symbols.is_empty() {
+                continue;
+            }
+            match mktdata
+                .read()
+                .await
+                .resolve_streamer_symbols(&symbols, option_type)
+                .await
+            {
+                Ok(resolved) => {
+                    let unknown = symbols
+                        .iter()
+                        .filter(|symbol| !resolved.contains_key(**symbol))
+                        .collect::<Vec<_>>();
+                    if !unknown.is_empty() {
+                        warn!("No instruments found for legs: {:?}", unknown);
+                    }
+                }
+                Err(err) => warn!(
+                    "Batched instrument lookup failed, resolving legs one at a time, error: {}",
+                    err
+                ),
             }
         }
-
-        async fn subscribe_to_symbol(
-            symbol: &str,
-            underlying: &str,
-            event_type: &str,
-            option_type: OptionType,
-            strike_price: Option<Decimal>,
-            mktdata: Arc<RwLock<MktData>>,
-        ) {
+        for subscription in subscriptions {
             let mut write_lock = mktdata.write().await;
             if let Err(err) = write_lock
                 .subscribe_to_feed(
-                    symbol,
-                    underlying,
-                    vec![event_type].as_slice(),
-                    option_type,
-                    strike_price,
+                    &subscription.symbol,
+                    &subscription.underlying,
+                    subscription.event_types,
+                    subscription.option_type,
+                    subscription.strike_price,
                 )
                 .await
             {
                 error!(
-                    "Failed to subscribe to symbol: {} feed, error: {}",
-                    symbol, err
+                    "Failed to subscribe to symbol: {} feed, skipping it, error: {}",
+                    subscription.symbol, err
                 );
             }
         }
+    }
 
-        async fn subscribe_to_option_and_underlying<Strat>(
-            strategy: &Strat,
-            mktdata: &Arc<RwLock<MktData>>,
-        ) where
-            Strat: StrategyMeta + Sync + Send,
-        {
+    // Every tracked strategy, the portfolio greeks need all their legs. An
+    // underlying or leg shared by several strategies is subscribed once
+    fn feed_subscriptions(strategies: &[Strategy]) -> Vec<FeedSubscription> {
+        fn get_underlying_instrument_type(instrument_type: OptionType) -> OptionType {
+            match instrument_type {
+                OptionType::EquityOption => OptionType::Equity,
+                OptionType::FutureOption => OptionType::Future,
+                _ => panic!("Unsupported Type"),
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut subscriptions = Vec::new();
+        for strategy in strategies.iter().filter_map(|strategy| strategy.meta()) {
             let underlying = strategy.get_underlying();
-            for leg in strategy.get_position().legs.iter() {
-                subscribe_to_symbol(
-                    &leg.symbol,
-                    underlying,
-                    "Quote",
-                    leg.option_type,
-                    Some(leg.strike_price),
-                    mktdata.clone(),
-                )
-                .await;
+            // Greeks on the legs as well, the same as a resubscribe asks for
+            let legs = strategy
+                .get_position()
+                .legs
+                .iter()
+                .map(|leg| FeedSubscription {
+                    symbol: leg.symbol.clone(),
+                    underlying: underlying.to_string(),
+                    event_types: &["Quote", "Greeks"],
+                    option_type: leg.option_type,
+                    strike_price: Some(leg.strike_price),
+                });
+            let underlying = FeedSubscription {
+                symbol: underlying.to_string(),
+                underlying: underlying.to_string(),
+                event_types: &["Quote"],
+                option_type: get_underlying_instrument_type(strategy.get_instrument_type()),
+                strike_price: None,
+            };
+            for subscription in legs.chain(std::iter::once(underlying)) {
+                if seen.insert((subscription.symbol.clone(), subscription.event_types)) {
+                    subscriptions.push(subscription);
+                }
             }
+        }
+        subscriptions
+    }
 
-            subscribe_to_symbol(
-                underlying,
-                underlying,
-                "Quote",
-                get_underlying_instrument_type(strategy.get_instrument_type()),
-                None,
-                mktdata.clone(),
-            )
-            .await;
+    async fn portfolio_greeks(strategies: &[Strategy], mktdata: &MktData) -> PortfolioGreeks {
+        let mut greeks = PortfolioGreeks::default();
+        for position in strategies
+            .iter()
+            .filter_map(|strategy| strategy.get_position())
+        {
+            for leg in &position.legs {
+                let snapshot = mktdata.get_snapshot_by_symbol::<Greeks>(&leg.symbol).await;
+                greeks.add(leg, snapshot.and_then(|snapshot| snapshot.greeks).as_ref());
+            }
+        }
+        greeks
+    }
+
+    // Exits are decided under one mktdata read that is dropped before any
+    // order goes out, liquidating reads mktdata again and would otherwise
+    // queue behind a writer that is itself waiting on this read
+    async fn run_stop_check(
+        strategies: &[Strategy],
+        mktdata: &RwLock<MktData>,
+        orders: &Orders,
+        flatten: Option<&FlattenRule>,
+        enabled: &StrategiesConfig,
+        now: DateTime<Utc>,
+    ) {
+        let mut exits = Vec::new();
+        {
+            let read_guard = mktdata.read().await;
+            for strategy in strategies {
+                // Exit decisions on a dead underlying would act on stale prices
+                let underlying = strategy
+                    .get_position()
+                    .and_then(|position| position.legs.first())
+                    .map(|leg| leg.underlying.as_str());
+                if let Some(underlying) = underlying {
+                    if read_guard.is_stale(underlying).await {
+                        warn!(
+                            "Pausing stop checks for {}, underlying {} is stale",
+                            strategy, underlying
+                        );
+                        continue;
+                    }
+                }
+                if let Some(reason) =
+                    Self::check_stops(strategy, &read_guard, flatten, enabled, now).await
+                {
+                    exits.push((strategy, underlying, reason));
+                }
+            }
         }
 
-        for strategy in strategies {
-            match &strategy {
-                Strategy::Credit(strategy) => {
-                    subscribe_to_option_and_underlying(strategy, mktdata).await
+        for (strategy, underlying, reason) in exits {
+            match Self::send_liquidate(strategy, orders, reason).await {
+                Ok(()) => (),
+                Err(err) if err.is_retryable() => {
+                    warn!(
+                        underlying,
+                        "Deferring liquidation to next stop check, error: {}", err
+                    )
                 }
-                // Strategy::Calendar(strat) => subscribe(strat, mktdata).await,
-                // Strategy::Condor(strat) => subscribe(strat, mktdata).await,
-                _ => (),
+                Err(err) => error!(underlying, "Failed to liquidate position, error: {}", err),
             }
         }
     }
 
+    async fn send_liquidate(
+        strategy: &Strategy,
+        orders: &Orders,
+        reason: ExitReason,
+    ) -> Result<(), StrategyError> {
+        let Some(strat) = strategy.meta() else {
+            return Ok(());
+        };
+        let price_effect = match strat.get_position().legs[0].direction {
+            Direction::Short => PriceEffect::Credit,
+            Direction::Long => PriceEffect::Debit,
+        };
+        orders
+            .liquidate_position(strat, price_effect, reason, CloseSize::default())
+            .await
+    }
+
     async fn check_stops(
         strategy: &Strategy,
         mktdata: &MktData,
-        orders: &mut Orders,
-    ) -> Result<()> {
-        async fn send_liquidate<Strat>(strat: &Strat, orders: &mut Orders) -> Result<()>
-        where
-            Strat: StrategyMeta,
-        {
-            let price_effect = match strat.get_position().legs[0].direction {
-                Direction::Short => PriceEffect::Credit,
-                Direction::Long => PriceEffect::Debit,
-            };
-            orders.liquidate_position(strat, price_effect).await
+        flatten: Option<&FlattenRule>,
+        enabled: &StrategiesConfig,
+        now: DateTime<Utc>,
+    ) -> Option<ExitReason> {
+        let strat = strategy.meta()?;
+        // Assignment risk applies whatever the strategy, so this runs ahead
+        // of the per strategy exit rules
+        let reason = match flatten.is_some_and(|rule| rule.is_due(strat.get_position(), now)) {
+            true => Some(ExitReason::Expiring),
+            false => match strategy {
+                Strategy::Credit(strat) => strat.should_exit(mktdata).await,
+                Strategy::Diagonal(strat) => strat.should_exit(mktdata).await,
+                Strategy::Butterfly(strat) => strat.should_exit(mktdata).await,
+                // Strategy::Calendar(strat) => strat.should_exit(mktdata).await,
+                // Strategy::Condor(strat) => strat.should_exit(mktdata).await,
+                _ => None,
+            },
+        };
+        let reason = reason?;
+        // Disabled types are still watched so the trader sees the signal
+        if !enabled.is_enabled(strat.get_position().strategy_type) {
+            info!(
+                "Not liquidating {}, strategy type is disabled, exit reason: {}",
+                strategy, reason
+            );
+            return None;
+        }
+        if reason == ExitReason::Expiring {
+            info!("Flattening {} ahead of expiration", strategy);
         }
+        Some(reason)
+    }
 
-        match strategy {
-            Strategy::Credit(strat) => {
-                if strat.should_exit(mktdata).await {
-                    match send_liquidate(strat, orders).await {
-                        Ok(val) => val,
-                        Err(err) => error!("Failed to liquidate position, error: {}", err),
-                    }
-                }
-            }
-            // Strategy::Calendar(strat) => {
-            //     if strat.should_exit(mktdata).await {
-            //         match send_liquidate(strat, orders).await {
-            //             Ok(val) => val,
-            //             Err(err) => error!("Failed to liquidate position, error: {}", err),
-            //         }
-            //     }
-            // }
-            // Strategy::Condor(strat) => {
-            //     if strat.should_exit(mktdata).await {
-            //         match send_liquidate(strat, orders).await {
-            //             Ok(val) => val,
-            //             Err(err) => error!("Failed to liquidate position, error: {}", err),
-            //         }
-            //     }
-            // }
-            _ => (),
+    pub async fn print_positions(
+        web_client: &WebClient,
+        settings: &Settings,
+        underlying: Option<&str>,
+    ) -> Result<()> {
+        let strategies = Self::get_strategies(
+            web_client,
+            settings.loss_multiple,
+            &UnderlyingFilter::default(),
+            underlying,
+        )
+        .await?;
+        if strategies.is_empty() {
+            println!("No open positions");
         }
+        strategies
+            .iter()
+            .for_each(|strategy| println!("{}", strategy));
         Ok(())
     }
 
-    async fn get_strategies(web_client: &WebClient) -> Result<Vec<Strategy>> {
+    async fn get_strategies(
+        web_client: &WebClient,
+        loss_multiple: Option<Decimal>,
+        filter: &UnderlyingFilter,
+        underlying: Option<&str>,
+    ) -> Result<Vec<Strategy>> {
+        let query = underlying
+            .map(|underlying| vec![("underlying-symbol", underlying)])
+            .unwrap_or_default();
         let positions = match web_client
-            .get::<AccountPositions>(
+            .get_with_query::<AccountPositions>(
                 format!("accounts/{}/positions", web_client.get_account()).as_str(),
+                &query,
             )
             .await
         {
@@ -495,26 +1285,96 @@ impl Strategies {
                 )
             }
         };
-        Ok(Self::convert_api_data_into_strategies(positions.data.legs).await)
+        let instruments =
+            Self::get_future_option_instruments(web_client, &positions.data.legs).await;
+        Ok(Self::convert_api_data_into_strategies(
+            positions.data.legs,
+            &instruments,
+            loss_multiple,
+            filter,
+        )
+        .await)
+    }
+
+    async fn get_future_option_instruments(
+        web_client: &WebClient,
+        legs: &[Leg],
+    ) -> HashMap<String, FutureOption> {
+        let mut instruments = HashMap::new();
+        for leg in legs
+            .iter()
+            .filter(|leg| leg.instrument_type.as_deref() == Some("Future Option"))
+        {
+            let symbol = utf8_percent_encode(&leg.symbol, UTF8_ECODING).to_string();
+            match web_client
+                .get::<Response<FutureOption>>(&format!("instruments/future-options/{}", symbol))
+                .await
+            {
+                Ok(val) => {
+                    instruments.insert(leg.symbol.clone(), val.data);
+                }
+                Err(err) => warn!(
+                    "Failed to fetch instrument data for symbol: {}, error: {}",
+                    leg.symbol, err
+                ),
+            }
+        }
+        instruments
     }
 
-    async fn convert_api_data_into_strategies(legs: Vec<Leg>) -> Vec<Strategy> {
+    async fn convert_api_data_into_strategies(
+        legs: Vec<Leg>,
+        instruments: &HashMap<String, FutureOption>,
+        loss_multiple: Option<Decimal>,
+        filter: &UnderlyingFilter,
+    ) -> Vec<Strategy> {
         let mut sorted_legs: HashMap<String, Vec<Leg>> = HashMap::new();
 
+        // Suppressed legs are expired or otherwise removed but still listed
         legs.iter().for_each(|leg| {
+            if leg.is_suppressed {
+                info!("Skipping suppressed position: {}", leg.symbol);
+                return;
+            }
             let underlying = leg.underlying_symbol.clone().unwrap(); // Assuming underlying_symbol is a string
             sorted_legs.entry(underlying).or_default().push(leg.clone());
         });
 
         let strats: Vec<Strategy> = sorted_legs
-            .values()
-            .map(|legs| {
-                let spread = Position::new(legs.clone());
+            .iter()
+            .map(|(underlying, legs)| {
+                if !filter.is_monitored(underlying) {
+                    info!("Leaving position in {} unmanaged", underlying);
+                    return Strategy::NotTracked;
+                }
+                // A frozen leg cannot be traded, orders against it would
+                // only be rejected
+                if let Some(frozen) = legs.iter().find(|leg| leg.is_frozen) {
+                    warn!(
+                        "Leaving position in {} unmanaged, leg: {} is frozen",
+                        underlying, frozen.symbol
+                    );
+                    return Strategy::NotTracked;
+                }
+                let spread = match Position::new(legs.clone(), instruments) {
+                    Ok(spread) => spread,
+                    Err(err) => {
+                        warn!(
+                            "Leaving position in {} unmanaged, error: {}",
+                            underlying, err
+                        );
+                        return Strategy::NotTracked;
+                    }
+                };
 
                 match &spread.strategy_type {
-                    StrategyType::CreditSpread => Strategy::Credit(CreditSpread::new(spread)),
+                    StrategyType::CreditSpread => {
+                        Strategy::Credit(CreditSpread::new(spread, loss_multiple))
+                    }
                     StrategyType::CalendarSpread => Strategy::Calendar(CalendarSpread::new(spread)),
+                    StrategyType::Diagonal => Strategy::Diagonal(DiagonalSpread::new(spread)),
                     StrategyType::IronCondor => Strategy::Condor(IronCondor::new(spread)),
+                    StrategyType::IronButterfly => Strategy::Butterfly(IronButterfly::new(spread)),
                     _ => Strategy::NotTracked,
                 }
             })
@@ -527,9 +1387,357 @@ impl Strategies {
     fn print_strategy_data(strats: &[Strategy]) {
         strats.iter().for_each(|strategy| match strategy {
             Strategy::Calendar(strat) => strat.print(),
+            Strategy::Diagonal(strat) => strat.print(),
             Strategy::Credit(strat) => strat.print(),
             Strategy::Condor(strat) => strat.print(),
+            Strategy::Butterfly(strat) => strat.print(),
             _ => (),
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::make_mktdata;
+    use crate::utils::fixtures::make_web_client;
+    use crate::utils::fixtures::UNREACHABLE_URL;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_exceeds_max_loss() {
+        assert!(CreditSpread::exceeds_max_loss(
+            Some(dec!(1.00)),
+            dec!(2.10),
+            dec!(2)
+        ));
+        assert!(!CreditSpread::exceeds_max_loss(
+            Some(dec!(1.00)),
+            dec!(1.90),
+            dec!(2)
+        ));
+        assert!(!CreditSpread::exceeds_max_loss(None, dec!(5.00), dec!(2)));
+    }
+
+    #[test]
+    fn test_spx_contracts_capped_by_buying_power() {
+        let make_leg = |strike_price: Decimal, direction: Direction| OptionLeg {
+            symbol: format!("SPXW  240315P0{}000", strike_price),
+            underlying: SPX.to_string(),
+            expiration_date: NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+            direction,
+            side: OptionSide::Put,
+            strike_price,
+            quantity: 1,
+            multiplier: Decimal::from(100),
+            average_open_price: None,
+            mark_price: None,
+            option_type: OptionType::EquityOption,
+        };
+        let position = Position {
+            legs: vec![
+                make_leg(dec!(5100), Direction::Short),
+                make_leg(dec!(5095), Direction::Long),
+            ],
+            strategy_type: StrategyType::CreditSpread,
+        };
+        let margin = position.estimated_margin();
+        assert_eq!(margin, dec!(500));
+
+        assert_eq!(SpxSpread::affordable_contracts(2, margin, dec!(10000)), 2);
+        assert_eq!(SpxSpread::affordable_contracts(2, margin, dec!(999)), 1);
+        assert_eq!(SpxSpread::affordable_contracts(2, margin, dec!(499)), 0);
+        assert_eq!(SpxSpread::affordable_contracts(2, margin, dec!(-50)), 0);
+    }
+
+    #[test]
+    fn test_portfolio_greeks_weighted_and_partial() {
+        let make_leg = |quantity: i32, direction: Direction| OptionLeg {
+            symbol: String::from("SPY   240315P00420000"),
+            underlying: String::from("SPY"),
+            expiration_date: NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+            direction,
+            side: OptionSide::Put,
+            strike_price: dec!(420),
+            quantity,
+            multiplier: Decimal::from(100),
+            average_open_price: None,
+            mark_price: None,
+            option_type: OptionType::EquityOption,
+        };
+        let make_greeks = |delta: f64, theta: f64| -> Greeks {
+            serde_json::from_value(serde_json::json!({
+                "eventSymbol": ".SPY240315P420", "price": 1.0, "volatility": 0.2,
+                "delta": delta, "gamma": 0.01, "theta": theta, "rho": 0.0, "vega": 0.1,
+            }))
+            .unwrap()
+        };
+
+        let mut greeks = PortfolioGreeks::default();
+        greeks.add(
+            &make_leg(2, Direction::Short),
+            Some(&make_greeks(-0.30, -0.05)),
+        );
+        greeks.add(
+            &make_leg(2, Direction::Long),
+            Some(&make_greeks(-0.20, -0.04)),
+        );
+        assert!((greeks.delta - 0.2).abs() < 1e-9);
+        assert!((greeks.theta - 0.02).abs() < 1e-9);
+        assert_eq!(greeks.gamma, 0.);
+        assert_eq!(greeks.legs, 2);
+        assert!(!greeks.is_partial());
+
+        greeks.add(&make_leg(1, Direction::Short), None);
+        assert_eq!(greeks.legs, 2);
+        assert!(greeks.is_partial());
+        assert!(greeks
+            .to_string()
+            .ends_with("partial, 1 legs without greeks"));
+    }
+
+    #[test]
+    fn test_shared_underlying_subscribed_once() {
+        let make_spread = |strikes: [Decimal; 2]| {
+            let make_leg = |strike_price: Decimal, direction: Direction| OptionLeg {
+                symbol: format!("SPXW  240315P0{}000", strike_price),
+                underlying: SPX.to_string(),
+                expiration_date: NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+                direction,
+                side: OptionSide::Put,
+                strike_price,
+                quantity: 1,
+                multiplier: Decimal::from(100),
+                average_open_price: None,
+                mark_price: None,
+                option_type: OptionType::EquityOption,
+            };
+            Strategy::Credit(CreditSpread::new(
+                Position {
+                    legs: vec![
+                        make_leg(strikes[0], Direction::Short),
+                        make_leg(strikes[1], Direction::Long),
+                    ],
+                    strategy_type: StrategyType::CreditSpread,
+                },
+                None,
+            ))
+        };
+        let strategies = vec![
+            make_spread([dec!(5100), dec!(5090)]),
+            make_spread([dec!(5090), dec!(5080)]),
+        ];
+
+        let subscriptions = Strategies::feed_subscriptions(&strategies);
+        let symbols = subscriptions
+            .iter()
+            .map(|subscription| subscription.symbol.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            symbols,
+            vec![
+                "SPXW  240315P05100000",
+                "SPXW  240315P05090000",
+                SPX,
+                "SPXW  240315P05080000",
+            ]
+        );
+        assert_eq!(subscriptions[2].option_type, OptionType::Equity);
+        assert_eq!(subscriptions[2].event_types, ["Quote"]);
+    }
+
+    fn make_leg(symbol: &str, underlying: &str, direction: &str) -> Leg {
+        serde_json::from_value(serde_json::json!({
+            "symbol": symbol,
+            "instrument-type": "Equity Option",
+            "underlying-symbol": underlying,
+            "quantity": 1,
+            "quantity-direction": direction,
+            "average-open-price": "1.00",
+            "is-frozen": false,
+            "is-suppressed": false,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_frozen_and_suppressed_legs() {
+        let mut frozen = make_leg("AAPL  240315P00165000", "AAPL", "Long");
+        frozen.is_frozen = true;
+        let mut suppressed = make_leg("SPY   240308P00410000", "SPY", "Long");
+        suppressed.is_suppressed = true;
+        let legs = vec![
+            make_leg("AAPL  240315P00170000", "AAPL", "Short"),
+            frozen,
+            make_leg("SPY   240315P00420000", "SPY", "Short"),
+            make_leg("SPY   240315P00415000", "SPY", "Long"),
+            suppressed,
+        ];
+
+        let strategies = Strategies::convert_api_data_into_strategies(
+            legs,
+            &HashMap::new(),
+            None,
+            &UnderlyingFilter::default(),
+        )
+        .await;
+
+        assert_eq!(strategies.len(), 2);
+        let tracked = strategies
+            .iter()
+            .filter_map(|strategy| strategy.get_position())
+            .collect::<Vec<_>>();
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].strategy_type, StrategyType::CreditSpread);
+        assert_eq!(tracked[0].legs[0].underlying, "SPY");
+        assert_eq!(tracked[0].legs.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unmonitored_underlyings_not_tracked() {
+        let legs = vec![
+            make_leg("AAPL  240315P00170000", "AAPL", "Short"),
+            make_leg("AAPL  240315P00165000", "AAPL", "Long"),
+            make_leg("SPY   240315P00420000", "SPY", "Short"),
+            make_leg("SPY   240315P00415000", "SPY", "Long"),
+        ];
+        let filter = UnderlyingFilter {
+            monitored: Some(vec![String::from("SPY")]),
+            ignored: Vec::new(),
+        };
+
+        let strategies =
+            Strategies::convert_api_data_into_strategies(legs, &HashMap::new(), None, &filter)
+                .await;
+
+        let tracked: Vec<&str> = strategies
+            .iter()
+            .filter_map(|strategy| strategy.get_position())
+            .map(|position| position.legs[0].underlying.as_str())
+            .collect();
+        assert_eq!(tracked, vec!["SPY"]);
+        assert_eq!(strategies.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unresolved_legs_skipped_on_subscribe() {
+        let legs = vec![
+            make_leg("SPY   240315P00420000", "SPY", "Short"),
+            make_leg("SPY   240315P00415000", "SPY", "Long"),
+        ];
+        let strategies = Strategies::convert_api_data_into_strategies(
+            legs,
+            &HashMap::new(),
+            None,
+            &UnderlyingFilter::default(),
+        )
+        .await;
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mktdata = Arc::new(RwLock::new(make_mktdata(web_client, &cancel_token)));
+
+        // Both the batched and the per leg lookups fail, every leg is
+        // logged and skipped rather than taking the process down
+        Strategies::subscribe_to_updates(&strategies, &mktdata, &cancel_token).await;
+        cancel_token.cancel();
+        assert!(mktdata
+            .read()
+            .await
+            .get_snapshot_by_symbol::<Quote>("SPY   240315P00420000")
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_diagonal_spread_is_tracked() {
+        let legs = vec![
+            make_leg("SPY   240315C00430000", "SPY", "Short"),
+            make_leg("SPY   240419C00425000", "SPY", "Long"),
+        ];
+
+        let strategies = Strategies::convert_api_data_into_strategies(
+            legs,
+            &HashMap::new(),
+            None,
+            &UnderlyingFilter::default(),
+        )
+        .await;
+
+        assert_eq!(strategies.len(), 1);
+        let Strategy::Diagonal(diagonal) = &strategies[0] else {
+            panic!("Expected a diagonal spread, got: {}", strategies[0]);
+        };
+        assert_eq!(diagonal.get_underlying(), "SPY");
+        assert_eq!(
+            diagonal.get_symbols(),
+            vec!["SPY   240315C00430000", "SPY   240419C00425000"]
+        );
+        assert_eq!(
+            diagonal.get_position().strategy_type,
+            StrategyType::Diagonal
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stop_check_with_writer_queued() {
+        use crate::settings::FlattenConfig;
+        use chrono::TimeZone;
+
+        let cancel_token = CancellationToken::new();
+        let web_client = make_web_client(UNREACHABLE_URL, &cancel_token).await;
+        let mktdata = Arc::new(RwLock::new(make_mktdata(
+            Arc::clone(&web_client),
+            &cancel_token,
+        )));
+        let orders = Arc::new(Orders::new_for_test(web_client, Arc::clone(&mktdata)));
+        let strategies = Strategies::convert_api_data_into_strategies(
+            vec![
+                make_leg("AAPL  240315P00170000", "AAPL", "Short"),
+                make_leg("AAPL  240315P00165000", "AAPL", "Long"),
+            ],
+            &HashMap::new(),
+            None,
+            &UnderlyingFilter::default(),
+        )
+        .await;
+        let flatten = FlattenRule::new(&FlattenConfig {
+            before: String::from("15:45"),
+            cash_settled: false,
+        })
+        .unwrap();
+        // 16:00 in New York on expiry day, so the spread is flattened
+        let now = Utc.with_ymd_and_hms(2024, 3, 15, 20, 0, 0).unwrap();
+
+        // The liquidation waits on the underlying while a writer queues up
+        let guard = orders.lock_underlying("AAPL").await;
+        let stop_check = tokio::spawn({
+            let (mktdata, orders) = (Arc::clone(&mktdata), Arc::clone(&orders));
+            async move {
+                Strategies::run_stop_check(
+                    &strategies,
+                    &mktdata,
+                    &orders,
+                    Some(&flatten),
+                    &StrategiesConfig::default(),
+                    now,
+                )
+                .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let writer = tokio::spawn({
+            let mktdata = Arc::clone(&mktdata);
+            async move { drop(mktdata.write().await) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(guard);
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            stop_check.await.unwrap();
+            writer.await.unwrap();
+        })
+        .await
+        .unwrap();
+        cancel_token.cancel();
+    }
+}
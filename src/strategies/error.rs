@@ -0,0 +1,93 @@
+use std::fmt;
+
+use crate::web_client::errors::ApiError;
+
+#[derive(Debug)]
+pub enum StrategyError {
+    NoMarketData,
+    StaleQuote,
+    BrokerRejected(String),
+    InsufficientBuyingPower,
+    // The broker did not answer in time, what became of the request is unknown
+    Timeout,
+    Other(anyhow::Error),
+}
+
+impl StrategyError {
+    // Sorts a failed order placement by the reason the broker gave
+    pub fn from_broker(err: anyhow::Error) -> Self {
+        if Self::is_timeout(&err) {
+            return StrategyError::Timeout;
+        }
+        let msg = err.to_string();
+        let lower = msg.to_lowercase();
+        if lower.contains("buying power") || lower.contains("buying_power") {
+            StrategyError::InsufficientBuyingPower
+        } else {
+            StrategyError::BrokerRejected(msg)
+        }
+    }
+
+    fn is_timeout(err: &anyhow::Error) -> bool {
+        err.downcast_ref::<ApiError>() == Some(&ApiError::Timeout)
+    }
+
+    // Market data errors and broker timeouts clear up on their own, the next
+    // check can retry
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            StrategyError::NoMarketData | StrategyError::StaleQuote | StrategyError::Timeout
+        )
+    }
+}
+
+impl fmt::Display for StrategyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StrategyError::NoMarketData => write!(f, "No market data available"),
+            StrategyError::StaleQuote => write!(f, "Quote is stale"),
+            StrategyError::BrokerRejected(msg) => write!(f, "Broker rejected order: {}", msg),
+            StrategyError::InsufficientBuyingPower => write!(f, "Insufficient buying power"),
+            StrategyError::Timeout => write!(f, "Broker request timed out"),
+            StrategyError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for StrategyError {}
+
+impl From<anyhow::Error> for StrategyError {
+    fn from(err: anyhow::Error) -> Self {
+        match Self::is_timeout(&err) {
+            true => StrategyError::Timeout,
+            false => StrategyError::Other(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_from_broker_classification() {
+        let err = StrategyError::from_broker(anyhow!(
+            "{{\"code\":\"margin_check_failed\",\"message\":\"Insufficient buying power\"}}"
+        ));
+        assert!(matches!(err, StrategyError::InsufficientBuyingPower));
+
+        let err = StrategyError::from_broker(anyhow!("invalid_price"));
+        assert!(matches!(&err, StrategyError::BrokerRejected(msg) if msg == "invalid_price"));
+        assert!(!err.is_retryable());
+        assert!(StrategyError::StaleQuote.is_retryable());
+
+        let timeout = || anyhow::Error::new(ApiError::Timeout).context("get request timed out");
+        assert!(matches!(
+            StrategyError::from_broker(timeout()),
+            StrategyError::Timeout
+        ));
+        assert!(StrategyError::from(timeout()).is_retryable());
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::NaiveTime;
+use chrono::Utc;
+use chrono_tz::America::New_York;
+
+use crate::positions::Position;
+use crate::settings::FlattenConfig;
+
+// Closes positions on their expiration day once the New York time passes
+// before, ahead of any assignment. Cash settled legs carry no assignment so
+// only count when the config asks for them
+#[derive(Debug, Clone)]
+pub(crate) struct FlattenRule {
+    before: NaiveTime,
+    cash_settled: bool,
+}
+
+impl FlattenRule {
+    pub fn new(config: &FlattenConfig) -> Result<Self> {
+        Ok(Self {
+            before: config.before()?,
+            cash_settled: config.cash_settled,
+        })
+    }
+
+    pub fn is_due(&self, position: &Position, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&New_York);
+        if local.time() < self.before {
+            return false;
+        }
+        let Some(nearest) = position.legs.iter().map(|leg| leg.expiration_date).min() else {
+            return false;
+        };
+        nearest <= local.date_naive()
+            && position.legs.iter().any(|leg| {
+                leg.expiration_date == nearest && (self.cash_settled || !leg.is_cash_settled())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::positions::Direction;
+    use crate::positions::OptionLeg;
+    use crate::positions::OptionSide;
+    use crate::positions::OptionType;
+    use crate::positions::StrategyType;
+    use chrono::NaiveDate;
+    use chrono::TimeZone;
+    use rust_decimal::Decimal;
+
+    fn make_position(underlying: &str, expirations: [u32; 2]) -> Position {
+        let make_leg = |day: u32, direction: Direction| OptionLeg {
+            symbol: format!("{} {} {:?}", underlying, day, direction),
+            underlying: underlying.to_string(),
+            expiration_date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+            direction,
+            side: OptionSide::Put,
+            strike_price: Decimal::from(420),
+            quantity: 1,
+            multiplier: Decimal::from(100),
+            average_open_price: None,
+            mark_price: None,
+            option_type: OptionType::EquityOption,
+        };
+        Position {
+            legs: vec![
+                make_leg(expirations[0], Direction::Short),
+                make_leg(expirations[1], Direction::Long),
+            ],
+            strategy_type: StrategyType::CalendarSpread,
+        }
+    }
+
+    fn make_rule(cash_settled: bool) -> FlattenRule {
+        FlattenRule::new(&FlattenConfig {
+            before: String::from("15:45"),
+            cash_settled,
+        })
+        .unwrap()
+    }
+
+    // New York is on EDT by the 15th of March
+    fn new_york(day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, day, hour + 4, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_due_after_cutoff_on_expiration_day() {
+        let rule = make_rule(false);
+        let position = make_position("SPY", [15, 22]);
+        assert!(!rule.is_due(&position, new_york(15, 15, 44)));
+        assert!(rule.is_due(&position, new_york(15, 15, 45)));
+        // Only the nearest leg decides, the back month is days away
+        assert!(!rule.is_due(&position, new_york(14, 15, 50)));
+        assert!(!rule.is_due(&make_position("SPY", [22, 29]), new_york(15, 15, 50)));
+    }
+
+    #[test]
+    fn test_cash_settled_left_alone_by_default() {
+        let position = make_position("SPX", [15, 15]);
+        assert!(!make_rule(false).is_due(&position, new_york(15, 15, 50)));
+        assert!(make_rule(true).is_due(&position, new_york(15, 15, 50)));
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::bail;
+use anyhow::Result;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use super::CreditSpread;
+use super::IronButterfly;
+use super::IronCondor;
+use super::Strategy;
+use crate::orders::Orders;
+use crate::positions::Direction;
+use crate::positions::OptionLeg;
+use crate::positions::OptionSide;
+use crate::positions::OptionType;
+use crate::positions::Position;
+use crate::positions::PriceEffect;
+use crate::positions::StrategyType;
+use crate::settings::Settings;
+use crate::tt_api::option_chain::OptionChain;
+use crate::tt_api::orders::DryRunResult;
+use crate::web_client::WebClient;
+
+// Spreads the preview command can describe. A vertical takes the short
+// strike then the long one, the condor and butterfly take theirs low to high
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum PreviewStrategy {
+    PutSpread,
+    CallSpread,
+    IronCondor,
+    IronButterfly,
+}
+
+// A position described on the command line rather than held, sold at price
+#[derive(Debug, Clone)]
+pub(crate) struct Preview {
+    pub underlying: String,
+    pub strategy: PreviewStrategy,
+    pub expiration: NaiveDate,
+    pub strikes: Vec<Decimal>,
+    pub quantity: i32,
+    pub price: Decimal,
+}
+
+impl Preview {
+    pub async fn run(&self, web_client: &WebClient, settings: &Settings) -> Result<DryRunResult> {
+        let chain = web_client.get_option_chain(&self.underlying).await?;
+        let position = self.position(&chain)?;
+        let strategy = match self.strategy {
+            PreviewStrategy::PutSpread | PreviewStrategy::CallSpread => {
+                Strategy::Credit(CreditSpread::new(position, None))
+            }
+            PreviewStrategy::IronCondor => Strategy::Condor(IronCondor::new(position)),
+            PreviewStrategy::IronButterfly => Strategy::Butterfly(IronButterfly::new(position)),
+        };
+        let Some(meta) = strategy.meta() else {
+            bail!("{:?} cannot be previewed", self.strategy);
+        };
+        Orders::preview_position(
+            web_client,
+            meta,
+            PriceEffect::Credit,
+            self.price,
+            &settings.order,
+        )
+        .await
+    }
+
+    fn legs(&self) -> Result<Vec<(OptionSide, Direction, Decimal)>> {
+        use Direction::Long;
+        use Direction::Short;
+        use OptionSide::Call;
+        use OptionSide::Put;
+
+        let legs = match (self.strategy, self.strikes.as_slice()) {
+            (PreviewStrategy::PutSpread, &[short, long]) => {
+                vec![(Put, Short, short), (Put, Long, long)]
+            }
+            (PreviewStrategy::CallSpread, &[short, long]) => {
+                vec![(Call, Short, short), (Call, Long, long)]
+            }
+            (PreviewStrategy::IronCondor, &[long_put, short_put, short_call, long_call]) => vec![
+                (Put, Long, long_put),
+                (Put, Short, short_put),
+                (Call, Short, short_call),
+                (Call, Long, long_call),
+            ],
+            (PreviewStrategy::IronButterfly, &[long_put, body, long_call]) => vec![
+                (Put, Long, long_put),
+                (Put, Short, body),
+                (Call, Short, body),
+                (Call, Long, long_call),
+            ],
+            (strategy, strikes) => bail!(
+                "{:?} takes {} strikes, got {}",
+                strategy,
+                match strategy {
+                    PreviewStrategy::PutSpread | PreviewStrategy::CallSpread => 2,
+                    PreviewStrategy::IronCondor => 4,
+                    PreviewStrategy::IronButterfly => 3,
+                },
+                strikes.len()
+            ),
+        };
+        Ok(legs)
+    }
+
+    // Legs take the broker's symbols from the chain, a strike the chain does
+    // not list for the expiration is an error rather than a guessed symbol
+    fn position(&self, chain: &OptionChain) -> Result<Position> {
+        if self.quantity <= 0 {
+            bail!("Quantity: {} must be positive", self.quantity);
+        }
+        let expiration_date = self.expiration.format("%Y-%m-%d").to_string();
+        let Some(expiration) = chain
+            .expirations
+            .iter()
+            .find(|expiration| expiration.expiration_date == expiration_date)
+        else {
+            bail!("No {} expiration on: {}", self.underlying, expiration_date);
+        };
+        let legs = self
+            .legs()?
+            .into_iter()
+            .map(|(side, direction, strike_price)| {
+                let Some(strike) = expiration
+                    .strikes
+                    .iter()
+                    .find(|strike| strike.strike_price == strike_price)
+                else {
+                    bail!(
+                        "No {} strike: {} for expiration: {}",
+                        self.underlying,
+                        strike_price,
+                        expiration_date
+                    );
+                };
+                Ok(OptionLeg {
+                    symbol: match side {
+                        OptionSide::Call => strike.call.clone(),
+                        OptionSide::Put => strike.put.clone(),
+                    },
+                    underlying: chain.underlying_symbol.clone(),
+                    expiration_date: self.expiration,
+                    direction,
+                    side,
+                    strike_price,
+                    quantity: self.quantity,
+                    multiplier: Decimal::from(chain.shares_per_contract.unwrap_or(100)),
+                    average_open_price: None,
+                    mark_price: None,
+                    option_type: OptionType::EquityOption,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Position {
+            legs,
+            strategy_type: match self.strategy {
+                PreviewStrategy::PutSpread | PreviewStrategy::CallSpread => {
+                    StrategyType::CreditSpread
+                }
+                PreviewStrategy::IronCondor => StrategyType::IronCondor,
+                PreviewStrategy::IronButterfly => StrategyType::IronButterfly,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn make_chain() -> OptionChain {
+        let strikes = [dec!(5080), dec!(5090), dec!(5100), dec!(5110)]
+            .iter()
+            .map(|strike| {
+                serde_json::json!({
+                    "strike-price": strike.to_string(),
+                    "call": format!("SPXW  240315C0{}000", strike),
+                    "call-streamer-symbol": format!(".SPXW240315C{}", strike),
+                    "put": format!("SPXW  240315P0{}000", strike),
+                    "put-streamer-symbol": format!(".SPXW240315P{}", strike),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::from_value(serde_json::json!({
+            "underlying-symbol": "SPX",
+            "root-symbol": "SPXW",
+            "shares-per-contract": 100,
+            "expirations": [{
+                "expiration-date": "2024-03-15",
+                "days-to-expiration": 0,
+                "strikes": strikes,
+            }],
+        }))
+        .unwrap()
+    }
+
+    fn make_preview(strategy: PreviewStrategy, strikes: Vec<Decimal>) -> Preview {
+        Preview {
+            underlying: String::from("SPX"),
+            strategy,
+            expiration: NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+            strikes,
+            quantity: 2,
+            price: dec!(2.50),
+        }
+    }
+
+    #[test]
+    fn test_preview_resolves_legs_from_chain() {
+        let chain = make_chain();
+        let position = make_preview(PreviewStrategy::PutSpread, vec![dec!(5100), dec!(5090)])
+            .position(&chain)
+            .unwrap();
+        assert_eq!(position.strategy_type, StrategyType::CreditSpread);
+        let legs = position
+            .legs
+            .iter()
+            .map(|leg| (leg.symbol.as_str(), leg.direction, leg.quantity))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            legs,
+            vec![
+                ("SPXW  240315P05100000", Direction::Short, 2),
+                ("SPXW  240315P05090000", Direction::Long, 2),
+            ]
+        );
+
+        let position = make_preview(
+            PreviewStrategy::IronButterfly,
+            vec![dec!(5080), dec!(5100), dec!(5110)],
+        )
+        .position(&chain)
+        .unwrap();
+        assert_eq!(position.legs.len(), 4);
+        assert_eq!(position.legs[2].symbol, "SPXW  240315C05100000");
+
+        let err = make_preview(PreviewStrategy::IronCondor, vec![dec!(5080), dec!(5090)])
+            .position(&chain)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "IronCondor takes 4 strikes, got 2");
+
+        let err = make_preview(PreviewStrategy::CallSpread, vec![dec!(5105), dec!(5110)])
+            .position(&chain)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "No SPX strike: 5105 for expiration: 2024-03-15"
+        );
+    }
+}
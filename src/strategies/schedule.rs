@@ -0,0 +1,103 @@
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use chrono::Utc;
+use chrono::Weekday;
+use chrono_tz::America::New_York;
+
+use crate::settings::SpxConfig;
+
+// Due within the window after each configured New York time on weekdays, at
+// most one entry is made a day. Market holidays are not known here, an entry
+// on one fails for want of a 0DTE expiration
+#[derive(Debug, Clone)]
+pub(crate) struct EntrySchedule {
+    times: Vec<NaiveTime>,
+    window: Duration,
+    last_entry: Option<NaiveDate>,
+}
+
+impl EntrySchedule {
+    pub fn new(config: &SpxConfig) -> Result<Self> {
+        Ok(Self {
+            times: config.entry_times()?,
+            window: Duration::minutes(config.entry_window_mins),
+            last_entry: None,
+        })
+    }
+
+    // Always due when no times are configured
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        if self.times.is_empty() {
+            return true;
+        }
+        let local = now.with_timezone(&New_York);
+        if matches!(local.weekday(), Weekday::Sat | Weekday::Sun) {
+            return false;
+        }
+        if self.last_entry == Some(local.date_naive()) {
+            return false;
+        }
+        self.times.iter().any(|start| {
+            let elapsed = local.time().signed_duration_since(*start);
+            elapsed >= Duration::zero() && elapsed < self.window
+        })
+    }
+
+    pub fn mark_entered(&mut self, now: DateTime<Utc>) {
+        self.last_entry = Some(now.with_timezone(&New_York).date_naive());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn make_schedule(times: &[&str]) -> EntrySchedule {
+        let config: SpxConfig = serde_json::from_value(serde_json::json!({
+            "otm_points": 20,
+            "wing_width": 5,
+            "entry_times": times,
+        }))
+        .unwrap();
+        EntrySchedule::new(&config).unwrap()
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_entry_due_once_a_day_at_new_york_time() {
+        let mut schedule = make_schedule(&["10:00"]);
+
+        // 2024-03-15 is a Friday, New York is on daylight time at UTC-4
+        assert!(!schedule.is_due(utc(2024, 3, 15, 13, 59)));
+        assert!(schedule.is_due(utc(2024, 3, 15, 14, 0)));
+        assert!(schedule.is_due(utc(2024, 3, 15, 14, 14)));
+        assert!(!schedule.is_due(utc(2024, 3, 15, 14, 15)));
+
+        schedule.mark_entered(utc(2024, 3, 15, 14, 1));
+        assert!(!schedule.is_due(utc(2024, 3, 15, 14, 2)));
+
+        // Weekends are skipped, the next session is due again
+        assert!(!schedule.is_due(utc(2024, 3, 16, 14, 0)));
+        assert!(schedule.is_due(utc(2024, 3, 18, 14, 0)));
+
+        // Standard time in January is UTC-5
+        assert!(!schedule.is_due(utc(2024, 1, 12, 14, 0)));
+        assert!(schedule.is_due(utc(2024, 1, 12, 15, 0)));
+    }
+
+    #[test]
+    fn test_entry_always_due_without_times() {
+        let mut schedule = make_schedule(&[]);
+        schedule.mark_entered(utc(2024, 3, 16, 14, 0));
+        assert!(schedule.is_due(utc(2024, 3, 16, 14, 0)));
+    }
+}
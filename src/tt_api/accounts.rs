@@ -0,0 +1,22 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CustomerAccounts {
+    pub items: Vec<CustomerAccount>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CustomerAccount {
+    pub account: AccountInfo,
+    #[serde(rename = "authority-level")]
+    pub authority_level: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccountInfo {
+    #[serde(rename = "account-number")]
+    pub account_number: String,
+    #[serde(rename = "nickname")]
+    pub nickname: Option<String>,
+}
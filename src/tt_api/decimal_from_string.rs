@@ -0,0 +1,105 @@
+// Prices and amounts come over the api as strings, blank when they do not
+// apply. Use with #[serde(default, with = "decimal_from_string")] on an
+// Option<Decimal> field. A malformed value is logged and read as None so
+// one bad field does not fail the whole response
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serializer;
+use std::str::FromStr;
+use tracing::warn;
+
+pub fn parse(value: &str) -> Result<Option<Decimal>, rust_decimal::Error> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let parsed = match value.contains(['e', 'E']) {
+        true => Decimal::from_scientific(value),
+        false => Decimal::from_str(value),
+    };
+    parsed.map(Some)
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    Ok(parse(&value).unwrap_or_else(|err| {
+        warn!(
+            "Invalid decimal: {:?}, reading it as empty, error: {}",
+            value, err
+        );
+        None
+    }))
+}
+
+pub fn serialize<S>(value: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(value) => serializer.serialize_str(&value.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use serde::Serialize;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Priced {
+        #[serde(default, with = "super")]
+        price: Option<Decimal>,
+    }
+
+    fn price(json: serde_json::Value) -> serde_json::Result<Option<Decimal>> {
+        serde_json::from_value::<Priced>(json).map(|priced| priced.price)
+    }
+
+    #[test]
+    fn test_empty_and_missing_are_none() {
+        assert_eq!(price(serde_json::json!({"price": ""})).unwrap(), None);
+        assert_eq!(price(serde_json::json!({"price": "  "})).unwrap(), None);
+        assert_eq!(price(serde_json::json!({"price": null})).unwrap(), None);
+        assert_eq!(price(serde_json::json!({})).unwrap(), None);
+    }
+
+    #[test]
+    fn test_negative_and_scientific() {
+        assert_eq!(
+            price(serde_json::json!({"price": "-1.25"})).unwrap(),
+            Some(dec!(-1.25))
+        );
+        assert_eq!(
+            price(serde_json::json!({"price": "1.5E-3"})).unwrap(),
+            Some(dec!(0.0015))
+        );
+        assert_eq!(
+            price(serde_json::json!({"price": "-2e2"})).unwrap(),
+            Some(dec!(-200))
+        );
+    }
+
+    #[test]
+    fn test_malformed_is_none() {
+        assert_eq!(price(serde_json::json!({"price": "1.2.3"})).unwrap(), None);
+        assert_eq!(price(serde_json::json!({"price": "NaN"})).unwrap(), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let priced = Priced {
+            price: Some(dec!(4.05)),
+        };
+        let json = serde_json::to_value(&priced).unwrap();
+        assert_eq!(json, serde_json::json!({"price": "4.05"}));
+        assert_eq!(price(json).unwrap(), Some(dec!(4.05)));
+    }
+}
@@ -2,6 +2,55 @@ use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::Deserialize;
 use serde::Serialize;
+use std::fmt;
+
+use super::decimal_from_string;
+
+// How dxlink lays out FEED_DATA, COMPACT sends the values of each event
+// positionally in the order of the fields asked for in FEED_SETUP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DataFormat {
+    #[default]
+    #[serde(rename = "FULL")]
+    Full,
+    #[serde(rename = "COMPACT")]
+    Compact,
+}
+
+impl fmt::Display for DataFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataFormat::Full => write!(f, "FULL"),
+            DataFormat::Compact => write!(f, "COMPACT"),
+        }
+    }
+}
+
+// Which dxlink feed contract the channel asks for, AUTO lets the server pick
+// per event type while the others trade latency against conflation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FeedContract {
+    #[default]
+    #[serde(rename = "AUTO")]
+    Auto,
+    #[serde(rename = "TICKER")]
+    Ticker,
+    #[serde(rename = "STREAM")]
+    Stream,
+    #[serde(rename = "HISTORY")]
+    History,
+}
+
+impl fmt::Display for FeedContract {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedContract::Auto => write!(f, "AUTO"),
+            FeedContract::Ticker => write!(f, "TICKER"),
+            FeedContract::Stream => write!(f, "STREAM"),
+            FeedContract::History => write!(f, "HISTORY"),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Message {
@@ -17,21 +66,30 @@ pub struct FeedDataMessage {
     pub data: Vec<FeedEvent>,
 }
 
+// Event types alternate with flat arrays of values, one array can hold
+// several events back to back
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompactFeedDataMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    pub data: Vec<serde_json::Value>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "eventType")]
 pub enum FeedEvent {
-    #[serde(rename = "Quote")]
-    QuoteEvent(Quote),
-    #[serde(rename = "Greeks")]
-    GreeksEvent(Greeks),
+    Quote(Quote),
+    Greeks(Greeks),
+    Candle(Candle),
 }
 
 impl PartialEq for FeedEvent {
     fn eq(&self, other: &Self) -> bool {
         matches!(
             (self, other),
-            (FeedEvent::QuoteEvent(_), FeedEvent::QuoteEvent(_))
-                | (FeedEvent::GreeksEvent(_), FeedEvent::GreeksEvent(_))
+            (FeedEvent::Quote(_), FeedEvent::Quote(_))
+                | (FeedEvent::Greeks(_), FeedEvent::Greeks(_))
+                | (FeedEvent::Candle(_), FeedEvent::Candle(_))
         )
     }
 }
@@ -56,17 +114,38 @@ pub struct Quote {
 }
 
 impl Quote {
-    pub fn midprice(&self) -> Decimal {
-        (self.ask_price.abs() + self.bid_price.abs()) / dec!(2)
+    // Falls back to the quoted side when the market is one sided
+    pub fn midprice(&self) -> Option<Decimal> {
+        let bid = self.bid_price.abs();
+        let ask = self.ask_price.abs();
+        match (bid.is_zero(), ask.is_zero()) {
+            (true, true) => None,
+            (true, false) => Some(ask),
+            (false, true) => Some(bid),
+            (false, false) => Some((bid + ask) / dec!(2)),
+        }
+    }
+
+    // The side traded against must show at least min_size, a buy takes the
+    // offer and a sell hits the bid
+    pub fn is_liquid(&self, buying: bool, min_size: f64) -> bool {
+        match buying {
+            true => self.ask_size >= min_size,
+            false => self.bid_size >= min_size,
+        }
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Greeks {
+    #[serde(default)]
     pub event_flags: f64,
+    #[serde(default)]
     pub index: f64,
+    #[serde(default)]
     pub time: f64,
+    #[serde(default)]
     pub sequence: f64,
     pub price: f64,
     pub volatility: f64,
@@ -76,7 +155,36 @@ pub struct Greeks {
     pub rho: f64,
     pub vega: f64,
     pub event_symbol: String,
+    #[serde(default)]
+    pub event_time: f64,
+}
+
+// One bar of the candle symbol subscribed to, the period is part of the
+// symbol such as SPX{=5m}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candle {
+    pub event_symbol: String,
+    #[serde(default)]
     pub event_time: f64,
+    #[serde(default)]
+    pub event_flags: f64,
+    #[serde(default)]
+    pub index: f64,
+    #[serde(default)]
+    pub time: f64,
+    #[serde(default)]
+    pub sequence: f64,
+    #[serde(default)]
+    pub count: f64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    #[serde(default)]
+    pub volume: f64,
+    #[serde(default)]
+    pub vwap: f64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -99,16 +207,19 @@ pub struct FutureOptionProduct {
 #[serde(rename_all = "kebab-case")]
 pub struct FutureOption {
     pub future_option_product: Option<FutureOptionProduct>,
-    pub multiplier: Option<String>,
+    #[serde(default, with = "decimal_from_string")]
+    pub multiplier: Option<Decimal>,
     pub root_symbol: String,
     pub exchange: Option<String>,
-    pub notional_value: Option<String>,
+    #[serde(default, with = "decimal_from_string")]
+    pub notional_value: Option<Decimal>,
     pub active: Option<bool>,
     pub is_closing_only: Option<bool>,
     pub underlying_symbol: String,
     pub maturity_date: Option<String>,
     pub is_exercisable_weekly: Option<bool>,
-    pub strike_factor: Option<String>,
+    #[serde(default, with = "decimal_from_string")]
+    pub strike_factor: Option<Decimal>,
     pub product_code: Option<String>,
     pub days_to_expiration: Option<i32>,
     pub option_root_symbol: Option<String>,
@@ -293,9 +404,70 @@ pub struct Equity {
     pub description: Option<String>,
 }
 
+// Data of a lookup by several symbols at once, only the ones the broker
+// knows come back
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Instruments<T> {
+    pub items: Vec<T>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Response<T> {
     // pub message: Message,
     pub data: T,
     pub context: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_quote(bid_price: Decimal, ask_price: Decimal) -> Quote {
+        Quote {
+            event_symbol: String::from("SPY"),
+            event_time: 0.,
+            sequence: 0.,
+            time_nano_part: 0.,
+            bid_time: 0.,
+            bid_exchange_code: String::from("Q"),
+            bid_price,
+            bid_size: 10.,
+            ask_time: 0.,
+            ask_exchange_code: String::from("Q"),
+            ask_price,
+            ask_size: 10.,
+        }
+    }
+
+    #[test]
+    fn test_is_liquid_checks_side_traded_against() {
+        let mut quote = make_quote(dec!(1.10), dec!(1.20));
+        assert!(quote.is_liquid(true, 10.));
+        assert!(quote.is_liquid(false, 10.));
+        quote.ask_size = 1.;
+        assert!(!quote.is_liquid(true, 10.));
+        assert!(quote.is_liquid(false, 10.));
+        assert!(quote.is_liquid(true, 0.));
+    }
+
+    #[test]
+    fn test_midprice_two_sided_quote() {
+        let quote = make_quote(dec!(1.10), dec!(1.20));
+        assert_eq!(quote.midprice(), Some(dec!(1.15)));
+    }
+
+    #[test]
+    fn test_midprice_one_sided_quote() {
+        let quote = make_quote(Decimal::ZERO, dec!(0.05));
+        assert_eq!(quote.midprice(), Some(dec!(0.05)));
+
+        let quote = make_quote(dec!(0.05), Decimal::ZERO);
+        assert_eq!(quote.midprice(), Some(dec!(0.05)));
+    }
+
+    #[test]
+    fn test_midprice_empty_quote() {
+        let quote = make_quote(Decimal::ZERO, Decimal::ZERO);
+        assert_eq!(quote.midprice(), None);
+    }
+}
@@ -1,9 +1,13 @@
-// use serde::Deserialize;
-// use serde::Serialize;
+use serde::Deserialize;
+use serde::Serialize;
 
+pub mod accounts;
+pub mod decimal_from_string;
 pub mod mktdata;
+pub mod option_chain;
 pub mod orders;
 pub mod positions;
+pub mod transactions;
 
 // #[derive(Clone, Serialize, Deserialize, Debug)]
 // #[serde(rename_all = "camelCase")]
@@ -60,22 +64,56 @@ pub mod positions;
 //     contract: FeedContract,
 // }
 
-// #[derive(Serialize, Deserialize, Debug)]
-// #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-// enum FeedContract {
-//     History,
-//     Ticker,
-//     Stream,
-//     Auto,
-// }
+// Error codes dxlink sends in an ERROR message
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum StreamErrorCode {
+    UnsupportedProtocol,
+    Timeout,
+    Unauthorized,
+    InvalidMessage,
+    BadAction,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
 
-// #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-// pub struct StreamApiError {
-//     #[serde(rename = "code")]
-//     pub code: u64,
-//     #[serde(rename = "msg")]
-//     pub message: String,
-// }
+// Body of a dxlink ERROR message
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct StreamApiError {
+    pub error: StreamErrorCode,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamErrorKind {
+    Unauthorized,
+    // The server does not speak the dxlink version asked for in SETUP
+    Protocol,
+    BadSymbol(String),
+    Other,
+}
+
+impl StreamApiError {
+    // dxlink only names the offending symbol in the message text, it has to
+    // be a whole word there so SPX is not read out of .SPXW240315C5100
+    pub fn kind(&self, subscribed: &[String]) -> StreamErrorKind {
+        match self.error {
+            StreamErrorCode::UnsupportedProtocol => StreamErrorKind::Protocol,
+            StreamErrorCode::Unauthorized => StreamErrorKind::Unauthorized,
+            StreamErrorCode::InvalidMessage => self
+                .message
+                .split_whitespace()
+                .map(|word| word.trim_matches(|c| c == '"' || c == '\'' || c == ','))
+                .find_map(|word| subscribed.iter().find(|symbol| symbol.as_str() == word))
+                .map_or(StreamErrorKind::Other, |symbol| {
+                    StreamErrorKind::BadSymbol(symbol.clone())
+                }),
+            _ => StreamErrorKind::Other,
+        }
+    }
+}
 
 // #[derive(Clone, Debug, Deserialize, Serialize)]
 // pub enum WsRequest {
@@ -95,3 +133,51 @@ pub mod positions;
 //     #[serde(rename = "error")]
 //     Error(StreamApiError),
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_error_kind() {
+        let subscribed = vec![String::from(".SPXW240315C5100")];
+        let error: StreamApiError = serde_json::from_str(
+            r#"{"type":"ERROR","channel":0,"error":"UNAUTHORIZED","message":"Token expired"}"#,
+        )
+        .unwrap();
+        assert_eq!(error.kind(&subscribed), StreamErrorKind::Unauthorized);
+
+        let error = StreamApiError {
+            error: StreamErrorCode::InvalidMessage,
+            message: String::from("Unknown symbol .SPXW240315C5100"),
+        };
+        assert_eq!(
+            error.kind(&subscribed),
+            StreamErrorKind::BadSymbol(String::from(".SPXW240315C5100"))
+        );
+
+        // Only the code decides, and a symbol has to match a whole word
+        let error = StreamApiError {
+            error: StreamErrorCode::InvalidMessage,
+            message: String::from("Auth token missing for .SPXW240315C51000"),
+        };
+        assert_eq!(error.kind(&subscribed), StreamErrorKind::Other);
+        let error: StreamApiError = serde_json::from_str(
+            r#"{"type":"ERROR","channel":0,"error":"SERVER_BUSY","message":"Try again"}"#,
+        )
+        .unwrap();
+        assert_eq!(error.error, StreamErrorCode::Unknown);
+
+        let error = StreamApiError {
+            error: StreamErrorCode::Unknown,
+            message: String::from("Internal failure"),
+        };
+        assert_eq!(error.kind(&subscribed), StreamErrorKind::Other);
+
+        let error = StreamApiError {
+            error: StreamErrorCode::UnsupportedProtocol,
+            message: String::from("Unsupported version 0.1"),
+        };
+        assert_eq!(error.kind(&subscribed), StreamErrorKind::Protocol);
+    }
+}
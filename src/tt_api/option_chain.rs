@@ -0,0 +1,133 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Strike {
+    pub strike_price: Decimal,
+    pub call: String,
+    pub call_streamer_symbol: String,
+    pub put: String,
+    pub put_streamer_symbol: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Expiration {
+    pub expiration_type: Option<String>,
+    pub expiration_date: String,
+    pub days_to_expiration: i32,
+    pub settlement_type: Option<String>,
+    pub strikes: Vec<Strike>,
+}
+
+impl Expiration {
+    pub fn strike_nearest_price(&self, target_price: Decimal) -> Option<&Strike> {
+        self.strikes
+            .iter()
+            .min_by_key(|strike| (strike.strike_price - target_price).abs())
+    }
+
+    // The strike nearest the price plus up to count strikes either side of it
+    pub fn strikes_around(&self, price: Decimal, count: usize) -> Vec<&Strike> {
+        let mut strikes = self.strikes.iter().collect::<Vec<_>>();
+        strikes.sort_by_key(|strike| strike.strike_price);
+        let Some(nearest) = strikes
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, strike)| (strike.strike_price - price).abs())
+            .map(|(index, _)| index)
+        else {
+            return Vec::new();
+        };
+        let start = nearest.saturating_sub(count);
+        let end = (nearest + count + 1).min(strikes.len());
+        strikes[start..end].to_vec()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OptionChain {
+    pub underlying_symbol: String,
+    pub root_symbol: String,
+    pub option_chain_type: Option<String>,
+    pub shares_per_contract: Option<i32>,
+    pub expirations: Vec<Expiration>,
+}
+
+impl OptionChain {
+    pub fn expiration_by_dte(&self, days_to_expiration: i32) -> Option<&Expiration> {
+        self.expirations
+            .iter()
+            .find(|expiration| expiration.days_to_expiration >= days_to_expiration)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OptionChains {
+    pub items: Vec<OptionChain>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn make_chain() -> OptionChain {
+        serde_json::from_value(serde_json::json!({
+            "underlying-symbol": "SPX",
+            "root-symbol": "SPXW",
+            "option-chain-type": "Standard",
+            "shares-per-contract": 100,
+            "expirations": [{
+                "expiration-type": "Weekly",
+                "expiration-date": "2024-03-15",
+                "days-to-expiration": 0,
+                "settlement-type": "PM",
+                "strikes": [
+                    {
+                        "strike-price": "5100.0",
+                        "call": "SPXW  240315C05100000",
+                        "call-streamer-symbol": ".SPXW240315C5100",
+                        "put": "SPXW  240315P05100000",
+                        "put-streamer-symbol": ".SPXW240315P5100",
+                    },
+                    {
+                        "strike-price": "5105.0",
+                        "call": "SPXW  240315C05105000",
+                        "call-streamer-symbol": ".SPXW240315C5105",
+                        "put": "SPXW  240315P05105000",
+                        "put-streamer-symbol": ".SPXW240315P5105",
+                    },
+                ],
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_strike_nearest_price() {
+        let chain = make_chain();
+        let expiration = chain.expiration_by_dte(0).unwrap();
+        let strike = expiration.strike_nearest_price(dec!(5104)).unwrap();
+        assert_eq!(strike.strike_price, dec!(5105));
+        assert_eq!(strike.put_streamer_symbol, ".SPXW240315P5105");
+    }
+
+    #[test]
+    fn test_strikes_around() {
+        let chain = make_chain();
+        let expiration = chain.expiration_by_dte(0).unwrap();
+        let strikes = |count| {
+            expiration
+                .strikes_around(dec!(5099), count)
+                .iter()
+                .map(|strike| strike.strike_price)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(strikes(0), vec![dec!(5100)]);
+        assert_eq!(strikes(1), vec![dec!(5100), dec!(5105)]);
+    }
+}
@@ -1,6 +1,39 @@
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+use super::decimal_from_string;
+use super::transactions::signed;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    #[default]
+    Day,
+    #[serde(rename = "GTC")]
+    Gtc,
+    #[serde(rename = "GTD")]
+    Gtd,
+}
+
+impl fmt::Display for TimeInForce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time_in_force = match self {
+            TimeInForce::Day => String::from("Day"),
+            TimeInForce::Gtc => String::from("GTC"),
+            TimeInForce::Gtd => String::from("GTD"),
+        };
+        write!(f, "{}", time_in_force)
+    }
+}
+
+impl TimeInForce {
+    // A gtc date is required for GTD orders and rejected on everything else
+    pub fn allows_gtc_date(&self, gtc_date: Option<&str>) -> bool {
+        (*self == TimeInForce::Gtd) == gtc_date.is_some()
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -10,16 +43,50 @@ pub struct LegData {
     pub quantity: i32,
     pub remaining_quantity: i32,
     pub action: String,
-    pub fills: Vec<String>,
+    #[serde(default)]
+    pub fills: Vec<Fill>,
+}
+
+impl LegData {
+    // Volume weighted over every fill on the leg, None until one arrives
+    pub fn average_fill_price(&self) -> Option<Decimal> {
+        let (quantity, value) = self
+            .fills
+            .iter()
+            .filter_map(|fill| Some((fill.quantity?, fill.fill_price?)))
+            .fold(
+                (Decimal::ZERO, Decimal::ZERO),
+                |(quantity, value), (size, price)| (quantity + size, value + size * price),
+            );
+        (!quantity.is_zero()).then(|| (value / quantity).normalize())
+    }
+}
+
+// One execution against a leg, a leg filled in pieces carries several
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Fill {
+    pub fill_id: String,
+    pub ext_exec_id: Option<String>,
+    pub ext_group_fill_id: Option<String>,
+    #[serde(with = "decimal_from_string")]
+    pub quantity: Option<Decimal>,
+    #[serde(with = "decimal_from_string")]
+    pub fill_price: Option<Decimal>,
+    pub filled_at: String,
+    pub destination_venue: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", default)]
 pub struct OrderData {
     pub id: i32,
     pub account_number: String,
     pub time_in_force: String,
+    pub gtc_date: Option<String>,
     pub order_type: String,
+    pub price: Option<String>,
+    pub price_effect: Option<String>,
     pub size: i32,
     pub underlying_symbol: String,
     pub underlying_instrument_type: String,
@@ -27,12 +94,70 @@ pub struct OrderData {
     pub cancellable: bool,
     pub editable: bool,
     pub edited: bool,
+    pub source: Option<String>,
     pub legs: Vec<LegData>,
 }
 
+impl OrderData {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "Filled" | "Cancelled" | "Expired" | "Rejected" | "Removed" | "Partially Removed"
+        )
+    }
+
+    // None when the update carries no legs to read fills from
+    pub fn remaining_quantity(&self) -> Option<i32> {
+        self.legs.iter().map(|leg| leg.remaining_quantity).max()
+    }
+}
+
+impl From<&OrderData> for Order {
+    fn from(data: &OrderData) -> Self {
+        Order {
+            id: Some(data.id),
+            time_in_force: data.time_in_force.clone(),
+            gtc_date: data.gtc_date.clone(),
+            order_type: data.order_type.clone(),
+            price: data
+                .price
+                .as_ref()
+                .and_then(|price| Decimal::from_str(price).ok())
+                .unwrap_or_default(),
+            price_effect: data.price_effect.clone().unwrap_or_default(),
+            source: data.source.clone(),
+            legs: data
+                .legs
+                .iter()
+                .map(|leg| Leg {
+                    instrument_type: leg.instrument_type.clone(),
+                    symbol: leg.symbol.clone(),
+                    quantity: leg.quantity,
+                    action: leg.action.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Order status transition pushed over the account stream
+#[derive(Debug, Deserialize)]
+pub struct OrderUpdate {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub data: OrderData,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LiveOrders {
+    pub items: Vec<OrderData>,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Order {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
     pub time_in_force: String,
     pub order_type: String,
     // pub stop_trigger: Option<u32>,
@@ -41,8 +166,13 @@ pub struct Order {
     pub price_effect: String,
     // pub value: Option<u32>,
     // pub value_effect: Option<String>,
-    // pub gtc_date: Option<String>,
-    // pub source: Option<String>,
+    // Expiry of a GTD order as YYYY-MM-DD, tastytrade rejects it on any other
+    // time in force
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gtc_date: Option<String>,
+    // Tags the orders this bot places so a restart can tell them from manual ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
     // pub partition_key: Option<String>,
     // pub preflight_id: Option<String>,
     // pub automated_source: Option<bool>,
@@ -51,6 +181,16 @@ pub struct Order {
     // pub advanced_instructions: Option<AdvancedInstructions>,
 }
 
+impl Order {
+    pub fn quantity(&self) -> i32 {
+        self.legs
+            .iter()
+            .map(|leg| leg.quantity)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Leg {
@@ -94,3 +234,258 @@ pub struct PriceComponent {
 pub struct AdvancedInstructions {
     pub strict_position_effect_validation: bool,
 }
+
+// Placing, replacing and cancelling all answer with the order under data
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderResponse {
+    pub data: OrderEnvelope,
+    #[serde(default)]
+    pub context: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderEnvelope {
+    pub order: OrderData,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DryRunResponse {
+    pub data: DryRunResult,
+    pub context: String,
+}
+
+// What placing an order would do to the account, the dry-run endpoint never
+// sends it to the exchange
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct DryRunResult {
+    pub order: OrderData,
+    pub warnings: Vec<DryRunWarning>,
+    pub buying_power_effect: BuyingPowerEffect,
+    pub fee_calculation: FeeCalculation,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DryRunWarning {
+    pub code: String,
+    pub message: String,
+}
+
+// Amounts are unsigned with an effect of Debit, Credit or None beside them
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct BuyingPowerEffect {
+    #[serde(with = "decimal_from_string")]
+    pub change_in_buying_power: Option<Decimal>,
+    pub change_in_buying_power_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub isolated_order_margin_requirement: Option<Decimal>,
+    pub isolated_order_margin_requirement_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub new_buying_power: Option<Decimal>,
+    pub new_buying_power_effect: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct FeeCalculation {
+    #[serde(with = "decimal_from_string")]
+    pub commission: Option<Decimal>,
+    pub commission_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub clearing_fees: Option<Decimal>,
+    pub clearing_fees_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub regulatory_fees: Option<Decimal>,
+    pub regulatory_fees_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub proprietary_index_option_fees: Option<Decimal>,
+    pub proprietary_index_option_fees_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub total_fees: Option<Decimal>,
+    pub total_fees_effect: String,
+}
+
+impl fmt::Display for DryRunResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bp = &self.buying_power_effect;
+        let fees = &self.fee_calculation;
+        writeln!(
+            f,
+            "Margin requirement: {}, buying power change: {}, new buying power: {}",
+            signed(
+                bp.isolated_order_margin_requirement,
+                &bp.isolated_order_margin_requirement_effect
+            ),
+            signed(bp.change_in_buying_power, &bp.change_in_buying_power_effect),
+            signed(bp.new_buying_power, &bp.new_buying_power_effect),
+        )?;
+        write!(
+            f,
+            "Fees: {}, commission: {}, clearing: {}, regulatory: {}, index option: {}",
+            signed(fees.total_fees, &fees.total_fees_effect),
+            signed(fees.commission, &fees.commission_effect),
+            signed(fees.clearing_fees, &fees.clearing_fees_effect),
+            signed(fees.regulatory_fees, &fees.regulatory_fees_effect),
+            signed(
+                fees.proprietary_index_option_fees,
+                &fees.proprietary_index_option_fees_effect
+            ),
+        )?;
+        for warning in &self.warnings {
+            write!(f, "\nWarning {}: {}", warning.code, warning.message)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_order_data(status: &str) -> OrderData {
+        serde_json::from_value(serde_json::json!({
+            "id": 42,
+            "account-number": "5WX01234",
+            "time-in-force": "Day",
+            "order-type": "Limit",
+            "price": "1.25",
+            "price-effect": "Debit",
+            "size": 1,
+            "underlying-symbol": "SPY",
+            "underlying-instrument-type": "Equity",
+            "status": status,
+            "cancellable": true,
+            "editable": true,
+            "edited": false,
+            "legs": [{
+                "instrument-type": "Equity Option",
+                "symbol": "SPY   240315C00425000",
+                "quantity": 1,
+                "remaining-quantity": 1,
+                "action": "Buy to Close",
+                "fills": [],
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dry_run_result_display() {
+        let response: DryRunResponse = serde_json::from_value(serde_json::json!({
+            "data": {
+                "order": {"order-type": "Limit", "price": "2.0", "price-effect": "Credit", "size": 1},
+                "warnings": [{"code": "tif_next_valid_sesssion", "message": "Market is closed"}],
+                "buying-power-effect": {
+                    "change-in-buying-power": "800.0",
+                    "change-in-buying-power-effect": "Debit",
+                    "isolated-order-margin-requirement": "1000.0",
+                    "isolated-order-margin-requirement-effect": "Debit",
+                    "new-buying-power": "9200.0",
+                    "new-buying-power-effect": "Credit",
+                },
+                "fee-calculation": {
+                    "commission": "2.0",
+                    "commission-effect": "Debit",
+                    "clearing-fees": "0.2",
+                    "clearing-fees-effect": "Debit",
+                    "regulatory-fees": "0.06",
+                    "regulatory-fees-effect": "Debit",
+                    "proprietary-index-option-fees": "1.3",
+                    "proprietary-index-option-fees-effect": "Debit",
+                    "total-fees": "3.56",
+                    "total-fees-effect": "Debit",
+                },
+            },
+            "context": "/accounts/5WX01234/orders/dry-run",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            response.data.to_string(),
+            "Margin requirement: -1000.0, buying power change: -800.0, new buying power: 9200.0\n\
+             Fees: -3.56, commission: -2.0, clearing: -0.2, regulatory: -0.06, index option: -1.3\n\
+             Warning tif_next_valid_sesssion: Market is closed"
+        );
+    }
+
+    #[test]
+    fn test_terminal_order_status() {
+        assert!(!make_order_data("Live").is_terminal());
+        assert!(!make_order_data("Received").is_terminal());
+        assert!(make_order_data("Filled").is_terminal());
+        assert!(make_order_data("Cancelled").is_terminal());
+        assert!(make_order_data("Rejected").is_terminal());
+    }
+
+    #[test]
+    fn test_remaining_quantity_from_legs() {
+        let mut data = make_order_data("Live");
+        assert_eq!(data.remaining_quantity(), Some(1));
+        data.legs[0].remaining_quantity = 0;
+        assert_eq!(data.remaining_quantity(), Some(0));
+        data.legs.clear();
+        assert_eq!(data.remaining_quantity(), None);
+    }
+
+    #[test]
+    fn test_order_from_order_data() {
+        let order = Order::from(&make_order_data("Live"));
+        assert_eq!(order.id, Some(42));
+        assert_eq!(order.price, Decimal::new(125, 2));
+        assert_eq!(order.legs.len(), 1);
+        assert_eq!(order.legs[0].symbol, "SPY   240315C00425000");
+        assert_eq!(order.legs[0].action, "Buy to Close");
+    }
+
+    #[test]
+    fn test_gtc_order_serialization() {
+        let order = Order {
+            time_in_force: TimeInForce::Gtc.to_string(),
+            order_type: String::from("Limit"),
+            price: Decimal::new(125, 2),
+            price_effect: String::from("Debit"),
+            legs: vec![Leg {
+                instrument_type: String::from("Equity Option"),
+                symbol: String::from("SPY   240315C00425000"),
+                quantity: 1,
+                action: String::from("Buy to Close"),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&order).unwrap(),
+            serde_json::json!({
+                "time-in-force": "GTC",
+                "order-type": "Limit",
+                "price": 1.25,
+                "price-effect": "Debit",
+                "legs": [{
+                    "instrument-type": "Equity Option",
+                    "symbol": "SPY   240315C00425000",
+                    "quantity": 1,
+                    "action": "Buy to Close",
+                }],
+            })
+        );
+
+        let order = Order {
+            time_in_force: TimeInForce::Gtd.to_string(),
+            gtc_date: Some(String::from("2024-03-15")),
+            ..order
+        };
+        let value = serde_json::to_value(&order).unwrap();
+        assert_eq!(value["time-in-force"], "GTD");
+        assert_eq!(value["gtc-date"], "2024-03-15");
+    }
+
+    #[test]
+    fn test_gtc_date_only_allowed_on_gtd() {
+        assert!(TimeInForce::Day.allows_gtc_date(None));
+        assert!(TimeInForce::Gtc.allows_gtc_date(None));
+        assert!(!TimeInForce::Gtc.allows_gtc_date(Some("2024-03-15")));
+        assert!(TimeInForce::Gtd.allows_gtc_date(Some("2024-03-15")));
+        assert!(!TimeInForce::Gtd.allows_gtc_date(None));
+    }
+}
@@ -1,6 +1,9 @@
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde::Serialize;
 
+use super::decimal_from_string;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AccountPositions {
     pub data: Positions,
@@ -30,8 +33,8 @@ pub struct Leg {
     pub deliverable_type: Option<String>,
     #[serde(rename = "underlying-symbol")]
     pub underlying_symbol: Option<String>,
-    #[serde(rename = "mark-price")]
-    pub mark_price: Option<String>,
+    #[serde(rename = "mark-price", default, with = "decimal_from_string")]
+    pub mark_price: Option<Decimal>,
     #[serde(rename = "account-number")]
     pub account_number: Option<String>,
     #[serde(rename = "fixing-price")]
@@ -48,12 +51,12 @@ pub struct Leg {
     pub realized_day_gain_effect: Option<String>,
     #[serde(rename = "cost-effect")]
     pub cost_effect: Option<String>,
-    #[serde(rename = "close-price")]
-    pub close_price: Option<String>,
+    #[serde(rename = "close-price", default, with = "decimal_from_string")]
+    pub close_price: Option<Decimal>,
     #[serde(rename = "average-yearly-market-close-price")]
     pub average_yearly_market_close_price: Option<String>,
-    #[serde(rename = "average-open-price")]
-    pub average_open_price: Option<String>,
+    #[serde(rename = "average-open-price", default, with = "decimal_from_string")]
+    pub average_open_price: Option<Decimal>,
     #[serde(rename = "is-suppressed")]
     pub is_suppressed: bool,
     pub created_at: Option<String>,
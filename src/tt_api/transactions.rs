@@ -0,0 +1,171 @@
+use chrono::DateTime;
+use chrono::Utc;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+
+use super::decimal_from_string;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccountTransactions {
+    pub data: Transactions,
+    pub context: String,
+    pub pagination: Option<Pagination>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Transactions {
+    pub items: Vec<Transaction>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Pagination {
+    pub per_page: usize,
+    pub page_offset: usize,
+    pub total_pages: usize,
+}
+
+// Amounts are unsigned with an effect of Debit, Credit or None beside them
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Transaction {
+    pub id: i64,
+    pub account_number: String,
+    pub order_id: Option<i64>,
+    pub symbol: Option<String>,
+    pub instrument_type: Option<String>,
+    pub underlying_symbol: Option<String>,
+    pub transaction_type: String,
+    pub transaction_sub_type: String,
+    pub description: String,
+    pub action: Option<String>,
+    #[serde(with = "decimal_from_string")]
+    pub quantity: Option<Decimal>,
+    #[serde(with = "decimal_from_string")]
+    pub price: Option<Decimal>,
+    pub executed_at: String,
+    pub transaction_date: String,
+    #[serde(with = "decimal_from_string")]
+    pub value: Option<Decimal>,
+    pub value_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub net_value: Option<Decimal>,
+    pub net_value_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub commission: Option<Decimal>,
+    pub commission_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub clearing_fees: Option<Decimal>,
+    pub clearing_fees_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub regulatory_fees: Option<Decimal>,
+    pub regulatory_fees_effect: String,
+    #[serde(with = "decimal_from_string")]
+    pub proprietary_index_option_fees: Option<Decimal>,
+    pub proprietary_index_option_fees_effect: String,
+}
+
+pub fn signed(amount: Option<Decimal>, effect: &str) -> Decimal {
+    let amount = amount.unwrap_or_default();
+    match effect {
+        "Debit" => -amount,
+        _ => amount,
+    }
+}
+
+impl Transaction {
+    pub fn is_fill(&self) -> bool {
+        self.transaction_type == "Trade"
+    }
+
+    pub fn executed_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.executed_at)
+            .ok()
+            .map(|executed_at| executed_at.with_timezone(&Utc))
+    }
+
+    // Commission and every exchange fee together, negative when paid
+    pub fn fees(&self) -> Decimal {
+        signed(self.commission, &self.commission_effect)
+            + signed(self.clearing_fees, &self.clearing_fees_effect)
+            + signed(self.regulatory_fees, &self.regulatory_fees_effect)
+            + signed(
+                self.proprietary_index_option_fees,
+                &self.proprietary_index_option_fees_effect,
+            )
+    }
+
+    // Cash moved by the transaction after fees, debits negative
+    pub fn net_value(&self) -> Decimal {
+        signed(self.net_value, &self.net_value_effect)
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} @ {}, fees: {}, net: {}",
+            self.executed_at,
+            self.action.as_deref().unwrap_or(&self.transaction_sub_type),
+            self.quantity.unwrap_or_default().normalize(),
+            self.symbol.as_deref().unwrap_or_default(),
+            self.price.unwrap_or_default().normalize(),
+            self.fees(),
+            self.net_value()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_fill_fees_and_net_value() {
+        let transaction: Transaction = serde_json::from_value(serde_json::json!({
+            "id": 281_923_541,
+            "account-number": "5WX01234",
+            "order-id": 331_231,
+            "symbol": "SPXW  240315P05100000",
+            "instrument-type": "Equity Option",
+            "underlying-symbol": "SPX",
+            "transaction-type": "Trade",
+            "transaction-sub-type": "Sell to Open",
+            "description": "Sold 1 SPXW 03/15/24 Put 5100.00 @ 4.10",
+            "action": "Sell to Open",
+            "quantity": "1.0",
+            "price": "4.1",
+            "executed-at": "2024-03-15T14:32:05.123+00:00",
+            "transaction-date": "2024-03-15",
+            "value": "410.0",
+            "value-effect": "Credit",
+            "net-value": "407.88",
+            "net-value-effect": "Credit",
+            "commission": "1.0",
+            "commission-effect": "Debit",
+            "clearing-fees": "0.1",
+            "clearing-fees-effect": "Debit",
+            "regulatory-fees": "0.37",
+            "regulatory-fees-effect": "Debit",
+            "proprietary-index-option-fees": "0.65",
+            "proprietary-index-option-fees-effect": "Debit",
+        }))
+        .unwrap();
+
+        assert!(transaction.is_fill());
+        assert_eq!(transaction.fees(), dec!(-2.12));
+        assert_eq!(transaction.net_value(), dec!(407.88));
+        assert_eq!(
+            transaction.value.unwrap() + transaction.fees(),
+            transaction.net_value()
+        );
+        assert_eq!(
+            transaction.executed_at().unwrap().to_rfc3339(),
+            "2024-03-15T14:32:05.123+00:00"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+use crate::mktdata::MktData;
+use crate::tt_api::mktdata::Quote;
+use crate::web_client::WebClient;
+
+// Self signed CA standing in for an inspecting proxy's certificate
+pub const PROXY_CA: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIIBgTCCASegAwIBAgIUfsXWGBWkHEtfSIUodUyrtD6kem4wCgYIKoZIzj0EAwIw\n\
+FTETMBEGA1UEAwwKdGVzdC1wcm94eTAgFw0yNjEwMTcxOTQyMTNaGA8yMTI2MDky\n\
+MzE5NDIxM1owFTETMBEGA1UEAwwKdGVzdC1wcm94eTBZMBMGByqGSM49AgEGCCqG\n\
+SM49AwEHA0IABHyidIZdu368pruk/8no4KWQhDJKdEecA7bm7rMyyjfEZqav9FU0\n\
+ycGPteKM4N2IlFpm4ZgGrbGmicOOFB+mdgijUzBRMB0GA1UdDgQWBBSLjblFjZrG\n\
+e4IsLNrH+ThRQzXKbDAfBgNVHSMEGDAWgBSLjblFjZrGe4IsLNrH+ThRQzXKbDAP\n\
+BgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQCzhOjS3cu40eQrdPpb\n\
+2kQzFsK5ujjBnmRFhaybBt67BgIgZ0dK3ROHjehfZdCnMmPp3FIrPI8YVvb98td+\n\
+Svq2/1M=\n\
+-----END CERTIFICATE-----\n";
+
+// Nothing listens here, for tests whose requests are meant to go nowhere
+pub const UNREACHABLE_URL: &str = "http://127.0.0.1:1";
+
+pub async fn make_web_client(url: &str, cancel_token: &CancellationToken) -> Arc<WebClient> {
+    Arc::new(WebClient::new(url, cancel_token.clone()).await.unwrap())
+}
+
+// Default settings and no feed behind it, quotes come from push_quotes
+pub fn make_mktdata(web_client: Arc<WebClient>, cancel_token: &CancellationToken) -> MktData {
+    MktData::new(
+        web_client,
+        1,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        cancel_token.clone(),
+    )
+}
+
+// Ten lots on either side of the book
+pub fn make_quote(symbol: &str, bid: Decimal, ask: Decimal) -> Quote {
+    Quote {
+        event_symbol: symbol.to_string(),
+        event_time: 0.,
+        sequence: 0.,
+        time_nano_part: 0.,
+        bid_time: 0.,
+        bid_exchange_code: String::new(),
+        bid_price: bid,
+        bid_size: 10.,
+        ask_time: 0.,
+        ask_exchange_code: String::new(),
+        ask_price: ask,
+        ask_size: 10.,
+    }
+}
+
+// Each entry is symbol, bid and ask
+pub async fn push_quotes(mktdata: &MktData, underlying: &str, quotes: &[(&str, Decimal, Decimal)]) {
+    for (symbol, bid, ask) in quotes {
+        mktdata
+            .push_test_quote(symbol, underlying, make_quote(symbol, *bid, *ask))
+            .await;
+    }
+}
+
+// Serves the router on a free local port, returns its base url
+pub async fn start_stub_server(router: axum::Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{}", addr)
+}
@@ -0,0 +1,64 @@
+use anyhow::bail;
+use anyhow::Result;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use crate::auth_store::AuthStore;
+use crate::db_client::DbStoredCreds;
+use crate::web_client::EndPoint;
+
+#[derive(Debug, Default)]
+pub struct MockDb {
+    pub creds: Mutex<Vec<DbStoredCreds>>,
+    // This many of the next update_auth calls fail
+    pub failing_updates: AtomicUsize,
+}
+
+impl MockDb {
+    pub fn new(creds: Vec<DbStoredCreds>) -> Self {
+        Self {
+            creds: Mutex::new(creds),
+            ..Default::default()
+        }
+    }
+}
+
+impl AuthStore for MockDb {
+    async fn fetch(&self, username: &str, endpoint: EndPoint) -> Result<Vec<DbStoredCreds>> {
+        Ok(self
+            .creds
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|creds| creds.username == username && creds.endpoint == endpoint)
+            .cloned()
+            .collect())
+    }
+
+    async fn update(&self, session: &str, remember: &str, endpoint: EndPoint) -> Result<()> {
+        let failing =
+            self.failing_updates
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                    count.checked_sub(1)
+                });
+        if failing.is_ok() {
+            bail!("Database unavailable");
+        }
+        self.creds
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|creds| creds.endpoint == endpoint)
+            .for_each(|creds| {
+                creds.session = session.to_string();
+                creds.remember = remember.to_string();
+            });
+        Ok(())
+    }
+
+    async fn insert(&self, creds: &DbStoredCreds) -> Result<()> {
+        self.creds.lock().unwrap().push(creds.clone());
+        Ok(())
+    }
+}
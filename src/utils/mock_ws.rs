@@ -0,0 +1,70 @@
+use serde::Serialize;
+use serde_json::to_string as to_json;
+use std::sync::Arc;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+
+use crate::web_client::sessions::WsSession;
+
+// Stands in for the socket a WebSocketClient owns, canned frames are handed
+// to the session and everything it would write back is kept in sent
+pub struct MockWsTransport<Session> {
+    session: Arc<RwLock<Session>>,
+    to_app: Receiver<String>,
+    pub sent: Vec<String>,
+    pub cancel_token: CancellationToken,
+}
+
+impl<Session> MockWsTransport<Session>
+where
+    Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
+{
+    pub fn new(session: Arc<RwLock<Session>>, to_app: Receiver<String>) -> Self {
+        Self {
+            session,
+            to_app,
+            sent: Vec::new(),
+            cancel_token: CancellationToken::new(),
+        }
+    }
+
+    pub fn session(&self) -> Arc<RwLock<Session>> {
+        Arc::clone(&self.session)
+    }
+
+    pub fn send<Payload>(&mut self, payload: &Payload)
+    where
+        Payload: Serialize,
+    {
+        self.sent.push(to_json(payload).unwrap());
+    }
+
+    // Returns the replies the connection loop would write straight back
+    pub async fn receive(&mut self, response: &str) -> Vec<String> {
+        let replies = self
+            .session
+            .write()
+            .await
+            .handle_response::<Session>(response.to_string(), self.cancel_token.clone());
+        self.sent.extend(replies.iter().cloned());
+        replies
+    }
+
+    pub async fn heartbeat(&mut self) -> String {
+        let mut session = self.session.write().await;
+        let heartbeat = session.get_heart_beat_message();
+        session.update_last_sent();
+        self.sent.push(heartbeat.clone());
+        heartbeat
+    }
+
+    // Drains everything the session forwarded on to the app
+    pub fn published(&mut self) -> Vec<String> {
+        let mut published = Vec::new();
+        while let Ok(msg) = self.to_app.try_recv() {
+            published.push(msg);
+        }
+        published
+    }
+}
@@ -0,0 +1,3 @@
+pub mod fixtures;
+pub mod mock_db;
+pub mod mock_ws;
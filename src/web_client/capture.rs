@@ -0,0 +1,142 @@
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::Utc;
+use std::path::Path;
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufWriter;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::interval;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+use tracing::info;
+
+// Buffered lines reach the file at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Tees raw websocket payloads to an append only file, the writes happen on a
+// separate task so the socket read loop only pays for a channel send
+#[derive(Clone, Debug)]
+pub struct FeedCapture {
+    sender: UnboundedSender<(DateTime<Utc>, String)>,
+}
+
+impl FeedCapture {
+    pub fn new(directory: &str, prefix: &str, cancel_token: CancellationToken) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let directory = PathBuf::from(directory);
+        let prefix = prefix.to_string();
+        tokio::spawn(async move {
+            if let Err(err) = Self::write_loop(&directory, &prefix, receiver, cancel_token).await {
+                error!(
+                    "Feed capture to {} stopped, error: {}",
+                    directory.display(),
+                    err
+                );
+            }
+        });
+        Self { sender }
+    }
+
+    pub fn record(&self, msg: &str) {
+        let _ = self.sender.send((Utc::now(), msg.to_string()));
+    }
+
+    pub fn file_name(directory: &Path, prefix: &str, date: NaiveDate) -> PathBuf {
+        directory.join(format!("{}-{}.log", prefix, date.format("%Y%m%d")))
+    }
+
+    async fn open(directory: &Path, prefix: &str, date: NaiveDate) -> Result<BufWriter<File>> {
+        let path = Self::file_name(directory, prefix, date);
+        info!("Capturing websocket feed to {}", path.display());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(BufWriter::new(file))
+    }
+
+    async fn write_loop(
+        directory: &Path,
+        prefix: &str,
+        mut receiver: UnboundedReceiver<(DateTime<Utc>, String)>,
+        cancel_token: CancellationToken,
+    ) -> Result<()> {
+        tokio::fs::create_dir_all(directory).await?;
+        let mut date = Utc::now().date_naive();
+        let mut writer = Self::open(directory, prefix, date).await?;
+        let mut flush = interval(FLUSH_INTERVAL);
+        loop {
+            tokio::select! {
+                msg = receiver.recv() => {
+                    let Some((received_at, msg)) = msg else {
+                        break;
+                    };
+                    if received_at.date_naive() != date {
+                        writer.flush().await?;
+                        date = received_at.date_naive();
+                        writer = Self::open(directory, prefix, date).await?;
+                    }
+                    let line = format!("{}\t{}\n", received_at.to_rfc3339(), msg);
+                    writer.write_all(line.as_bytes()).await?;
+                }
+                _ = flush.tick() => {
+                    writer.flush().await?;
+                }
+                _ = cancel_token.cancelled() => {
+                    break;
+                }
+            }
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_file_name_rotates_by_date() {
+        let directory = Path::new("/tmp/capture");
+        let first = FeedCapture::file_name(
+            directory,
+            "mktdata",
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+        );
+        let second = FeedCapture::file_name(
+            directory,
+            "mktdata",
+            NaiveDate::from_ymd_opt(2024, 3, 2).unwrap(),
+        );
+        assert_eq!(first, PathBuf::from("/tmp/capture/mktdata-20240301.log"));
+        assert_eq!(second, PathBuf::from("/tmp/capture/mktdata-20240302.log"));
+    }
+
+    #[tokio::test]
+    async fn test_capture_flushed_while_feed_busy() {
+        let directory = std::env::temp_dir().join(format!("capture-{}", std::process::id()));
+        let cancel_token = CancellationToken::new();
+        let capture =
+            FeedCapture::new(directory.to_str().unwrap(), "mktdata", cancel_token.clone());
+
+        // Messages arriving faster than the flush period must not hold it off
+        for index in 0..30 {
+            capture.record(&format!("msg-{}", index));
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let path = FeedCapture::file_name(&directory, "mktdata", Utc::now().date_naive());
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        cancel_token.cancel();
+        let _ = tokio::fs::remove_dir_all(&directory).await;
+        assert!(contents.contains("msg-0\n"));
+    }
+}
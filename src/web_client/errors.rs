@@ -1,9 +1,14 @@
-enum ApiError {
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
     InvalidRequest,
     AuthorizationError,
     Forbidden,
     NotFound,
     TooManyRequests,
+    // No response inside the configured request timeout
+    Timeout,
     ServerError(String), // Assuming the server error includes an identifier
 }
 
@@ -15,16 +20,18 @@ impl ApiError {
             ApiError::Forbidden => 403,
             ApiError::NotFound => 404,
             ApiError::TooManyRequests => 429,
+            ApiError::Timeout => 408,
             ApiError::ServerError(_) => 500,
         }
     }
 
-    fn from_code(code: u16, message: Option<String>) -> Option<ApiError> {
+    pub fn from_code(code: u16, message: Option<String>) -> Option<ApiError> {
         match code {
             400 => Some(ApiError::InvalidRequest),
             401 => Some(ApiError::AuthorizationError),
             403 => Some(ApiError::Forbidden),
             404 => Some(ApiError::NotFound),
+            408 => Some(ApiError::Timeout),
             429 => Some(ApiError::TooManyRequests),
             500 => Some(ApiError::ServerError(
                 message.unwrap_or_else(|| "Unknown Error".to_string()),
@@ -33,14 +40,23 @@ impl ApiError {
         }
     }
 
-    fn description(&self) -> &str {
+    fn description(&self) -> String {
         match self {
-            ApiError::InvalidRequest => "Invalid request. Often indicates that your request body has missing or invalid parameters.",
-            ApiError::AuthorizationError => "Authorization token has expired or is invalid. Also indicates an invalid username/password when logging in.",
-            ApiError::Forbidden => "User is not authorized to access this resource. This may occur when a customer tries to access data for an account belonging to a different customer, for example.",
-            ApiError::NotFound => "Endpoint or resource not found. This may occur when attempting to fetch data that does not exist (a specific order, for example).",
-            ApiError::TooManyRequests => "Too Many Requests. This occurs when you send a high amount of requests in a short period of time to the point where it exceeds reasonable thresholds.",
-            ApiError::ServerError(identifier) => &format!("Indicates an issue with tastytrade's servers. Returns a support identifier ({}) that our team can use to track down the issue.", identifier),
+            ApiError::InvalidRequest => "Invalid request. Often indicates that your request body has missing or invalid parameters.".to_string(),
+            ApiError::AuthorizationError => "Authorization token has expired or is invalid. Also indicates an invalid username/password when logging in.".to_string(),
+            ApiError::Forbidden => "User is not authorized to access this resource. This may occur when a customer tries to access data for an account belonging to a different customer, for example.".to_string(),
+            ApiError::NotFound => "Endpoint or resource not found. This may occur when attempting to fetch data that does not exist (a specific order, for example).".to_string(),
+            ApiError::TooManyRequests => "Too Many Requests. This occurs when you send a high amount of requests in a short period of time to the point where it exceeds reasonable thresholds.".to_string(),
+            ApiError::Timeout => "Request timed out. The broker did not answer within the configured request timeout, the request may or may not have been acted on.".to_string(),
+            ApiError::ServerError(identifier) => format!("Indicates an issue with tastytrade's servers. Returns a support identifier ({}) that our team can use to track down the issue.", identifier),
         }
     }
 }
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.to_code(), self.description())
+    }
+}
+
+impl std::error::Error for ApiError {}
@@ -1,25 +1,60 @@
+use ::http_client::isahc::IsahcClient;
+use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
+use futures_util::future::BoxFuture;
+use isahc::config::CaCertificate;
+use isahc::config::Configurable;
 use serde::Deserialize;
 use serde::Serialize;
-use serde_json::to_string as to_json;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
 
 use surf::middleware::Middleware;
 use surf::Client;
 use surf::Request;
 use surf::RequestBuilder;
+use surf::StatusCode;
 use tracing::debug;
 use tracing::info;
+use tracing::warn;
 use url::Url;
-// Custom middleware to log requests before they are sent
 
-#[derive(Clone, Debug)]
+use super::errors::ApiError;
+use super::websocket::read_ca_bundle;
+use crate::settings::TlsConfig;
+
+// Hands back a fresh session token once a request comes back unauthorized
+pub type Reauth = Arc<dyn Fn() -> BoxFuture<'static, Result<String>> + Send + Sync>;
+
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
 pub struct HttpClient {
     base_url: String,
     client: Client,
+    reauth: Option<Reauth>,
+    // The last token handed out by reauth, shared by every clone so requests
+    // rejected together log in once
+    reauthed_session: Arc<Mutex<Option<String>>>,
+    timeout: Duration,
 }
 
+impl fmt::Debug for HttpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpClient")
+            .field("base_url", &self.base_url)
+            .field("reauth", &self.reauth.is_some())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+// Custom middleware to log requests before they are sent
 struct LoggingMiddleware {}
 
 #[surf::utils::async_trait]
@@ -46,9 +81,46 @@ impl HttpClient {
         Self {
             base_url: base_url.to_string(),
             client: Client::new().with(LoggingMiddleware {}),
+            reauth: None,
+            reauthed_session: Arc::default(),
+            timeout: DEFAULT_REQUEST_TIMEOUT,
         }
     }
 
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_reauth(&mut self, reauth: Reauth) {
+        self.reauth = Some(reauth);
+    }
+
+    // Behind an inspecting proxy logins and orders need the proxy's CA too.
+    // curl reads the bundle in place of its default roots. isahc has no
+    // setting for the protocol floor, min_version is left to curl's default
+    pub fn set_tls(&mut self, tls: &TlsConfig) -> Result<()> {
+        let Some(path) = &tls.ca_bundle else {
+            return Ok(());
+        };
+        // curl only opens the file on the first request, read it here so a
+        // bad bundle fails now
+        read_ca_bundle(path)?;
+        let client = isahc::HttpClient::builder()
+            .ssl_ca_certificate(CaCertificate::file(path))
+            .build()?;
+        self.client =
+            Client::with_http_client(IsahcClient::from_client(client)).with(LoggingMiddleware {});
+        Ok(())
+    }
+
+    fn build_url(&self, endpoint: &str, query: &[(&str, &str)]) -> Result<Url> {
+        let mut url = Url::parse(format!("{}/{}", self.base_url, endpoint).as_str())?;
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        Ok(url)
+    }
+
     fn add_custom_headers(session: Option<&str>, request: RequestBuilder) -> RequestBuilder {
         let request = match session {
             Some(session) => request
@@ -60,23 +132,133 @@ impl HttpClient {
         request.header("Content-Type", "application/json".to_string())
     }
 
+    // Authenticated requests that come back 401 are sent once more with the
+    // token from the reauth callback, a second 401 is returned as it is
+    async fn send<Build>(
+        &self,
+        method: &str,
+        session: Option<&str>,
+        build: Build,
+    ) -> Result<surf::Response>
+    where
+        Build: Fn(Option<&str>) -> Result<RequestBuilder>,
+    {
+        let response = self.send_timed(method, build(session)?).await?;
+        let Some(reauth) = self.reauth.as_ref() else {
+            return Ok(response);
+        };
+        if session.is_none() || response.status() != StatusCode::Unauthorized {
+            return Ok(response);
+        }
+        warn!(
+            "{} request unauthorized, refreshing session and retrying",
+            method
+        );
+        let session = self.reauthed(reauth, session).await?;
+        self.send_timed(method, build(Some(&session))?).await
+    }
+
+    // Logins run one at a time as each spends the stored remember token. A
+    // request rejected with a token that has since been replaced takes up the
+    // replacement instead of logging in again
+    async fn reauthed(&self, reauth: &Reauth, rejected: Option<&str>) -> Result<String> {
+        let mut latest = self.reauthed_session.lock().await;
+        if let Some(session) = latest
+            .as_deref()
+            .filter(|session| Some(*session) != rejected)
+        {
+            return Ok(session.to_string());
+        }
+        let session = reauth().await?;
+        *latest = Some(session.clone());
+        Ok(session)
+    }
+
+    // A request with no answer inside the timeout comes back as
+    // ApiError::Timeout so callers can tell it from a refusal. The body is
+    // read under the same timeout, headers alone do not count as an answer
+    async fn send_timed(&self, method: &str, request: RequestBuilder) -> Result<surf::Response> {
+        let send = async {
+            let mut response = request.await?;
+            let body = response.body_bytes().await?;
+            response.set_body(body);
+            surf::Result::Ok(response)
+        };
+        match timeout(self.timeout, send).await {
+            core::result::Result::Ok(core::result::Result::Ok(val)) => Ok(val),
+            core::result::Result::Ok(Err(err)) => {
+                bail!("Failed {} request, error: {}", method, err)
+            }
+            Err(_) => Err(anyhow::Error::new(ApiError::Timeout).context(format!(
+                "{} request timed out after {:?}",
+                method, self.timeout
+            ))),
+        }
+    }
+
+    // Passwords and remember tokens are masked before a payload is logged
+    fn redacted<Payload>(data: &Payload) -> Result<String>
+    where
+        Payload: Serialize,
+    {
+        let mut payload = serde_json::to_value(data)?;
+        if let Some(fields) = payload.as_object_mut() {
+            for key in ["password", "remember-token"] {
+                if let Some(value) = fields.get_mut(key) {
+                    *value = serde_json::Value::from("***");
+                }
+            }
+        }
+        Ok(payload.to_string())
+    }
+
+    // Known statuses keep their ApiError so callers can downcast to it
+    fn status_error(status: StatusCode, msg: String) -> anyhow::Error {
+        match ApiError::from_code(status.into(), Some(msg.clone())) {
+            Some(err) => anyhow::Error::new(err).context(msg),
+            None => anyhow!(msg),
+        }
+    }
+
     pub async fn get<Response>(&self, endpoint: &str, session: Option<&str>) -> Result<Response>
     where
         Response: Serialize + for<'a> Deserialize<'a>,
     {
-        let url = Url::parse(format!("{}/{}", self.base_url, endpoint).as_str())?;
+        self.get_with_query(endpoint, &[], session).await
+    }
+
+    // Query values are percent encoded, so symbols with spaces or slashes
+    // can be passed as they are
+    pub async fn get_with_query<Response>(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+        session: Option<&str>,
+    ) -> Result<Response>
+    where
+        Response: Serialize + for<'a> Deserialize<'a>,
+    {
+        let url = self.build_url(endpoint, query)?;
         info!("request base: {} endpoint:{}", self.base_url, endpoint);
-        let mut response = match Self::add_custom_headers(session, self.client.get(url)).await {
-            core::result::Result::Ok(val) => val,
-            Err(err) => bail!("Failed get request, error: {}", err),
-        };
+        let mut response = self
+            .send("get", session, |session| {
+                Ok(Self::add_custom_headers(
+                    session,
+                    self.client.get(url.clone()),
+                ))
+            })
+            .await?;
 
         if !response.status().is_success() {
-            bail!(
-                "GET Request failed with status: {} text: {:?}",
-                response.status(),
-                response.body_string().await
-            );
+            let status = response.status();
+            return Err(Self::status_error(
+                status,
+                format!(
+                    "GET Request failed with status: {} text: {:?}",
+                    status,
+                    response.body_string().await
+                ),
+            ));
         }
 
         debug!("GET Response body: {:?}", response.body_string().await);
@@ -90,6 +272,39 @@ impl HttpClient {
         }
     }
 
+    pub async fn delete<Response>(&self, endpoint: &str, session: Option<&str>) -> Result<Response>
+    where
+        Response: Serialize + for<'a> Deserialize<'a>,
+    {
+        let url = Url::parse(format!("{}/{}", self.base_url, endpoint).as_str())?;
+        info!("request base: {} endpoint:{}", self.base_url, endpoint);
+        let mut response = self
+            .send("delete", session, |session| {
+                Ok(Self::add_custom_headers(
+                    session,
+                    self.client.delete(url.clone()),
+                ))
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(Self::status_error(
+                status,
+                format!(
+                    "DELETE Request failed with status: {} text: {:?}",
+                    status,
+                    response.body_string().await
+                ),
+            ));
+        }
+
+        match response.body_json::<Response>().await {
+            surf::Result::Ok(val) => Ok(val),
+            Err(err) => bail!("Could not read json body, error: {}", err),
+        }
+    }
+
     pub async fn post<Payload, Response>(
         &self,
         endpoint: &str,
@@ -101,24 +316,28 @@ impl HttpClient {
         Response: Serialize + for<'a> Deserialize<'a>,
     {
         let url = Url::parse(format!("{}/{}", self.base_url, endpoint).as_str())?;
-        let payload = to_json(&data)?;
+        let payload = Self::redacted(&data)?;
         info!(
             "request to endpoint: {}/{} with payload: {}",
             self.base_url, endpoint, payload
         );
-        let builder =
-            match Self::add_custom_headers(session, self.client.post(url)).body_json(&data) {
-                core::result::Result::Ok(val) => val,
-                Err(err) => bail!("Failed to post request {}", err),
-            };
-
-        let mut response = match builder.await {
-            core::result::Result::Ok(val) => val,
-            Err(err) => bail!("Failed to post request {}", err),
-        };
+        let mut response = self
+            .send("post", session, |session| {
+                match Self::add_custom_headers(session, self.client.post(url.clone()))
+                    .body_json(&data)
+                {
+                    core::result::Result::Ok(val) => Ok(val),
+                    Err(err) => bail!("Failed to post request {}", err),
+                }
+            })
+            .await?;
 
         if !response.status().is_success() {
-            bail!("POST Request failed with status: {}", response.status());
+            let status = response.status();
+            return Err(Self::status_error(
+                status,
+                format!("POST Request failed with status: {}", status),
+            ));
         }
 
         debug!("POST Response body: {:?}", response);
@@ -128,6 +347,39 @@ impl HttpClient {
         }
     }
 
+    // For endpoints that answer with plain text, webhooks in particular,
+    // only the status of the response is checked
+    pub async fn post_no_content<Payload>(
+        &self,
+        endpoint: &str,
+        data: Payload,
+        session: Option<&str>,
+    ) -> Result<()>
+    where
+        Payload: Serialize,
+    {
+        let url = Url::parse(format!("{}/{}", self.base_url, endpoint).as_str())?;
+        let response = self
+            .send("post", session, |session| {
+                match Self::add_custom_headers(session, self.client.post(url.clone()))
+                    .body_json(&data)
+                {
+                    core::result::Result::Ok(val) => Ok(val),
+                    Err(err) => bail!("Failed to post request {}", err),
+                }
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(Self::status_error(
+                status,
+                format!("POST Request failed with status: {}", status),
+            ));
+        }
+        Ok(())
+    }
+
     pub async fn put<Payload, Response>(
         &self,
         endpoint: &str,
@@ -139,24 +391,28 @@ impl HttpClient {
         Response: Serialize + for<'a> Deserialize<'a>,
     {
         let url = Url::parse(format!("{}/{}", self.base_url, endpoint).as_str())?;
-        let payload = to_json(&data)?;
+        let payload = Self::redacted(&data)?;
         info!(
             "request to endpoint: {}/{} with payload: {}",
             self.base_url, endpoint, payload
         );
-        let builder = match Self::add_custom_headers(session, self.client.put(url)).body_json(&data)
-        {
-            core::result::Result::Ok(val) => val,
-            Err(err) => bail!("Failed to post request {}", err),
-        };
-
-        let mut response = match builder.await {
-            core::result::Result::Ok(val) => val,
-            Err(err) => bail!("Failed to post request {}", err),
-        };
+        let mut response = self
+            .send("put", session, |session| {
+                match Self::add_custom_headers(session, self.client.put(url.clone()))
+                    .body_json(&data)
+                {
+                    core::result::Result::Ok(val) => Ok(val),
+                    Err(err) => bail!("Failed to post request {}", err),
+                }
+            })
+            .await?;
 
         if !response.status().is_success() {
-            bail!("POST Request failed with status: {}", response.status());
+            let status = response.status();
+            return Err(Self::status_error(
+                status,
+                format!("POST Request failed with status: {}", status),
+            ));
         }
 
         debug!("POST Response body: {:?}", response);
@@ -166,3 +422,218 @@ impl HttpClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::start_stub_server;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_query_values_are_encoded() {
+        let client = HttpClient::new("https://api.cert.tastyworks.com");
+        let url = client
+            .build_url(
+                "accounts/5WT0001/positions",
+                &[
+                    ("underlying-symbol", "/ES"),
+                    ("symbol", "SPY   240315C00425000"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://api.cert.tastyworks.com/accounts/5WT0001/positions?underlying-symbol=%2FES&symbol=SPY+++240315C00425000"
+        );
+        let url = client.build_url("api-quote-tokens", &[]).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://api.cert.tastyworks.com/api-quote-tokens"
+        );
+    }
+
+    #[test]
+    fn test_secrets_redacted_from_logged_payload() {
+        let payload =
+            serde_json::json!({"login": "trader", "password": "secret", "remember-me": true});
+        let logged = HttpClient::redacted(&payload).unwrap();
+        assert!(!logged.contains("secret"));
+        assert!(logged.contains(r#""password":"***""#));
+        assert!(logged.contains(r#""login":"trader""#));
+    }
+
+    // Only accepts the token handed out by the reauth callback
+    async fn accounts(
+        headers: axum::http::HeaderMap,
+    ) -> core::result::Result<axum::Json<serde_json::Value>, axum::http::StatusCode> {
+        match headers.get("Authorization").map(|token| token.as_bytes()) {
+            Some(b"new-session") => core::result::Result::Ok(axum::Json(
+                serde_json::json!({"data": {"items": []}, "context": "/customers/me/accounts"}),
+            )),
+            _ => Err(axum::http::StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    fn make_reauth(token: &'static str, calls: Arc<AtomicUsize>) -> Reauth {
+        Arc::new(move || {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { Ok(token.to_string()) })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_request_retried_after_reauth() {
+        let url = start_stub_server(
+            axum::Router::new().route("/customers/me/accounts", axum::routing::get(accounts)),
+        )
+        .await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut client = HttpClient::new(&url);
+        client.set_reauth(make_reauth("new-session", Arc::clone(&calls)));
+
+        let response = client
+            .get::<serde_json::Value>("customers/me/accounts", Some("expired-session"))
+            .await
+            .unwrap();
+
+        assert_eq!(response["context"], "/customers/me/accounts");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_unauthorized_requests_log_in_once() {
+        let url = start_stub_server(
+            axum::Router::new().route("/customers/me/accounts", axum::routing::get(accounts)),
+        )
+        .await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut client = HttpClient::new(&url);
+        // Slow enough that every request is rejected before the login ends
+        client.set_reauth(Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(String::from("new-session"))
+            })
+        }));
+
+        let get = || client.get::<serde_json::Value>("customers/me/accounts", Some("expired"));
+        let (first, second, third) = tokio::join!(get(), get(), get());
+
+        assert!(first.is_ok() && second.is_ok() && third.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    fn ca_bundle_config(name: &str, contents: Option<&str>) -> TlsConfig {
+        let path = std::env::temp_dir().join(format!("{}-{}.pem", name, std::process::id()));
+        if let Some(contents) = contents {
+            std::fs::write(&path, contents).unwrap();
+        }
+        TlsConfig {
+            ca_bundle: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_client_loads_ca_bundle() {
+        use crate::utils::fixtures::PROXY_CA;
+
+        let mut client = HttpClient::new("https://api.cert.tastyworks.com");
+        client.set_tls(&TlsConfig::default()).unwrap();
+        let config = ca_bundle_config("rest-ca-bundle", Some(PROXY_CA));
+        client.set_tls(&config).unwrap();
+        std::fs::remove_file(config.ca_bundle.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_client_rejects_bad_ca_bundle() {
+        let mut client = HttpClient::new("https://api.cert.tastyworks.com");
+        let err = client
+            .set_tls(&ca_bundle_config("rest-missing-bundle", None))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Failed to read CA bundle"));
+
+        let config = ca_bundle_config("rest-bad-bundle", Some("not a certificate"));
+        let err = client.set_tls(&config).unwrap_err();
+        std::fs::remove_file(config.ca_bundle.unwrap()).unwrap();
+        assert!(err.to_string().starts_with("No certificates found"));
+    }
+
+    #[tokio::test]
+    async fn test_stuck_request_times_out() {
+        let url = start_stub_server(axum::Router::new().route(
+            "/instruments/equity-options",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                axum::Json(serde_json::json!({}))
+            }),
+        ))
+        .await;
+        let mut client = HttpClient::new(&url);
+        client.set_timeout(Duration::from_millis(100));
+
+        let err = client
+            .get::<serde_json::Value>("instruments/equity-options", Some("session"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ApiError>(), Some(&ApiError::Timeout));
+        assert_eq!(err.to_string(), "get request timed out after 100ms");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_body_times_out() {
+        // Headers go out straight away, the body never follows
+        let url = start_stub_server(axum::Router::new().route(
+            "/instruments/equity-options",
+            axum::routing::get(|| async {
+                axum::body::Body::from_stream(futures_util::stream::pending::<
+                    core::result::Result<axum::body::Bytes, std::io::Error>,
+                >())
+            }),
+        ))
+        .await;
+        let mut client = HttpClient::new(&url);
+        client.set_timeout(Duration::from_millis(100));
+
+        let err = client
+            .get::<serde_json::Value>("instruments/equity-options", Some("session"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ApiError>(), Some(&ApiError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_reauth_retried_only_once() {
+        let url = start_stub_server(
+            axum::Router::new().route("/customers/me/accounts", axum::routing::get(accounts)),
+        )
+        .await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut client = HttpClient::new(&url);
+        client.set_reauth(make_reauth("still-expired", Arc::clone(&calls)));
+
+        let err = client
+            .get::<serde_json::Value>("customers/me/accounts", Some("expired-session"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            err.downcast_ref::<ApiError>(),
+            Some(&ApiError::AuthorizationError)
+        );
+
+        // Logins carry no session and are never retried
+        let err = client
+            .get::<serde_json::Value>("customers/me/accounts", None)
+            .await
+            .unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(err
+            .to_string()
+            .starts_with("GET Request failed with status: 401"));
+    }
+}
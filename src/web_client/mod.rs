@@ -1,33 +1,63 @@
 use anyhow::bail;
 use anyhow::Ok;
 use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
 use core::result::Result as CoreResult;
+use percent_encoding::utf8_percent_encode;
 use serde::Deserialize;
 use serde::Serialize;
 use sqlx::postgres::PgRow;
 use sqlx::FromRow;
 use sqlx::Row;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc;
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::sync::RwLock;
 use tokio_util::sync::CancellationToken;
-use tracing::debug;
+use tracing::error;
 use tracing::info;
+use tracing::warn;
 
+mod capture;
+pub(crate) mod errors;
 pub(crate) mod http_client;
 pub(crate) mod sessions;
 mod websocket;
 
-use crate::db_client::SqlQueryBuilder;
+use crate::auth_store::AuthStore;
+use crate::db_client::DbStoredCreds;
+use crate::metrics::CHANNEL_DROPPED_MESSAGES;
+use crate::mktdata::UTF8_ECODING;
+use crate::tt_api::accounts::CustomerAccounts;
+use crate::tt_api::mktdata::DataFormat;
+use crate::tt_api::mktdata::Equity;
+use crate::tt_api::mktdata::FeedContract;
+use crate::tt_api::mktdata::Future;
+use crate::tt_api::option_chain::OptionChain;
+use crate::tt_api::option_chain::OptionChains;
+use crate::tt_api::orders::LiveOrders;
+use crate::tt_api::orders::OrderData;
+use crate::tt_api::transactions::AccountTransactions;
+use crate::tt_api::transactions::Transaction;
 
 use self::sessions::acc_api;
 use self::sessions::md_api;
 
-use super::db_client::DBClient;
+use super::settings::ChannelConfig;
+use super::settings::ReconnectConfig;
 use super::settings::Settings;
+use super::settings::TlsConfig;
+use capture::FeedCapture;
 use http_client::HttpClient;
 use sessions::AccountSession;
 use sessions::MktdataSession;
+use sessions::WsSession;
 use websocket::WebSocketClient;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,7 +67,7 @@ struct Wrapper<Response> {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct ApiQuoteToken {
+pub(crate) struct ApiQuoteToken {
     token: String,
     #[serde(rename = "streamer-url")]
     streamer_url: Option<String>,
@@ -48,7 +78,7 @@ struct ApiQuoteToken {
     level: String,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
 pub enum EndPoint {
     #[default]
     Sandbox,
@@ -57,10 +87,18 @@ pub enum EndPoint {
 
 impl FromRow<'_, PgRow> for EndPoint {
     fn from_row(row: &PgRow) -> sqlx::Result<Self> {
-        sqlx::Result::Ok(match row.try_get("endpoint")? {
+        sqlx::Result::Ok(match row.try_get::<i32, _>("endpoint")? {
             1 => EndPoint::Sandbox,
             2 => EndPoint::Live,
-            _ => panic!("Unknown endpoint"),
+            other => {
+                return Err(sqlx::Error::Decode(
+                    format!(
+                        "Unknown endpoint: {}, expected 1 (Sandbox) or 2 (Live)",
+                        other
+                    )
+                    .into(),
+                ))
+            }
         })
     }
 }
@@ -95,16 +133,6 @@ struct LoginAuthToken {
     remember_me: bool,
 }
 
-#[derive(FromRow, Clone, PartialEq, Eq, Debug)]
-struct DbStoredCreds {
-    username: String,
-    account: String,
-    session: String,
-    remember: String,
-    #[sqlx(flatten)]
-    endpoint: EndPoint,
-}
-
 #[derive(FromRow, Clone, Default, Debug, Serialize, Deserialize)]
 struct AuthResponse {
     #[sqlx(flatten)]
@@ -123,86 +151,135 @@ struct User {
     external_id: String,
 }
 
-const CHANNEL_CAPACITY_TO_WS: usize = 100;
-const CHANNEL_CAPACITY_FROM_MD_WS: usize = 100;
-const CHANNEL_CAPACITY_FROM_ACC_WS: usize = 50;
+#[derive(Clone, Debug)]
+pub struct SessionHealth {
+    pub name: &'static str,
+    pub is_alive: bool,
+    pub last_received: DateTime<Utc>,
+    pub last_heartbeat: Option<DateTime<Utc>>,
+}
+
+// A broadcast receiver more than the capacity behind loses the oldest
+// messages, this counts them and says which setting buys more headroom
+pub(crate) fn report_lagged(channel: &str, dropped: u64, capacity: usize) {
+    CHANNEL_DROPPED_MESSAGES
+        .with_label_values(&[channel])
+        .inc_by(dropped);
+    warn!(
+        "{} channel lagged and dropped {} messages, capacity: {}, raise channels {}_channel_capacity to absorb bursts at the cost of memory",
+        channel, dropped, capacity, channel
+    );
+}
+
+const TRANSACTIONS_PER_PAGE: usize = 250;
+
+// A rotated remember token only lives in memory until stored, so a failed
+// write is retried before it is held over to the next refresh
+const TOKEN_STORE_ATTEMPTS: u32 = 3;
+const TOKEN_STORE_BACKOFF: Duration = Duration::from_millis(200);
 
 #[derive(Clone, Debug)]
 pub struct WebClient {
-    session: String,
+    // Shared so a token refreshed mid-session is picked up by every request
+    session: Arc<RwLock<String>>,
+    // A login whose rotated remember token could not be stored, the next
+    // refresh logs in with it and shutdown tries storing it again
+    unstored_auth: Arc<Mutex<Option<AuthResponse>>>,
     account: String,
     http_client: HttpClient,
     account_ws: Option<WebSocketClient<AccountSession>>,
     mktdata_ws: Option<WebSocketClient<MktdataSession>>,
     mktdata_session: Sender<String>,
     account_session: Sender<String>,
+    capture_path: Option<String>,
+    reconnect: ReconnectConfig,
+    tls: TlsConfig,
+    data_format: DataFormat,
+    feed_contract: FeedContract,
+    dxlink_version: String,
+    channels: ChannelConfig,
     cancel_token: CancellationToken,
 }
 
 impl WebClient {
     pub async fn new(base_url: &str, cancel_token: CancellationToken) -> Result<Self> {
-        let (md_channel, _) = broadcast::channel::<String>(CHANNEL_CAPACITY_FROM_MD_WS);
-        let (acc_channel, _) = broadcast::channel::<String>(CHANNEL_CAPACITY_FROM_ACC_WS);
+        let channels = ChannelConfig::default();
+        let (md_channel, _) = broadcast::channel::<String>(channels.md_channel_capacity);
+        let (acc_channel, _) = broadcast::channel::<String>(channels.account_channel_capacity);
 
         Ok(WebClient {
-            session: String::default(),
+            session: Arc::new(RwLock::new(String::default())),
+            unstored_auth: Arc::default(),
             account: String::default(),
-            http_client: HttpClient::new(&format!("https://{}", base_url)),
+            http_client: HttpClient::new(base_url),
             account_ws: None,
             mktdata_ws: None,
             mktdata_session: md_channel,
             account_session: acc_channel,
+            capture_path: None,
+            reconnect: ReconnectConfig::default(),
+            tls: TlsConfig::default(),
+            data_format: DataFormat::default(),
+            feed_contract: FeedContract::default(),
+            dxlink_version: String::default(),
+            channels,
             cancel_token,
         })
     }
 
-    pub async fn startup(
+    pub async fn startup<Store>(
         &mut self,
         account_session_url: &str,
-        settings: Settings,
-        db: &DBClient,
-    ) -> Result<()> {
-        let mut creds = Self::fetch_auth_from_db(&settings.username, settings.endpoint, db).await?;
-        assert!(creds.len() == 1);
-        let data = &mut creds[0];
+        settings: &Settings,
+        store: &Store,
+    ) -> Result<()>
+    where
+        Store: AuthStore,
+    {
+        let data = self.login(settings, store).await?;
+        let api_quote_token = self.api_quote_token().await?;
+        self.open_streams(
+            account_session_url,
+            settings,
+            &data.account,
+            api_quote_token,
+        )
+        .await
+    }
 
-        let password = std::env::var("TASTY_PASSWORD").ok();
-        let updates =
-            match Self::initialise_session(&self.http_client, data.clone(), password).await {
-                CoreResult::Ok(val) => {
-                    Self::update_auth_from_db(
-                        &val.data.session,
-                        &val.data.remember,
-                        settings.endpoint,
-                        db,
-                    )
-                    .await?;
-                    val
-                }
-                Err(err) => bail!("Failed to update refresh token, error: {}", err),
-            };
-        self.session = updates.data.session;
-        self.account.clone_from(&data.account);
+    pub(crate) async fn api_quote_token(&self) -> Result<ApiQuoteToken> {
+        let session = self.session_token().await;
+        Self::get_api_quote_token(&self.http_client, &session).await
+    }
 
-        let api_quote_token = self
-            .get_api_quote_token(&self.http_client, &self.session)
-            .await?;
+    // Dials the mktdata and account websockets, both run their handshakes in
+    // the background so being open here says nothing about being connected
+    pub(crate) async fn open_streams(
+        &mut self,
+        account_session_url: &str,
+        settings: &Settings,
+        account: &str,
+        api_quote_token: ApiQuoteToken,
+    ) -> Result<()> {
+        self.capture_path = settings.capture_path.clone();
+        self.reconnect = settings.reconnect.clone();
+        self.tls = settings.tls.clone();
+        self.data_format = settings.data_format;
+        self.feed_contract = settings.feed_contract;
+        self.dxlink_version = settings.dxlink_version.clone();
+        self.resize_channels(&settings.channels);
 
-        let (to_ws, _) = broadcast::channel::<String>(CHANNEL_CAPACITY_TO_WS);
         self.mktdata_ws = Some(
-            self.subscribe_to_mktdata(api_quote_token, to_ws, self.cancel_token.clone())
+            self.subscribe_to_mktdata(api_quote_token, self.cancel_token.clone())
                 .await?,
         );
 
-        info!("Session token {}", self.session.clone());
+        info!("Session token {}", self.session_token().await);
 
-        let (to_ws, _) = broadcast::channel::<String>(CHANNEL_CAPACITY_TO_WS);
         self.account_ws = Some(
             self.subscribe_to_account_updates(
                 account_session_url,
-                &data.account.clone(),
-                &self.session.clone(),
-                to_ws,
+                account,
                 self.cancel_token.clone(),
             )
             .await?,
@@ -211,12 +288,316 @@ impl WebClient {
         Ok(())
     }
 
+    // Sent ahead of the regular heartbeat so a response can be waited on
+    pub async fn send_account_heartbeat(&self) -> Result<()> {
+        let Some(client) = self.account_ws.as_ref() else {
+            bail!("Account websocket not open");
+        };
+        let heartbeat = acc_api::AccHeartbeat {
+            action: "heartbeat".to_string(),
+            auth_token: self.session_token().await,
+        };
+        client.send_message(heartbeat).await
+    }
+
+    pub async fn login<Store>(
+        &mut self,
+        settings: &Settings,
+        store: &Store,
+    ) -> Result<DbStoredCreds>
+    where
+        Store: AuthStore,
+    {
+        let password = std::env::var("TASTY_PASSWORD").ok();
+        self.refresh_session(settings, password, store).await
+    }
+
+    // Logs in with the password and stores the remember token, the account
+    // only has to be given when the login holds more than one
+    pub async fn seed_credentials<Store>(
+        &mut self,
+        username: &str,
+        endpoint: EndPoint,
+        password: String,
+        account: Option<&str>,
+        store: &Store,
+    ) -> Result<DbStoredCreds>
+    where
+        Store: AuthStore,
+    {
+        let login = DbStoredCreds {
+            username: username.to_string(),
+            account: String::default(),
+            session: String::default(),
+            remember: String::default(),
+            endpoint,
+        };
+        let auth = match Self::initialise_session(&self.http_client, login, Some(password)).await {
+            CoreResult::Ok(val) => val,
+            Err(err) => bail!("Failed to login as user: {}, error: {}", username, err),
+        };
+        *self.session.write().await = auth.data.session.clone();
+
+        let accounts = self
+            .get::<Wrapper<CustomerAccounts>>("customers/me/accounts")
+            .await?
+            .data
+            .items
+            .into_iter()
+            .map(|item| item.account.account_number)
+            .collect::<Vec<String>>();
+        let account = match (account, accounts.as_slice()) {
+            (Some(account), _) if accounts.iter().any(|number| number == account) => {
+                account.to_string()
+            }
+            (Some(account), _) => bail!(
+                "Account: {} not found for user: {}, accounts: {:?}",
+                account,
+                username,
+                accounts
+            ),
+            (None, [account]) => account.clone(),
+            (None, _) => bail!(
+                "User: {} holds accounts: {:?}, pick one with --account",
+                username,
+                accounts
+            ),
+        };
+        self.account.clone_from(&account);
+
+        let creds = DbStoredCreds {
+            username: username.to_string(),
+            account,
+            session: auth.data.session,
+            remember: auth.data.remember,
+            endpoint,
+        };
+        if store.fetch(username, endpoint).await?.is_empty() {
+            store.insert(&creds).await?;
+            info!(
+                "Stored credentials for user: {} on {:?}",
+                username, endpoint
+            );
+        } else {
+            store
+                .update(&creds.session, &creds.remember, endpoint)
+                .await?;
+            info!(
+                "Updated credentials for user: {} on {:?}",
+                username, endpoint
+            );
+        }
+        Ok(creds)
+    }
+
+    async fn refresh_session<Store>(
+        &mut self,
+        settings: &Settings,
+        password: Option<String>,
+        store: &Store,
+    ) -> Result<DbStoredCreds>
+    where
+        Store: AuthStore,
+    {
+        let data = Self::renew_session(
+            &self.http_client,
+            &self.session,
+            &self.unstored_auth,
+            &settings.username,
+            settings.endpoint,
+            password,
+            store,
+        )
+        .await?;
+        self.account.clone_from(&data.account);
+        Ok(data)
+    }
+
+    // Set ahead of enable_reauth so its login client times out too
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.http_client.set_timeout(timeout);
+    }
+
+    // Set ahead of enable_reauth too. The websockets take the same config when
+    // opened, it is checked here so a bad CA bundle fails before the login
+    pub fn set_tls(&mut self, tls: &TlsConfig) -> Result<()> {
+        WebSocketClient::<AccountSession>::tls_connector(tls)?;
+        self.http_client.set_tls(tls)?;
+        self.tls = tls.clone();
+        Ok(())
+    }
+
+    // Requests that come back unauthorized log in again and are retried once
+    // with the new token. Set before startup so every clone of the http
+    // client picks it up
+    pub fn enable_reauth<Store>(&mut self, settings: &Settings, store: Arc<Store>)
+    where
+        Store: AuthStore + Send + 'static,
+    {
+        // Logins carry no session so this client never re-enters the callback
+        let http_client = self.http_client.clone();
+        let session = Arc::clone(&self.session);
+        let unstored_auth = Arc::clone(&self.unstored_auth);
+        let username = settings.username.clone();
+        let endpoint = settings.endpoint;
+        self.http_client.set_reauth(Arc::new(move || {
+            let http_client = http_client.clone();
+            let session = Arc::clone(&session);
+            let unstored_auth = Arc::clone(&unstored_auth);
+            let username = username.clone();
+            let store = Arc::clone(&store);
+            Box::pin(async move {
+                let password = std::env::var("TASTY_PASSWORD").ok();
+                Self::renew_session(
+                    &http_client,
+                    &session,
+                    &unstored_auth,
+                    &username,
+                    endpoint,
+                    password,
+                    store.as_ref(),
+                )
+                .await?;
+                let token = session.read().await.clone();
+                Ok(token)
+            })
+        }));
+    }
+
+    async fn renew_session<Store>(
+        http_client: &HttpClient,
+        session: &RwLock<String>,
+        unstored_auth: &Mutex<Option<AuthResponse>>,
+        username: &str,
+        endpoint: EndPoint,
+        password: Option<String>,
+        store: &Store,
+    ) -> Result<DbStoredCreds>
+    where
+        Store: AuthStore,
+    {
+        let mut data =
+            Self::select_creds(store.fetch(username, endpoint).await?, username, endpoint)?;
+        // The stored remember token is spent when the last rotation was never
+        // stored, the one held in memory is the live one
+        let mut unstored_auth = unstored_auth.lock().await;
+        if let Some(unstored) = unstored_auth.as_ref() {
+            data.remember.clone_from(&unstored.remember);
+        }
+
+        let updates = match Self::initialise_session(http_client, data.clone(), password).await {
+            CoreResult::Ok(val) => val,
+            Err(err) => bail!("Failed to update refresh token, error: {}", err),
+        };
+        *session.write().await = updates.data.session.clone();
+        // The new session is good whether or not its remember token is
+        // stored, a failed write is carried until it can be
+        match Self::store_rotated_token(store, &updates.data, endpoint).await {
+            CoreResult::Ok(()) => *unstored_auth = None,
+            Err(err) => {
+                error!(
+                    "{}, keeping it in memory until the next refresh or shutdown",
+                    err
+                );
+                *unstored_auth = Some(updates.data);
+            }
+        }
+        Ok(data)
+    }
+
+    // Called on shutdown, a remember token only held in memory is lost with
+    // the process
+    pub async fn store_unstored_token<Store>(&self, store: &Store, endpoint: EndPoint)
+    where
+        Store: AuthStore,
+    {
+        let mut unstored_auth = self.unstored_auth.lock().await;
+        let Some(unstored) = unstored_auth.as_ref() else {
+            return;
+        };
+        match Self::store_rotated_token(store, unstored, endpoint).await {
+            CoreResult::Ok(()) => *unstored_auth = None,
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    async fn store_rotated_token<Store>(
+        store: &Store,
+        auth: &AuthResponse,
+        endpoint: EndPoint,
+    ) -> Result<()>
+    where
+        Store: AuthStore,
+    {
+        let mut attempt = 1;
+        loop {
+            match store.update(&auth.session, &auth.remember, endpoint).await {
+                CoreResult::Ok(()) => return Ok(()),
+                Err(err) if attempt < TOKEN_STORE_ATTEMPTS => {
+                    warn!(
+                        "Failed to store rotated remember token, attempt: {}, error: {}",
+                        attempt, err
+                    );
+                    tokio::time::sleep(TOKEN_STORE_BACKOFF * attempt).await;
+                    attempt += 1;
+                }
+                Err(err) => bail!(
+                    "Failed to store rotated remember token after {} attempts, the stored token \
+                     is spent so set TASTY_PASSWORD or run login before the next start, error: {}",
+                    attempt,
+                    err
+                ),
+            }
+        }
+    }
+
+    // A fresh install has no row yet, the login subcommand stores one
+    pub(crate) fn select_creds(
+        mut creds: Vec<DbStoredCreds>,
+        username: &str,
+        endpoint: EndPoint,
+    ) -> Result<DbStoredCreds> {
+        match creds.len() {
+            1 => Ok(creds.remove(0)),
+            0 => bail!(
+                "No credentials configured for user: {} on endpoint: {:?}, run `options-trader \
+                 login` to store a remember token",
+                username,
+                endpoint
+            ),
+            count => bail!(
+                "Found {} credential rows for user: {} on endpoint: {:?} in tasty_auth, remove \
+                 the duplicates so only one is left",
+                count,
+                username,
+                endpoint
+            ),
+        }
+    }
+
+    async fn session_token(&self) -> String {
+        self.session.read().await.clone()
+    }
+
     pub async fn get<Response>(&self, endpoint: &str) -> Result<Response>
     where
         Response: Serialize + for<'a> Deserialize<'a>,
     {
         self.http_client
-            .get::<Response>(endpoint, Some(&self.session))
+            .get::<Response>(endpoint, Some(&self.session_token().await))
+            .await
+    }
+
+    pub async fn get_with_query<Response>(
+        &self,
+        endpoint: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Response>
+    where
+        Response: Serialize + for<'a> Deserialize<'a>,
+    {
+        self.http_client
+            .get_with_query::<Response>(endpoint, query, Some(&self.session_token().await))
             .await
     }
 
@@ -226,7 +607,7 @@ impl WebClient {
         Response: Serialize + for<'a> Deserialize<'a>,
     {
         self.http_client
-            .post::<Data, Response>(endpoint, data, Some(&self.session))
+            .post::<Data, Response>(endpoint, data, Some(&self.session_token().await))
             .await
     }
 
@@ -236,70 +617,189 @@ impl WebClient {
         Response: Serialize + for<'a> Deserialize<'a>,
     {
         self.http_client
-            .put::<Data, Response>(endpoint, data, Some(&self.session))
+            .put::<Data, Response>(endpoint, data, Some(&self.session_token().await))
+            .await
+    }
+
+    pub async fn delete<Response>(&self, endpoint: &str) -> Result<Response>
+    where
+        Response: Serialize + for<'a> Deserialize<'a>,
+    {
+        self.http_client
+            .delete::<Response>(endpoint, Some(&self.session_token().await))
             .await
     }
 
+    pub async fn get_live_orders(&self) -> Result<Vec<OrderData>> {
+        let response = self
+            .get::<Wrapper<LiveOrders>>(&format!("accounts/{}/orders/live", self.account))
+            .await?;
+        Ok(response.data.items)
+    }
+
+    // The endpoint only filters by date, so the page is trimmed to since
+    pub async fn get_transactions(
+        &self,
+        account: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<Transaction>> {
+        let endpoint = format!("accounts/{}/transactions", account);
+        let start_date = since.date_naive().to_string();
+        let per_page = TRANSACTIONS_PER_PAGE.to_string();
+        let mut transactions = Vec::new();
+        let mut page_offset = 0;
+        loop {
+            let page_param = page_offset.to_string();
+            let response = self
+                .get_with_query::<AccountTransactions>(
+                    &endpoint,
+                    &[
+                        ("start-date", &start_date),
+                        ("sort", "Asc"),
+                        ("per-page", &per_page),
+                        ("page-offset", &page_param),
+                    ],
+                )
+                .await?;
+            transactions.extend(response.data.items.into_iter().filter(|transaction| {
+                transaction
+                    .executed_at()
+                    .is_none_or(|executed_at| executed_at >= since)
+            }));
+            page_offset += 1;
+            match response.pagination {
+                Some(pagination) if page_offset < pagination.total_pages => (),
+                _ => break,
+            }
+        }
+        Ok(transactions)
+    }
+
+    pub async fn get_option_chain(&self, underlying: &str) -> Result<OptionChain> {
+        let symbol = utf8_percent_encode(underlying, UTF8_ECODING).to_string();
+        let response = self
+            .get::<Wrapper<OptionChains>>(&format!("option-chains/{}/nested", symbol))
+            .await?;
+        match response.data.items.into_iter().next() {
+            Some(chain) => Ok(chain),
+            None => bail!("No option chain returned for underlying: {}", underlying),
+        }
+    }
+
+    pub async fn get_equity(&self, symbol: &str) -> Result<Equity> {
+        let symbol = utf8_percent_encode(symbol, UTF8_ECODING).to_string();
+        let response = self
+            .get::<Wrapper<Equity>>(&format!("instruments/equities/{}", symbol))
+            .await?;
+        Ok(response.data)
+    }
+
+    pub async fn get_future(&self, symbol: &str) -> Result<Future> {
+        let symbol = utf8_percent_encode(symbol, UTF8_ECODING).to_string();
+        let response = self
+            .get::<Wrapper<Future>>(&format!("instruments/futures/{}", symbol))
+            .await?;
+        Ok(response.data)
+    }
+
     pub fn get_account(&self) -> &str {
         &self.account
     }
 
+    pub async fn session_health(&self) -> Vec<SessionHealth> {
+        async fn health<Session>(
+            name: &'static str,
+            client: &Option<WebSocketClient<Session>>,
+        ) -> SessionHealth
+        where
+            Session: WsSession,
+        {
+            match client {
+                Some(client) => {
+                    let session = client.get_session();
+                    let session = session.read().await;
+                    SessionHealth {
+                        name: session.name(),
+                        is_alive: session.is_alive(),
+                        last_received: session.last_received(),
+                        last_heartbeat: session.last_heartbeat(),
+                    }
+                }
+                None => SessionHealth {
+                    name,
+                    is_alive: false,
+                    last_received: DateTime::<Utc>::MIN_UTC,
+                    last_heartbeat: None,
+                },
+            }
+        }
+
+        vec![
+            health("account", &self.account_ws).await,
+            health("mktdata", &self.mktdata_ws).await,
+        ]
+    }
+
     pub async fn subscribe_to_symbol(&self, symbol: &str, event_type: &[&str]) -> Result<()> {
         let client = self.mktdata_ws.as_ref().unwrap();
-        client
+        // The session lock is released before waiting on the outbound queue
+        let subscription = client
             .get_session()
             .write()
             .await
-            .subscribe(Some(symbol), event_type)
+            .subscribe(Some(symbol), event_type);
+        match subscription {
+            Some(subscription) => {
+                // Left queued on failure so the next subscribe sends it again
+                client.send_message(subscription.clone()).await?;
+                client
+                    .get_session()
+                    .write()
+                    .await
+                    .confirm_subscription(&subscription);
+                Ok(())
+            }
+            None => Ok(()),
+        }
     }
 
-    async fn fetch_auth_from_db(
-        username: &str,
-        endpoint: EndPoint,
-        db: &DBClient,
-    ) -> Result<Vec<DbStoredCreds>> {
-        let columns = vec!["username", "endpoint"];
-
-        let stmt = SqlQueryBuilder::prepare_fetch_statement("tasty_auth", &columns);
-        match sqlx::query_as::<_, DbStoredCreds>(&stmt)
-            .bind(username.to_string())
-            .bind::<i32>(endpoint.into())
-            .fetch_all(&db.pool)
+    pub async fn subscribe_to_symbols(
+        &self,
+        symbols: &[String],
+        event_type: &[&str],
+    ) -> Result<()> {
+        let client = self.mktdata_ws.as_ref().unwrap();
+        // The session lock is released before waiting on the outbound queue
+        let subscription = client
+            .get_session()
+            .write()
             .await
-        {
-            sqlx::Result::Ok(val) => Ok(val),
-            Err(err) => bail!(
-                "Failed to fetch transactions from db, err={}, closing app",
-                err
-            ),
+            .subscribe_many(symbols, event_type);
+        match subscription {
+            Some(subscription) => {
+                client.send_message(subscription.clone()).await?;
+                client
+                    .get_session()
+                    .write()
+                    .await
+                    .confirm_subscription(&subscription);
+                Ok(())
+            }
+            None => Ok(()),
         }
     }
 
-    async fn update_auth_from_db(
-        session: &str,
-        remember: &str,
-        endpoint: EndPoint,
-
-        db: &DBClient,
-    ) -> Result<()> {
-        let stmt = SqlQueryBuilder::prepare_update_statement(
-            "tasty_auth",
-            &["session", "remember", "endpoint"],
-        );
-
-        debug!(
-            "Writing remember token {} to db for statement {}",
-            remember, stmt
-        );
-        match sqlx::query(&stmt)
-            .bind(session)
-            .bind(remember)
-            .bind::<i32>(endpoint.into())
-            .execute(&db.pool)
+    pub async fn unsubscribe_from_symbol(&self, symbol: &str, event_type: &[&str]) -> Result<()> {
+        let client = self.mktdata_ws.as_ref().unwrap();
+        // The session lock is released before waiting on the outbound queue
+        let subscription = client
+            .get_session()
+            .write()
             .await
-        {
-            CoreResult::Ok(_) => Ok(()),
-            Err(err) => bail!("Failed to publish to db, error={}", err),
+            .unsubscribe(symbol, event_type);
+        match subscription {
+            Some(subscription) => client.send_message(subscription).await,
+            None => Ok(()),
         }
     }
 
@@ -342,16 +842,32 @@ impl WebClient {
             Ok(result)
         }
 
-        match password {
-            Some(password) => {
-                init_session_with_password(&data.username, password, http_client).await
-            }
-            None => init_session_with_token(&data.username, &data.remember, http_client).await,
+        // The remember token is tried first, a spent one falls back to the
+        // password when there is one
+        if data.remember.is_empty() {
+            return match password {
+                Some(password) => {
+                    init_session_with_password(&data.username, password, http_client).await
+                }
+                None => bail!("No remember token or password for user: {}", data.username),
+            };
+        }
+        match init_session_with_token(&data.username, &data.remember, http_client).await {
+            CoreResult::Ok(val) => Ok(val),
+            Err(err) => match password {
+                Some(password) => {
+                    warn!(
+                        "Remember token login failed for user: {}, error: {}, trying the password",
+                        data.username, err
+                    );
+                    init_session_with_password(&data.username, password, http_client).await
+                }
+                None => Err(err),
+            },
         }
     }
 
     async fn get_api_quote_token(
-        &self,
         http_client: &HttpClient,
         auth_token: &str,
     ) -> Result<ApiQuoteToken> {
@@ -361,38 +877,67 @@ impl WebClient {
         Ok(response.data)
     }
 
+    // Broadcast channels are sized when made, so they are swapped for ones of
+    // the configured size. Only done while nothing listens on them, which
+    // holds as every receiver subscribes once the streams are open
+    fn resize_channels(&mut self, channels: &ChannelConfig) {
+        if self.mktdata_session.receiver_count() > 0 || self.account_session.receiver_count() > 0 {
+            warn!(
+                "Channels already have receivers, keeping capacities: {:?}",
+                self.channels
+            );
+            return;
+        }
+        (self.mktdata_session, _) = broadcast::channel::<String>(channels.md_channel_capacity);
+        (self.account_session, _) = broadcast::channel::<String>(channels.account_channel_capacity);
+        self.channels = channels.clone();
+    }
+
+    pub fn md_channel_capacity(&self) -> usize {
+        self.channels.md_channel_capacity
+    }
+
+    pub fn account_channel_capacity(&self) -> usize {
+        self.channels.account_channel_capacity
+    }
+
     pub fn subscribe_md_events(&self) -> Receiver<String> {
         self.mktdata_session.subscribe()
     }
 
     pub fn subscribe_acc_events(&self) -> Receiver<String> {
-        self.mktdata_session.subscribe()
+        self.account_session.subscribe()
+    }
+
+    fn feed_capture(&self, prefix: &str) -> Option<FeedCapture> {
+        self.capture_path
+            .as_ref()
+            .map(|path| FeedCapture::new(path, prefix, self.cancel_token.clone()))
     }
 
     async fn subscribe_to_account_updates(
         &mut self,
         url: &str,
         account_id: &str,
-        auth_token: &str,
-        to_ws: Sender<String>,
         cancel_token: CancellationToken,
     ) -> Result<WebSocketClient<AccountSession>> {
-        let account_session = AccountSession::new(
-            &format!("wss://{}", url),
-            to_ws,
-            self.account_session.clone(),
-        );
+        let account_session =
+            AccountSession::new(url, Arc::clone(&self.session), self.account_session.clone());
 
-        let auth = account_session
-            .write()
-            .await
-            .startup(account_id, auth_token)
-            .await;
+        let auth = account_session.write().await.startup(account_id).await;
 
-        let ws_client =
-            WebSocketClient::<AccountSession>::new(account_session, cancel_token.clone())?;
+        let capture = self.feed_capture("account");
+        let (to_ws, from_app) = mpsc::channel::<String>(self.channels.ws_channel_capacity);
+        let ws_client = WebSocketClient::<AccountSession>::new(
+            account_session,
+            to_ws,
+            capture,
+            self.reconnect.clone(),
+            &self.tls,
+            cancel_token.clone(),
+        )?;
 
-        ws_client.subscribe_to_events().await?;
+        ws_client.subscribe_to_events(from_app).await?;
         ws_client.send_message::<acc_api::Connect>(auth).await?;
         Ok(ws_client)
     }
@@ -400,18 +945,530 @@ impl WebClient {
     async fn subscribe_to_mktdata(
         &mut self,
         api_quote_token: ApiQuoteToken,
-        to_ws: Sender<String>,
         cancel_token: CancellationToken,
     ) -> Result<WebSocketClient<MktdataSession>> {
-        let mktdata_session =
-            MktdataSession::new(api_quote_token, to_ws, self.mktdata_session.clone());
+        let reauth = Arc::new(Notify::new());
+        let mktdata_session = MktdataSession::new(
+            api_quote_token,
+            self.mktdata_session.clone(),
+            Arc::clone(&reauth),
+            self.data_format,
+            self.feed_contract,
+            &self.dxlink_version,
+        );
 
         let auth = mktdata_session.write().await.startup().await;
 
-        let ws_client = WebSocketClient::<MktdataSession>::new(mktdata_session, cancel_token)?;
+        let capture = self.feed_capture("mktdata");
+        let (to_ws, from_app) = mpsc::channel::<String>(self.channels.ws_channel_capacity);
+        let ws_client = WebSocketClient::<MktdataSession>::new(
+            mktdata_session,
+            to_ws,
+            capture,
+            self.reconnect.clone(),
+            &self.tls,
+            cancel_token.clone(),
+        )?;
 
-        ws_client.subscribe_to_events().await?;
+        ws_client.subscribe_to_events(from_app).await?;
         ws_client.send_message::<md_api::Connect>(auth).await?;
+        self.reauth_monitor(reauth, ws_client.clone(), cancel_token);
         Ok(ws_client)
     }
+
+    // Fetches a new quote token whenever the feed reports it as expired
+    fn reauth_monitor(
+        &self,
+        reauth: Arc<Notify>,
+        ws_client: WebSocketClient<MktdataSession>,
+        cancel_token: CancellationToken,
+    ) {
+        let http_client = self.http_client.clone();
+        let session = Arc::clone(&self.session);
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = reauth.notified() => {
+                        let auth_token = session.read().await.clone();
+                        let token = match Self::get_api_quote_token(&http_client, &auth_token).await {
+                            CoreResult::Ok(val) => val,
+                            Err(err) => {
+                                error!("Failed to refresh api quote token, error: {}", err);
+                                continue;
+                            }
+                        };
+                        let auth = ws_client.get_session().write().await.update_token(token);
+                        if let Err(err) = ws_client.send_message::<md_api::Auth>(auth).await {
+                            error!("Failed to re-authenticate mktdata feed, error: {}", err);
+                        }
+                    }
+                    _ = cancel_token.cancelled() => {
+                        break
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::start_stub_server;
+    use crate::utils::mock_db::MockDb;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::routing::post;
+    use axum::Json;
+    use axum::Router;
+
+    fn make_settings() -> Settings {
+        serde_json::from_value(serde_json::json!({
+            "username": "trader",
+            "endpoint": "Sandbox",
+            "log_level": "info",
+            "database": {"name": "db", "port": 5432, "host": "localhost", "user": "user"},
+        }))
+        .unwrap()
+    }
+
+    fn make_creds() -> DbStoredCreds {
+        DbStoredCreds {
+            username: String::from("trader"),
+            account: String::from("5WX01234"),
+            session: String::from("old-session"),
+            remember: String::from("old-remember"),
+            endpoint: EndPoint::Sandbox,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_updates_stored_creds() {
+        async fn sessions(Json(creds): Json<LoginAuthToken>) -> Json<serde_json::Value> {
+            assert_eq!(creds.username, "trader");
+            assert_eq!(creds.remember, "old-remember");
+            Json(serde_json::json!({
+                "data": {
+                    "user": {"email": "a@b.com", "username": "trader", "external-id": "U1"},
+                    "session-token": "new-session",
+                    "remember-token": "new-remember",
+                },
+                "context": "/sessions",
+            }))
+        }
+        let url = start_stub_server(Router::new().route("/sessions", post(sessions))).await;
+        let store = MockDb::new(vec![make_creds()]);
+        let mut client = WebClient::new(&url, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let creds = client
+            .refresh_session(&make_settings(), None, &store)
+            .await
+            .unwrap();
+
+        assert_eq!(creds.account, "5WX01234");
+        assert_eq!(client.session_token().await, "new-session");
+        assert_eq!(client.get_account(), "5WX01234");
+        let stored = store.creds.lock().unwrap();
+        assert_eq!(stored[0].session, "new-session");
+        assert_eq!(stored[0].remember, "new-remember");
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_request_logs_in_again() {
+        async fn sessions() -> Json<serde_json::Value> {
+            Json(serde_json::json!({
+                "data": {
+                    "user": {"email": "a@b.com", "username": "trader", "external-id": "U1"},
+                    "session-token": "new-session",
+                    "remember-token": "new-remember",
+                },
+                "context": "/sessions",
+            }))
+        }
+        async fn orders(
+            headers: axum::http::HeaderMap,
+        ) -> CoreResult<Json<serde_json::Value>, StatusCode> {
+            match headers.get("Authorization").map(|token| token.as_bytes()) {
+                Some(b"new-session") => CoreResult::Ok(Json(serde_json::json!({
+                    "data": {"items": []},
+                    "context": "/accounts/5WX01234/orders/live",
+                }))),
+                _ => Err(StatusCode::UNAUTHORIZED),
+            }
+        }
+        let url = start_stub_server(
+            Router::new()
+                .route("/sessions", post(sessions))
+                .route("/accounts/5WX01234/orders/live", get(orders)),
+        )
+        .await;
+        let store = MockDb::new(vec![make_creds()]);
+        let store = Arc::new(store);
+        let mut client = WebClient::new(&url, CancellationToken::new())
+            .await
+            .unwrap();
+        client.enable_reauth(&make_settings(), Arc::clone(&store));
+        client.account = String::from("5WX01234");
+        *client.session.write().await = String::from("old-session");
+
+        assert!(client.get_live_orders().await.unwrap().is_empty());
+        assert_eq!(client.session_token().await, "new-session");
+        assert_eq!(store.creds.lock().unwrap()[0].session, "new-session");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_failure_leaves_stored_creds() {
+        async fn sessions() -> StatusCode {
+            StatusCode::UNAUTHORIZED
+        }
+        let url = start_stub_server(Router::new().route("/sessions", post(sessions))).await;
+        let store = MockDb::new(vec![make_creds()]);
+        let mut client = WebClient::new(&url, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let result = client.refresh_session(&make_settings(), None, &store).await;
+
+        assert!(result.is_err());
+        assert_eq!(store.creds.lock().unwrap()[0], make_creds());
+    }
+
+    #[tokio::test]
+    async fn test_remember_token_rotation_survives_failed_write() {
+        use std::sync::atomic::Ordering;
+        use std::sync::Mutex;
+
+        // Only the latest remember token is accepted and every login rotates it
+        let valid = Arc::new(Mutex::new(String::from("old-remember")));
+        let issued = Arc::clone(&valid);
+        let sessions = move |Json(body): Json<serde_json::Value>| {
+            let issued = Arc::clone(&issued);
+            async move {
+                let mut current = issued.lock().unwrap();
+                let accepted = match (body.get("remember-token"), body.get("password")) {
+                    (Some(token), _) => *token == *current,
+                    (None, Some(password)) => password == "secret",
+                    _ => false,
+                };
+                if !accepted {
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+                *current = format!("{}-rotated", current);
+                CoreResult::Ok(Json(serde_json::json!({
+                    "data": {
+                        "user": {"email": "a@b.com", "username": "trader", "external-id": "U1"},
+                        "session-token": format!("session-for-{}", current),
+                        "remember-token": current.clone(),
+                    },
+                    "context": "/sessions",
+                })))
+            }
+        };
+        let url = start_stub_server(Router::new().route("/sessions", post(sessions))).await;
+        let store = MockDb::new(vec![make_creds()]);
+        let mut client = WebClient::new(&url, CancellationToken::new())
+            .await
+            .unwrap();
+
+        // A write that fails once is retried before the session is used
+        store.failing_updates.store(1, Ordering::SeqCst);
+        client
+            .refresh_session(&make_settings(), None, &store)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.creds.lock().unwrap()[0].remember,
+            *valid.lock().unwrap()
+        );
+
+        // When every write fails the new session is still taken up and its
+        // remember token kept in memory, the stored one is spent
+        store
+            .failing_updates
+            .store(TOKEN_STORE_ATTEMPTS as usize, Ordering::SeqCst);
+        client
+            .refresh_session(&make_settings(), None, &store)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.session_token().await,
+            format!("session-for-{}", valid.lock().unwrap())
+        );
+        assert_ne!(
+            store.creds.lock().unwrap()[0].remember,
+            *valid.lock().unwrap()
+        );
+
+        // The next refresh logs in with the token held in memory and stores
+        // its replacement
+        client
+            .refresh_session(&make_settings(), None, &store)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.creds.lock().unwrap()[0].remember,
+            *valid.lock().unwrap()
+        );
+        assert!(client.unstored_auth.lock().await.is_none());
+
+        // Or shutdown stores it when no refresh came first
+        store
+            .failing_updates
+            .store(TOKEN_STORE_ATTEMPTS as usize, Ordering::SeqCst);
+        client
+            .refresh_session(&make_settings(), None, &store)
+            .await
+            .unwrap();
+        client.store_unstored_token(&store, EndPoint::Sandbox).await;
+        assert_eq!(
+            store.creds.lock().unwrap()[0].remember,
+            *valid.lock().unwrap()
+        );
+        assert!(client.unstored_auth.lock().await.is_none());
+    }
+
+    async fn customer_accounts() -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "data": {"items": [{"account": {"account-number": "5WX01234"}, "authority-level": "owner"}]},
+            "context": "/customers/me/accounts",
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_seed_credentials_inserts_remember_token() {
+        async fn sessions(Json(creds): Json<LoginCreds>) -> Json<serde_json::Value> {
+            assert_eq!(creds.username, "trader");
+            assert_eq!(creds.password, "secret");
+            assert!(creds.remember_me);
+            Json(serde_json::json!({
+                "data": {
+                    "user": {"email": "t@example.com", "username": "trader", "external-id": "U1"},
+                    "session-token": "new-session",
+                    "remember-token": "new-remember",
+                },
+                "context": "/sessions",
+            }))
+        }
+        let url = start_stub_server(
+            Router::new()
+                .route("/sessions", post(sessions))
+                .route("/customers/me/accounts", get(customer_accounts)),
+        )
+        .await;
+        let store = MockDb::default();
+        let mut client = WebClient::new(&url, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let creds = client
+            .seed_credentials(
+                "trader",
+                EndPoint::Sandbox,
+                String::from("secret"),
+                None,
+                &store,
+            )
+            .await
+            .unwrap();
+
+        let expected = DbStoredCreds {
+            username: String::from("trader"),
+            account: String::from("5WX01234"),
+            session: String::from("new-session"),
+            remember: String::from("new-remember"),
+            endpoint: EndPoint::Sandbox,
+        };
+        assert_eq!(creds, expected);
+        assert_eq!(*store.creds.lock().unwrap(), vec![expected]);
+
+        // Seeding again refreshes the stored row rather than adding one
+        let store = MockDb::new(vec![make_creds()]);
+        client
+            .seed_credentials(
+                "trader",
+                EndPoint::Sandbox,
+                String::from("secret"),
+                None,
+                &store,
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.creds.lock().unwrap().len(), 1);
+        assert_eq!(store.creds.lock().unwrap()[0].remember, "new-remember");
+
+        let err = client
+            .seed_credentials(
+                "trader",
+                EndPoint::Sandbox,
+                String::from("secret"),
+                Some("5WX99999"),
+                &store,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Account: 5WX99999 not found"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_or_duplicate_creds_rejected() {
+        let mut client = WebClient::new("http://localhost", CancellationToken::new())
+            .await
+            .unwrap();
+
+        let store = MockDb::new(Vec::new());
+        let err = client
+            .refresh_session(&make_settings(), None, &store)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("No credentials configured for user: trader on endpoint: Sandbox"));
+
+        let store = MockDb::new(vec![make_creds(), make_creds()]);
+        let err = client
+            .refresh_session(&make_settings(), None, &store)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Found 2 credential rows"));
+    }
+
+    #[tokio::test]
+    async fn test_event_channels_are_separate() {
+        let client = WebClient::new("http://localhost", CancellationToken::new())
+            .await
+            .unwrap();
+        let mut md_events = client.subscribe_md_events();
+        let mut acc_events = client.subscribe_acc_events();
+        client.account_session.send(String::from("order")).unwrap();
+        client.mktdata_session.send(String::from("quote")).unwrap();
+        assert_eq!(acc_events.recv().await.unwrap(), "order");
+        assert!(acc_events.try_recv().is_err());
+        assert_eq!(md_events.recv().await.unwrap(), "quote");
+    }
+
+    #[tokio::test]
+    async fn test_md_channel_capacity_bounds_lag() {
+        use tokio::sync::broadcast::error::TryRecvError;
+
+        let mut client = WebClient::new("http://localhost", CancellationToken::new())
+            .await
+            .unwrap();
+        let burst = |client: &WebClient| {
+            let mut md_events = client.subscribe_md_events();
+            for quote in 0..10 {
+                client.mktdata_session.send(quote.to_string()).unwrap();
+            }
+            md_events.try_recv()
+        };
+        let resize = |md_channel_capacity| ChannelConfig {
+            md_channel_capacity,
+            ..Default::default()
+        };
+
+        client.resize_channels(&resize(4));
+        assert_eq!(client.md_channel_capacity(), 4);
+        assert_eq!(burst(&client), Err(TryRecvError::Lagged(6)));
+
+        client.resize_channels(&resize(16));
+        assert_eq!(client.md_channel_capacity(), 16);
+        assert_eq!(burst(&client), std::result::Result::Ok(String::from("0")));
+
+        // Swapping a channel would strand anyone already listening
+        let _md_events = client.subscribe_md_events();
+        client.resize_channels(&resize(4));
+        assert_eq!(client.md_channel_capacity(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_get_transactions_pages_and_trims_to_since() {
+        use axum::extract::Query;
+        use rust_decimal_macros::dec;
+        use std::collections::HashMap;
+
+        async fn transactions(
+            Query(query): Query<HashMap<String, String>>,
+        ) -> Json<serde_json::Value> {
+            assert_eq!(query["start-date"], "2024-03-15");
+            let fill = |id: i64, executed_at: &str| {
+                serde_json::json!({
+                    "id": id,
+                    "account-number": "5WX01234",
+                    "transaction-type": "Trade",
+                    "transaction-sub-type": "Sell to Open",
+                    "executed-at": executed_at,
+                    "commission": "1.0",
+                    "commission-effect": "Debit",
+                    "clearing-fees": "0.1",
+                    "clearing-fees-effect": "Debit",
+                })
+            };
+            let items = match query["page-offset"].as_str() {
+                "0" => vec![
+                    fill(1, "2024-03-15T13:00:00.000+00:00"),
+                    fill(2, "2024-03-15T15:00:00.000+00:00"),
+                ],
+                _ => vec![fill(3, "2024-03-18T14:00:00.000+00:00")],
+            };
+            Json(serde_json::json!({
+                "data": {"items": items},
+                "context": "/accounts/5WX01234/transactions",
+                "pagination": {"per-page": 2, "page-offset": 0, "total-pages": 2},
+            }))
+        }
+        let url = start_stub_server(
+            Router::new().route("/accounts/5WX01234/transactions", get(transactions)),
+        )
+        .await;
+        let client = WebClient::new(&url, CancellationToken::new())
+            .await
+            .unwrap();
+        let since = DateTime::parse_from_rfc3339("2024-03-15T14:00:00+00:00")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let fills = client.get_transactions("5WX01234", since).await.unwrap();
+
+        let ids: Vec<i64> = fills.iter().map(|fill| fill.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert!(fills
+            .iter()
+            .all(|fill| fill.is_fill() && fill.fees() == dec!(-1.1)));
+    }
+
+    #[tokio::test]
+    async fn test_get_option_chain() {
+        async fn nested() -> Json<serde_json::Value> {
+            Json(serde_json::json!({
+                "data": {"items": [{
+                    "underlying-symbol": "SPX",
+                    "root-symbol": "SPXW",
+                    "expirations": [{
+                        "expiration-date": "2024-03-15",
+                        "days-to-expiration": 0,
+                        "strikes": [{
+                            "strike-price": "5100.0",
+                            "call": "SPXW  240315C05100000",
+                            "call-streamer-symbol": ".SPXW240315C5100",
+                            "put": "SPXW  240315P05100000",
+                            "put-streamer-symbol": ".SPXW240315P5100",
+                        }],
+                    }],
+                }]},
+                "context": "/option-chains/SPX/nested",
+            }))
+        }
+        let url =
+            start_stub_server(Router::new().route("/option-chains/SPX/nested", get(nested))).await;
+        let client = WebClient::new(&url, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let chain = client.get_option_chain("SPX").await.unwrap();
+
+        let strike = &chain.expirations[0].strikes[0];
+        assert_eq!(strike.call_streamer_symbol, ".SPXW240315C5100");
+        assert_eq!(strike.put_streamer_symbol, ".SPXW240315P5100");
+    }
 }
@@ -1,6 +1,7 @@
 use anyhow::bail;
 
 use chrono::DateTime;
+use chrono::TimeDelta;
 use chrono::Utc;
 use serde::Deserialize;
 use serde::Serialize;
@@ -9,11 +10,13 @@ use sqlx::FromRow;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::broadcast::Sender;
+use tokio::sync::Notify;
 use tokio::sync::RwLock;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 use url::Url;
 
 use crate::web_client::sessions::md_api::AddItem;
@@ -21,20 +24,35 @@ use crate::web_client::sessions::md_api::AddItem;
 use self::md_api::FeedData;
 use self::md_api::Header;
 use super::ApiQuoteToken;
+use crate::tt_api::mktdata::DataFormat;
+use crate::tt_api::mktdata::FeedContract;
+use crate::tt_api::StreamApiError;
+use crate::tt_api::StreamErrorKind;
 
 pub trait WsSession {
+    fn name(&self) -> &'static str;
     fn url(&self) -> Url;
     fn token(&self) -> String;
-    fn to_ws(&self) -> &Sender<String>;
     fn is_alive(&self) -> bool;
     fn heartbeat_interval(&self) -> u64;
     fn last_received(&self) -> DateTime<Utc>;
     fn last_sent(&self) -> DateTime<Utc>;
+    // None until the first heartbeat response arrives
+    fn last_heartbeat(&self) -> Option<DateTime<Utc>>;
     fn update_last_sent(&mut self);
     fn get_heart_beat_message(&self) -> String;
+    // Handshake to replay after the socket drops, None if the session has
+    // to be rebuilt from scratch
+    fn reconnect_message(&mut self) -> Option<String>;
     // fn handle_connect(&mut self, websocket_session_id: String);
     fn handle_heartbeat(&mut self);
-    fn handle_response<Session>(&mut self, response: String, cancel_token: CancellationToken)
+    // Replies are written straight back to the socket by the connection
+    // loop rather than queued behind app traffic
+    fn handle_response<Session>(
+        &mut self,
+        response: String,
+        cancel_token: CancellationToken,
+    ) -> Vec<String>
     where
         Session: WsSession + std::marker::Send + std::marker::Sync + 'static;
 }
@@ -75,65 +93,112 @@ pub(crate) mod acc_api {
         pub timestamp: u32,
     }
 }
+// The session id handed out on connect is only valid while Connected, a new
+// one is issued every time the socket is redialled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
 #[derive(Clone, Debug)]
 pub struct AccountSession {
     url: Url,
+    account_id: String,
+    // Shared with the web client so a refreshed session token is used by
+    // the next handshake, auth_token is the last one read from it
+    live_token: Arc<RwLock<String>>,
     auth_token: String,
     session_id: String,
     last_received: DateTime<Utc>,
     last_sent: DateTime<Utc>,
-    to_ws: Sender<String>,
+    last_heartbeat: Option<DateTime<Utc>>,
     to_app: Sender<String>,
-    is_alive: bool,
+    state: ConnectionState,
     heartbeat_interval: u64,
 }
 
 impl AccountSession {
     pub fn new(
         url: &str,
-        to_ws: Sender<String>,
+        live_token: Arc<RwLock<String>>,
         to_app: Sender<String>,
     ) -> Arc<RwLock<AccountSession>> {
         Arc::new(RwLock::new(AccountSession {
             url: Url::parse(url).unwrap(),
+            account_id: String::default(),
+            live_token,
             session_id: String::default(),
             auth_token: String::default(),
             last_received: Utc::now(),
             last_sent: Utc::now(),
-            to_ws,
+            last_heartbeat: None,
             to_app,
-            is_alive: false,
+            state: ConnectionState::Disconnected,
             heartbeat_interval: 30,
         }))
     }
 
-    pub async fn startup(&mut self, account_id: &str, auth_token: &str) -> acc_api::Connect {
-        let connect = acc_api::Connect {
+    pub async fn startup(&mut self, account_id: &str) -> acc_api::Connect {
+        self.account_id = account_id.to_string();
+        self.auth_token = self.live_token.read().await.clone();
+        self.connect_message()
+    }
+
+    #[cfg(test)]
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    // Drops the id of the previous connection so nothing can pick it up
+    // before the connect response arrives
+    fn begin_connect(&mut self) {
+        self.session_id.clear();
+        self.state = ConnectionState::Connecting;
+    }
+
+    fn connect_message(&mut self) -> acc_api::Connect {
+        self.begin_connect();
+        acc_api::Connect {
             action: "connect".to_string(),
-            account_ids: vec![account_id.to_string()],
-            auth_token: auth_token.to_string(),
-        };
-        self.auth_token = auth_token.to_string();
-        connect
+            account_ids: vec![self.account_id.clone()],
+            auth_token: self.auth_token.clone(),
+        }
     }
 
     fn handle_connect(&mut self, websocket_session_id: String) {
+        if self.session_id != websocket_session_id {
+            info!(
+                "[Account Session] session id now: {}, was: {:?}",
+                websocket_session_id, self.session_id
+            );
+        }
         self.session_id = websocket_session_id;
-        self.is_alive = true;
+        self.state = ConnectionState::Connected;
+    }
+
+    fn handle_disconnect(&mut self) {
+        self.session_id.clear();
+        self.state = ConnectionState::Disconnected;
     }
 }
 
 impl WsSession for AccountSession {
+    fn name(&self) -> &'static str {
+        "account"
+    }
+
     fn url(&self) -> Url {
         self.url.clone()
     }
 
+    // Empty unless connected, the id is never handed out mid-reconnect
     fn token(&self) -> String {
-        self.session_id.clone()
-    }
-
-    fn to_ws(&self) -> &Sender<String> {
-        &self.to_ws
+        match self.state {
+            ConnectionState::Connected => self.session_id.clone(),
+            ConnectionState::Connecting | ConnectionState::Disconnected => String::default(),
+        }
     }
 
     fn last_received(&self) -> DateTime<Utc> {
@@ -144,8 +209,12 @@ impl WsSession for AccountSession {
         self.last_sent
     }
 
+    fn last_heartbeat(&self) -> Option<DateTime<Utc>> {
+        self.last_heartbeat
+    }
+
     fn is_alive(&self) -> bool {
-        self.is_alive
+        self.state == ConnectionState::Connected
     }
 
     fn heartbeat_interval(&self) -> u64 {
@@ -160,15 +229,29 @@ impl WsSession for AccountSession {
         to_json(&heartbeat).unwrap()
     }
 
+    // Replays the startup connect with the current session token, the last
+    // one read is kept if a refresh is being written right now
+    fn reconnect_message(&mut self) -> Option<String> {
+        if let std::result::Result::Ok(token) = self.live_token.try_read() {
+            self.auth_token = token.clone();
+        }
+        to_json(&self.connect_message()).ok()
+    }
+
     fn update_last_sent(&mut self) {
         self.last_sent = Utc::now();
     }
 
     fn handle_heartbeat(&mut self) {
         self.last_received = Utc::now();
+        self.last_heartbeat = Some(self.last_received);
     }
 
-    fn handle_response<Session>(&mut self, response: String, cancel_token: CancellationToken)
+    fn handle_response<Session>(
+        &mut self,
+        response: String,
+        cancel_token: CancellationToken,
+    ) -> Vec<String>
     where
         Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
     {
@@ -176,6 +259,7 @@ impl WsSession for AccountSession {
             "[Account Session] response on account session, msg: {}",
             response
         );
+        self.last_received = Utc::now();
         if let serde_json::Result::Ok(response) =
             serde_json::from_str::<acc_api::Response>(&response)
         {
@@ -196,11 +280,13 @@ impl WsSession for AccountSession {
                     "[Account Session] Failed to connect to stream, action: {}, status: {}",
                     response.action, response.status
                 );
+                self.handle_disconnect();
                 cancel_token.cancel()
             }
         } else {
             let _ = self.to_app.send(response).unwrap();
         }
+        Vec::new()
     }
 }
 
@@ -223,11 +309,14 @@ pub(crate) mod md_api {
         pub parameters: Parameters,
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Clone, Debug, Serialize, Deserialize)]
     pub struct FeedSubscription {
         #[serde(flatten)]
         pub msg: Header,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
         pub add: Vec<AddItem>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub remove: Vec<AddItem>,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -250,35 +339,82 @@ pub(crate) mod md_api {
         pub contract: String,
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
     pub struct AddItem {
         pub symbol: String,
         #[serde(rename = "type")]
         pub msg_type: String,
-        // #[serde(skip_serializing_if = "Option::is_none")]
-        // pub from_time: Option<i64>,
+        // Where the history of a Candle subscription starts, epoch millis
+        #[serde(rename = "fromTime", skip_serializing_if = "Option::is_none")]
+        pub from_time: Option<i64>,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
     pub struct FeedSetup {
         #[serde(flatten)]
         pub msg: Header,
-        #[serde(rename = "acceptAggregationPeriod")]
+        #[serde(
+            rename = "acceptAggregationPeriod",
+            skip_serializing_if = "Option::is_none"
+        )]
         pub accept_aggregation_period: Option<i64>,
-        #[serde(rename = "acceptDataFormat")]
+        #[serde(rename = "acceptDataFormat", skip_serializing_if = "Option::is_none")]
         pub accept_data_format: Option<String>,
-        #[serde(rename = "acceptEventFields")]
+        #[serde(rename = "acceptEventFields", skip_serializing_if = "Option::is_none")]
         pub accept_event_fields: Option<AcceptEventFields>,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
     pub struct AcceptEventFields {
-        #[serde(rename = "Quote")]
+        #[serde(rename = "Quote", skip_serializing_if = "Option::is_none")]
         pub quote: Option<Vec<String>>,
-        #[serde(rename = "Candle")]
+        #[serde(rename = "Candle", skip_serializing_if = "Option::is_none")]
         pub candle: Option<Vec<String>>,
+        #[serde(rename = "Greeks", skip_serializing_if = "Option::is_none")]
+        pub greeks: Option<Vec<String>>,
     }
 
+    // The default dxlink quote fields, named so COMPACT values can be mapped
+    pub const QUOTE_FIELDS: [&str; 13] = [
+        "eventType",
+        "eventSymbol",
+        "eventTime",
+        "sequence",
+        "timeNanoPart",
+        "bidTime",
+        "bidExchangeCode",
+        "bidPrice",
+        "bidSize",
+        "askTime",
+        "askExchangeCode",
+        "askPrice",
+        "askSize",
+    ];
+
+    // The candle columns the indicators read, named for COMPACT like quotes
+    pub const CANDLE_FIELDS: [&str; 8] = [
+        "eventType",
+        "eventSymbol",
+        "time",
+        "open",
+        "high",
+        "low",
+        "close",
+        "volume",
+    ];
+
+    pub const GREEKS_FIELDS: [&str; 9] = [
+        "eventType",
+        "eventSymbol",
+        "price",
+        "volatility",
+        "delta",
+        "gamma",
+        "theta",
+        "rho",
+        "vega",
+    ];
+
     #[derive(Clone, Debug, Serialize, Deserialize)]
     pub struct Connect {
         #[serde(flatten)]
@@ -338,33 +474,55 @@ pub(crate) mod md_api {
     }
 }
 
+// Keepalive we ask the feed for, also the ceiling on our heartbeat interval
+const MKTDATA_KEEPALIVE: u64 = 55;
+// Candle history asked for on subscribing, reaches back over a long weekend
+const CANDLE_LOOKBACK: TimeDelta = TimeDelta::days(4);
+
 #[derive(Clone, Debug)]
 pub struct MktdataSession {
     api_quote_token: ApiQuoteToken,
     last_received: DateTime<Utc>,
     last_sent: DateTime<Utc>,
-    to_ws: Sender<String>,
+    last_heartbeat: Option<DateTime<Utc>>,
     to_app: Sender<String>,
-    waiting_on_subscription: Vec<String>,
+    // Kept until the request carrying them has gone out
+    waiting_on_subscription: Vec<md_api::AddItem>,
+    subscribed: Vec<String>,
+    reauth: Arc<Notify>,
     is_alive: bool,
     heartbeat_interval: u64,
+    data_format: DataFormat,
+    feed_contract: FeedContract,
+    dxlink_version: String,
+    // Taken from the server's SETUP reply, None until it arrives
+    server_version: Option<String>,
 }
 
 impl MktdataSession {
     pub fn new(
         api_quote_token: ApiQuoteToken,
-        to_ws: Sender<String>,
         to_app: Sender<String>,
+        reauth: Arc<Notify>,
+        data_format: DataFormat,
+        feed_contract: FeedContract,
+        dxlink_version: &str,
     ) -> Arc<RwLock<MktdataSession>> {
         Arc::new(RwLock::new(MktdataSession {
             api_quote_token,
             last_received: Utc::now(),
             last_sent: Utc::now(),
-            to_ws,
+            last_heartbeat: None,
             to_app,
             waiting_on_subscription: Vec::default(),
+            subscribed: Vec::default(),
+            reauth,
             is_alive: false,
-            heartbeat_interval: 55,
+            heartbeat_interval: MKTDATA_KEEPALIVE,
+            data_format,
+            feed_contract,
+            dxlink_version: dxlink_version.to_string(),
+            server_version: None,
         }))
     }
 
@@ -374,15 +532,17 @@ impl MktdataSession {
                 msg_type: "SETUP".to_string(),
                 channel: 0,
             },
-            keepalive_timeout: self.heartbeat_interval,
-            accept_keepalive_timeout: self.heartbeat_interval,
-            version: "0.1".to_string(),
+            keepalive_timeout: MKTDATA_KEEPALIVE,
+            accept_keepalive_timeout: MKTDATA_KEEPALIVE,
+            version: self.dxlink_version.clone(),
         }
     }
 
-    fn handle_auth(&self, payload: FeedData) -> anyhow::Result<()> {
+    fn handle_auth(&mut self, payload: FeedData) -> anyhow::Result<String> {
         match payload.state.unwrap().as_str() {
             "UNAUTHORIZED" => {
+                // Subscriptions queue up again until the channel reopens
+                self.is_alive = false;
                 let request = md_api::Auth {
                     msg: Header {
                         msg_type: "AUTH".to_string(),
@@ -390,16 +550,13 @@ impl MktdataSession {
                     },
                     token: self.api_quote_token.token.clone(),
                 };
-                match self.to_ws.send(to_json(&request).unwrap()) {
-                    Err(err) => bail!("Failed to subscribe request: {:?}, error: {}", request, err),
-                    _ => anyhow::Ok(()),
-                }
+                anyhow::Ok(to_json(&request)?)
             }
             "AUTHORIZED" => {
                 info!("Connection authorized, channel: {}", 0);
 
                 let mut parameters = HashMap::new();
-                parameters.insert("contract".to_string(), "AUTO".to_string());
+                parameters.insert("contract".to_string(), self.feed_contract.to_string());
                 let request = md_api::Channel {
                     msg: Header {
                         msg_type: "CHANNEL_REQUEST".to_string(),
@@ -408,58 +565,246 @@ impl MktdataSession {
                     service: "FEED".to_string(),
                     parameters,
                 };
-                match self.to_ws.send(to_json(&request).unwrap()) {
-                    Err(err) => bail!("Failed to subscribe request: {:?}, error: {}", request, err),
-                    _ => anyhow::Ok(()),
-                }
+                anyhow::Ok(to_json(&request)?)
             }
             _ => bail!("Unknown auth"),
         }
     }
 
-    pub fn subscribe(&mut self, symbol: Option<&str>, event_type: &[&str]) -> anyhow::Result<()> {
+    // Queues every symbol so they go out in a single FEED_SUBSCRIPTION
+    pub fn subscribe_many(
+        &mut self,
+        symbols: &[String],
+        event_type: &[&str],
+    ) -> Option<md_api::FeedSubscription> {
+        symbols
+            .iter()
+            .for_each(|symbol| self.queue_subscription(symbol, event_type));
+        self.subscribe(None, event_type)
+    }
+
+    // Symbols queue up until the feed channel is open, the returned request
+    // is sent by the caller once the session lock is released and only
+    // leaves the queue once confirm_subscription says it went out
+    pub fn subscribe(
+        &mut self,
+        symbol: Option<&str>,
+        event_type: &[&str],
+    ) -> Option<md_api::FeedSubscription> {
         if let Some(symbol) = symbol {
-            self.waiting_on_subscription.push(symbol.to_string());
+            self.queue_subscription(symbol, event_type);
         }
-        if !self.is_alive || self.waiting_on_subscription.is_empty() {
-            return anyhow::Ok(());
+        if !self.is_alive {
+            return None;
         }
-        let mut subscriptions = Vec::new();
-        self.waiting_on_subscription.iter().for_each(|symbol| {
-            event_type.iter().for_each(|event| {
-                subscriptions.push(AddItem {
-                    symbol: symbol.clone(),
-                    msg_type: event.to_string(),
-                })
-            })
+        let subscription = self.pending_subscription()?;
+        info!("Subscription looks like {:?}", &subscription);
+        Some(subscription)
+    }
+
+    pub fn confirm_subscription(&mut self, subscription: &md_api::FeedSubscription) {
+        subscription.add.iter().for_each(|item| {
+            self.waiting_on_subscription
+                .retain(|waiting| waiting != item);
+            if !self.subscribed.contains(&item.symbol) {
+                self.subscribed.push(item.symbol.clone());
+            }
+        });
+    }
+
+    fn queue_subscription(&mut self, symbol: &str, event_type: &[&str]) {
+        let from_time = (Utc::now() - CANDLE_LOOKBACK).timestamp_millis();
+        event_type.iter().for_each(|event| {
+            let item = AddItem {
+                symbol: symbol.to_string(),
+                msg_type: event.to_string(),
+                from_time: (*event == "Candle").then_some(from_time),
+            };
+            let queued = self
+                .waiting_on_subscription
+                .iter()
+                .any(|waiting| waiting.symbol == item.symbol && waiting.msg_type == item.msg_type);
+            if !queued {
+                self.waiting_on_subscription.push(item);
+            }
         });
+    }
+
+    fn pending_subscription(&self) -> Option<md_api::FeedSubscription> {
+        if self.waiting_on_subscription.is_empty() {
+            return None;
+        }
+        Some(md_api::FeedSubscription {
+            msg: Header {
+                msg_type: "FEED_SUBSCRIPTION".to_string(),
+                channel: 1_u64,
+            },
+            add: self.waiting_on_subscription.clone(),
+            remove: Vec::new(),
+        })
+    }
+
+    pub fn unsubscribe(
+        &mut self,
+        symbol: &str,
+        event_type: &[&str],
+    ) -> Option<md_api::FeedSubscription> {
+        self.waiting_on_subscription
+            .retain(|waiting| waiting.symbol != symbol);
+        self.subscribed.retain(|subscribed| subscribed != symbol);
+        if !self.is_alive {
+            return None;
+        }
         let subscription = md_api::FeedSubscription {
             msg: Header {
                 msg_type: "FEED_SUBSCRIPTION".to_string(),
                 channel: 1_u64,
             },
-            add: subscriptions,
+            add: Vec::new(),
+            remove: event_type
+                .iter()
+                .map(|event| AddItem {
+                    symbol: symbol.to_string(),
+                    msg_type: event.to_string(),
+                    from_time: None,
+                })
+                .collect(),
         };
-        info!("Subscription looks like {:?}", &subscription);
-        match self.to_ws.send(to_json(&subscription).unwrap()) {
-            Err(err) => bail!(
-                "Failed to subscribe request: {:?}, error: {}",
-                subscription,
-                err
-            ),
-            _ => {
-                self.waiting_on_subscription.clear();
-                anyhow::Ok(())
+        info!("Unsubscription looks like {:?}", &subscription);
+        Some(subscription)
+    }
+
+    // Swaps in a freshly issued quote token, the returned AUTH restarts the
+    // dxlink auth handshake on the open socket
+    pub(super) fn update_token(&mut self, api_quote_token: ApiQuoteToken) -> md_api::Auth {
+        self.api_quote_token = api_quote_token;
+        md_api::Auth {
+            msg: Header {
+                msg_type: "AUTH".to_string(),
+                channel: 0,
+            },
+            token: self.api_quote_token.token.clone(),
+        }
+    }
+
+    // Versions read as protocol-client, only the protocol part has to agree
+    fn versions_agree(requested: &str, server: &str) -> bool {
+        let protocol = |version: &str| version.split('-').next().unwrap_or_default().to_string();
+        protocol(requested) == protocol(server)
+    }
+
+    fn handle_setup(&mut self, setup: md_api::Connect) {
+        if !Self::versions_agree(&self.dxlink_version, &setup.version) {
+            warn!(
+                "[MktData Session] dxlink version mismatch, requested: {}, server: {}",
+                self.dxlink_version, setup.version
+            );
+        } else {
+            info!("[MktData Session] dxlink server version: {}", setup.version);
+        }
+        self.server_version = Some(setup.version);
+
+        // The server drops us after its own keepalive timeout, send at half of
+        // it so one late heartbeat does not cost the connection
+        let interval = Self::heartbeat_for(setup.keepalive_timeout);
+        if interval != self.heartbeat_interval {
+            info!(
+                "[MktData Session] server keepalive timeout: {}s, heartbeat interval now: {}s",
+                setup.keepalive_timeout, interval
+            );
+            self.heartbeat_interval = interval;
+        }
+    }
+
+    fn heartbeat_for(server_timeout: u64) -> u64 {
+        (server_timeout / 2).clamp(1, MKTDATA_KEEPALIVE)
+    }
+
+    fn handle_error(&mut self, error: StreamApiError) -> Vec<String> {
+        match error.kind(&self.subscribed) {
+            StreamErrorKind::Protocol => {
+                error!(
+                    "[MktData Session] protocol error, requested dxlink version: {}, server version: {:?}, error: {:?}",
+                    self.dxlink_version, self.server_version, error
+                );
+                Vec::new()
+            }
+            StreamErrorKind::Unauthorized => {
+                warn!(
+                    "[MktData Session] feed unauthorized, requesting a new token: {:?}",
+                    error
+                );
+                self.reauth.notify_one();
+                Vec::new()
+            }
+            StreamErrorKind::BadSymbol(symbol) => {
+                warn!(
+                    "[MktData Session] dropping bad symbol: {}, error: {:?}",
+                    symbol, error
+                );
+                let event_type: &[&str] = match symbol.contains("{=") {
+                    true => &["Candle"],
+                    false => &["Quote", "Greeks"],
+                };
+                self.unsubscribe(&symbol, event_type)
+                    .and_then(|subscription| to_json(&subscription).ok())
+                    .into_iter()
+                    .collect()
+            }
+            StreamErrorKind::Other => {
+                error!("[MktData Session] feed error: {:?}", error);
+                Vec::new()
             }
         }
     }
 
-    fn handle_connect(&mut self) {
+    // The channel only opens once AUTH_STATE is AUTHORIZED, symbols queued
+    // before then follow the feed setup in the same batch of replies
+    fn handle_connect(&mut self) -> Vec<String> {
         self.is_alive = true;
+        let mut replies = vec![to_json(&self.feed_setup()).unwrap()];
+        if let Some(subscription) = self.pending_subscription() {
+            info!("Sending queued subscription {:?}", &subscription);
+            replies.push(to_json(&subscription).unwrap());
+            self.confirm_subscription(&subscription);
+        }
+        replies
+    }
+
+    // Quotes and candles keep the full default field set, greeks only carry
+    // the columns the strategies read. COMPACT names the quote and candle
+    // fields too so their positions are known
+    fn feed_setup(&self) -> md_api::FeedSetup {
+        let named = |fields: &[&str]| match self.data_format {
+            DataFormat::Full => None,
+            DataFormat::Compact => Some(fields.iter().map(|field| field.to_string()).collect()),
+        };
+        md_api::FeedSetup {
+            msg: Header {
+                msg_type: "FEED_SETUP".to_string(),
+                channel: 1_u64,
+            },
+            accept_aggregation_period: None,
+            accept_data_format: Some(self.data_format.to_string()),
+            accept_event_fields: Some(md_api::AcceptEventFields {
+                quote: named(&md_api::QUOTE_FIELDS),
+                candle: named(&md_api::CANDLE_FIELDS),
+                greeks: Some(
+                    md_api::GREEKS_FIELDS
+                        .iter()
+                        .map(|field| field.to_string())
+                        .collect(),
+                ),
+            }),
+        }
     }
 }
 
 impl WsSession for MktdataSession {
+    fn name(&self) -> &'static str {
+        "mktdata"
+    }
+
     fn url(&self) -> Url {
         Url::parse(&self.api_quote_token.dxlink_url).unwrap()
     }
@@ -468,10 +813,6 @@ impl WsSession for MktdataSession {
         self.api_quote_token.token.clone()
     }
 
-    fn to_ws(&self) -> &Sender<String> {
-        &self.to_ws
-    }
-
     fn last_received(&self) -> DateTime<Utc> {
         self.last_received
     }
@@ -480,6 +821,10 @@ impl WsSession for MktdataSession {
         self.last_sent
     }
 
+    fn last_heartbeat(&self) -> Option<DateTime<Utc>> {
+        self.last_heartbeat
+    }
+
     fn update_last_sent(&mut self) {
         self.last_sent = Utc::now();
     }
@@ -500,15 +845,27 @@ impl WsSession for MktdataSession {
         to_json(&heartbeat).unwrap()
     }
 
+    // Feed channels and subscriptions are not replayed yet
+    fn reconnect_message(&mut self) -> Option<String> {
+        None
+    }
+
     fn handle_heartbeat(&mut self) {
         self.last_received = Utc::now();
+        self.last_heartbeat = Some(self.last_received);
     }
 
-    fn handle_response<Session>(&mut self, response: String, _cancel_token: CancellationToken)
+    fn handle_response<Session>(
+        &mut self,
+        response: String,
+        _cancel_token: CancellationToken,
+    ) -> Vec<String>
     where
         Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
     {
         debug!("response {}", response);
+        self.last_received = Utc::now();
+        let mut replies = Vec::new();
         if let serde_json::Result::Ok(payload) = serde_json::from_str::<md_api::FeedData>(&response)
         {
             match payload.msg.msg_type.as_str() {
@@ -516,16 +873,28 @@ impl WsSession for MktdataSession {
                     info!("[MktData Session] heartbeat {:?}", payload);
                     self.handle_heartbeat();
                 }
+                "SETUP" => match serde_json::from_str::<md_api::Connect>(&response) {
+                    Ok(setup) => self.handle_setup(setup),
+                    Err(err) => error!(
+                        "[MktData Session] unreadable setup: {}, error: {}",
+                        response, err
+                    ),
+                },
                 "AUTH_STATE" => {
                     info!(
                         "[MktData Session] connection response auth state: {:?}",
                         payload
                     );
-                    let _ = self.handle_auth(payload);
+                    match self.handle_auth(payload) {
+                        Ok(reply) => replies.push(reply),
+                        Err(err) => error!("[MktData Session] auth failed, error: {}", err),
+                    }
                 }
                 "CHANNEL_OPENED" => {
                     info!("[MktData Session] Channel session {:?}", payload);
-                    self.handle_connect();
+                    replies.extend(self.handle_connect());
+                    // Lets the app know the feed has started over
+                    let _ = self.to_app.send(response.clone());
                 }
                 "FEED_CONFIG" => {
                     if let Some(_config) = payload.event_fields.as_ref() {
@@ -543,6 +912,7 @@ impl WsSession for MktdataSession {
                         //     accept_event_fields: Some(md_api::AcceptEventFields {
                         //         quote: None,
                         //         candle: Some(update),
+                        //         greeks: None,
                         //     }),
                         // };
                         // let _ = self.to_app.send(to_json(&response).unwrap());
@@ -551,9 +921,17 @@ impl WsSession for MktdataSession {
                 "FEED_DATA" => {
                     let _ = self.to_app.send(response);
                 }
-                "ERROR" => {
-                    info!("{:?} ", payload);
-                }
+                "ERROR" => match serde_json::from_str::<StreamApiError>(&response) {
+                    Ok(error) => {
+                        replies.extend(self.handle_error(error));
+                        // The app drops the snapshot of a bad symbol
+                        let _ = self.to_app.send(response);
+                    }
+                    Err(err) => error!(
+                        "[MktData Session] unreadable error: {}, error: {}",
+                        response, err
+                    ),
+                },
                 _ => info!("Unknown? {:?} ", payload),
             };
             // } else if let Ok(response) = serde_json::from_str::<md_api::Channel>(&response) {
@@ -565,5 +943,370 @@ impl WsSession for MktdataSession {
         } else {
             info!("Mktdata end? {:?} ", response);
         }
+        replies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_ws::MockWsTransport;
+
+    fn make_mktdata_session(data_format: DataFormat) -> Arc<RwLock<MktdataSession>> {
+        let (to_app, _) = tokio::sync::broadcast::channel::<String>(8);
+        let token = serde_json::from_value(serde_json::json!({
+            "token": "token",
+            "dxlink-url": "wss://localhost",
+            "level": "api",
+        }))
+        .unwrap();
+        MktdataSession::new(
+            token,
+            to_app,
+            Arc::new(Notify::new()),
+            data_format,
+            FeedContract::default(),
+            "0.1",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_feed_setup_requests_greeks() {
+        let session = make_mktdata_session(DataFormat::Full);
+        let setup = serde_json::to_value(session.read().await.feed_setup()).unwrap();
+        assert_eq!(setup["type"], "FEED_SETUP");
+        assert_eq!(setup["acceptDataFormat"], "FULL");
+        let greeks = setup["acceptEventFields"]["Greeks"].as_array().unwrap();
+        assert!(greeks.iter().any(|field| field == "delta"));
+        assert!(greeks.iter().any(|field| field == "theta"));
+        assert!(setup["acceptEventFields"].get("Quote").is_none());
+
+        let session = make_mktdata_session(DataFormat::Compact);
+        let setup = serde_json::to_value(session.read().await.feed_setup()).unwrap();
+        assert_eq!(setup["acceptDataFormat"], "COMPACT");
+        assert_eq!(
+            setup["acceptEventFields"]["Quote"],
+            serde_json::json!(md_api::QUOTE_FIELDS)
+        );
+        assert_eq!(
+            setup["acceptEventFields"]["Candle"],
+            serde_json::json!(md_api::CANDLE_FIELDS)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_request_uses_configured_contract() {
+        let authorized = || -> FeedData {
+            serde_json::from_value(serde_json::json!({
+                "type": "AUTH_STATE",
+                "channel": 0,
+                "state": "AUTHORIZED",
+            }))
+            .unwrap()
+        };
+        let contract = |session: &mut MktdataSession| {
+            let request: serde_json::Value =
+                serde_json::from_str(&session.handle_auth(authorized()).unwrap()).unwrap();
+            assert_eq!(request["type"], "CHANNEL_REQUEST");
+            request["parameters"]["contract"].clone()
+        };
+
+        let session = make_mktdata_session(DataFormat::Full);
+        assert_eq!(contract(&mut *session.write().await), "AUTO");
+
+        let (to_app, _) = tokio::sync::broadcast::channel::<String>(8);
+        let token = serde_json::from_value(serde_json::json!({
+            "token": "token",
+            "dxlink-url": "wss://localhost",
+            "level": "api",
+        }))
+        .unwrap();
+        let session = MktdataSession::new(
+            token,
+            to_app,
+            Arc::new(Notify::new()),
+            DataFormat::Full,
+            FeedContract::Stream,
+            "0.1",
+        );
+        assert_eq!(contract(&mut *session.write().await), "STREAM");
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_many_builds_one_request() {
+        let (to_app, _) = tokio::sync::broadcast::channel::<String>(8);
+        let token = serde_json::from_value(serde_json::json!({
+            "token": "token",
+            "dxlink-url": "wss://localhost",
+            "level": "api",
+        }))
+        .unwrap();
+        let session = MktdataSession::new(
+            token,
+            to_app,
+            Arc::new(Notify::new()),
+            DataFormat::Full,
+            FeedContract::default(),
+            "0.1",
+        );
+        let mut session = session.write().await;
+        assert!(session
+            .subscribe_many(&[String::from(".SPXW240315C5100")], &["Quote"])
+            .is_none());
+
+        session.is_alive = true;
+        let subscription = session
+            .subscribe_many(
+                &[
+                    String::from(".SPXW240315C5100"),
+                    String::from(".SPXW240315P5100"),
+                ],
+                &["Quote", "Greeks"],
+            )
+            .unwrap();
+
+        // Symbols queued while the channel was closed go out in the same request
+        let msg = serde_json::to_value(&subscription).unwrap();
+        assert_eq!(msg["type"], "FEED_SUBSCRIPTION");
+        assert_eq!(msg["add"].as_array().unwrap().len(), 4);
+        assert!(msg.get("remove").is_none());
+        assert!(msg["add"][0].get("fromTime").is_none());
+
+        // A request that never went out is built again on the next subscribe
+        assert_eq!(session.subscribe(None, &["Quote"]).unwrap().add.len(), 4);
+        session.confirm_subscription(&subscription);
+        assert!(session.subscribe(None, &["Quote"]).is_none());
+        assert_eq!(session.subscribed.len(), 2);
+
+        // Candles ask for their history along with the live ones
+        let subscription = session.subscribe(Some("$SPX{=5m}"), &["Candle"]).unwrap();
+        let msg = serde_json::to_value(subscription).unwrap();
+        let from_time = msg["add"][0]["fromTime"].as_i64().unwrap();
+        assert!(from_time < (Utc::now() - TimeDelta::days(3)).timestamp_millis());
+    }
+
+    #[tokio::test]
+    async fn test_feed_errors_handled_by_kind() {
+        let (to_app, _) = tokio::sync::broadcast::channel::<String>(8);
+        let token = serde_json::from_value(serde_json::json!({
+            "token": "token",
+            "dxlink-url": "wss://localhost",
+            "level": "api",
+        }))
+        .unwrap();
+        let reauth = Arc::new(Notify::new());
+        let session = MktdataSession::new(
+            token,
+            to_app,
+            Arc::clone(&reauth),
+            DataFormat::Full,
+            FeedContract::default(),
+            "0.1",
+        );
+        let mut session = session.write().await;
+        session.is_alive = true;
+        let subscription = session
+            .subscribe(Some(".SPXW240315C5100"), &["Quote"])
+            .unwrap();
+        session.confirm_subscription(&subscription);
+
+        let replies = session.handle_response::<MktdataSession>(
+            String::from(
+                r#"{"type":"ERROR","channel":0,"error":"INVALID_MESSAGE","message":"Unknown symbol .SPXW240315C5100"}"#,
+            ),
+            CancellationToken::new(),
+        );
+        let remove: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(remove["remove"][0]["symbol"], ".SPXW240315C5100");
+        assert!(session.subscribed.is_empty());
+
+        let replies = session.handle_response::<MktdataSession>(
+            String::from(
+                r#"{"type":"ERROR","channel":0,"error":"UNAUTHORIZED","message":"Token expired"}"#,
+            ),
+            CancellationToken::new(),
+        );
+        assert!(replies.is_empty());
+        tokio::time::timeout(std::time::Duration::from_secs(1), reauth.notified())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mktdata_auth_flow_opens_feed() {
+        let (to_app, from_session) = tokio::sync::broadcast::channel::<String>(8);
+        let token = serde_json::from_value(serde_json::json!({
+            "token": "quote-token",
+            "dxlink-url": "wss://localhost",
+            "level": "api",
+        }))
+        .unwrap();
+        let session = MktdataSession::new(
+            token,
+            to_app,
+            Arc::new(Notify::new()),
+            DataFormat::Full,
+            FeedContract::default(),
+            "0.1",
+        );
+        let mut transport = MockWsTransport::new(session, from_session);
+        let setup = transport.session().write().await.startup().await;
+        transport.send(&setup);
+        assert!(!transport.session().read().await.is_alive());
+        // Held back until the feed channel opens after the auth
+        assert!(transport
+            .session()
+            .write()
+            .await
+            .subscribe(Some("SPY"), &["Quote"])
+            .is_none());
+
+        let replies = transport
+            .receive(r#"{"type":"AUTH_STATE","channel":0,"state":"UNAUTHORIZED"}"#)
+            .await;
+        assert_eq!(replies.len(), 1);
+        let auth: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(auth["type"], "AUTH");
+        assert_eq!(auth["token"], "quote-token");
+
+        let replies = transport
+            .receive(r#"{"type":"AUTH_STATE","channel":0,"state":"AUTHORIZED","userId":"user"}"#)
+            .await;
+        let request: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(request["type"], "CHANNEL_REQUEST");
+        assert_eq!(request["channel"], 1);
+        assert_eq!(request["service"], "FEED");
+        assert!(!transport.session().read().await.is_alive());
+
+        let replies = transport
+            .receive(r#"{"type":"CHANNEL_OPENED","channel":1,"service":"FEED","parameters":{}}"#)
+            .await;
+        let setup: serde_json::Value = serde_json::from_str(&replies[0]).unwrap();
+        assert_eq!(setup["type"], "FEED_SETUP");
+        let queued: serde_json::Value = serde_json::from_str(&replies[1]).unwrap();
+        assert_eq!(queued["type"], "FEED_SUBSCRIPTION");
+        assert_eq!(queued["add"][0]["symbol"], "SPY");
+        assert!(transport.session().read().await.is_alive());
+        assert_eq!(transport.sent.len(), 5);
+        assert_eq!(transport.published().len(), 1);
+
+        // Feed data goes on to the app rather than back to the socket
+        transport
+            .receive(r#"{"type":"FEED_DATA","channel":1,"data":[]}"#)
+            .await;
+        assert_eq!(transport.published().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_setup_reply_version_recorded() {
+        let session = make_mktdata_session(DataFormat::Full);
+        let setup = session.write().await.startup().await;
+        assert_eq!(setup.version, "0.1");
+
+        let mut session = session.write().await;
+        session.handle_response::<MktdataSession>(
+            String::from(
+                r#"{"type":"SETUP","channel":0,"keepaliveTimeout":60,"acceptKeepaliveTimeout":60,"version":"1.0-1.2.1-20240722"}"#,
+            ),
+            CancellationToken::new(),
+        );
+        assert_eq!(
+            session.server_version.as_deref(),
+            Some("1.0-1.2.1-20240722")
+        );
+        assert_eq!(session.heartbeat_interval, 30);
+
+        assert_eq!(MktdataSession::heartbeat_for(20), 10);
+        assert_eq!(MktdataSession::heartbeat_for(300), MKTDATA_KEEPALIVE);
+        assert_eq!(MktdataSession::heartbeat_for(0), 1);
+
+        assert!(MktdataSession::versions_agree("0.1", "0.1-js/1.0.0"));
+        assert!(MktdataSession::versions_agree("1.0-app", "1.0-1.2.1"));
+        assert!(!MktdataSession::versions_agree("0.1", "1.0-1.2.1"));
+    }
+
+    #[tokio::test]
+    async fn test_account_connect_and_heartbeat() {
+        let (to_app, from_session) = tokio::sync::broadcast::channel::<String>(8);
+        let live_token = Arc::new(RwLock::new(String::from("auth-token")));
+        let session = AccountSession::new("wss://localhost", live_token, to_app);
+        let mut transport = MockWsTransport::new(session, from_session);
+        let connect = transport.session().write().await.startup("5WX01234").await;
+        transport.send(&connect);
+        assert!(!transport.session().read().await.is_alive());
+
+        let replies = transport
+            .receive(r#"{"status":"ok","action":"connect","web-socket-session-id":"session-id","value":["5WX01234"],"request-id":1}"#)
+            .await;
+        assert!(replies.is_empty());
+        assert!(transport.session().read().await.is_alive());
+        assert_eq!(transport.session().read().await.token(), "session-id");
+
+        let heartbeat: acc_api::AccHeartbeat =
+            serde_json::from_str(&transport.heartbeat().await).unwrap();
+        assert_eq!(heartbeat.action, "heartbeat");
+        assert_eq!(heartbeat.auth_token, "auth-token");
+        let sent_at = transport.session().read().await.last_sent();
+        assert!(transport.session().read().await.last_heartbeat().is_none());
+        transport
+            .receive(r#"{"status":"ok","action":"heartbeat","web-socket-session-id":"session-id","request-id":2}"#)
+            .await;
+        assert!(transport.session().read().await.last_received() >= sent_at);
+        assert!(transport.session().read().await.last_heartbeat() >= Some(sent_at));
+
+        // Anything that is not a stream response belongs to the app
+        transport
+            .receive(r#"{"type":"Order","data":{"id":1},"timestamp":1}"#)
+            .await;
+        assert_eq!(transport.published().len(), 1);
+
+        transport
+            .receive(r#"{"status":"error","action":"connect","web-socket-session-id":"","request-id":3}"#)
+            .await;
+        assert!(transport.cancel_token.is_cancelled());
+        assert_eq!(
+            transport.session().read().await.state(),
+            ConnectionState::Disconnected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_account_reconnect_replaces_session_id() {
+        let (to_app, from_session) = tokio::sync::broadcast::channel::<String>(8);
+        let live_token = Arc::new(RwLock::new(String::from("auth-token")));
+        let session = AccountSession::new("wss://localhost", Arc::clone(&live_token), to_app);
+        let mut transport = MockWsTransport::new(session, from_session);
+        let connect = transport.session().write().await.startup("5WX01234").await;
+        assert_eq!(connect.auth_token, "auth-token");
+        assert_eq!(
+            transport.session().read().await.state(),
+            ConnectionState::Connecting
+        );
+        transport
+            .receive(r#"{"status":"ok","action":"connect","web-socket-session-id":"old-id","request-id":1}"#)
+            .await;
+        assert_eq!(transport.session().read().await.token(), "old-id");
+
+        *live_token.write().await = String::from("refreshed-token");
+
+        let handshake = transport
+            .session()
+            .write()
+            .await
+            .reconnect_message()
+            .unwrap();
+        let connect: acc_api::Connect = serde_json::from_str(&handshake).unwrap();
+        assert_eq!(connect.account_ids, vec!["5WX01234"]);
+        assert_eq!(connect.auth_token, "refreshed-token");
+        let session = transport.session();
+        assert_eq!(session.read().await.state(), ConnectionState::Connecting);
+        assert!(!session.read().await.is_alive());
+        assert_eq!(session.read().await.token(), "");
+
+        transport
+            .receive(r#"{"status":"ok","action":"connect","web-socket-session-id":"new-id","request-id":2}"#)
+            .await;
+        assert_eq!(session.read().await.state(), ConnectionState::Connected);
+        assert_eq!(session.read().await.token(), "new-id");
     }
 }
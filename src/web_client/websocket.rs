@@ -1,41 +1,143 @@
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
-use broadcast::error::RecvError;
+use chrono::DateTime;
 use chrono::Utc;
 use futures_util::SinkExt;
 use futures_util::StreamExt as _;
+use native_tls::Certificate;
 use native_tls::Protocol;
 use native_tls::TlsConnector as NativeTlsConnector;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::to_string as to_json;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::TcpStream;
 use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 
-use tokio::sync::broadcast;
+use tokio::sync::mpsc;
 use tokio::sync::RwLock;
+use tokio::time::interval;
 use tokio::time::sleep;
 use tokio::time::Duration;
 use tokio_tungstenite::tungstenite::Error as WebSocketError;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::Connector;
+use tokio_tungstenite::MaybeTlsStream;
+use tokio_tungstenite::WebSocketStream;
 use tokio_util::sync::CancellationToken;
 
 use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use super::capture::FeedCapture;
 use super::sessions::WsSession;
+use crate::metrics::WEBSOCKET_RECONNECTS;
+use crate::settings::ReconnectConfig;
+use crate::settings::TlsConfig;
+use crate::settings::TlsVersion;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+// A bundle can hold several certificates, one that cannot be read or holds
+// none is an error rather than a client trusting nothing
+pub(super) fn read_ca_bundle(path: &str) -> Result<Vec<Certificate>> {
+    let bundle =
+        std::fs::read(path).with_context(|| format!("Failed to read CA bundle: {}", path))?;
+    let certs = String::from_utf8_lossy(&bundle)
+        .split_inclusive(PEM_END)
+        .filter(|block| block.contains(PEM_END))
+        .map(|pem| {
+            Certificate::from_pem(pem.trim().as_bytes())
+                .with_context(|| format!("Invalid certificate in CA bundle: {}", path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        bail!("No certificates found in CA bundle: {}", path);
+    }
+    info!(
+        "Trusting {} certificates from CA bundle: {}",
+        certs.len(),
+        path
+    );
+    Ok(certs)
+}
+
+// Rolling window of redial attempts
+#[derive(Debug)]
+struct ReconnectBreaker {
+    config: ReconnectConfig,
+    attempts: VecDeque<Instant>,
+}
+
+impl ReconnectBreaker {
+    fn new(config: ReconnectConfig) -> Self {
+        Self {
+            config,
+            attempts: VecDeque::new(),
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        let window = Duration::from_secs(self.config.window_secs);
+        while self
+            .attempts
+            .front()
+            .is_some_and(|attempt| now.duration_since(*attempt) > window)
+        {
+            self.attempts.pop_front();
+        }
+        if self.attempts.len() >= self.config.max_attempts {
+            return false;
+        }
+        self.attempts.push_back(now);
+        true
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum HeartbeatCheck {
+    Wait,
+    Send,
+    // Nothing heard inside the grace period, only this socket is redialled
+    Missed,
+}
 
 #[derive(Clone, Debug)]
 pub struct WebSocketClient<Session> {
     session: Arc<RwLock<Session>>,
+    to_ws: mpsc::Sender<String>,
+    capture: Option<FeedCapture>,
+    reconnect: ReconnectConfig,
+    tls_connector: NativeTlsConnector,
+    has_connected: Arc<AtomicBool>,
     cancel_token: CancellationToken,
 }
 
 impl<Session> WebSocketClient<Session> {
-    pub fn new(session: Arc<RwLock<Session>>, cancel_token: CancellationToken) -> Result<Self> {
+    pub fn new(
+        session: Arc<RwLock<Session>>,
+        to_ws: mpsc::Sender<String>,
+        capture: Option<FeedCapture>,
+        reconnect: ReconnectConfig,
+        tls: &TlsConfig,
+        cancel_token: CancellationToken,
+    ) -> Result<Self> {
         Ok(Self {
             session,
+            to_ws,
+            capture,
+            reconnect,
+            tls_connector: Self::tls_connector(tls)?,
+            has_connected: Arc::new(AtomicBool::new(false)),
             cancel_token,
         })
     }
@@ -44,17 +146,40 @@ impl<Session> WebSocketClient<Session> {
         self.session.clone()
     }
 
+    // Built once so a bad CA bundle fails startup rather than every redial.
+    // The bundle replaces the system roots, as it does for the REST client
+    pub(super) fn tls_connector(tls: &TlsConfig) -> Result<NativeTlsConnector> {
+        let min_version = match tls.min_version {
+            TlsVersion::Tls12 => Protocol::Tlsv12,
+        };
+        let mut builder = NativeTlsConnector::builder();
+        builder.min_protocol_version(Some(min_version));
+        if let Some(path) = &tls.ca_bundle {
+            builder.disable_built_in_roots(true);
+            for cert in read_ca_bundle(path)? {
+                builder.add_root_certificate(cert);
+            }
+        }
+        Ok(builder.build()?)
+    }
+
     async fn handle_socket_messages(
         message: Option<Result<Message, WebSocketError>>,
         session: Arc<RwLock<Session>>,
+        capture: Option<&FeedCapture>,
+        connection_token: &CancellationToken,
         cancel_token: CancellationToken,
-    ) where
+    ) -> Vec<String>
+    where
         Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
     {
         match message {
             Some(var) => match var {
                 Ok(Message::Text(response)) => {
-                    session
+                    if let Some(capture) = capture {
+                        capture.record(&response);
+                    }
+                    return session
                         .write()
                         .await
                         .handle_response::<Session>(response, cancel_token);
@@ -66,91 +191,203 @@ impl<Session> WebSocketClient<Session> {
                 Err(err) => error!("Error: {}", err),
             },
             None => {
-                info!("Stream closed, cancelling session on client");
-                cancel_token.cancel();
+                info!("Stream closed, dropping connection");
+                connection_token.cancel();
             }
         };
+        Vec::new()
     }
 
-    pub async fn subscribe_to_events(&self) -> Result<()>
+    async fn connect(&self) -> Result<WsStream>
     where
-        Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
+        Session: WsSession,
     {
-        let tls_connector = NativeTlsConnector::builder()
-            .min_protocol_version(Some(Protocol::Tlsv12))
-            .build()
-            .expect("Failed to build tlsconnector");
-
         let (stream, response) = tokio_tungstenite::connect_async_tls_with_config(
             self.session.read().await.url(),
             None,
             false,
-            Some(Connector::NativeTls(tls_connector)),
+            Some(Connector::NativeTls(self.tls_connector.clone())),
         )
         .await?;
 
         dbg!("Websocket connect response: {:?}", response);
+        if self.has_connected.swap(true, Ordering::Relaxed) {
+            WEBSOCKET_RECONNECTS
+                .with_label_values(&[self.session.read().await.name()])
+                .inc();
+        }
+        Ok(stream)
+    }
 
-        let (mut write, mut read) = stream.split();
-        let cancel_token = self.cancel_token.clone();
-        let session = Arc::clone(&self.session);
-        let mut to_ws = session.read().await.to_ws().subscribe();
-        let heartbeat_interval = session.read().await.heartbeat_interval();
+    // Owns the outbound queue for the lifetime of the client so nothing sent
+    // by the app is lost across reconnects
+    pub async fn subscribe_to_events(&self, mut to_ws: mpsc::Receiver<String>) -> Result<()>
+    where
+        Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
+    {
+        let mut stream = self.connect().await?;
+        let client = Self {
+            session: Arc::clone(&self.session),
+            to_ws: self.to_ws.clone(),
+            capture: self.capture.clone(),
+            reconnect: self.reconnect.clone(),
+            tls_connector: self.tls_connector.clone(),
+            has_connected: Arc::clone(&self.has_connected),
+            cancel_token: self.cancel_token.clone(),
+        };
         tokio::spawn(async move {
+            let mut handshake = None;
+            let mut breaker = ReconnectBreaker::new(client.reconnect.clone());
             loop {
-                tokio::select! {
-                    msg = read.next() => {
-                        Self::handle_socket_messages(msg, session.clone(), cancel_token.clone()).await;
+                client.run_connection(stream, handshake, &mut to_ws).await;
+                if client.cancel_token.is_cancelled() {
+                    break;
+                }
+                // Only this socket is redialled, other sessions keep streaming
+                handshake = client.session.write().await.reconnect_message();
+                if handshake.is_none() {
+                    error!("Session cannot reconnect, cancelling client");
+                    client.cancel_token.cancel();
+                    break;
+                }
+                stream = match client.reconnect(&mut breaker).await {
+                    Some(val) => val,
+                    None => break,
+                };
+            }
+        });
+        Ok(())
+    }
+
+    async fn reconnect(&self, breaker: &mut ReconnectBreaker) -> Option<WsStream>
+    where
+        Session: WsSession,
+    {
+        loop {
+            tokio::select! {
+                _ = sleep(RECONNECT_DELAY) => {
+                    if !breaker.allow(Instant::now()) {
+                        error!(
+                            "FATAL: {} websocket reconnected more than {} times in {} seconds, giving up",
+                            self.session.read().await.name(),
+                            self.reconnect.max_attempts,
+                            self.reconnect.window_secs
+                        );
+                        self.cancel_token.cancel();
+                        return None;
                     }
-                    msg = to_ws.recv() => {
-                        match msg {
-                            Err(RecvError::Lagged(err)) => warn!("Publisher channel skipping a number of messages: {}", err),
-                            Err(RecvError::Closed) => {
-                                error!("Publisher channel closed");
-                                cancel_token.cancel();
-                            }
-                            std::result::Result::Ok(val) => {
-                                info!("Sending payload {}", val);
-                                let _ = write.send(Message::Text(val)).await;
-                            }
-                        };
+                    match self.connect().await {
+                        Ok(val) => return Some(val),
+                        Err(err) => error!("Failed to reconnect websocket, error: {}", err),
                     }
-                    _ = sleep(Duration::from_secs(1)) => {
-                        if Self::should_send_heartbeat(heartbeat_interval, &session, &cancel_token).await {
+                }
+                _ = self.cancel_token.cancelled() => {
+                    return None;
+                }
+            }
+        }
+    }
+
+    async fn run_connection(
+        &self,
+        stream: WsStream,
+        handshake: Option<String>,
+        to_ws: &mut mpsc::Receiver<String>,
+    ) where
+        Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
+    {
+        let (mut write, mut read) = stream.split();
+        let connection_token = self.cancel_token.child_token();
+        let session = &self.session;
+        let heartbeat_grace = self.reconnect.heartbeat_grace(session.read().await.name());
+        if let Some(handshake) = handshake {
+            info!("Sending handshake {}", handshake);
+            if let Err(err) = write.send(Message::Text(handshake)).await {
+                error!("Failed to send handshake, error: {}", err);
+                return;
+            }
+        }
+        // Kept across iterations, a sleep made inside the select would be
+        // reset by every message and never fire on a busy feed
+        let mut heartbeat_check = interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                msg = read.next() => {
+                    let replies = Self::handle_socket_messages(msg, session.clone(), self.capture.as_ref(), &connection_token, self.cancel_token.clone()).await;
+                    for reply in replies {
+                        info!("Sending reply {}", reply);
+                        if let Err(err) = write.send(Message::Text(reply)).await {
+                            error!("Failed to send reply, error: {}", err);
+                            connection_token.cancel();
+                        }
+                    }
+                }
+                msg = to_ws.recv() => {
+                    match msg {
+                        None => {
+                            error!("Publisher channel closed");
+                            self.cancel_token.cancel();
+                        }
+                        Some(val) => {
+                            info!("Sending payload {}", val);
+                            if let Err(err) = write.send(Message::Text(val)).await {
+                                error!("Failed to send payload, error: {}", err);
+                                connection_token.cancel();
+                            }
+                        }
+                    };
+                }
+                _ = heartbeat_check.tick() => {
+                    // Read each tick, the session may adjust it once the server replies
+                    let heartbeat_interval = session.read().await.heartbeat_interval();
+                    match Self::check_heartbeat(heartbeat_interval, heartbeat_grace, session, Utc::now()).await {
+                        HeartbeatCheck::Send => {
                             let heartbeat = session.read().await.get_heart_beat_message();
                             if write.send(Message::Text(heartbeat)).await.is_ok() {
                                 session.write().await.update_last_sent();
                             }
                         }
+                        HeartbeatCheck::Missed => {
+                            warn!(
+                                "No {} heartbeat response in {} seconds, reconnecting",
+                                session.read().await.name(),
+                                heartbeat_interval as f64 * heartbeat_grace
+                            );
+                            connection_token.cancel();
+                        }
+                        HeartbeatCheck::Wait => (),
                     }
-                    _ = cancel_token.cancelled() => {
-                        break;
-                    }
+                }
+                _ = connection_token.cancelled() => {
+                    break;
                 }
             }
-        });
-        Ok(())
+        }
     }
 
-    async fn should_send_heartbeat(
+    async fn check_heartbeat(
         interval: u64,
+        grace: f64,
         session: &Arc<RwLock<Session>>,
-        cancel_token: &CancellationToken,
-    ) -> bool
+        now: DateTime<Utc>,
+    ) -> HeartbeatCheck
     where
         Session: WsSession,
     {
         let session = session.read().await;
         if !session.is_alive() {
-            return false;
+            return HeartbeatCheck::Wait;
         }
-        let now = Utc::now();
-        if session.last_received() + Duration::from_millis(interval * 1200) < now {
-            error!("Heartbeat response not received in the last minute, forcing a restart");
-            cancel_token.cancel();
-            false
+        let grace = Duration::from_secs_f64(interval as f64 * grace);
+        // Sent a little early, a server asking for a beat every few seconds
+        // gets one on every check
+        let send_after = Duration::from_secs(interval.saturating_sub(5));
+        if session.last_received() + grace < now {
+            HeartbeatCheck::Missed
+        } else if session.last_sent() + send_after <= now {
+            HeartbeatCheck::Send
         } else {
-            session.last_sent() + Duration::from_secs(interval - 5) <= now
+            HeartbeatCheck::Wait
         }
     }
 
@@ -160,9 +397,140 @@ impl<Session> WebSocketClient<Session> {
         Session: WsSession + std::marker::Send + std::marker::Sync + 'static,
     {
         info!("Sending to websocket: {}", to_json(&payload)?);
-        match self.session.read().await.to_ws().send(to_json(&payload)?) {
+        // Waits for room in the queue rather than dropping the payload
+        match self.to_ws.send(to_json(&payload)?).await {
             Err(err) => anyhow::bail!("Error sending payload to websocket stream, error: {}", err),
             _ => anyhow::Ok(()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fixtures::PROXY_CA;
+    use crate::web_client::sessions::acc_api;
+    use crate::web_client::sessions::AccountSession;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_account_socket_reconnects_with_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (handshakes, mut received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            // Drop each connection once the handshake arrives
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                if let Some(Ok(Message::Text(msg))) = ws.next().await {
+                    handshakes.send(msg).unwrap();
+                }
+            }
+        });
+
+        let (to_ws, from_app) = mpsc::channel(16);
+        let (to_app, _) = tokio::sync::broadcast::channel(16);
+        let live_token = Arc::new(RwLock::new(String::from("token")));
+        let session = AccountSession::new(&url, Arc::clone(&live_token), to_app);
+        let connect = session.write().await.startup("5WX01234").await;
+        let cancel_token = CancellationToken::new();
+        let client = WebSocketClient::new(
+            session,
+            to_ws,
+            None,
+            ReconnectConfig::default(),
+            &TlsConfig::default(),
+            cancel_token.clone(),
+        )
+        .unwrap();
+        client.subscribe_to_events(from_app).await.unwrap();
+        client
+            .send_message::<acc_api::Connect>(connect)
+            .await
+            .unwrap();
+
+        // The session token is refreshed while the socket is down
+        for token in ["token", "refreshed"] {
+            let msg = tokio::time::timeout(Duration::from_secs(5), received.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let connect: acc_api::Connect = serde_json::from_str(&msg).unwrap();
+            assert_eq!(connect.action, "connect");
+            assert_eq!(connect.account_ids, vec!["5WX01234".to_string()]);
+            assert_eq!(connect.auth_token, token);
+            *live_token.write().await = String::from("refreshed");
+        }
+        assert!(!cancel_token.is_cancelled());
+        cancel_token.cancel();
+    }
+
+    #[test]
+    fn test_tls_connector_loads_ca_bundle() {
+        let tls_connector = WebSocketClient::<AccountSession>::tls_connector;
+        assert!(tls_connector(&TlsConfig::default()).is_ok());
+
+        let path = std::env::temp_dir().join(format!("ca-bundle-{}.pem", std::process::id()));
+        let config = TlsConfig {
+            min_version: TlsVersion::Tls12,
+            ca_bundle: Some(path.to_string_lossy().to_string()),
+        };
+        let err = tls_connector(&config).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to read CA bundle"));
+
+        std::fs::write(&path, "not a certificate").unwrap();
+        let err = tls_connector(&config).unwrap_err();
+        assert!(err.to_string().starts_with("No certificates found"));
+
+        std::fs::write(&path, format!("{}{}", PROXY_CA, PROXY_CA)).unwrap();
+        assert!(tls_connector(&config).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missed_heartbeat_only_after_grace() {
+        let (to_app, _) = tokio::sync::broadcast::channel(16);
+        let session = AccountSession::new("ws://localhost", Default::default(), to_app);
+        let check = |grace: f64, now| {
+            WebSocketClient::<AccountSession>::check_heartbeat(30, grace, &session, now)
+        };
+        // Nothing is expected back before the connect response
+        assert_eq!(
+            check(2.0, Utc::now() + Duration::from_secs(600)).await,
+            HeartbeatCheck::Wait
+        );
+
+        session.write().await.startup("5WX01234").await;
+        session.write().await.handle_response::<AccountSession>(
+            r#"{"status":"ok","action":"connect","web-socket-session-id":"id","request-id":1}"#
+                .to_string(),
+            CancellationToken::new(),
+        );
+        let received = session.read().await.last_received();
+        let after = |secs| received + Duration::from_secs(secs);
+        assert_eq!(check(2.0, after(1)).await, HeartbeatCheck::Wait);
+        assert_eq!(check(2.0, after(30)).await, HeartbeatCheck::Send);
+        assert_eq!(check(2.0, after(59)).await, HeartbeatCheck::Send);
+        assert_eq!(check(2.0, after(61)).await, HeartbeatCheck::Missed);
+        assert_eq!(check(1.2, after(37)).await, HeartbeatCheck::Missed);
+
+        // An interval shorter than the lead no longer underflows
+        let short = WebSocketClient::<AccountSession>::check_heartbeat(3, 2.0, &session, after(1));
+        assert_eq!(short.await, HeartbeatCheck::Send);
+    }
+
+    #[test]
+    fn test_breaker_trips_inside_window() {
+        let mut breaker = ReconnectBreaker::new(ReconnectConfig {
+            max_attempts: 2,
+            window_secs: 60,
+            ..ReconnectConfig::default()
+        });
+        let start = Instant::now();
+        assert!(breaker.allow(start));
+        assert!(breaker.allow(start + Duration::from_secs(10)));
+        assert!(!breaker.allow(start + Duration::from_secs(20)));
+        // The first attempt has rolled out of the window
+        assert!(breaker.allow(start + Duration::from_secs(61)));
+    }
+}